@@ -0,0 +1,48 @@
+//! Parse time for long games with a comment run after every move.
+//!
+//! Each group parses synthetic games of increasing length so the per-move cost
+//! can be compared across sizes; it should stay roughly constant.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use csa::parse_csa;
+
+const COMMENTS_PER_MOVE: usize = 10;
+const MOVE_COUNTS: &[usize] = &[250, 500, 1000, 2000];
+
+/// Builds a game of `moves` plies shuffling the rooks back and forth, each
+/// followed by a consumed time and `COMMENTS_PER_MOVE` comment lines.
+fn synthetic_game(version: &str, moves: usize) -> String {
+    let plies = ["+2858HI", "-8252HI", "+5828HI", "-5282HI"];
+    let mut csa = format!("{version}\nN+black\nN-white\nPI\n+\n");
+    for i in 0..moves {
+        csa.push_str(plies[i % plies.len()]);
+        csa.push_str("\nT1\n");
+        for j in 0..COMMENTS_PER_MOVE {
+            csa.push_str(&format!(
+                "'ply {i} line {j}: eval {}, depth 20\n",
+                i * 7 % 300
+            ));
+        }
+    }
+    csa.push_str("%CHUDAN\n");
+    csa
+}
+
+fn bench_parse(c: &mut Criterion) {
+    for version in ["V2.2", "V3.0"] {
+        let mut group = c.benchmark_group(format!("parse_{version}"));
+        group.sample_size(20);
+        for &moves in MOVE_COUNTS {
+            let csa = synthetic_game(version, moves);
+            group.throughput(Throughput::Elements(moves as u64));
+            group.bench_with_input(BenchmarkId::from_parameter(moves), &csa, |b, csa| {
+                b.iter(|| parse_csa(csa).unwrap())
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_parse);
+criterion_main!(benches);
@@ -0,0 +1,90 @@
+//! Prints a table of figures for every CSA file in a directory.
+//!
+//! ```text
+//! cargo run --example kifu-stats -- fixtures/
+//! ```
+
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use csa::{parse_csa, Color};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let dir = std::env::args().nth(1).unwrap_or_else(|| ".".to_string());
+    run(Path::new(&dir), &mut io::stdout().lock())
+}
+
+/// Writes a row for each `.csa` file directly in `dir`, sorted by name. A
+/// file that does not parse gets its error in place of the figures.
+fn run(dir: &Path, out: &mut impl Write) -> Result<(), Box<dyn Error>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "csa") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    writeln!(
+        out,
+        "{:<24} {:>5} {:>6} {:>8} {:>8}  opening",
+        "file", "plies", "result", "time +", "time -"
+    )?;
+    for path in paths {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let game = match parse_csa(&fs::read_to_string(&path)?) {
+            Ok(game) => game,
+            Err(err) => {
+                writeln!(out, "{name:<24} {err}")?;
+                continue;
+            }
+        };
+        let stats = game.stats();
+        let summary = game.summary(false);
+        let result = summary.result.unwrap_or_else(|| "-".to_string());
+        let opening = summary.opening.unwrap_or_else(|| "-".to_string());
+        writeln!(
+            out,
+            "{name:<24} {:>5} {result:>6} {:>8} {:>8}  {opening}",
+            stats.plies,
+            clock(stats.time_spent[Color::Black as usize]),
+            clock(stats.time_spent[Color::White as usize]),
+        )?;
+    }
+    Ok(())
+}
+
+/// Formats a duration as `h:mm:ss`.
+fn clock(time: Duration) -> String {
+    let secs = time.as_secs();
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixtures() {
+        let mut out = Vec::new();
+        run(Path::new("fixtures"), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let rows: Vec<&str> = out.lines().collect();
+        assert!(rows[0].starts_with("file"));
+        assert!(rows
+            .iter()
+            .any(|row| row.starts_with("v1.csa ") && row.contains("failed to parse")));
+        let v2_2 = rows
+            .iter()
+            .find(|row| row.starts_with("v2_2.csa "))
+            .unwrap();
+        assert!(
+            v2_2.contains(" 0:00:12 ") && v2_2.ends_with("YAGURA"),
+            "{v2_2}"
+        );
+    }
+}
@@ -0,0 +1,137 @@
+//! Follows a game as its CSA lines arrive on stdin, printing each event once
+//! it is complete.
+//!
+//! ```text
+//! tail -f game.csa | cargo run --example live-follow
+//! ```
+
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+
+use csa::{parse_csa, InterMoveEvent, KifuEvent};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut follower = Follower::default();
+    let mut out = io::stdout().lock();
+    for line in io::stdin().lock().lines() {
+        for event in follower.feed(&line?) {
+            writeln!(out, "{}", describe(&event))?;
+        }
+    }
+    for event in follower.finish() {
+        writeln!(out, "{}", describe(&event))?;
+    }
+    Ok(())
+}
+
+/// The lines received so far, and how many of their events were reported.
+#[derive(Default)]
+struct Follower {
+    text: String,
+    reported: usize,
+    events: Vec<KifuEvent>,
+}
+
+impl Follower {
+    /// Adds a line and returns the events it completed.
+    ///
+    /// The text so far is parsed again, and the events of the game so far
+    /// are those of [`csa::GameRecord::events`]. The last one is held back,
+    /// since the line after a move may still give its time. Until the text
+    /// parses, for lack of a position or a side to move, nothing is reported.
+    fn feed(&mut self, line: &str) -> Vec<KifuEvent> {
+        self.text.push_str(line);
+        self.text.push('\n');
+        if let Ok(game) = parse_csa(&self.text) {
+            self.events = game.events();
+        }
+        let complete = self.events.len().saturating_sub(1);
+        self.take(complete)
+    }
+
+    /// Returns the events not reported yet, once the input has ended.
+    fn finish(&mut self) -> Vec<KifuEvent> {
+        self.take(self.events.len())
+    }
+
+    fn take(&mut self, end: usize) -> Vec<KifuEvent> {
+        let start = self.reported.min(end);
+        self.reported = self.reported.max(end);
+        self.events[start..end].to_vec()
+    }
+}
+
+fn describe(event: &KifuEvent) -> String {
+    match event {
+        KifuEvent::GameStart {
+            black_player,
+            white_player,
+            ..
+        } => format!(
+            "start: {} vs {}",
+            black_player.as_deref().unwrap_or("?"),
+            white_player.as_deref().unwrap_or("?")
+        ),
+        KifuEvent::Move {
+            ply,
+            action,
+            spent,
+            remaining,
+        } => {
+            let mut text = match ply {
+                Some(ply) => format!("{ply}: {action}"),
+                None => format!("{action}"),
+            };
+            if let Some(spent) = spent {
+                text.push_str(&format!(" in {}s", spent.as_secs()));
+            }
+            if let Some(remaining) = remaining {
+                text.push_str(&format!(", {}s left", remaining.as_secs()));
+            }
+            text
+        }
+        KifuEvent::Adjourn { sealed_move, .. } => match sealed_move {
+            Some(sealed) => format!("adjourned, sealed move {sealed}"),
+            None => "adjourned".to_string(),
+        },
+        KifuEvent::GameEnd { result } => format!("end: {result}"),
+        KifuEvent::InterMove { event, .. } => match event {
+            InterMoveEvent::DrawOffered(color) => format!("{color:?} offers a draw"),
+            InterMoveEvent::DrawDeclined(color) => format!("{color:?} declines the draw"),
+            InterMoveEvent::Pause => "paused".to_string(),
+            InterMoveEvent::Resume => "resumed".to_string(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_fixtures() {
+        for name in ["v2_2", "v3_fischer", "forfeit_chudan"] {
+            let csa = std::fs::read_to_string(format!("fixtures/{name}.csa")).unwrap();
+            let mut follower = Follower::default();
+            let mut events = Vec::new();
+            for line in csa.lines() {
+                events.extend(follower.feed(line));
+            }
+            events.extend(follower.finish());
+            assert_eq!(events, parse_csa(&csa).unwrap().events(), "{name}");
+        }
+    }
+
+    #[test]
+    fn times_wait_for_their_line() {
+        let mut follower = Follower::default();
+        for line in ["V2.2", "N+A", "N-B", "PI", "+"] {
+            assert!(follower.feed(line).is_empty());
+        }
+        assert_eq!(describe(&follower.feed("+7776FU")[0]), "start: A vs B");
+        assert!(follower.feed("T12").is_empty());
+        let events = follower.feed("%TORYO");
+        assert_eq!(describe(&events[0]), "1: +7776FU in 12s");
+        assert_eq!(describe(&follower.finish()[0]), "end: %TORYO");
+    }
+}
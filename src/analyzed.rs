@@ -0,0 +1,150 @@
+//! A record replayed once, for many readers to share.
+
+use std::ops::Deref;
+
+use crate::board::{Board, BoardError};
+use crate::clock::MoveClock;
+use crate::stats::GameStats;
+use crate::value::{Action, GameRecord};
+
+/// A [`GameRecord`] with the data derived from it computed up front, so that
+/// it can be shared, e.g. behind an `Arc`, by readers that would otherwise
+/// each replay the game.
+///
+/// It derefs to the record for the metadata. Its methods of the same names
+/// as those of [`GameRecord`] return the stored results instead of
+/// computing them again. It cannot be changed; [`AnalyzedRecord::into_record`]
+/// gives the record back to change it.
+///
+/// ```
+/// use std::sync::Arc;
+/// use csa::{parse_csa, AnalyzedRecord};
+///
+/// let game = parse_csa("V2.2\nN+Habu\nPI\n+\n+7776FU\n-3334FU\n%TORYO\n").unwrap();
+/// let analyzed = Arc::new(AnalyzedRecord::new(game).unwrap());
+/// let reader = Arc::clone(&analyzed);
+/// std::thread::spawn(move || assert_eq!(reader.position_hashes().len(), 3))
+///     .join()
+///     .unwrap();
+/// assert_eq!(analyzed.black_player.as_deref(), Some("Habu"));
+/// assert_eq!(analyzed.board(2).unwrap().position_hash(), analyzed.position_hashes()[2]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct AnalyzedRecord {
+    record: GameRecord,
+    boards: Vec<Board>,
+    hashes: Vec<u64>,
+    clocks: Vec<MoveClock>,
+    stats: GameStats,
+}
+
+impl AnalyzedRecord {
+    /// Replays `record`, failing as [`GameRecord::start_board`] and
+    /// [`Board::apply`] do if a move cannot be played.
+    pub fn new(record: GameRecord) -> Result<AnalyzedRecord, BoardError> {
+        let mut board = record.start_board()?;
+        let mut boards = Vec::with_capacity(record.moves.len() + 1);
+        boards.push(board.clone());
+        for entry in &record.moves {
+            if let Action::Move(..) = entry.action {
+                board.apply(&entry.action)?;
+                boards.push(board.clone());
+            }
+        }
+        Ok(AnalyzedRecord {
+            hashes: boards.iter().map(Board::position_hash).collect(),
+            clocks: record.clocks(),
+            stats: record.stats(),
+            boards,
+            record,
+        })
+    }
+
+    pub fn record(&self) -> &GameRecord {
+        &self.record
+    }
+
+    pub fn into_record(self) -> GameRecord {
+        self.record
+    }
+
+    /// Returns the board after ply `ply`, the starting position for 0.
+    pub fn board(&self, ply: usize) -> Option<&Board> {
+        self.boards.get(ply)
+    }
+
+    /// Returns the board after each ply, from the starting position on.
+    pub fn boards(&self) -> &[Board] {
+        &self.boards
+    }
+
+    /// See [`GameRecord::position_hashes`].
+    pub fn position_hashes(&self) -> &[u64] {
+        &self.hashes
+    }
+
+    /// See [`GameRecord::clocks`].
+    pub fn clocks(&self) -> &[MoveClock] {
+        &self.clocks
+    }
+
+    /// See [`GameRecord::stats`].
+    pub fn stats(&self) -> &GameStats {
+        &self.stats
+    }
+}
+
+impl Deref for AnalyzedRecord {
+    type Target = GameRecord;
+
+    fn deref(&self) -> &GameRecord {
+        &self.record
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_csa;
+
+    #[test]
+    fn matches_the_record() {
+        let game = parse_csa(
+            "V2.2\n$TIME_LIMIT:00:01+10\nPI\n+\n+7776FU\nT30\n-3334FU\nT70\n+2726FU\nT40\n%TORYO\n",
+        )
+        .unwrap();
+        let analyzed = AnalyzedRecord::new(game.clone()).unwrap();
+        assert_eq!(analyzed.position_hashes(), game.position_hashes().unwrap());
+        assert_eq!(analyzed.clocks(), game.clocks());
+        assert_eq!(*analyzed.stats(), game.stats());
+        assert_eq!(analyzed.boards().len(), game.ply_count() + 1);
+        assert_eq!(analyzed.board(0), Some(&game.start_board().unwrap()));
+        assert_eq!(analyzed.board(4), None);
+        assert_eq!(analyzed.into_record(), game);
+
+        let illegal = parse_csa("V2.2\nPI\n+\n+7776FU\n+2726FU\n").unwrap();
+        assert!(AnalyzedRecord::new(illegal).is_err());
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let csa = std::fs::read_to_string("fixtures/v2_2.csa").unwrap();
+        let game = parse_csa(&csa).unwrap();
+        let hashes = game.position_hashes().unwrap();
+        let analyzed = AnalyzedRecord::new(game).unwrap();
+        std::thread::scope(|scope| {
+            for offset in 0..8 {
+                let (analyzed, hashes) = (&analyzed, &hashes);
+                scope.spawn(move || {
+                    for round in 0..200 {
+                        let ply = (offset + round) % hashes.len();
+                        assert_eq!(analyzed.position_hashes()[ply], hashes[ply]);
+                        assert_eq!(analyzed.board(ply).unwrap().position_hash(), hashes[ply]);
+                        assert_eq!(analyzed.stats().plies, hashes.len() - 1);
+                        assert!(analyzed.to_string().starts_with("V2.2"));
+                    }
+                });
+            }
+        });
+    }
+}
@@ -0,0 +1,461 @@
+//! Stripping personal data from records before they are published.
+
+use time::Date as NativeDate;
+
+use crate::names::{DefaultNormalizer, NameNormalizer};
+use crate::value::{AttrValue, Color, GameRecord, RecordText, Time};
+
+/// What [`GameRecord::anonymized`] does with player names.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub enum NamePolicy {
+    /// Leaves the names as they are.
+    Keep,
+    /// Removes the names, keeping bare `N+`/`N-` lines.
+    #[default]
+    Remove,
+    /// Replaces each name with a pseudonym such as `player-1a2b3c4d5e6f7a8b`,
+    /// derived from the name by HMAC-SHA256 under this key, so a player gets
+    /// the same pseudonym in every record anonymized with the same key. Names
+    /// are compared as [`DefaultNormalizer`] normalizes them.
+    ///
+    /// A pseudonym keeps 64 bits of the HMAC. Two players can still get the
+    /// same one, merging them, but with a billion distinct names the chance
+    /// is below 3%.
+    Pseudonymize(Vec<u8>),
+}
+
+/// What [`GameRecord::anonymized`] removes. The default removes everything
+/// it can.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AnonymizePolicy {
+    pub names: NamePolicy,
+    /// Removes `$EVENT`.
+    pub clear_event: bool,
+    /// Removes `$SITE`.
+    pub clear_site: bool,
+    /// Removes the header comments and the comments of bookmarks, which are
+    /// free text.
+    pub clear_comments: bool,
+    /// Keeps only the year and month of dates, written as the first of the
+    /// month, and drops times of day.
+    pub coarsen_dates: bool,
+}
+
+impl Default for AnonymizePolicy {
+    fn default() -> Self {
+        AnonymizePolicy {
+            names: NamePolicy::Remove,
+            clear_event: true,
+            clear_site: true,
+            clear_comments: true,
+            coarsen_dates: true,
+        }
+    }
+}
+
+/// The shortest part of a name, in characters, that is scrubbed from text on
+/// its own.
+const MIN_NAME_PART: usize = 3;
+
+impl GameRecord {
+    /// Returns a copy of the record with personal data removed as `policy`
+    /// says. The position, the moves and their times are left intact.
+    ///
+    /// Unless names are kept, player aliases are removed, rating comments
+    /// are written again under the new names, and every name is replaced
+    /// wherever it appears in text: the event, the site, the opening, text
+    /// attributes, including those read from commented attributes, the
+    /// header comments, bookmarks and the continuation of an adjournment. Words of three characters or
+    /// more from a name are replaced on their own too, ASCII letters in any
+    /// case.
+    ///
+    /// ```
+    /// use csa::{parse_csa, AnonymizePolicy, NamePolicy};
+    ///
+    /// let game = parse_csa(
+    ///     "V2.2\nN+HABU Yoshiharu\nN-SATO Amahiko\n$EVENT:Habu vs Sato\n\
+    ///      $START_TIME:2024/01/06 10:00\nPI\n+\n+7776FU\n",
+    /// )
+    /// .unwrap();
+    /// let policy = AnonymizePolicy {
+    ///     names: NamePolicy::Pseudonymize(b"secret".to_vec()),
+    ///     clear_event: false,
+    ///     ..AnonymizePolicy::default()
+    /// };
+    /// let csa = game.anonymized(&policy).to_string();
+    /// assert!(csa.contains("$START_TIME:2024/01/01\n"));
+    /// assert!(!csa.contains("HABU") && !csa.to_lowercase().contains("sato"));
+    /// ```
+    pub fn anonymized(&self, policy: &AnonymizePolicy) -> GameRecord {
+        let mut record = self.clone();
+        if policy.clear_event {
+            record.event = None;
+        }
+        if policy.clear_site {
+            record.site = None;
+        }
+        if policy.clear_comments {
+            record.header_comments.clear();
+            for bookmark in &mut record.bookmarks {
+                bookmark.comment = None;
+            }
+        }
+        if policy.names != NamePolicy::Keep {
+            record.replace_names(&policy.names);
+        }
+        if policy.coarsen_dates {
+            record.coarsen_dates();
+        }
+        record
+    }
+
+    fn replace_names(&mut self, policy: &NamePolicy) {
+        let mut replacements = Vec::new();
+        for color in [Color::Black, Color::White] {
+            let player = match color {
+                Color::Black => &self.black_player,
+                Color::White => &self.white_player,
+            };
+            let names: Vec<String> = player
+                .iter()
+                .map(|name| name.to_string())
+                .chain(self.player_aliases(color).into_iter().map(str::to_string))
+                .collect();
+            let Some(name) = names.first() else {
+                continue;
+            };
+            let replacement = match policy {
+                NamePolicy::Pseudonymize(key) => pseudonym(key, name),
+                _ => "anonymous".to_string(),
+            };
+            for name in &names {
+                replacements.push((name.clone(), replacement.clone()));
+                for part in name.split_whitespace() {
+                    if part.chars().count() >= MIN_NAME_PART {
+                        replacements.push((part.to_string(), replacement.clone()));
+                    }
+                }
+            }
+            let player = match policy {
+                NamePolicy::Pseudonymize(_) => Some(RecordText::from(replacement)),
+                _ => None,
+            };
+            match color {
+                Color::Black => {
+                    self.layout.bare_black_player = player.is_none();
+                    self.black_player = player;
+                }
+                Color::White => {
+                    self.layout.bare_white_player = player.is_none();
+                    self.white_player = player;
+                }
+            }
+        }
+        // Longer names first, so a name is not half replaced by one of its
+        // parts.
+        replacements.sort_by_key(|(name, _)| std::cmp::Reverse(name.len()));
+        let scrub = |text: &str| {
+            replacements
+                .iter()
+                .fold(text.to_string(), |text, (name, replacement)| {
+                    replace_ignoring_ascii_case(&text, name, replacement)
+                })
+        };
+
+        self.extra_attrs
+            .retain(|(key, _)| key != "BLACK_ALIAS" && key != "WHITE_ALIAS");
+        self.layout.rating_comments.clear();
+        for comment in &mut self.header_comments {
+            *comment = scrub(comment);
+        }
+        for text in [&mut self.event, &mut self.site, &mut self.opening]
+            .into_iter()
+            .flatten()
+        {
+            *text = RecordText::from(scrub(text));
+        }
+        for (_, value) in &mut self.extra_attrs {
+            match value {
+                AttrValue::Text(text) | AttrValue::Url(text) => *text = scrub(text),
+                AttrValue::Integer(..) | AttrValue::Boolean(..) | AttrValue::DateTime(..) => {}
+            }
+        }
+        for bookmark in &mut self.bookmarks {
+            bookmark.label = scrub(&bookmark.label);
+            if let Some(ref mut comment) = bookmark.comment {
+                *comment = scrub(comment);
+            }
+        }
+        if let Some(ref mut adjournment) = self.adjournment {
+            if let Some(ref mut continuation) = adjournment.continuation {
+                *continuation = scrub(continuation);
+            }
+        }
+    }
+
+    fn coarsen_dates(&mut self) {
+        let coarsen = |time: &mut Time| {
+            let date = time.date;
+            time.date = NativeDate::from_calendar_date(date.year(), date.month(), 1).unwrap();
+            time.time = None;
+        };
+        for time in [&mut self.start_time, &mut self.end_time]
+            .into_iter()
+            .flatten()
+        {
+            coarsen(time);
+        }
+        if let Some(time) = self
+            .adjournment
+            .as_mut()
+            .and_then(|a| a.resume_time.as_mut())
+        {
+            coarsen(time);
+        }
+        for (_, value) in &mut self.extra_attrs {
+            if let AttrValue::DateTime(time, raw) = value {
+                coarsen(time);
+                *raw = time.to_string();
+            }
+        }
+    }
+}
+
+/// Returns the pseudonym of `name` under `key`.
+fn pseudonym(key: &[u8], name: &str) -> String {
+    let mac = hmac_sha256(key, DefaultNormalizer.normalize(name).as_bytes());
+    let hex: String = mac[..8].iter().map(|b| format!("{b:02x}")).collect();
+    format!("player-{hex}")
+}
+
+/// Replaces every occurrence of `name` in `text`, comparing ASCII letters in
+/// any case.
+fn replace_ignoring_ascii_case(text: &str, name: &str, replacement: &str) -> String {
+    if name.is_empty() {
+        return text.to_string();
+    }
+    // ASCII lowercasing keeps every byte offset.
+    let lowered = text.to_ascii_lowercase();
+    let name = name.to_ascii_lowercase();
+    let mut out = String::with_capacity(text.len());
+    let mut start = 0;
+    while let Some(found) = lowered[start..].find(&name) {
+        out.push_str(&text[start..start + found]);
+        out.push_str(replacement);
+        start += found + name.len();
+    }
+    out.push_str(&text[start..]);
+    out
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut block = [0; 64];
+    if key.len() > block.len() {
+        block[..32].copy_from_slice(&sha256(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let pad = |byte: u8| block.iter().map(|b| b ^ byte).collect::<Vec<u8>>();
+    let mut inner = pad(0x36);
+    inner.extend_from_slice(message);
+    let mut outer = pad(0x5c);
+    outer.extend_from_slice(&sha256(&inner));
+    sha256(&outer)
+}
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+fn sha256(message: &[u8]) -> [u8; 32] {
+    let mut state: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+    let mut padded = message.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&((message.len() as u64) * 8).to_be_bytes());
+
+    for chunk in padded.chunks(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in chunk.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(SHA256_K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (s, v) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+
+    let mut digest = [0; 32];
+    for (out, word) in digest.chunks_mut(4).zip(state) {
+        out.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_csa, WriteOptions};
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    #[test]
+    fn digests() {
+        assert_eq!(
+            hex(&sha256(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hex(&sha256(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        // RFC 4231, test cases 2 and 6.
+        assert_eq!(
+            hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            hex(&hmac_sha256(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    const GAME: &str = "V2.2\nN+HABU Yoshiharu\nN-Sato Amahiko\n\
+        $EVENT:Habu vs Sato\n$SITE:Sato's club\n$START_TIME:2024/01/06 10:00\n\
+        $NOTE:annotated by habu yoshiharu\n$BLACK_ALIAS:羽生善治\n\
+        'black_rate:HABU Yoshiharu+0123:1800\nPI\n+\n+7776FU\nT3\n\
+        '&sato resigns: SATO Amahiko lost here\n%CHUDAN\n\
+        'CHUDAN:CONTINUATION:wdoor+HABU Yoshiharu+Sato Amahiko\n";
+
+    #[test]
+    fn no_name_survives() {
+        let game = parse_csa(GAME).unwrap();
+        for names in [
+            NamePolicy::Remove,
+            NamePolicy::Pseudonymize(b"key".to_vec()),
+        ] {
+            let policy = AnonymizePolicy {
+                names,
+                clear_event: false,
+                clear_site: false,
+                clear_comments: false,
+                coarsen_dates: true,
+            };
+            let anonymized = game.anonymized(&policy);
+            let csa = anonymized.to_string().to_lowercase();
+            for name in ["habu", "yoshiharu", "sato", "amahiko", "羽生"] {
+                assert!(!csa.contains(name), "{name} in {csa}");
+            }
+            assert!(csa.contains("$start_time:2024/01/01\n"), "{csa}");
+            assert_eq!(anonymized.moves, game.moves);
+            assert_eq!(anonymized.black_rating, Some(1800));
+        }
+    }
+
+    #[test]
+    fn header_comments_are_scrubbed() {
+        let game = parse_csa("'Game between HABU and SATO\nV3.0\nN+HABU\nN-SATO\nPI\n+\n").unwrap();
+        let policy = AnonymizePolicy {
+            names: NamePolicy::Pseudonymize(b"key".to_vec()),
+            clear_comments: false,
+            ..AnonymizePolicy::default()
+        };
+        let anonymized = game.anonymized(&policy);
+        let expected = format!(
+            "'Game between {} and {}",
+            pseudonym(b"key", "HABU"),
+            pseudonym(b"key", "SATO")
+        );
+        assert_eq!(anonymized.header_comments, [expected]);
+        let same_version = WriteOptions {
+            same_version: true,
+            ..WriteOptions::default()
+        };
+        let csa = anonymized.to_csa(&same_version);
+        assert!(!csa.contains("HABU") && !csa.contains("SATO"), "{csa}");
+    }
+
+    #[test]
+    fn pseudonyms_are_stable() {
+        let key = b"corpus key".to_vec();
+        let policy = AnonymizePolicy {
+            names: NamePolicy::Pseudonymize(key.clone()),
+            ..AnonymizePolicy::default()
+        };
+        let first = parse_csa(GAME).unwrap().anonymized(&policy);
+        let second = parse_csa("V2.2\nN+ｓａｔｏ  amahiko\nN-habu yoshiharu\nPI\n+\n")
+            .unwrap()
+            .anonymized(&policy);
+        assert_eq!(first.black_player, second.white_player);
+        assert_eq!(first.white_player, second.black_player);
+        assert_ne!(first.black_player, first.white_player);
+        assert_eq!(
+            first.black_player.as_deref(),
+            Some(pseudonym(&key, "HABU Yoshiharu").as_str())
+        );
+        assert_ne!(
+            pseudonym(b"other key", "HABU Yoshiharu"),
+            pseudonym(&key, "HABU Yoshiharu")
+        );
+        // 64 bits of the HMAC, the first eight bytes.
+        let mac = hmac_sha256(&key, "habu yoshiharu".as_bytes());
+        assert_eq!(
+            pseudonym(&key, "HABU Yoshiharu"),
+            format!("player-{}", hex(&mac[..8]))
+        );
+
+        let removed = parse_csa(GAME)
+            .unwrap()
+            .anonymized(&AnonymizePolicy::default());
+        assert_eq!(removed.black_player, None);
+        assert!(removed.layout.bare_black_player);
+        assert_eq!((removed.event, removed.site), (None, None));
+        assert_eq!(removed.bookmarks[0].comment, None);
+    }
+}
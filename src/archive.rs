@@ -0,0 +1,448 @@
+//! File names of records in an archive: reading metadata out of them and
+//! naming records after their metadata.
+
+use std::time::Duration;
+
+use crate::tournament::is_game_name;
+use crate::value::{GameRecord, RecordText, Time, TimeLimit};
+
+/// A way of naming record files, see [`suggest_filename`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum FilenameConvention {
+    /// The shogi-server game name Floodgate names its files after, such as
+    /// `wdoor+floodgate-300-10F+gikou+apery+20160606193002.csa`: the game
+    /// name, which may hold `+` itself, then the black and white players and
+    /// the start time, separated by `+`. A game name ending in
+    /// `-<seconds>-<seconds>` gives the main time and the byoyomi, or the
+    /// increment when an `F` follows.
+    Wdoor,
+    /// The start date and the players, as in
+    /// `20160606_gikou_vs_apery.csa`, or `undated_gikou_vs_apery.csa`.
+    DateAndPlayers,
+}
+
+/// Metadata read out of a file name by [`parse_filename`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct FilenameMeta {
+    pub convention: FilenameConvention,
+    /// The shogi-server game name, such as `wdoor+floodgate-300-10F`.
+    pub game_name: Option<String>,
+    /// The event the game name starts with, such as `floodgate`.
+    pub event: Option<String>,
+    pub time_limit: Option<TimeLimit>,
+    pub black_player: String,
+    pub white_player: String,
+    /// The start time, or only the date with
+    /// [`FilenameConvention::DateAndPlayers`].
+    pub start_time: Option<Time>,
+}
+
+/// Reads the metadata out of a record's file name. A directory and a `.csa`
+/// extension are allowed and ignored. `None` when the name follows none of
+/// the [`FilenameConvention`]s.
+///
+/// ```
+/// use std::time::Duration;
+/// use csa::archive::parse_filename;
+///
+/// let meta = parse_filename("2016/wdoor+floodgate-300-10F+gikou+apery+20160606193002.csa").unwrap();
+/// assert_eq!(meta.event.as_deref(), Some("floodgate"));
+/// assert_eq!(meta.black_player, "gikou");
+/// assert_eq!(meta.time_limit.unwrap().increment, Duration::from_secs(10));
+/// assert_eq!(meta.start_time.unwrap().to_string(), "2016/06/06 19:30:02");
+/// ```
+pub fn parse_filename(name: &str) -> Option<FilenameMeta> {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+    let stem = name.strip_suffix(".csa").unwrap_or(name);
+    parse_wdoor(stem).or_else(|| parse_date_and_players(stem))
+}
+
+fn parse_wdoor(stem: &str) -> Option<FilenameMeta> {
+    if !is_game_name(stem) {
+        return None;
+    }
+    let mut fields = stem.rsplitn(4, '+');
+    let timestamp = fields.next()?;
+    let white_player = fields.next()?;
+    let black_player = fields.next()?;
+    let game_name = fields.next()?;
+
+    let last = game_name.rsplit('+').next().unwrap_or(game_name);
+    let (event, time_limit) = match parse_time_control(last) {
+        Some((event, time_limit)) => (event, Some(time_limit)),
+        None => (last, None),
+    };
+    let digits = |range: std::ops::Range<usize>| &timestamp[range];
+    let start_time = Time::parse(&format!(
+        "{}/{}/{} {}:{}:{}",
+        digits(0..4),
+        digits(4..6),
+        digits(6..8),
+        digits(8..10),
+        digits(10..12),
+        digits(12..14)
+    ));
+    Some(FilenameMeta {
+        convention: FilenameConvention::Wdoor,
+        game_name: Some(game_name.to_string()),
+        event: Some(event.to_string()),
+        time_limit,
+        black_player: black_player.to_string(),
+        white_player: white_player.to_string(),
+        start_time,
+    })
+}
+
+/// Splits `floodgate-300-10F` into the event and the time limit.
+fn parse_time_control(field: &str) -> Option<(&str, TimeLimit)> {
+    let mut parts = field.rsplitn(3, '-');
+    let last = parts.next()?;
+    let main: u64 = parts.next()?.parse().ok()?;
+    let event = parts.next()?;
+    let (seconds, fischer) = match last.strip_suffix('F') {
+        Some(seconds) => (seconds, true),
+        None => (last, false),
+    };
+    let seconds = Duration::from_secs(seconds.parse().ok()?);
+    let time_limit = TimeLimit {
+        main_time: Duration::from_secs(main),
+        byoyomi: if fischer { Duration::ZERO } else { seconds },
+        increment: if fischer { seconds } else { Duration::ZERO },
+        ..TimeLimit::default()
+    };
+    Some((event, time_limit))
+}
+
+fn parse_date_and_players(stem: &str) -> Option<FilenameMeta> {
+    let (date, players) = stem.split_once('_')?;
+    let (black_player, white_player) = players.split_once("_vs_")?;
+    let start_time = match date {
+        "undated" => None,
+        _ if date.len() == 8 && date.bytes().all(|b| b.is_ascii_digit()) => Some(Time::parse(
+            &format!("{}/{}/{}", &date[..4], &date[4..6], &date[6..]),
+        )?),
+        _ => return None,
+    };
+    if black_player.is_empty() || white_player.is_empty() {
+        return None;
+    }
+    Some(FilenameMeta {
+        convention: FilenameConvention::DateAndPlayers,
+        game_name: None,
+        event: None,
+        time_limit: None,
+        black_player: black_player.to_string(),
+        white_player: white_player.to_string(),
+        start_time,
+    })
+}
+
+/// Returns a file name for `record` following `convention`, with the `.csa`
+/// extension.
+///
+/// Whitespace and the characters no common file system allows, `<>:"/\|?*`
+/// and control characters, become `_`, as does `+` in
+/// [`FilenameConvention::Wdoor`] fields. A missing player is `anonymous`.
+///
+/// With [`FilenameConvention::Wdoor`], an `$EVENT` holding a shogi-server
+/// game name is used as the whole name. Otherwise the name is built from
+/// `$EVENT` (`game` when missing), the time limit and the start time, all
+/// zeros when missing.
+///
+/// ```
+/// use csa::archive::{suggest_filename, FilenameConvention};
+/// use csa::parse_csa;
+///
+/// let game = parse_csa("V2.2\nN+Habu Yoshiharu\nN-Sato\n$START_TIME:2024/01/06 10:00:00\nPI\n+\n").unwrap();
+/// assert_eq!(
+///     suggest_filename(&game, FilenameConvention::DateAndPlayers),
+///     "20240106_Habu_Yoshiharu_vs_Sato.csa"
+/// );
+/// ```
+pub fn suggest_filename(record: &GameRecord, convention: FilenameConvention) -> String {
+    let player = |name: &Option<RecordText>| {
+        let name = name.as_deref().unwrap_or("anonymous");
+        sanitize(name, convention)
+    };
+    let (black, white) = (player(&record.black_player), player(&record.white_player));
+    match convention {
+        FilenameConvention::Wdoor => {
+            if let Some(event) = record.event.as_deref().filter(|event| is_game_name(event)) {
+                return format!("{}.csa", sanitize_with(event, &[]));
+            }
+            let mut game_name = sanitize(record.event.as_deref().unwrap_or("game"), convention);
+            if let Some(limit) = record.time_limit() {
+                let (seconds, fischer) = if limit.increment.is_zero() {
+                    (limit.byoyomi, "")
+                } else {
+                    (limit.increment, "F")
+                };
+                game_name.push_str(&format!(
+                    "-{}-{}{fischer}",
+                    limit.main_time.as_secs(),
+                    seconds.as_secs()
+                ));
+            }
+            let timestamp = match record.start_time {
+                Some(ref start) => {
+                    let date = start.date;
+                    let (hour, minute, second) = start.time.map_or((0, 0, 0), |t| t.as_hms());
+                    format!(
+                        "{:04}{:02}{:02}{hour:02}{minute:02}{second:02}",
+                        date.year(),
+                        u8::from(date.month()),
+                        date.day()
+                    )
+                }
+                None => "0".repeat(14),
+            };
+            format!("{game_name}+{black}+{white}+{timestamp}.csa")
+        }
+        FilenameConvention::DateAndPlayers => {
+            let date = match record.start_time {
+                Some(ref start) => format!(
+                    "{:04}{:02}{:02}",
+                    start.date.year(),
+                    u8::from(start.date.month()),
+                    start.date.day()
+                ),
+                None => "undated".to_string(),
+            };
+            format!("{date}_{black}_vs_{white}.csa")
+        }
+    }
+}
+
+/// Replaces the characters `convention` cannot hold in a field with `_`.
+fn sanitize(s: &str, convention: FilenameConvention) -> String {
+    match convention {
+        FilenameConvention::Wdoor => sanitize_with(s, &['+']),
+        FilenameConvention::DateAndPlayers => sanitize_with(s, &[]),
+    }
+}
+
+/// Replaces whitespace, the characters no common file system allows, and
+/// `extra` with `_`. Trailing dots, which Windows drops, go too.
+fn sanitize_with(s: &str, extra: &[char]) -> String {
+    let replaced: String = s
+        .chars()
+        .map(|c| {
+            if c.is_whitespace()
+                || c.is_control()
+                || "<>:\"/\\|?*".contains(c)
+                || extra.contains(&c)
+            {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    replaced.trim_end_matches('.').to_string()
+}
+
+impl FilenameMeta {
+    /// Compares the metadata with the record's and describes each
+    /// difference. Names are compared in the form the convention writes
+    /// them in, and only the date when the file name has no time of day.
+    ///
+    /// ```
+    /// use csa::archive::parse_filename;
+    /// use csa::parse_csa;
+    ///
+    /// let meta = parse_filename("20240106_Habu_Yoshiharu_vs_Sato.csa").unwrap();
+    /// let game = parse_csa("V2.2\nN+Habu Yoshiharu\nN-Fujii\n$START_TIME:2024/01/06 10:00:00\nPI\n+\n").unwrap();
+    /// assert_eq!(
+    ///     meta.mismatches(&game),
+    ///     ["white player is `Sato` in the file name but `Fujii` in the record"]
+    /// );
+    /// ```
+    pub fn mismatches(&self, record: &GameRecord) -> Vec<String> {
+        let mut mismatches = Vec::new();
+        let mut check = |what: &str, file: Option<String>, recorded: Option<String>| {
+            if let (Some(file), Some(recorded)) = (file, recorded) {
+                if file != recorded {
+                    mismatches.push(format!(
+                        "{what} is `{file}` in the file name but `{recorded}` in the record"
+                    ));
+                }
+            }
+        };
+
+        let player =
+            |name: &Option<RecordText>| name.as_deref().map(|name| sanitize(name, self.convention));
+        check(
+            "black player",
+            Some(self.black_player.clone()),
+            player(&record.black_player),
+        );
+        check(
+            "white player",
+            Some(self.white_player.clone()),
+            player(&record.white_player),
+        );
+
+        let start = |time: &Time, with_time: bool| {
+            let time = Time {
+                date: time.date,
+                time: time.time.filter(|_| with_time),
+            };
+            time.to_string()
+        };
+        if let (Some(file), Some(recorded)) = (&self.start_time, &record.start_time) {
+            let with_time = file.time.is_some() && recorded.time.is_some();
+            check(
+                "start time",
+                Some(start(file, with_time)),
+                Some(start(recorded, with_time)),
+            );
+        }
+
+        let time_limit = |limit: &TimeLimit| (limit.main_time, limit.byoyomi, limit.increment);
+        if let (Some(file), Some(recorded)) = (&self.time_limit, record.time_limit()) {
+            if time_limit(file) != time_limit(recorded) {
+                let file = format_time_control(file);
+                let recorded = format_time_control(recorded);
+                check("time limit", Some(file), Some(recorded));
+            }
+        }
+
+        let event = record.event.as_deref().filter(|event| is_game_name(event));
+        check(
+            "game name",
+            self.game_name.clone(),
+            event.and_then(|event| event.rsplitn(4, '+').last().map(str::to_string)),
+        );
+        mismatches
+    }
+}
+
+/// Writes a time limit as main time, byoyomi and increment in seconds.
+fn format_time_control(limit: &TimeLimit) -> String {
+    format!(
+        "{}s + {}s byoyomi + {}s increment",
+        limit.main_time.as_secs(),
+        limit.byoyomi.as_secs(),
+        limit.increment.as_secs()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_csa;
+
+    #[test]
+    fn parse_wdoor_names() {
+        let meta =
+            parse_filename("wdoor+floodgate-300-10F+gikou+apery+20160606193002.csa").unwrap();
+        assert_eq!(meta.convention, FilenameConvention::Wdoor);
+        assert_eq!(meta.game_name.as_deref(), Some("wdoor+floodgate-300-10F"));
+        assert_eq!(meta.event.as_deref(), Some("floodgate"));
+        assert_eq!(
+            (meta.black_player.as_str(), meta.white_player.as_str()),
+            ("gikou", "apery")
+        );
+        let limit = meta.time_limit.unwrap();
+        assert_eq!(limit.main_time, Duration::from_secs(300));
+        assert_eq!(limit.byoyomi, Duration::ZERO);
+
+        // Byoyomi without the `F`, and a game name without a time control.
+        let meta = parse_filename("C:\\kifu\\wdoor+floodgate-900-30+a+b+20080505000000").unwrap();
+        assert_eq!(meta.time_limit.unwrap().byoyomi, Duration::from_secs(30));
+        let meta = parse_filename("local+test_game+a+b+20240106100000.csa").unwrap();
+        assert_eq!(meta.event.as_deref(), Some("test_game"));
+        assert_eq!(meta.time_limit, None);
+
+        // An impossible date leaves only the start time out.
+        let meta = parse_filename("x-1-2+a+b+20241399000000").unwrap();
+        assert_eq!(meta.start_time, None);
+        assert_eq!(meta.event.as_deref(), Some("x"));
+
+        for name in ["floodgate+a+b.csa", "notes.txt", "a+b+c+2024010610000"] {
+            assert_eq!(parse_filename(name), None, "{name}");
+        }
+    }
+
+    #[test]
+    fn parse_date_names() {
+        let meta = parse_filename("20240106_Habu_Yoshiharu_vs_Sato.csa").unwrap();
+        assert_eq!(meta.convention, FilenameConvention::DateAndPlayers);
+        assert_eq!(meta.black_player, "Habu_Yoshiharu");
+        assert_eq!(meta.white_player, "Sato");
+        assert_eq!(meta.start_time.unwrap().to_string(), "2024/01/06");
+
+        let meta = parse_filename("undated_a_vs_b").unwrap();
+        assert_eq!(meta.start_time, None);
+        for name in [
+            "20240106_a_vs_.csa",
+            "2024016_a_vs_b.csa",
+            "20240106_a_b.csa",
+        ] {
+            assert_eq!(parse_filename(name), None, "{name}");
+        }
+    }
+
+    #[test]
+    fn suggest_and_parse_back() {
+        let floodgate = parse_csa(include_str!("../fixtures/trailing_attrs.csa")).unwrap();
+        let name = suggest_filename(&floodgate, FilenameConvention::Wdoor);
+        assert_eq!(
+            name,
+            "wdoor+floodgate-300-10F+player1+player2+20240106100000.csa"
+        );
+
+        let game = parse_csa(
+            "V2.2\nN+a/b+c\nN-名人: \"X\".\n$EVENT:Meijin-sen\n$START_TIME:2024/01/06 10:00:00\n\
+             $TIME_LIMIT:02:00+60\nPI\n+\n",
+        )
+        .unwrap();
+        let name = suggest_filename(&game, FilenameConvention::Wdoor);
+        assert_eq!(
+            name,
+            "Meijin-sen-7200-60+a_b_c+名人___X_+20240106100000.csa"
+        );
+        let meta = parse_filename(&name).unwrap();
+        assert_eq!(meta.event.as_deref(), Some("Meijin-sen"));
+        assert!(
+            meta.mismatches(&game).is_empty(),
+            "{:?}",
+            meta.mismatches(&game)
+        );
+
+        let name = suggest_filename(&game, FilenameConvention::DateAndPlayers);
+        assert_eq!(name, "20240106_a_b+c_vs_名人___X_.csa");
+        let meta = parse_filename(&name).unwrap();
+        assert!(
+            meta.mismatches(&game).is_empty(),
+            "{:?}",
+            meta.mismatches(&game)
+        );
+
+        let bare = parse_csa("V2.2\nPI\n+\n").unwrap();
+        assert_eq!(
+            suggest_filename(&bare, FilenameConvention::Wdoor),
+            "game+anonymous+anonymous+00000000000000.csa"
+        );
+        assert_eq!(
+            suggest_filename(&bare, FilenameConvention::DateAndPlayers),
+            "undated_anonymous_vs_anonymous.csa"
+        );
+    }
+
+    #[test]
+    fn mismatches() {
+        let game = parse_csa(include_str!("../fixtures/trailing_attrs.csa")).unwrap();
+        let meta =
+            parse_filename("wdoor+floodgate-600-10F+player1+player3+20240107100000.csa").unwrap();
+        assert_eq!(
+            meta.mismatches(&game),
+            [
+                "white player is `player3` in the file name but `player2` in the record",
+                "start time is `2024/01/07 10:00:00` in the file name but `2024/01/06 10:00:00` in the record",
+                "time limit is `600s + 0s byoyomi + 10s increment` in the file name but `300s + 10s byoyomi + 0s increment` in the record",
+                "game name is `wdoor+floodgate-600-10F` in the file name but `wdoor+floodgate-300-10F` in the record",
+            ]
+        );
+    }
+}
@@ -0,0 +1,999 @@
+//! A compact binary encoding of [`GameRecord`] for caching parsed records.
+//!
+//! The encoding is private to this crate and tied to [`FORMAT_VERSION`]: a
+//! cache written by a different version is rejected rather than misread. The
+//! layout is a 4-byte magic, the format version, the payload and a 32-bit
+//! FNV-1a checksum of everything before it. Moves take three bytes plus the
+//! time consumed, if any.
+
+use std::fmt;
+use std::time::Duration;
+
+use time::{Date as NativeDate, Time as NativeTime};
+
+use crate::parser::csa::{EventConvention, Version};
+use crate::value::{
+    Action, Adjournment, AttrValue, Bookmark, Color, GameRecord, InterMoveEvent, Layout,
+    MoveRecord, PieceList, PieceType, Position, RecordText, SourceFormat, SpecialToken, Square,
+    Time, TimeLimit, TimeScale, TimeUnit,
+};
+
+/// Leading bytes of every encoded record.
+const MAGIC: &[u8; 4] = b"CSAR";
+
+/// Version of the encoding, bumped whenever the layout changes.
+pub const FORMAT_VERSION: u8 = 10;
+
+/// CSA versions in the order of their codes.
+const VERSIONS: [Version; 4] = [Version::V2, Version::V2_1, Version::V2_2, Version::V3];
+
+/// Piece types in the order of their codes.
+const PIECE_TYPES: [PieceType; 15] = [
+    PieceType::Pawn,
+    PieceType::Lance,
+    PieceType::Knight,
+    PieceType::Silver,
+    PieceType::Gold,
+    PieceType::Bishop,
+    PieceType::Rook,
+    PieceType::King,
+    PieceType::ProPawn,
+    PieceType::ProLance,
+    PieceType::ProKnight,
+    PieceType::ProSilver,
+    PieceType::Horse,
+    PieceType::Dragon,
+    PieceType::All,
+];
+
+/// Special moves in the order of their codes, starting from 1.
+const SPECIAL_ACTIONS: [Action; 15] = [
+    Action::Toryo,
+    Action::Chudan,
+    Action::Sennichite,
+    Action::TimeUp,
+    Action::IllegalMove,
+    Action::IllegalAction(Color::Black),
+    Action::IllegalAction(Color::White),
+    Action::Jishogi,
+    Action::Kachi,
+    Action::Hikiwake,
+    Action::Matta,
+    Action::Tsumi,
+    Action::Fuzumi,
+    Action::Error,
+    Action::MaxMoves,
+];
+
+/// The special move code of [`Action::Custom`], followed by its token and tag.
+const CUSTOM_SPECIAL: u8 = SPECIAL_ACTIONS.len() as u8 + 1;
+
+/// Set on the first byte of a move for a normal move, with the color in bit 4
+/// and the piece code in the low bits. Otherwise the low bits are a special
+/// move code.
+const MOVE_FLAG: u8 = 0x80;
+/// Set on the first byte of a move when the time consumed follows.
+const TIME_FLAG: u8 = 0x40;
+/// Marks a square that does not fit the one-byte `file * 10 + rank` form; the
+/// file and rank follow as two bytes.
+const WIDE_SQUARE: u8 = 0xff;
+
+/// Any of the board grids, `F` files by `R` ranks.
+type AnyGrid<const F: usize, const R: usize> = [[Option<(Color, PieceType)>; F]; R];
+
+/// An error raised when decoding a binary record.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum BinaryError {
+    /// The input does not start with the record magic.
+    NotARecord,
+    /// The record was written with a different format version.
+    UnsupportedVersion(u8),
+    /// The checksum does not match the contents.
+    ChecksumMismatch,
+    /// The input ends in the middle of the record.
+    Truncated,
+    /// The contents are malformed.
+    Invalid(String),
+}
+
+impl fmt::Display for BinaryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BinaryError::NotARecord => write!(f, "not a binary game record"),
+            BinaryError::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported binary format version {version} (expected {FORMAT_VERSION})"
+            ),
+            BinaryError::ChecksumMismatch => write!(f, "checksum mismatch"),
+            BinaryError::Truncated => write!(f, "unexpected end of input"),
+            BinaryError::Invalid(ref msg) => write!(f, "invalid binary record: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for BinaryError {}
+
+impl GameRecord {
+    /// Encodes the record in the compact binary format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut enc = Encoder {
+            buf: Vec::with_capacity(64 + self.moves.len() * 4),
+        };
+        enc.buf.extend_from_slice(MAGIC);
+        enc.buf.push(FORMAT_VERSION);
+        enc.record(self);
+        let checksum = fnv1a(&enc.buf);
+        enc.buf.extend_from_slice(&checksum.to_le_bytes());
+        enc.buf
+    }
+
+    /// Decodes a record written by [`GameRecord::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<GameRecord, BinaryError> {
+        if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(BinaryError::NotARecord);
+        }
+        match bytes.get(MAGIC.len()) {
+            Some(&FORMAT_VERSION) => {}
+            Some(&version) => return Err(BinaryError::UnsupportedVersion(version)),
+            None => return Err(BinaryError::Truncated),
+        }
+        if bytes.len() < MAGIC.len() + 1 + 4 {
+            return Err(BinaryError::Truncated);
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - 4);
+        if fnv1a(body).to_le_bytes() != checksum {
+            return Err(BinaryError::ChecksumMismatch);
+        }
+
+        let mut dec = Decoder {
+            bytes: body,
+            pos: MAGIC.len() + 1,
+        };
+        let record = dec.record()?;
+        if dec.pos != body.len() {
+            return Err(BinaryError::Invalid("trailing bytes".to_string()));
+        }
+        Ok(record)
+    }
+}
+
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, &b| {
+        (hash ^ b as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+fn piece_code(pt: PieceType) -> u8 {
+    PIECE_TYPES.iter().position(|&p| p == pt).unwrap_or(0) as u8
+}
+
+fn piece_byte(color: Color, pt: PieceType) -> u8 {
+    (color as u8) << 4 | piece_code(pt)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    fn byte(&mut self, b: u8) {
+        self.buf.push(b);
+    }
+
+    fn varint(&mut self, mut n: u64) {
+        while n >= 0x80 {
+            self.buf.push(n as u8 | 0x80);
+            n >>= 7;
+        }
+        self.buf.push(n as u8);
+    }
+
+    fn signed(&mut self, n: i64) {
+        self.varint(((n << 1) ^ (n >> 63)) as u64);
+    }
+
+    fn str(&mut self, s: &str) {
+        self.varint(s.len() as u64);
+        self.buf.extend_from_slice(s.as_bytes());
+    }
+
+    fn option<T>(&mut self, value: Option<&T>, write: impl FnOnce(&mut Self, &T)) {
+        match value {
+            Some(value) => {
+                self.byte(1);
+                write(self, value);
+            }
+            None => self.byte(0),
+        }
+    }
+
+    fn square(&mut self, sq: Square) {
+        if sq.file <= 9 && sq.rank <= 9 {
+            self.byte(sq.file * 10 + sq.rank);
+        } else {
+            self.byte(WIDE_SQUARE);
+            self.byte(sq.file);
+            self.byte(sq.rank);
+        }
+    }
+
+    fn duration(&mut self, d: &Duration) {
+        self.varint(d.as_secs());
+        self.varint(d.subsec_nanos() as u64);
+    }
+
+    fn time(&mut self, t: &Time) {
+        self.signed(t.date.to_julian_day() as i64);
+        self.option(t.time.as_ref(), |enc, t| {
+            let (h, m, s, nanos) = t.as_hms_nano();
+            enc.byte(h);
+            enc.byte(m);
+            enc.byte(s);
+            enc.varint(nanos as u64);
+        });
+    }
+
+    fn time_limit(&mut self, limit: &TimeLimit) {
+        self.duration(&limit.main_time);
+        self.duration(&limit.byoyomi);
+        self.duration(&limit.increment);
+        self.duration(&limit.least_time_per_move);
+    }
+
+    fn action(&mut self, action: &Action, flags: u8) {
+        match *action {
+            Action::Move(color, from, to, pt) => {
+                self.byte(MOVE_FLAG | flags | piece_byte(color, pt));
+                self.square(from);
+                self.square(to);
+            }
+            Action::Custom { token, tag } => {
+                self.byte(flags | CUSTOM_SPECIAL);
+                self.str(token.as_str());
+                self.varint(tag.into());
+            }
+            special => {
+                let code = SPECIAL_ACTIONS
+                    .iter()
+                    .position(|&a| a == special)
+                    .unwrap_or(0);
+                self.byte(flags | (code as u8 + 1));
+            }
+        }
+    }
+
+    fn attr_value(&mut self, value: &AttrValue) {
+        match *value {
+            AttrValue::Text(ref raw) => {
+                self.byte(0);
+                self.str(raw);
+            }
+            AttrValue::Integer(n, ref raw) => {
+                self.byte(1);
+                self.signed(n);
+                self.str(raw);
+            }
+            AttrValue::Boolean(b, ref raw) => {
+                self.byte(2);
+                self.byte(b as u8);
+                self.str(raw);
+            }
+            AttrValue::Url(ref raw) => {
+                self.byte(3);
+                self.str(raw);
+            }
+            AttrValue::DateTime(ref t, ref raw) => {
+                self.byte(4);
+                self.time(t);
+                self.str(raw);
+            }
+        }
+    }
+
+    fn grid<const F: usize, const R: usize>(&mut self, grid: &AnyGrid<F, R>) {
+        for cell in grid.iter().flatten() {
+            match *cell {
+                Some((color, pt)) => self.byte(MOVE_FLAG | piece_byte(color, pt)),
+                None => self.byte(0),
+            }
+        }
+    }
+
+    fn position(&mut self, pos: &Position) {
+        self.varint(pos.drop_pieces.len() as u64);
+        for &(sq, pt) in &pos.drop_pieces {
+            self.square(sq);
+            self.byte(piece_code(pt));
+        }
+
+        let grids = pos.bulk.is_some() as u8
+            | (pos.minishogi_bulk.is_some() as u8) << 1
+            | (pos.wildcat_bulk.is_some() as u8) << 2
+            | (pos.placements_only as u8) << 3;
+        self.byte(grids);
+        if let Some(ref grid) = pos.bulk {
+            self.grid(grid);
+        }
+        if let Some(ref grid) = pos.minishogi_bulk {
+            self.grid(grid);
+        }
+        if let Some(ref grid) = pos.wildcat_bulk {
+            self.grid(grid);
+        }
+
+        self.varint(pos.add_pieces.len() as u64);
+        for &(color, sq, pt) in &pos.add_pieces {
+            self.byte(piece_byte(color, pt));
+            self.square(sq);
+        }
+        self.byte(pos.side_to_move as u8);
+    }
+
+    fn record(&mut self, record: &GameRecord) {
+        for name in [
+            &record.black_player,
+            &record.white_player,
+            &record.event,
+            &record.site,
+            &record.opening,
+        ] {
+            self.option(name.as_ref(), |enc, s| enc.str(s));
+        }
+        self.option(record.start_time.as_ref(), Self::time);
+        self.option(record.end_time.as_ref(), Self::time);
+        self.option(record.time_limit_black.as_ref(), Self::time_limit);
+        self.option(record.time_limit_white.as_ref(), Self::time_limit);
+
+        self.varint(record.extra_attrs.len() as u64);
+        for (key, value) in &record.extra_attrs {
+            self.str(key);
+            self.attr_value(value);
+        }
+        self.varint(record.header_comments.len() as u64);
+        for comment in &record.header_comments {
+            self.str(comment);
+        }
+
+        self.position(&record.start_pos);
+
+        self.varint(record.moves.len() as u64);
+        for m in &record.moves {
+            match m.time {
+                Some(ref time) => {
+                    self.action(&m.action, TIME_FLAG);
+                    self.duration(time);
+                }
+                None => self.action(&m.action, 0),
+            }
+        }
+
+        self.option(record.adjournment.as_ref(), |enc, adjournment| {
+            enc.option(adjournment.sealed_move.as_ref(), |enc, a| enc.action(a, 0));
+            enc.option(adjournment.resume_time.as_ref(), Self::time);
+            enc.option(adjournment.continuation.as_ref(), |enc, s| enc.str(s));
+        });
+
+        let layout = record.layout.bare_black_player as u8
+            | (record.layout.bare_white_player as u8) << 1
+            | (record.layout.encoding_line as u8) << 2;
+        self.byte(layout);
+
+        self.option(record.rated.as_ref(), |enc, &rated| enc.byte(rated as u8));
+        for rating in [record.black_rating, record.white_rating] {
+            self.option(rating.as_ref(), |enc, &rating| enc.varint(rating as u64));
+        }
+        self.varint(record.layout.rating_comments.len() as u64);
+        for comment in &record.layout.rating_comments {
+            self.str(comment);
+        }
+        self.varint(record.layout.trailing_attrs.len() as u64);
+        for key in &record.layout.trailing_attrs {
+            self.str(key);
+        }
+        self.option(record.layout.source_format.as_ref(), |enc, format| {
+            let SourceFormat::Csa(version) = format;
+            let code = VERSIONS.iter().position(|v| v == version).unwrap();
+            enc.byte(code as u8);
+        });
+        self.varint(record.bookmarks.len() as u64);
+        for bookmark in &record.bookmarks {
+            self.varint(bookmark.ply as u64);
+            self.str(&bookmark.label);
+            self.option(bookmark.comment.as_ref(), |enc, s| enc.str(s));
+        }
+        self.option(record.time_unit.as_ref(), |enc, unit| {
+            enc.varint(unit.count as u64);
+            let scales = [TimeScale::Millisecond, TimeScale::Second, TimeScale::Minute];
+            enc.byte(scales.iter().position(|&s| s == unit.scale).unwrap() as u8);
+        });
+
+        // Events are rare, so they follow as (move index, event) pairs.
+        let events: Vec<_> = record
+            .moves
+            .iter()
+            .enumerate()
+            .flat_map(|(index, m)| m.events.iter().map(move |event| (index, event)))
+            .collect();
+        self.varint(events.len() as u64);
+        for (index, event) in events {
+            self.varint(index as u64);
+            self.event(event);
+        }
+        self.option(
+            record.layout.event_convention.as_ref(),
+            |enc, convention| {
+                enc.varint(convention.lines().count() as u64);
+                for (line, event) in convention.lines() {
+                    enc.str(line);
+                    enc.event(&event);
+                }
+            },
+        );
+    }
+
+    fn event(&mut self, event: &InterMoveEvent) {
+        let code = InterMoveEvent::ALL.iter().position(|e| e == event).unwrap();
+        self.byte(code as u8);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+fn invalid<T>(msg: &str) -> Result<T, BinaryError> {
+    Err(BinaryError::Invalid(msg.to_string()))
+}
+
+impl<'a> Decoder<'a> {
+    fn byte(&mut self) -> Result<u8, BinaryError> {
+        let b = *self.bytes.get(self.pos).ok_or(BinaryError::Truncated)?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn varint(&mut self) -> Result<u64, BinaryError> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            n |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        invalid("varint too long")
+    }
+
+    fn signed(&mut self) -> Result<i64, BinaryError> {
+        let n = self.varint()?;
+        Ok((n >> 1) as i64 ^ -((n & 1) as i64))
+    }
+
+    fn len(&mut self) -> Result<usize, BinaryError> {
+        let n = self.varint()?;
+        // Every element takes at least one byte, so a longer count is bogus.
+        if n > (self.bytes.len() - self.pos) as u64 {
+            return Err(BinaryError::Truncated);
+        }
+        Ok(n as usize)
+    }
+
+    fn str(&mut self) -> Result<&'a str, BinaryError> {
+        let len = self.len()?;
+        let bytes = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        std::str::from_utf8(bytes).or_else(|_| invalid("string is not UTF-8"))
+    }
+
+    fn option<T>(
+        &mut self,
+        read: impl FnOnce(&mut Self) -> Result<T, BinaryError>,
+    ) -> Result<Option<T>, BinaryError> {
+        match self.byte()? {
+            0 => Ok(None),
+            1 => read(self).map(Some),
+            _ => invalid("bad option tag"),
+        }
+    }
+
+    fn square(&mut self) -> Result<Square, BinaryError> {
+        match self.byte()? {
+            WIDE_SQUARE => Ok(Square::new(self.byte()?, self.byte()?)),
+            b if b < 100 => Ok(Square::new(b / 10, b % 10)),
+            _ => invalid("bad square"),
+        }
+    }
+
+    fn piece_type(code: u8) -> Result<PieceType, BinaryError> {
+        match PIECE_TYPES.get(code as usize) {
+            Some(&pt) => Ok(pt),
+            None => invalid("bad piece type"),
+        }
+    }
+
+    fn piece(b: u8) -> Result<(Color, PieceType), BinaryError> {
+        let color = match b >> 4 & 0x3 {
+            0 => Color::Black,
+            1 => Color::White,
+            _ => return invalid("bad color"),
+        };
+        Ok((color, Self::piece_type(b & 0x0f)?))
+    }
+
+    fn color(&mut self) -> Result<Color, BinaryError> {
+        match self.byte()? {
+            0 => Ok(Color::Black),
+            1 => Ok(Color::White),
+            _ => invalid("bad color"),
+        }
+    }
+
+    fn duration(&mut self) -> Result<Duration, BinaryError> {
+        let secs = self.varint()?;
+        let nanos = self.varint()?;
+        if nanos >= 1_000_000_000 {
+            return invalid("bad duration");
+        }
+        Ok(Duration::new(secs, nanos as u32))
+    }
+
+    fn time(&mut self) -> Result<Time, BinaryError> {
+        let day = i32::try_from(self.signed()?).or_else(|_| invalid("bad date"))?;
+        let date = NativeDate::from_julian_day(day).or_else(|_| invalid("bad date"))?;
+        let time = self.option(|dec| {
+            let (h, m, s) = (dec.byte()?, dec.byte()?, dec.byte()?);
+            let nanos = u32::try_from(dec.varint()?).or_else(|_| invalid("bad time"))?;
+            NativeTime::from_hms_nano(h, m, s, nanos).or_else(|_| invalid("bad time"))
+        })?;
+        Ok(Time { date, time })
+    }
+
+    fn time_limit(&mut self) -> Result<TimeLimit, BinaryError> {
+        Ok(TimeLimit {
+            main_time: self.duration()?,
+            byoyomi: self.duration()?,
+            increment: self.duration()?,
+            least_time_per_move: self.duration()?,
+        })
+    }
+
+    /// Reads an action, returning it along with the `TIME_FLAG` bit.
+    fn action(&mut self) -> Result<(Action, bool), BinaryError> {
+        let b = self.byte()?;
+        let timed = b & TIME_FLAG != 0;
+        let action = if b & MOVE_FLAG != 0 {
+            if b & 0x20 != 0 {
+                return invalid("bad move");
+            }
+            let (color, pt) = Self::piece(b & 0x1f)?;
+            Action::Move(color, self.square()?, self.square()?, pt)
+        } else {
+            match SPECIAL_ACTIONS.get(((b & 0x3f) as usize).wrapping_sub(1)) {
+                Some(&action) => action,
+                None if b & 0x3f == CUSTOM_SPECIAL => {
+                    let Some(token) = SpecialToken::new(self.str()?) else {
+                        return invalid("bad special move token");
+                    };
+                    let Ok(tag) = u16::try_from(self.varint()?) else {
+                        return invalid("bad special move tag");
+                    };
+                    Action::Custom { token, tag }
+                }
+                None => return invalid("bad special move"),
+            }
+        };
+        Ok((action, timed))
+    }
+
+    fn event(&mut self) -> Result<InterMoveEvent, BinaryError> {
+        match InterMoveEvent::ALL.get(self.byte()? as usize) {
+            Some(&event) => Ok(event),
+            None => invalid("bad event"),
+        }
+    }
+
+    fn attr_value(&mut self) -> Result<AttrValue, BinaryError> {
+        let value = match self.byte()? {
+            0 => AttrValue::Text(self.str()?.to_string()),
+            1 => AttrValue::Integer(self.signed()?, self.str()?.to_string()),
+            2 => {
+                let b = match self.byte()? {
+                    0 => false,
+                    1 => true,
+                    _ => return invalid("bad boolean"),
+                };
+                AttrValue::Boolean(b, self.str()?.to_string())
+            }
+            3 => AttrValue::Url(self.str()?.to_string()),
+            4 => AttrValue::DateTime(self.time()?, self.str()?.to_string()),
+            _ => return invalid("bad attribute type"),
+        };
+        Ok(value)
+    }
+
+    fn grid<const F: usize, const R: usize>(&mut self) -> Result<Box<AnyGrid<F, R>>, BinaryError> {
+        let mut grid = Box::new([[None; F]; R]);
+        for cell in grid.iter_mut().flatten() {
+            *cell = match self.byte()? {
+                0 => None,
+                b if b & MOVE_FLAG != 0 => Some(Self::piece(b & !MOVE_FLAG)?),
+                _ => return invalid("bad grid cell"),
+            };
+        }
+        Ok(grid)
+    }
+
+    fn position(&mut self) -> Result<Position, BinaryError> {
+        let mut pos = Position::default();
+
+        let count = self.len()?;
+        let mut drop_pieces = PieceList::with_capacity(count);
+        for _ in 0..count {
+            let sq = self.square()?;
+            drop_pieces.push((sq, Self::piece_type(self.byte()?)?));
+        }
+        pos.drop_pieces = drop_pieces;
+
+        let grids = self.byte()?;
+        if grids & !0xf != 0 {
+            return invalid("bad grid flags");
+        }
+        pos.placements_only = grids & 8 != 0;
+        if grids & 1 != 0 {
+            pos.bulk = Some(self.grid()?);
+        }
+        if grids & 2 != 0 {
+            pos.minishogi_bulk = Some(self.grid()?);
+        }
+        if grids & 4 != 0 {
+            pos.wildcat_bulk = Some(self.grid()?);
+        }
+
+        let count = self.len()?;
+        let mut add_pieces = PieceList::with_capacity(count);
+        for _ in 0..count {
+            let (color, pt) = Self::piece(self.byte()?)?;
+            add_pieces.push((color, self.square()?, pt));
+        }
+        pos.add_pieces = add_pieces;
+        pos.side_to_move = self.color()?;
+        Ok(pos)
+    }
+
+    fn name(&mut self) -> Result<Option<RecordText>, BinaryError> {
+        self.option(|dec| dec.str().map(RecordText::from))
+    }
+
+    fn record(&mut self) -> Result<GameRecord, BinaryError> {
+        let mut record = GameRecord {
+            black_player: self.name()?,
+            white_player: self.name()?,
+            event: self.name()?,
+            site: self.name()?,
+            opening: self.name()?,
+            start_time: self.option(Self::time)?,
+            end_time: self.option(Self::time)?,
+            time_limit_black: self.option(Self::time_limit)?,
+            time_limit_white: self.option(Self::time_limit)?,
+            ..GameRecord::default()
+        };
+
+        let count = self.len()?;
+        record.extra_attrs.reserve(count);
+        for _ in 0..count {
+            let key = self.str()?.to_string();
+            record.extra_attrs.push((key, self.attr_value()?));
+        }
+        let count = self.len()?;
+        record.header_comments.reserve(count);
+        for _ in 0..count {
+            let comment = self.str()?.to_string();
+            record.header_comments.push(comment);
+        }
+
+        record.start_pos = self.position()?;
+
+        let count = self.len()?;
+        record.moves.reserve(count);
+        for _ in 0..count {
+            let (action, timed) = self.action()?;
+            let time = if timed { Some(self.duration()?) } else { None };
+            record.moves.push(MoveRecord {
+                time,
+                ..MoveRecord::new(action)
+            });
+        }
+
+        record.adjournment = self.option(|dec| {
+            let sealed_move = dec.option(|dec| match dec.action()? {
+                (action, false) => Ok(action),
+                _ => invalid("bad sealed move"),
+            })?;
+            Ok(Adjournment {
+                sealed_move,
+                resume_time: dec.option(Self::time)?,
+                continuation: dec.option(|dec| dec.str().map(str::to_string))?,
+            })
+        })?;
+
+        let layout = self.byte()?;
+        if layout & !0x7 != 0 {
+            return invalid("bad layout flags");
+        }
+        record.layout = Layout {
+            bare_black_player: layout & 1 != 0,
+            bare_white_player: layout & 2 != 0,
+            encoding_line: layout & 4 != 0,
+            ..Layout::default()
+        };
+
+        record.rated = self.option(|dec| match dec.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => invalid("bad rated flag"),
+        })?;
+        let rating = |dec: &mut Self| {
+            u32::try_from(dec.varint()?).or_else(|_| invalid("rating out of range"))
+        };
+        record.black_rating = self.option(rating)?;
+        record.white_rating = self.option(rating)?;
+        let count = self.len()?;
+        for _ in 0..count {
+            let comment = self.str()?.to_string();
+            record.layout.rating_comments.push(comment);
+        }
+        let count = self.len()?;
+        for _ in 0..count {
+            let key = self.str()?.to_string();
+            record.layout.trailing_attrs.push(key);
+        }
+        record.layout.source_format =
+            self.option(|dec| match VERSIONS.get(dec.byte()? as usize) {
+                Some(&version) => Ok(SourceFormat::Csa(version)),
+                None => invalid("bad source format"),
+            })?;
+        let count = self.len()?;
+        record.bookmarks.reserve(count);
+        for _ in 0..count {
+            let ply = usize::try_from(self.varint()?).or_else(|_| invalid("ply out of range"))?;
+            record.bookmarks.push(Bookmark {
+                ply,
+                label: self.str()?.to_string(),
+                comment: self.option(|dec| dec.str().map(str::to_string))?,
+            });
+        }
+        record.time_unit = self.option(|dec| {
+            let count = u32::try_from(dec.varint()?).or_else(|_| invalid("bad time unit"))?;
+            let scale = match dec.byte()? {
+                0 => TimeScale::Millisecond,
+                1 => TimeScale::Second,
+                2 => TimeScale::Minute,
+                _ => return invalid("bad time unit"),
+            };
+            match count {
+                0 => invalid("bad time unit"),
+                _ => Ok(TimeUnit { count, scale }),
+            }
+        })?;
+
+        let count = self.len()?;
+        for _ in 0..count {
+            let index = self.varint()?;
+            let event = self.event()?;
+            match usize::try_from(index)
+                .ok()
+                .and_then(|i| record.moves.get_mut(i))
+            {
+                Some(m) => m.events.push(event),
+                None => return invalid("event after a missing move"),
+            }
+        }
+        record.layout.event_convention = self.option(|dec| {
+            let mut convention = EventConvention::new();
+            let count = dec.len()?;
+            for _ in 0..count {
+                let line = dec.str()?.to_string();
+                let event = dec.event()?;
+                if convention.register(&line, event).is_err() {
+                    return invalid("bad event convention");
+                }
+            }
+            Ok(convention)
+        })?;
+        Ok(record)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_csa;
+    use std::fs;
+
+    fn sample_record() -> GameRecord {
+        let mut record = GameRecord {
+            black_player: Some("NAKAHARA".into()),
+            event: Some("13th World Computer Shogi Championship".into()),
+            start_time: Time::parse("2002/07/01 19:04:30"),
+            end_time: Time::parse("2002/07/02"),
+            opening: Some("YAGURA".into()),
+            extra_attrs: vec![
+                ("ROUND".to_string(), AttrValue::parse("-3")),
+                ("RATED".to_string(), AttrValue::parse("yes")),
+                ("URL".to_string(), AttrValue::parse("https://example.com")),
+                ("DATE".to_string(), AttrValue::parse("2002/07/01 10:00:00")),
+                ("NOTE".to_string(), AttrValue::parse("hello, world")),
+            ],
+            header_comments: vec!["'from https://example.com".to_string(), "'".to_string()],
+            rated: Some(true),
+            black_rating: Some(1234),
+            layout: Layout {
+                bare_black_player: false,
+                bare_white_player: true,
+                rating_comments: vec!["'rating:NAKAHARA:1234".to_string()],
+                trailing_attrs: vec!["END_TIME".to_string()],
+                source_format: Some(SourceFormat::Csa(Version::V2_1)),
+                encoding_line: true,
+                event_convention: Some(EventConvention::standard()),
+            },
+            ..GameRecord::default()
+        };
+        record.time_limit_black = TimeLimit::parse("00:25+10");
+        record.time_limit_white = Some(TimeLimit {
+            increment: Duration::from_millis(1500),
+            ..TimeLimit::default()
+        });
+        record
+            .start_pos
+            .drop_pieces
+            .push((Square::new(8, 2), PieceType::Rook));
+        record
+            .start_pos
+            .add_pieces
+            .push((Color::White, Square::new(0, 0), PieceType::All));
+        record.start_pos.side_to_move = Color::White;
+        record.moves.push(MoveRecord {
+            action: Action::Custom {
+                token: SpecialToken::new("DRAW_OFFER").unwrap(),
+                tag: 300,
+            },
+            time: Some(Duration::from_secs(2)),
+            events: Vec::new(),
+        });
+        for (action, time) in [
+            ("-3334FU", Some(Duration::from_secs(12))),
+            ("+7776FU", None),
+            ("-2288UM", Some(Duration::new(3, 250_000_000))),
+            ("%CHUDAN", Some(Duration::from_secs(1))),
+        ] {
+            record.moves.push(MoveRecord {
+                action: Action::parse(action).unwrap(),
+                time,
+                events: Vec::new(),
+            });
+        }
+        record.moves.push(MoveRecord {
+            action: Action::Move(
+                Color::Black,
+                Square::new(10, 1),
+                Square::new(0, 0),
+                PieceType::Pawn,
+            ),
+            time: None,
+            events: Vec::new(),
+        });
+        record.adjournment = Some(Adjournment {
+            sealed_move: Action::parse("+8822UM"),
+            resume_time: Time::parse("2002/07/03 10:00"),
+            continuation: Some("game-2".to_string()),
+        });
+        record.add_bookmark("sealed", Some("before the adjournment"));
+        record.add_bookmark("start", None);
+        record.bookmarks[1].ply = 0;
+        record.time_unit = TimeUnit::parse("200msec");
+        record.moves[1].events = vec![
+            InterMoveEvent::DrawOffered(Color::White),
+            InterMoveEvent::DrawDeclined(Color::Black),
+        ];
+        record.moves[4].events.push(InterMoveEvent::Pause);
+        record
+    }
+
+    #[test]
+    fn round_trip() {
+        let record = sample_record();
+        let decoded = GameRecord::from_bytes(&record.to_bytes()).unwrap();
+        // Equality leaves the source format out.
+        assert_eq!(decoded.layout.source_format, record.layout.source_format);
+        assert!(decoded.layout.encoding_line);
+        assert_eq!(
+            decoded.layout.event_convention,
+            record.layout.event_convention
+        );
+        assert_eq!(decoded, record);
+
+        let record = GameRecord::default();
+        assert_eq!(GameRecord::from_bytes(&record.to_bytes()), Ok(record));
+
+        let mut record = GameRecord::default();
+        let mut grid = [[None; 9]; 9];
+        grid[0][0] = Some((Color::White, PieceType::Lance));
+        record.start_pos.bulk = Some(Box::new(grid));
+        record.start_pos.minishogi_bulk = Some(Box::new([[None; 5]; 5]));
+        record.start_pos.wildcat_bulk =
+            Some(Box::new([[Some((Color::Black, PieceType::Dragon)); 3]; 5]));
+        assert_eq!(GameRecord::from_bytes(&record.to_bytes()), Ok(record));
+    }
+
+    #[test]
+    fn round_trip_fixtures() {
+        for entry in fs::read_dir("fixtures/").unwrap() {
+            let path = entry.unwrap().path();
+            let Ok(record) = parse_csa(&fs::read_to_string(&path).unwrap_or_default()) else {
+                continue;
+            };
+            let bytes = record.to_bytes();
+            assert_eq!(GameRecord::from_bytes(&bytes), Ok(record), "{path:?}");
+        }
+    }
+
+    #[test]
+    fn compact_moves() {
+        let mut record = GameRecord::default();
+        let before = record.to_bytes().len();
+        record.moves.push(MoveRecord {
+            action: Action::parse("+7776FU").unwrap(),
+            time: Some(Duration::from_secs(12)),
+            events: Vec::new(),
+        });
+        // Piece byte and two squares, then seconds and nanoseconds.
+        assert_eq!(record.to_bytes().len() - before, 3 + 2);
+    }
+
+    #[test]
+    fn rejects_corrupted_input() {
+        let bytes = sample_record().to_bytes();
+
+        assert_eq!(GameRecord::from_bytes(b""), Err(BinaryError::NotARecord));
+        assert_eq!(
+            GameRecord::from_bytes(b"V2.2\nPI\n+\n"),
+            Err(BinaryError::NotARecord)
+        );
+
+        let mut other_version = bytes.clone();
+        other_version[4] = FORMAT_VERSION + 1;
+        assert_eq!(
+            GameRecord::from_bytes(&other_version),
+            Err(BinaryError::UnsupportedVersion(FORMAT_VERSION + 1))
+        );
+
+        for len in 0..bytes.len() {
+            assert!(GameRecord::from_bytes(&bytes[..len]).is_err(), "{len}");
+        }
+        for i in 5..bytes.len() {
+            for bit in 0..8 {
+                let mut flipped = bytes.clone();
+                flipped[i] ^= 1 << bit;
+                assert!(GameRecord::from_bytes(&flipped).is_err(), "{i} {bit}");
+            }
+        }
+
+        // A payload that is well-formed up to the checksum but malformed
+        // inside must still be rejected without panicking.
+        let mut body = MAGIC.to_vec();
+        body.push(FORMAT_VERSION);
+        body.extend_from_slice(b"\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\xff");
+        body.extend_from_slice(&fnv1a(&body).to_le_bytes());
+        assert!(matches!(
+            GameRecord::from_bytes(&body),
+            Err(BinaryError::Invalid(_))
+        ));
+    }
+}
@@ -0,0 +1,2058 @@
+//! A board that moves can be played on, and position hashing.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::{Bound, RangeBounds};
+
+use crate::value::{
+    infer_promotion, removal_problem, Action, Color, GameRecord, MoveRecord, PieceType, Position,
+    Square,
+};
+use crate::variant::Variant;
+
+/// Pieces that can be held in hand, in the order used for hand indices.
+pub(crate) const HAND_PIECES: [PieceType; 7] = [
+    PieceType::Pawn,
+    PieceType::Lance,
+    PieceType::Knight,
+    PieceType::Silver,
+    PieceType::Gold,
+    PieceType::Bishop,
+    PieceType::Rook,
+];
+
+/// Number of each hand piece in a standard 40-piece set.
+pub(crate) const STANDARD_SET: [u8; 7] = [18, 4, 4, 4, 4, 2, 2];
+
+/// Largest number of a single piece type one side can hold.
+const MAX_HAND: usize = 18;
+
+/// An error raised when setting up a [`Board`] or playing a move on it.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum BoardError {
+    /// The position cannot be set up.
+    InvalidPosition(String),
+    /// The move does not fit the board, e.g. it moves a piece that is not there.
+    InvalidMove(Action, String),
+}
+
+impl fmt::Display for BoardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BoardError::InvalidPosition(ref msg) => write!(f, "invalid position: {msg}"),
+            BoardError::InvalidMove(ref action, ref msg) => {
+                write!(f, "invalid move {action}: {msg}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for BoardError {}
+
+/// A position that moves can be played on: the pieces on the board, the pieces
+/// in hand and the side to move.
+///
+/// Unlike [`Position`], which mirrors how a CSA file describes the starting
+/// position, a `Board` has a single representation, so two boards holding the
+/// same position are equal.
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct Board {
+    /// Indexed by `(file - 1) * 9 + (rank - 1)`.
+    squares: [Option<(Color, PieceType)>; 81],
+    /// Indexed by `[color][hand index]`, see `HAND_PIECES`.
+    hands: [[u8; 7]; 2],
+    side_to_move: Color,
+    files: u8,
+    ranks: u8,
+    variant: Variant,
+}
+
+impl Board {
+    /// Sets up a board from a starting position.
+    ///
+    /// `AL` placements hand the given side every piece of a standard set that
+    /// is not on the board or in a hand yet, kings excepted, so they are only
+    /// accepted on a 9x9 board.
+    pub fn from_position(pos: &Position) -> Result<Board, BoardError> {
+        Board::with_variant(pos, Variant::of_dimensions(pos.dimensions()))
+    }
+
+    /// Sets up a board from a starting position, to play by the rules of
+    /// `variant`, which must be played on a board of the position's size.
+    ///
+    /// In Kyoto shogi, each piece in hand may be dropped with either face, so
+    /// a hand holds pairs of faces and [`Board::hand`] counts both faces of a
+    /// piece.
+    pub fn with_variant(pos: &Position, variant: Variant) -> Result<Board, BoardError> {
+        let (files, ranks) = pos.dimensions();
+        if variant.dimensions() != (files, ranks) && variant != Variant::Standard {
+            return Err(BoardError::InvalidPosition(format!(
+                "{variant} is not played on a {files}x{ranks} board"
+            )));
+        }
+        if pos.grid().is_none() && (files, ranks) == (9, 9) {
+            for i in 0..pos.drop_pieces.len() {
+                if let Some(problem) = removal_problem(&pos.drop_pieces, i) {
+                    return Err(BoardError::InvalidPosition(problem));
+                }
+            }
+        }
+        let mut board = Board {
+            squares: [None; 81],
+            hands: [[0; 7]; 2],
+            side_to_move: pos.side_to_move,
+            files,
+            ranks,
+            variant,
+        };
+
+        for file in 1..=files {
+            for rank in 1..=ranks {
+                let sq = Square::new(file, rank);
+                board.squares[index(sq)] = pos.piece_at(sq);
+            }
+        }
+
+        let mut all = None;
+        for &(color, sq, pt) in &pos.add_pieces {
+            if sq.file != 0 || sq.rank != 0 {
+                continue;
+            }
+            if pt == PieceType::All {
+                all = Some(color);
+                continue;
+            }
+            let slot = board.slot(pt).ok_or_else(|| {
+                BoardError::InvalidPosition(format!("{pt} cannot be held in hand"))
+            })?;
+            board.hands[color as usize][slot] += 1;
+        }
+
+        if let Some(color) = all {
+            if (files, ranks) != (9, 9) {
+                return Err(BoardError::InvalidPosition(
+                    "AL requires a standard 9x9 board".to_string(),
+                ));
+            }
+            for (slot, &total) in STANDARD_SET.iter().enumerate() {
+                let used = board.count(HAND_PIECES[slot]);
+                board.hands[color as usize][slot] += total.saturating_sub(used);
+            }
+        }
+
+        if board.hands.iter().flatten().any(|&n| n as usize > MAX_HAND) {
+            return Err(BoardError::InvalidPosition(
+                "more pieces in hand than a set holds".to_string(),
+            ));
+        }
+
+        Ok(board)
+    }
+
+    /// Returns the board size as `(files, ranks)`.
+    pub fn dimensions(&self) -> (u8, u8) {
+        (self.files, self.ranks)
+    }
+
+    /// Returns the variant the board plays by.
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    /// Returns the side to move.
+    pub fn side_to_move(&self) -> Color {
+        self.side_to_move
+    }
+
+    /// Returns the piece on the given square, or `None` if it is empty or lies
+    /// outside the board.
+    pub fn piece_at(&self, sq: Square) -> Option<(Color, PieceType)> {
+        if self.contains(sq) {
+            self.squares[index(sq)]
+        } else {
+            None
+        }
+    }
+
+    /// Returns how many pieces of the given type `color` holds in hand.
+    pub fn hand(&self, color: Color, pt: PieceType) -> u8 {
+        self.slot(pt)
+            .map_or(0, |slot| self.hands[color as usize][slot])
+    }
+
+    /// Returns the hand index pieces of type `pt` are held under: in Kyoto
+    /// shogi, that of the face of the piece that comes first in
+    /// `HAND_PIECES`.
+    fn slot(&self, pt: PieceType) -> Option<usize> {
+        if self.variant != Variant::Kyoto {
+            return hand_index(pt);
+        }
+        let face = Variant::kyoto_flip(pt)?;
+        hand_index(pt).into_iter().chain(hand_index(face)).min()
+    }
+
+    /// Returns the board as a starting position: a grid of the board's size
+    /// with the pieces in hand as `P+00`/`P-00` placements.
+    pub fn to_position(&self) -> Position {
+        let mut pos = Position {
+            side_to_move: self.side_to_move,
+            ..Position::default()
+        };
+        match (self.files, self.ranks) {
+            (5, 5) => pos.minishogi_bulk = Some(Box::default()),
+            (3, 5) => pos.wildcat_bulk = Some(Box::default()),
+            _ => pos.bulk = Some(Box::default()),
+        }
+        for (i, &square) in self.squares.iter().enumerate() {
+            let sq = square_of(i);
+            if square.is_some() && self.contains(sq) {
+                pos.set_piece(sq, square);
+            }
+        }
+        for color in [Color::Black, Color::White] {
+            for pt in HAND_PIECES {
+                if self.slot(pt) != hand_index(pt) {
+                    continue;
+                }
+                for _ in 0..self.hand(color, pt) {
+                    pos.add_pieces.push((color, Square::HAND, pt));
+                }
+            }
+        }
+        pos
+    }
+
+    /// Plays a move. Special moves such as `%TORYO` leave the board unchanged.
+    ///
+    /// The move must be consistent with the board: the side to move moves one
+    /// of its own pieces, or drops one it holds, onto a square it does not
+    /// occupy, and the piece type is either the moving piece or its promoted
+    /// form. Whether the piece can actually reach the square is not checked.
+    ///
+    /// In Kyoto shogi, the piece type is instead the other face of the moving
+    /// piece, see [`Variant::kyoto_flip`], and a drop may show either face.
+    pub fn apply(&mut self, action: &Action) -> Result<(), BoardError> {
+        let Action::Move(color, from, to, pt) = *action else {
+            return Ok(());
+        };
+        let invalid = |msg: String| Err(BoardError::InvalidMove(*action, msg));
+
+        if color != self.side_to_move {
+            return invalid(format!("it is not {color}'s turn"));
+        }
+        if !self.contains(to) {
+            return invalid(format!("square {to} is outside the board"));
+        }
+        let captured = match self.squares[index(to)] {
+            Some((c, _)) if c == color => return invalid(format!("{to} is occupied by own piece")),
+            Some((_, PieceType::King)) => return invalid("captures the king".to_string()),
+            Some((_, captured)) => Some(captured),
+            None => None,
+        };
+
+        if from.is_hand() {
+            if captured.is_some() {
+                return invalid(format!("drops onto occupied square {to}"));
+            }
+            if self.hand(color, pt) == 0 {
+                return invalid(format!("{color} has no {pt} in hand"));
+            }
+        } else {
+            if !self.contains(from) {
+                return invalid(format!("square {from} is outside the board"));
+            }
+            match self.squares[index(from)] {
+                Some((c, moving)) if c == color && self.variant == Variant::Kyoto => {
+                    let face = Variant::kyoto_flip(moving);
+                    if face != Some(pt) {
+                        return invalid(match face {
+                            Some(face) => format!("{moving} on {from} turns into {face}, not {pt}"),
+                            None => format!("{moving} on {from} is not a Kyoto shogi piece"),
+                        });
+                    }
+                }
+                Some((c, moving)) if c == color => {
+                    if let Err(err) = infer_promotion(moving, pt) {
+                        return invalid(format!("{err} on {from}"));
+                    }
+                }
+                _ => return invalid(format!("{color} has no piece on {from}")),
+            }
+        }
+
+        self.play(color, from, to, pt);
+        Ok(())
+    }
+
+    /// Returns whether `color`'s king is attacked. A side without a king on
+    /// the board is never in check.
+    pub fn in_check(&self, color: Color) -> bool {
+        self.squares
+            .iter()
+            .position(|&p| p == Some((color, PieceType::King)))
+            .is_some_and(|i| self.is_attacked(square_of(i), flip(color)))
+    }
+
+    /// Returns whether a piece of `by` attacks the given square.
+    pub fn is_attacked(&self, sq: Square, by: Color) -> bool {
+        self.any_attacker(sq, by, |_| true)
+    }
+
+    /// Returns the squares of the pieces of `by` that attack the given
+    /// square.
+    ///
+    /// ```
+    /// use csa::{parse_csa, Board, Color, Square};
+    ///
+    /// let game = parse_csa("V2.2\nPI\n+\n").unwrap();
+    /// let board = game.start_board().unwrap();
+    /// let mut attackers = board.attackers_of(Square::new(7, 8), Color::Black);
+    /// attackers.sort_by_key(|sq| (sq.file, sq.rank));
+    /// // The gold, the silver, and the rook along the second rank.
+    /// assert_eq!(attackers, [Square::new(2, 8), Square::new(6, 9), Square::new(7, 9)]);
+    /// ```
+    pub fn attackers_of(&self, sq: Square, by: Color) -> Vec<Square> {
+        let mut attackers = Vec::new();
+        self.any_attacker(sq, by, |attacker| {
+            attackers.push(attacker);
+            false
+        });
+        attackers
+    }
+
+    /// Counts the pieces of `by` attacking each square of the board, empty
+    /// or not. Sliding pieces attack up to and including the first piece in
+    /// their way.
+    ///
+    /// ```
+    /// use csa::{parse_csa, Board, Color, Square};
+    ///
+    /// let game = parse_csa("V2.2\nPI\n+\n").unwrap();
+    /// let board = game.start_board().unwrap();
+    /// let map = board.attack_map(Color::Black);
+    /// assert_eq!(map.get(Square::new(7, 8)), Some(3));
+    /// assert_eq!(map.get(Square::new(7, 5)), Some(0));
+    /// ```
+    pub fn attack_map(&self, by: Color) -> AttackMap {
+        self.count_attackers(by, |_, _| true)
+    }
+
+    /// Counts, like [`Board::attack_map`], the pieces of `by` attacking each
+    /// square, leaving out those that could not legally capture there: pieces
+    /// pinned to their king, unless they stay on the line of the pin, and
+    /// the king on squares the other side attacks.
+    pub fn defense_map(&self, by: Color) -> AttackMap {
+        self.count_attackers(by, |from, to| {
+            let mut next = self.clone();
+            next.squares[index(to)] = next.squares[index(from)].take();
+            !next.in_check(by)
+        })
+    }
+
+    /// Counts the attackers of each square for which `counts(from, to)`
+    /// holds.
+    fn count_attackers(
+        &self,
+        by: Color,
+        mut counts: impl FnMut(Square, Square) -> bool,
+    ) -> AttackMap {
+        let mut map = AttackMap {
+            counts: [[0; 9]; 9],
+            files: self.files,
+            ranks: self.ranks,
+        };
+        for file in 1..=self.files {
+            for rank in 1..=self.ranks {
+                let sq = Square::new(file, rank);
+                let mut count = 0;
+                self.any_attacker(sq, by, |from| {
+                    count += u8::from(counts(from, sq));
+                    false
+                });
+                map.counts[file as usize - 1][rank as usize - 1] = count;
+            }
+        }
+        map
+    }
+
+    /// Returns whether the side to move is checkmated: in check with no
+    /// legal move.
+    pub fn is_checkmate(&self) -> bool {
+        self.in_check(self.side_to_move) && self.legal_moves().is_empty()
+    }
+
+    /// Calls `f` with the square of each piece of `by` attacking `sq` until it
+    /// returns `true`, and returns whether it did.
+    fn any_attacker(&self, sq: Square, by: Color, mut f: impl FnMut(Square) -> bool) -> bool {
+        for dir in ALL_DIRECTIONS {
+            let mut distance = 1;
+            let mut target = offset(sq, dir);
+            while let Some(t) = target.filter(|&t| self.contains(t)) {
+                if let Some((color, pt)) = self.squares[index(t)] {
+                    // The attacker moves in the opposite direction to reach `sq`.
+                    let back = (-dir.0, -dir.1);
+                    if color == by && reaches(color, pt, back, distance) && f(t) {
+                        return true;
+                    }
+                    break;
+                }
+                distance += 1;
+                target = offset(t, dir);
+            }
+        }
+
+        KNIGHT_STEPS.iter().any(|&(df, dr)| {
+            let (df, dr) = orient(by, (-df, -dr));
+            offset(sq, (df, dr))
+                .filter(|&t| self.contains(t))
+                .filter(|&t| self.squares[index(t)] == Some((by, PieceType::Knight)))
+                .is_some_and(&mut f)
+        })
+    }
+
+    /// Returns every legal move of the side to move, under standard shogi
+    /// rules: the king may not be left in check, pieces may not be dropped or
+    /// left unpromoted where they could never move again, two unpromoted pawns
+    /// of one side may not share a file (nifu), and dropping a pawn may not
+    /// give checkmate (uchifuzume).
+    ///
+    /// The promotion zone is the last three ranks on a 9-rank board and the
+    /// last rank on smaller ones. Pieces move as in standard shogi on every
+    /// board size.
+    ///
+    /// In Kyoto shogi, every move turns the piece into its other face, there
+    /// is no promotion, and a piece in hand may be dropped with either face on
+    /// any empty square: only the king's safety limits the moves.
+    pub fn legal_moves(&self) -> Vec<Action> {
+        let color = self.side_to_move;
+        let mut moves = Vec::new();
+        self.push_board_moves(color, &mut moves);
+        self.push_drops(color, &mut moves);
+        moves.retain(|action| self.is_legal(action));
+        moves
+    }
+
+    /// Counts the leaf nodes of the legal move tree `depth` plies deep.
+    ///
+    /// Comparing these counts against another implementation is a quick way
+    /// to find move generation bugs. The standard starting position gives 30,
+    /// 900, 25470 and 719731 for depths 1 to 4.
+    pub fn perft(&self, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        let moves = self.legal_moves();
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+        moves
+            .iter()
+            .map(|action| {
+                let mut next = self.clone();
+                next.play_move(action);
+                next.perft(depth - 1)
+            })
+            .sum()
+    }
+
+    /// Like [`Board::perft`], but split by the first move, in the order
+    /// [`Board::legal_moves`] returns them.
+    pub fn perft_divide(&self, depth: u32) -> Vec<(Action, u64)> {
+        if depth == 0 {
+            return Vec::new();
+        }
+        self.legal_moves()
+            .into_iter()
+            .map(|action| {
+                let mut next = self.clone();
+                next.play_move(&action);
+                (action, next.perft(depth - 1))
+            })
+            .collect()
+    }
+
+    /// Returns the Zobrist hash of the position.
+    ///
+    /// The hash covers the pieces on the board, the pieces in hand and the
+    /// side to move. It is the XOR of the parts returned by
+    /// [`Board::hash_components`], each built from the keys described at
+    /// [`Board::piece_key`]. The keys, and therefore the hash of any given
+    /// position, will not change without a major version bump.
+    pub fn position_hash(&self) -> u64 {
+        let parts = self.hash_components();
+        parts.pieces ^ parts.hands ^ parts.side
+    }
+
+    /// Returns the parts the position hash is made of, for engines that keep
+    /// their own incrementally updated hash.
+    pub fn hash_components(&self) -> HashComponents {
+        let mut pieces = 0;
+        for (i, square) in self.squares.iter().enumerate() {
+            if let Some((color, pt)) = *square {
+                pieces ^= KEYS.pieces[color as usize][pt as usize][i];
+            }
+        }
+
+        let mut hands = 0;
+        for (color, hand) in self.hands.iter().enumerate() {
+            for (slot, &count) in hand.iter().enumerate() {
+                hands ^= KEYS.hands[color][slot][count as usize];
+            }
+        }
+
+        let side = match self.side_to_move {
+            Color::Black => 0,
+            Color::White => KEYS.side,
+        };
+
+        HashComponents {
+            pieces,
+            hands,
+            side,
+        }
+    }
+
+    /// Returns the key for `pt` of `color` on `sq`, or 0 for `AL` or a square
+    /// outside a 9x9 board.
+    ///
+    /// Keys are the successive outputs of SplitMix64 started from state 0.
+    /// The first 2 x 14 x 81 outputs are the piece-square keys, ordered by
+    /// color (black first), then piece type in [`PieceType`] declaration order,
+    /// then square index `(file - 1) * 9 + (rank - 1)`. The next 2 x 7 x 18
+    /// are the hand keys, ordered by color, then hand piece (FU, KY, KE, GI,
+    /// KI, KA, HI), then count from 1 to 18. The last one is the side key.
+    pub fn piece_key(color: Color, pt: PieceType, sq: Square) -> u64 {
+        if pt == PieceType::All || !(1..=9).contains(&sq.file) || !(1..=9).contains(&sq.rank) {
+            return 0;
+        }
+        KEYS.pieces[color as usize][pt as usize][index(sq)]
+    }
+
+    /// Returns the key for `color` holding exactly `count` pieces of `pt`.
+    /// Holding none has key 0, as do pieces that cannot be held in hand.
+    pub fn hand_key(color: Color, pt: PieceType, count: u8) -> u64 {
+        match hand_index(pt) {
+            Some(slot) if (count as usize) <= MAX_HAND => {
+                KEYS.hands[color as usize][slot][count as usize]
+            }
+            _ => 0,
+        }
+    }
+
+    /// Returns the key that is XORed in when White is to move.
+    pub fn side_key() -> u64 {
+        KEYS.side
+    }
+
+    fn play(&mut self, color: Color, from: Square, to: Square, pt: PieceType) {
+        if from.is_hand() {
+            if let Some(slot) = self.slot(pt) {
+                self.hands[color as usize][slot] -= 1;
+            }
+        } else {
+            self.squares[index(from)] = None;
+        }
+        if let Some((_, captured)) = self.squares[index(to)] {
+            let captured = match self.variant {
+                Variant::Kyoto => captured,
+                _ => captured.unpromote(),
+            };
+            if let Some(slot) = self.slot(captured) {
+                self.hands[color as usize][slot] += 1;
+            }
+        }
+        self.squares[index(to)] = Some((color, pt));
+        self.side_to_move = flip(color);
+    }
+
+    fn play_move(&mut self, action: &Action) {
+        if let Action::Move(color, from, to, pt) = *action {
+            self.play(color, from, to, pt);
+        }
+    }
+
+    /// Adds the moves of pieces on the board that follow the movement rules,
+    /// without checking king safety.
+    fn push_board_moves(&self, color: Color, moves: &mut Vec<Action>) {
+        for (i, square) in self.squares.iter().enumerate() {
+            let Some((c, pt)) = *square else { continue };
+            if c != color {
+                continue;
+            }
+            let from = square_of(i);
+            let (steps, slides) = movement(pt);
+            let mut targets = Vec::new();
+            for &dir in steps {
+                if let Some(to) = offset(from, orient(color, dir)) {
+                    targets.push(to);
+                }
+            }
+            for &dir in slides {
+                let dir = orient(color, dir);
+                let mut to = offset(from, dir);
+                while let Some(t) = to.filter(|&t| self.contains(t)) {
+                    targets.push(t);
+                    if self.squares[index(t)].is_some() {
+                        break;
+                    }
+                    to = offset(t, dir);
+                }
+            }
+
+            for to in targets {
+                if !self.contains(to)
+                    || matches!(self.squares[index(to)], Some((c, _)) if c == color)
+                {
+                    continue;
+                }
+                if self.variant == Variant::Kyoto {
+                    if let Some(face) = Variant::kyoto_flip(pt) {
+                        moves.push(Action::Move(color, from, to, face));
+                    }
+                    continue;
+                }
+                if let Some(promoted) = pt.promote() {
+                    if self.in_zone(color, from) || self.in_zone(color, to) {
+                        moves.push(Action::Move(color, from, to, promoted));
+                    }
+                }
+                if self.can_move_again(color, pt, to) {
+                    moves.push(Action::Move(color, from, to, pt));
+                }
+            }
+        }
+    }
+
+    /// Adds the drops that follow the placement rules, including nifu,
+    /// without checking king safety or uchifuzume.
+    fn push_drops(&self, color: Color, moves: &mut Vec<Action>) {
+        for (slot, &count) in self.hands[color as usize].iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            let pt = HAND_PIECES[slot];
+            if self.variant == Variant::Kyoto {
+                let faces = [Some(pt), Variant::kyoto_flip(pt)];
+                for to in self.empty_squares() {
+                    for face in faces.into_iter().flatten() {
+                        moves.push(Action::Move(color, Square::HAND, to, face));
+                    }
+                }
+                continue;
+            }
+            for file in 1..=self.files {
+                if pt == PieceType::Pawn
+                    && (1..=self.ranks).any(|rank| {
+                        self.squares[index(Square::new(file, rank))]
+                            == Some((color, PieceType::Pawn))
+                    })
+                {
+                    continue;
+                }
+                for rank in 1..=self.ranks {
+                    let to = Square::new(file, rank);
+                    if self.squares[index(to)].is_none() && self.can_move_again(color, pt, to) {
+                        moves.push(Action::Move(color, Square::HAND, to, pt));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns whether the move leaves the mover's king safe and is not a pawn
+    /// drop that gives checkmate.
+    fn is_legal(&self, action: &Action) -> bool {
+        let Action::Move(color, from, _, pt) = *action else {
+            return false;
+        };
+        let mut next = self.clone();
+        next.play_move(action);
+        if next.in_check(color) {
+            return false;
+        }
+        let pawn_drop = from.is_hand() && pt == PieceType::Pawn && self.variant != Variant::Kyoto;
+        !(pawn_drop && next.is_checkmate())
+    }
+
+    /// Returns whether `sq` lies in `color`'s promotion zone.
+    pub(crate) fn in_zone(&self, color: Color, sq: Square) -> bool {
+        let depth = if self.ranks == 9 { 3 } else { 1 };
+        match color {
+            Color::Black => sq.rank <= depth,
+            Color::White => sq.rank > self.ranks - depth,
+        }
+    }
+
+    /// Returns whether an unpromoted piece on `sq` would still have a move,
+    /// which rules out pawns and lances on the last rank and knights on the
+    /// last two.
+    fn can_move_again(&self, color: Color, pt: PieceType, sq: Square) -> bool {
+        let ranks_left = match color {
+            Color::Black => sq.rank - 1,
+            Color::White => self.ranks - sq.rank,
+        };
+        match pt {
+            PieceType::Pawn | PieceType::Lance => ranks_left >= 1,
+            PieceType::Knight => ranks_left >= 2,
+            _ => true,
+        }
+    }
+
+    fn empty_squares(&self) -> impl Iterator<Item = Square> + '_ {
+        (1..=self.files)
+            .flat_map(|file| (1..=self.ranks).map(move |rank| Square::new(file, rank)))
+            .filter(|&sq| self.squares[index(sq)].is_none())
+    }
+
+    fn contains(&self, sq: Square) -> bool {
+        (1..=self.files).contains(&sq.file) && (1..=self.ranks).contains(&sq.rank)
+    }
+
+    /// Counts the pieces of the given unpromoted type on the board and in
+    /// both hands.
+    pub(crate) fn count(&self, pt: PieceType) -> u8 {
+        let on_board = self
+            .squares
+            .iter()
+            .flatten()
+            .filter(|(_, p)| p.unpromote() == pt)
+            .count() as u8;
+        on_board + self.hand(Color::Black, pt) + self.hand(Color::White, pt)
+    }
+}
+
+/// The parts of [`Board::position_hash`]: the XOR of the piece-square keys, of
+/// the hand keys, and the side key when White is to move.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct HashComponents {
+    pub pieces: u64,
+    pub hands: u64,
+    pub side: u64,
+}
+
+/// The number of pieces attacking each square of a board, see
+/// [`Board::attack_map`].
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct AttackMap {
+    /// Indexed by `[file - 1][rank - 1]`.
+    counts: [[u8; 9]; 9],
+    files: u8,
+    ranks: u8,
+}
+
+impl AttackMap {
+    /// Returns the size of the board the map was made for, as
+    /// `(files, ranks)`.
+    pub fn dimensions(&self) -> (u8, u8) {
+        (self.files, self.ranks)
+    }
+
+    /// Returns the number of attackers of `sq`, or `None` when `sq` is not on
+    /// the board.
+    pub fn get(&self, sq: Square) -> Option<u8> {
+        let on_board = (1..=self.files).contains(&sq.file) && (1..=self.ranks).contains(&sq.rank);
+        on_board.then(|| self.counts[sq.file as usize - 1][sq.rank as usize - 1])
+    }
+}
+
+impl Position {
+    /// Returns whether the two positions set up the same board: the same
+    /// pieces on the same squares, the same pieces in hand and the same side
+    /// to move, however each is written. Boards of different sizes are never
+    /// equal.
+    ///
+    /// `==` compares how positions are written instead, so a `PI` position and
+    /// the grid of the same setup are not `==`. Positions that cannot be set
+    /// up have no board to compare and give an error.
+    ///
+    /// ```
+    /// use csa::parse_csa;
+    ///
+    /// let pi = parse_csa("V2.2\nPI82HI\n-\n").unwrap().start_pos;
+    /// let grid = parse_csa(
+    ///     "V2.2\nP1-KY-KE-GI-KI-OU-KI-GI-KE-KY\nP2 *  *  *  *  *  *  * -KA * \n\
+    ///      P3-FU-FU-FU-FU-FU-FU-FU-FU-FU\nP4 *  *  *  *  *  *  *  *  * \n\
+    ///      P5 *  *  *  *  *  *  *  *  * \nP6 *  *  *  *  *  *  *  *  * \n\
+    ///      P7+FU+FU+FU+FU+FU+FU+FU+FU+FU\nP8 * +KA *  *  *  *  * +HI * \n\
+    ///      P9+KY+KE+GI+KI+OU+KI+GI+KE+KY\n-\n",
+    /// )
+    /// .unwrap()
+    /// .start_pos;
+    /// assert_ne!(pi, grid);
+    /// assert!(pi.semantically_equals(&grid).unwrap());
+    /// ```
+    pub fn semantically_equals(&self, other: &Position) -> Result<bool, BoardError> {
+        Ok(Board::from_position(self)? == Board::from_position(other)?)
+    }
+
+    /// Puts a piece on an empty square of the board.
+    ///
+    /// The position must still fit in a standard set of pieces: one king per
+    /// side, and no more of any other piece, promoted or not, on the board
+    /// and in hand together, than a set has. `AL` placements are not counted.
+    ///
+    /// ```
+    /// use csa::{Color, PieceType, Position, Square};
+    ///
+    /// let mut pos = Position::empty();
+    /// pos.place(Square::new(5, 1), Color::White, PieceType::King).unwrap();
+    /// pos.place(Square::new(5, 3), Color::Black, PieceType::Pawn).unwrap();
+    /// pos.add_to_hand(Color::Black, PieceType::Gold, 1).unwrap();
+    /// assert_eq!(pos.to_string(), "P+53FU00KI\nP-51OU\n+\n");
+    /// assert!(pos.place(Square::new(1, 1), Color::White, PieceType::King).is_err());
+    /// ```
+    pub fn place(&mut self, sq: Square, color: Color, pt: PieceType) -> Result<(), BoardError> {
+        let invalid = |msg: String| Err(BoardError::InvalidPosition(msg));
+        let (files, ranks) = self.dimensions();
+        if !(1..=files).contains(&sq.file) || !(1..=ranks).contains(&sq.rank) {
+            return invalid(format!("{sq} is not on the board"));
+        }
+        if pt == PieceType::All {
+            return invalid("AL can only be placed in hand".to_string());
+        }
+        if let Some((c, p)) = self.piece_at(sq) {
+            return invalid(format!("{sq} already holds {c}{p}"));
+        }
+        self.check_census(color, pt, 1)?;
+        self.set_piece(sq, Some((color, pt)));
+        Ok(())
+    }
+
+    /// Gives `color` `count` more pieces of the given type in hand, checking
+    /// the position against a standard set as [`Position::place`] does.
+    pub fn add_to_hand(
+        &mut self,
+        color: Color,
+        pt: PieceType,
+        count: u8,
+    ) -> Result<(), BoardError> {
+        if hand_index(pt).is_none() {
+            return Err(BoardError::InvalidPosition(format!(
+                "{pt} cannot be held in hand"
+            )));
+        }
+        self.check_census(color, pt, count)?;
+        for _ in 0..count {
+            self.add_pieces.push((color, Square::HAND, pt));
+        }
+        Ok(())
+    }
+
+    /// Sets the side to move.
+    pub fn set_side_to_move(&mut self, color: Color) {
+        self.side_to_move = color;
+    }
+
+    /// Checks that `count` more pieces of `color`'s `pt` fit in a standard set.
+    fn check_census(&self, color: Color, pt: PieceType, count: u8) -> Result<(), BoardError> {
+        let kind = pt.unpromote();
+        let (files, ranks) = self.dimensions();
+        let mut present = 0;
+        for file in 1..=files {
+            for rank in 1..=ranks {
+                match self.piece_at(Square::new(file, rank)) {
+                    Some((c, PieceType::King)) if kind == PieceType::King && c == color => {
+                        present += 1
+                    }
+                    Some((_, p)) if kind != PieceType::King && p.unpromote() == kind => {
+                        present += 1
+                    }
+                    _ => {}
+                }
+            }
+        }
+        present += self
+            .add_pieces
+            .iter()
+            .filter(|&&(_, sq, p)| sq.is_hand() && p == kind)
+            .count();
+
+        let (limit, what) = match hand_index(kind) {
+            Some(slot) => (STANDARD_SET[slot] as usize, format!("{kind}")),
+            None => (1, format!("{color}{kind}")),
+        };
+        if present + count as usize > limit {
+            return Err(BoardError::InvalidPosition(format!(
+                "a standard set has only {limit} {what}"
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// A position standing on the board for the fourth time, see
+/// [`GameRecord::repetition`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct Repetition {
+    /// The plies after which the position stood, 0 for the starting
+    /// position.
+    pub plies: [usize; 4],
+    /// The side that gave check with every one of its moves from the first
+    /// time the position stood to the fourth, when only one side did. That
+    /// side loses instead of the game being drawn.
+    pub perpetual_check: Option<Color>,
+}
+
+/// A move being replayed by [`GameRecord::truncate_when`].
+#[derive(Debug, Clone, Copy)]
+pub struct AppliedMove<'a> {
+    /// The ply number of the move, see [`GameRecord::ply_of`].
+    pub ply: usize,
+    pub record: &'a MoveRecord,
+    /// The piece taken by the move, as it stood on the board before capture.
+    pub captured: Option<PieceType>,
+    /// The board after the move.
+    pub board: &'a Board,
+}
+
+impl GameRecord {
+    /// Returns the plies in `plies` as a record of their own, starting from the
+    /// position before the first of them.
+    ///
+    /// Ply numbers are those of [`GameRecord::ply_of`], and the range is clamped
+    /// to the plies the game has, so `record.slice(40..)` is everything after
+    /// ply 39. The starting position of the slice is written as a grid with the
+    /// pieces in hand as `P+00`/`P-00` placements. Special moves within the
+    /// range are kept, and so are the result and the adjournment details when
+    /// the range runs to the end of the game.
+    ///
+    /// ```
+    /// use csa::parse_csa;
+    ///
+    /// let game = parse_csa("V2.2\nPI\n+\n+7776FU\n-3334FU\n+8822UM\n%TORYO\n").unwrap();
+    /// let end = game.slice(2..).unwrap();
+    /// assert_eq!(end.ply_count(), 2);
+    /// assert_eq!(end.start_pos.side_to_move, csa::Color::White);
+    /// ```
+    pub fn slice(&self, plies: impl RangeBounds<usize>) -> Result<GameRecord, BoardError> {
+        let count = self.ply_count();
+        let first = match plies.start_bound() {
+            Bound::Included(&n) => n,
+            Bound::Excluded(&n) => n + 1,
+            Bound::Unbounded => 1,
+        }
+        .clamp(1, count + 1);
+        let last = match plies.end_bound() {
+            Bound::Included(&n) => n,
+            Bound::Excluded(&n) => n.saturating_sub(1),
+            Bound::Unbounded => count,
+        }
+        .max(first - 1);
+
+        let start = self.index_of_ply(first - 1).map_or(0, |i| i + 1);
+        let mut board = self.start_board()?;
+        for record in &self.moves[..start] {
+            board.apply(&record.action)?;
+        }
+
+        let mut slice = self.truncated(last);
+        slice.moves.drain(..start);
+        slice.start_pos = board.to_position();
+        Ok(slice)
+    }
+
+    /// Replays the game and cuts it after the first move `stop` returns `true`
+    /// for, as [`GameRecord::truncated`] does. Special moves are not passed to
+    /// `stop`. If it never returns `true`, the whole game is returned.
+    ///
+    /// ```
+    /// use csa::parse_csa;
+    ///
+    /// let game = parse_csa("V2.2\nPI\n+\n+7776FU\n-3334FU\n+8822UM\n-3122GI\n").unwrap();
+    /// let first_capture = game.truncate_when(|m| m.captured.is_some()).unwrap();
+    /// assert_eq!(first_capture.ply_count(), 3);
+    /// ```
+    pub fn truncate_when(
+        &self,
+        mut stop: impl FnMut(&AppliedMove) -> bool,
+    ) -> Result<GameRecord, BoardError> {
+        let mut board = self.start_board()?;
+        let mut ply = 0;
+        for record in &self.moves {
+            let Action::Move(_, _, to, _) = record.action else {
+                continue;
+            };
+            let captured = board.piece_at(to).map(|(_, pt)| pt);
+            board.apply(&record.action)?;
+            ply += 1;
+            let applied = AppliedMove {
+                ply,
+                record,
+                captured,
+                board: &board,
+            };
+            if stop(&applied) {
+                return Ok(self.truncated(ply));
+            }
+        }
+        Ok(self.clone())
+    }
+
+    /// Replays the game and returns the piece each move captured, as it stood
+    /// on the board, in the order of `moves`. Special moves and moves to an
+    /// empty square give `None`.
+    ///
+    /// CSA does not write captures down; formats that do, and viewers that
+    /// update hands or play a sound, need them worked out from the board.
+    ///
+    /// ```
+    /// use csa::{parse_csa, PieceType};
+    ///
+    /// let game = parse_csa("V2.2\nPI\n+\n+7776FU\n-3334FU\n+8822UM\n-3122GI\n%TORYO\n").unwrap();
+    /// assert_eq!(
+    ///     game.captures().unwrap(),
+    ///     [None, None, Some(PieceType::Bishop), Some(PieceType::Horse), None]
+    /// );
+    /// ```
+    pub fn captures(&self) -> Result<Vec<Option<PieceType>>, BoardError> {
+        let mut board = self.start_board()?;
+        let mut captures = Vec::with_capacity(self.moves.len());
+        for record in &self.moves {
+            let captured = match record.action {
+                Action::Move(_, _, to, _) => board.piece_at(to).map(|(_, pt)| pt),
+                _ => None,
+            };
+            board.apply(&record.action)?;
+            captures.push(captured);
+        }
+        Ok(captures)
+    }
+
+    /// Returns the hash of the starting position followed by the hash after
+    /// each ply, so that `hashes[n]` is the position after ply `n`. Special
+    /// moves do not change the position and add no entry.
+    pub fn position_hashes(&self) -> Result<Vec<u64>, BoardError> {
+        let mut board = self.start_board()?;
+        let mut hashes = vec![board.position_hash()];
+        for record in &self.moves {
+            if let Action::Move(..) = record.action {
+                board.apply(&record.action)?;
+                hashes.push(board.position_hash());
+            }
+        }
+        Ok(hashes)
+    }
+
+    /// Returns the first ply after which `board` stands on the board, or `Some(0)`
+    /// if the game starts from it.
+    ///
+    /// The pieces in hand and the side to move count, so a position reached
+    /// by another move order is found while the same board with other hands
+    /// is not. Positions are compared by [`Board::position_hash`] and then in
+    /// full.
+    ///
+    /// ```
+    /// use csa::{parse_csa, Board};
+    ///
+    /// let tabiya = parse_csa("V2.2\nPI\n+\n+7776FU\n-3334FU\n").unwrap();
+    /// let mut board = Board::from_position(&tabiya.start_pos).unwrap();
+    /// for record in &tabiya.moves {
+    ///     board.apply(&record.action).unwrap();
+    /// }
+    ///
+    /// let game = parse_csa("V2.2\nPI\n+\n+7776FU\n-3334FU\n+2726FU\n").unwrap();
+    /// assert_eq!(game.contains_position(&board).unwrap(), Some(2));
+    /// // The same pieces, but with Black to move.
+    /// let game = parse_csa("V2.2\nPI\n-\n-3334FU\n+7776FU\n").unwrap();
+    /// assert_eq!(game.contains_position(&board).unwrap(), None);
+    /// ```
+    pub fn contains_position(&self, board: &Board) -> Result<Option<usize>, BoardError> {
+        let target = board.position_hash();
+        let mut current = self.start_board()?;
+        let mut ply = 0;
+        let mut moves = self.moves.iter();
+        loop {
+            if current.position_hash() == target && current == *board {
+                return Ok(Some(ply));
+            }
+            let Some(record) = moves.find(|record| matches!(record.action, Action::Move(..)))
+            else {
+                return Ok(None);
+            };
+            current.apply(&record.action)?;
+            ply += 1;
+        }
+    }
+
+    /// Returns the first position to stand on the board for the fourth time,
+    /// which ends the game in sennichite, or `None` if no position does.
+    ///
+    /// As in [`GameRecord::contains_position`], the pieces in hand and the
+    /// side to move count.
+    ///
+    /// ```
+    /// use csa::{parse_csa, Color};
+    ///
+    /// let game = parse_csa(
+    ///     "V2.2\nP-12OU\nP+59OU98HI\n+\n+9892HI\n-1213OU\n+9293HI\n-1312OU\n\
+    ///      +9392HI\n-1213OU\n+9293HI\n-1312OU\n+9392HI\n-1213OU\n+9293HI\n-1312OU\n\
+    ///      +9392HI\n%SENNICHITE\n",
+    /// )
+    /// .unwrap();
+    /// let repetition = game.repetition().unwrap().unwrap();
+    /// assert_eq!(repetition.plies, [1, 5, 9, 13]);
+    /// // Every move of Black's in the cycle is a check.
+    /// assert_eq!(repetition.perpetual_check, Some(Color::Black));
+    /// ```
+    pub fn repetition(&self) -> Result<Option<Repetition>, BoardError> {
+        let mut board = self.start_board()?;
+        let mut seen: HashMap<Board, Vec<usize>> = HashMap::new();
+        // For each ply, the side that played it and whether it gave check.
+        let mut checks = vec![(board.side_to_move(), false)];
+        let mut moves = self
+            .moves
+            .iter()
+            .filter(|record| matches!(record.action, Action::Move(..)));
+        loop {
+            let ply = checks.len() - 1;
+            let plies = seen.entry(board.clone()).or_default();
+            plies.push(ply);
+            if let [first, second, third, fourth] = plies[..] {
+                let checking = |color: Color| {
+                    checks[first + 1..=fourth]
+                        .iter()
+                        .filter(|&&(mover, _)| mover == color)
+                        .all(|&(_, check)| check)
+                };
+                let perpetual_check = match (checking(Color::Black), checking(Color::White)) {
+                    (true, false) => Some(Color::Black),
+                    (false, true) => Some(Color::White),
+                    _ => None,
+                };
+                return Ok(Some(Repetition {
+                    plies: [first, second, third, fourth],
+                    perpetual_check,
+                }));
+            }
+
+            let Some(record) = moves.next() else {
+                return Ok(None);
+            };
+            let mover = board.side_to_move();
+            board.apply(&record.action)?;
+            checks.push((mover, board.in_check(board.side_to_move())));
+        }
+    }
+
+    /// Returns the ply after which `sequence` has first been played in a row,
+    /// or `None` if it never is. Special moves are skipped, so only moves can
+    /// be matched, and an empty sequence is found at ply 0.
+    ///
+    /// ```
+    /// use csa::{parse_csa, Action};
+    ///
+    /// let game = parse_csa("V2.2\nPI\n+\n+7776FU\n-3334FU\n+2726FU\n").unwrap();
+    /// let sequence = [
+    ///     Action::parse("-3334FU").unwrap(),
+    ///     Action::parse("+2726FU").unwrap(),
+    /// ];
+    /// assert_eq!(game.contains_sequence(&sequence), Some(3));
+    /// ```
+    pub fn contains_sequence(&self, sequence: &[Action]) -> Option<usize> {
+        let played: Vec<Action> = self
+            .moves
+            .iter()
+            .map(|record| record.action)
+            .filter(|action| matches!(action, Action::Move(..)))
+            .collect();
+        if sequence.is_empty() {
+            return Some(0);
+        }
+        played
+            .windows(sequence.len())
+            .position(|window| window == sequence)
+            .map(|start| start + sequence.len())
+    }
+
+    /// Like [`GameRecord::contains_sequence`], but also finds `sequence`
+    /// played by the other side: with the colors swapped and the board turned
+    /// round, as in an opening played the same way by White. The earlier of
+    /// the two matches is returned.
+    pub fn contains_sequence_or_flipped(&self, sequence: &[Action]) -> Option<usize> {
+        let (files, ranks) = self.start_pos.dimensions();
+        let flipped: Vec<Action> = sequence
+            .iter()
+            .map(|action| flipped(action, files, ranks))
+            .collect();
+        match (
+            self.contains_sequence(sequence),
+            self.contains_sequence(&flipped),
+        ) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+/// Returns `action` played by the other side on a board turned round.
+fn flipped(action: &Action, files: u8, ranks: u8) -> Action {
+    let turn = |sq: Square| {
+        if sq.is_hand() {
+            sq
+        } else {
+            Square::new(files + 1 - sq.file, ranks + 1 - sq.rank)
+        }
+    };
+    match *action {
+        Action::Move(color, from, to, pt) => Action::Move(flip(color), turn(from), turn(to), pt),
+        Action::IllegalAction(color) => Action::IllegalAction(flip(color)),
+        action => action,
+    }
+}
+
+fn index(sq: Square) -> usize {
+    (sq.file as usize - 1) * 9 + (sq.rank as usize - 1)
+}
+
+pub(crate) fn hand_index(pt: PieceType) -> Option<usize> {
+    HAND_PIECES.iter().position(|&p| p == pt)
+}
+
+pub(crate) fn flip(color: Color) -> Color {
+    match color {
+        Color::Black => Color::White,
+        Color::White => Color::Black,
+    }
+}
+
+fn square_of(index: usize) -> Square {
+    Square::new((index / 9) as u8 + 1, (index % 9) as u8 + 1)
+}
+
+/// A direction as `(file delta, rank delta)`. Piece movements are given for
+/// Black, whose pieces advance towards rank 1.
+type Direction = (i8, i8);
+
+/// Returns the square `dir` away, or `None` when that leaves the 9x9 range.
+fn offset(sq: Square, (df, dr): Direction) -> Option<Square> {
+    let file = sq.file as i8 + df;
+    let rank = sq.rank as i8 + dr;
+    if (1..=9).contains(&file) && (1..=9).contains(&rank) {
+        Some(Square::new(file as u8, rank as u8))
+    } else {
+        None
+    }
+}
+
+/// Turns a direction given from Black's point of view into `color`'s.
+fn orient(color: Color, (df, dr): Direction) -> Direction {
+    match color {
+        Color::Black => (df, dr),
+        Color::White => (-df, -dr),
+    }
+}
+
+const FORWARD: Direction = (0, -1);
+const ORTHOGONAL: [Direction; 4] = [(0, -1), (0, 1), (-1, 0), (1, 0)];
+const DIAGONAL: [Direction; 4] = [(-1, -1), (1, -1), (-1, 1), (1, 1)];
+const ALL_DIRECTIONS: [Direction; 8] = [
+    (0, -1),
+    (0, 1),
+    (-1, 0),
+    (1, 0),
+    (-1, -1),
+    (1, -1),
+    (-1, 1),
+    (1, 1),
+];
+const GOLD_STEPS: [Direction; 6] = [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (0, 1)];
+const SILVER_STEPS: [Direction; 5] = [(-1, -1), (0, -1), (1, -1), (-1, 1), (1, 1)];
+const KNIGHT_STEPS: [Direction; 2] = [(-1, -2), (1, -2)];
+
+/// Returns the single steps and the sliding directions of a piece, for Black.
+fn movement(pt: PieceType) -> (&'static [Direction], &'static [Direction]) {
+    match pt {
+        PieceType::Pawn => (&[FORWARD], &[]),
+        PieceType::Lance => (&[], &[FORWARD]),
+        PieceType::Knight => (&KNIGHT_STEPS, &[]),
+        PieceType::Silver => (&SILVER_STEPS, &[]),
+        PieceType::Gold
+        | PieceType::ProPawn
+        | PieceType::ProLance
+        | PieceType::ProKnight
+        | PieceType::ProSilver => (&GOLD_STEPS, &[]),
+        PieceType::Bishop => (&[], &DIAGONAL),
+        PieceType::Rook => (&[], &ORTHOGONAL),
+        PieceType::Horse => (&ORTHOGONAL, &DIAGONAL),
+        PieceType::Dragon => (&DIAGONAL, &ORTHOGONAL),
+        PieceType::King => (&ALL_DIRECTIONS, &[]),
+        PieceType::All => (&[], &[]),
+    }
+}
+
+/// Returns whether a `color` piece of type `pt` reaches the square `distance`
+/// steps away in direction `dir` (given from the board's point of view) when
+/// nothing stands in between. Knight jumps are not covered.
+fn reaches(color: Color, pt: PieceType, dir: Direction, distance: u8) -> bool {
+    let dir = orient(color, dir);
+    let (steps, slides) = movement(pt);
+    slides.contains(&dir) || (distance == 1 && steps.contains(&dir) && !KNIGHT_STEPS.contains(&dir))
+}
+
+struct ZobristKeys {
+    pieces: [[[u64; 81]; 14]; 2],
+    /// Indexed by count; the entry for 0 is 0.
+    hands: [[[u64; MAX_HAND + 1]; 7]; 2],
+    side: u64,
+}
+
+const KEYS: ZobristKeys = {
+    const fn splitmix64(state: &mut u64) -> u64 {
+        *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = *state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    let mut state = 0;
+    let mut keys = ZobristKeys {
+        pieces: [[[0; 81]; 14]; 2],
+        hands: [[[0; MAX_HAND + 1]; 7]; 2],
+        side: 0,
+    };
+
+    let mut color = 0;
+    while color < 2 {
+        let mut pt = 0;
+        while pt < 14 {
+            let mut sq = 0;
+            while sq < 81 {
+                keys.pieces[color][pt][sq] = splitmix64(&mut state);
+                sq += 1;
+            }
+            pt += 1;
+        }
+        color += 1;
+    }
+
+    let mut color = 0;
+    while color < 2 {
+        let mut slot = 0;
+        while slot < 7 {
+            let mut count = 1;
+            while count <= MAX_HAND {
+                keys.hands[color][slot][count] = splitmix64(&mut state);
+                count += 1;
+            }
+            slot += 1;
+        }
+        color += 1;
+    }
+
+    keys.side = splitmix64(&mut state);
+    keys
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_csa;
+
+    fn mv(s: &str) -> Action {
+        Action::parse(s).unwrap()
+    }
+
+    #[test]
+    fn from_position_hirate() {
+        let board = Board::from_position(&Position::default()).unwrap();
+        assert_eq!(board.dimensions(), (9, 9));
+        assert_eq!(board.side_to_move(), Color::Black);
+        assert_eq!(
+            board.piece_at(Square::new(5, 9)),
+            Some((Color::Black, PieceType::King))
+        );
+        assert_eq!(board.piece_at(Square::new(5, 5)), None);
+        assert_eq!(board.hand(Color::Black, PieceType::Pawn), 0);
+        assert_eq!(board.count(PieceType::Pawn), 18);
+    }
+
+    #[test]
+    fn from_position_hands() {
+        let record = parse_csa("V2.2\nPI82HI22KA\nP+00HI00FU00FU\nP-00AL\n-\n").unwrap();
+        let board = record.start_board().unwrap();
+        assert_eq!(board.hand(Color::Black, PieceType::Rook), 1);
+        assert_eq!(board.hand(Color::Black, PieceType::Pawn), 2);
+        assert_eq!(board.hand(Color::White, PieceType::Pawn), 0);
+        assert_eq!(board.hand(Color::White, PieceType::Bishop), 1);
+        assert_eq!(board.hand(Color::White, PieceType::Rook), 0);
+        assert_eq!(board.side_to_move(), Color::White);
+
+        let record = parse_csa("V2.2\nPI\nP+00OU\n+\n").unwrap();
+        assert!(matches!(
+            record.start_board(),
+            Err(BoardError::InvalidPosition(_))
+        ));
+    }
+
+    #[test]
+    fn from_position_bad_removals() {
+        let removing = |removals: &[(u8, u8, PieceType)]| Position {
+            drop_pieces: removals
+                .iter()
+                .map(|&(file, rank, pt)| (Square::new(file, rank), pt))
+                .collect(),
+            ..Position::default()
+        };
+
+        assert!(Board::from_position(&removing(&[(8, 2, PieceType::Rook)])).is_ok());
+        for removals in [
+            &[(8, 2, PieceType::Bishop)][..],
+            &[(5, 5, PieceType::Pawn)],
+            &[(8, 2, PieceType::Rook), (8, 2, PieceType::Rook)],
+        ] {
+            assert!(matches!(
+                Board::from_position(&removing(removals)),
+                Err(BoardError::InvalidPosition(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn apply_moves_and_captures() {
+        let mut board = Board::from_position(&Position::default()).unwrap();
+        for m in ["+7776FU", "-3334FU", "+8822UM", "-3122GI", "+0045KA"] {
+            board.apply(&mv(m)).unwrap();
+        }
+        assert_eq!(
+            board.piece_at(Square::new(2, 2)),
+            Some((Color::White, PieceType::Silver))
+        );
+        assert_eq!(
+            board.piece_at(Square::new(4, 5)),
+            Some((Color::Black, PieceType::Bishop))
+        );
+        assert_eq!(board.hand(Color::Black, PieceType::Bishop), 0);
+        assert_eq!(board.hand(Color::White, PieceType::Bishop), 1);
+        assert_eq!(board.side_to_move(), Color::White);
+
+        board.apply(&Action::Toryo).unwrap();
+        assert_eq!(board.side_to_move(), Color::White);
+    }
+
+    #[test]
+    fn apply_rejects_inconsistent_moves() {
+        let board = Board::from_position(&Position::default()).unwrap();
+        for m in [
+            "-3334FU", // not White's turn
+            "+5556FU", // no piece on 55
+            "+3132GI", // White's piece
+            "+2829HI", // own knight on 29
+            "+0055FU", // nothing in hand
+            "+7776KI", // a pawn cannot turn into a gold
+            "+2838UM", // a rook promotes to a dragon
+            "+1110KY", // off the board
+        ] {
+            let result = board.clone().apply(&mv(m));
+            assert!(
+                matches!(result, Err(BoardError::InvalidMove(..))),
+                "{m}: {result:?}"
+            );
+        }
+
+        // Whether the pawn may promote on 76 is not checked.
+        assert!(board.clone().apply(&mv("+7776TO")).is_ok());
+    }
+
+    /// Builds a board from `P+`/`P-` style pieces, e.g. `+59OU -00FU`.
+    fn setup(pieces: &str, side_to_move: Color) -> Board {
+        let mut pos = Position {
+            bulk: Some(Box::new([[None; 9]; 9])),
+            side_to_move,
+            ..Position::default()
+        };
+        for piece in pieces.split_whitespace() {
+            let color = if piece.starts_with('+') {
+                Color::Black
+            } else {
+                Color::White
+            };
+            let b = piece.as_bytes();
+            let sq = Square::new(b[1] - b'0', b[2] - b'0');
+            pos.add_pieces
+                .push((color, sq, PieceType::parse(&piece[3..]).unwrap()));
+        }
+        Board::from_position(&pos).unwrap()
+    }
+
+    #[test]
+    fn to_position_round_trip() {
+        let record = parse_csa("V2.2\nPI82HI22KA\nP+00HI00FU00FU\nP-00KA\n-\n").unwrap();
+        let board = record.start_board().unwrap();
+        let pos = board.to_position();
+        assert!(pos.grid().is_some());
+        assert_eq!(Board::from_position(&pos).unwrap(), board);
+
+        let written = GameRecord {
+            start_pos: pos,
+            ..GameRecord::default()
+        }
+        .to_string();
+        assert!(written.contains("P+00FU00FU00HI\nP-00KA\n"), "{written}");
+        let reparsed = parse_csa(&written).unwrap();
+        assert_eq!(Board::from_position(&reparsed.start_pos).unwrap(), board);
+
+        let mini = parse_csa(
+            "V2.2\nP1-HI-KA-GI-KI-OU\nP2 *  *  *  * -FU\nP3 *  *  *  *  * \n\
+             P4+FU *  *  *  * \nP5+OU+KI+GI+KA+HI\n+\n",
+        )
+        .unwrap();
+        let board = Board::from_position(&mini.start_pos).unwrap();
+        let pos = board.to_position();
+        assert!(pos.minishogi_grid().is_some());
+        assert_eq!(Board::from_position(&pos).unwrap(), board);
+    }
+
+    #[test]
+    fn attack_maps() {
+        let board = Board::from_position(&parse_csa("V2.2\nPI\n+\n").unwrap().start_pos).unwrap();
+        // The rook's rank is blocked by the bishop on 88, which it still attacks.
+        assert_eq!(
+            board.attackers_of(Square::new(9, 8), Color::Black),
+            [Square::new(9, 9)]
+        );
+        let map = board.attack_map(Color::Black);
+        assert_eq!(map.dimensions(), (9, 9));
+        assert_eq!(map.get(Square::new(8, 8)), Some(2));
+        assert_eq!(map.get(Square::new(9, 8)), Some(1));
+        assert_eq!(map.get(Square::new(0, 0)), None);
+        assert_eq!(board.defense_map(Color::Black), map);
+
+        // The gold on 55 is pinned by the rook on 51, and the pawn on 47
+        // keeps the king off 48.
+        let pinned = parse_csa("V2.2\nP-51HI47FU11OU\nP+55KI59OU\n+\n").unwrap();
+        let board = Board::from_position(&pinned.start_pos).unwrap();
+        let attacks = board.attack_map(Color::Black);
+        let defenses = board.defense_map(Color::Black);
+        for (file, rank, attacked, defended) in [
+            (5, 4, 1, 1),
+            (4, 4, 1, 0),
+            (6, 5, 1, 0),
+            (4, 8, 1, 0),
+            (5, 8, 1, 1),
+        ] {
+            let sq = Square::new(file, rank);
+            assert_eq!(attacks.get(sq), Some(attacked), "{sq}");
+            assert_eq!(defenses.get(sq), Some(defended), "{sq}");
+        }
+
+        let mini = parse_csa(
+            "V2.2\nP1-HI-KA-GI-KI-OU\nP2 *  *  *  * -FU\nP3 *  *  *  *  * \n\
+             P4+FU *  *  *  * \nP5+OU+KI+GI+KA+HI\n+\n",
+        )
+        .unwrap();
+        let board = Board::from_position(&mini.start_pos).unwrap();
+        let map = board.attack_map(Color::White);
+        assert_eq!(map.dimensions(), (5, 5));
+        assert_eq!(map.get(Square::new(1, 2)), Some(2));
+        assert_eq!(map.get(Square::new(6, 1)), None);
+        assert_eq!(map.get(Square::new(1, 6)), None);
+
+        let wildcat =
+            parse_csa("V2.2\nP1 * -OU * \nP2 *  *  * \nP3 *  *  * \nP4 *  *  * \nP5 * +OU * \n+\n")
+                .unwrap();
+        let board = Board::from_position(&wildcat.start_pos).unwrap();
+        let map = board.attack_map(Color::Black);
+        assert_eq!(map.dimensions(), (3, 5));
+        assert_eq!(map.get(Square::new(3, 4)), Some(1));
+        assert_eq!(map.get(Square::new(4, 4)), None);
+    }
+
+    #[test]
+    fn build_position() {
+        let fixture = parse_csa(include_str!("../fixtures/tsume_atamakin.csa")).unwrap();
+
+        let mut pos = Position::empty();
+        assert_eq!(pos.source_repr(), crate::PositionRepr::Placements);
+        pos.place(Square::new(5, 1), Color::White, PieceType::King)
+            .unwrap();
+        pos.place(Square::new(5, 3), Color::Black, PieceType::Pawn)
+            .unwrap();
+        pos.add_to_hand(Color::Black, PieceType::Gold, 1).unwrap();
+        for (pt, count) in [
+            (PieceType::Pawn, 17),
+            (PieceType::Lance, 4),
+            (PieceType::Knight, 4),
+            (PieceType::Silver, 4),
+            (PieceType::Gold, 3),
+            (PieceType::Bishop, 2),
+            (PieceType::Rook, 2),
+        ] {
+            pos.add_to_hand(Color::White, pt, count).unwrap();
+        }
+        pos.set_side_to_move(Color::Black);
+        assert!(pos.semantically_equals(&fixture.start_pos).unwrap());
+
+        let mut board = Board::from_position(&pos).unwrap();
+        board.apply(&fixture.moves[0].action).unwrap();
+        assert!(board.is_checkmate());
+
+        // Every piece of the set is used up.
+        let err = |result: Result<(), BoardError>| match result {
+            Err(BoardError::InvalidPosition(msg)) => msg,
+            other => panic!("{other:?}"),
+        };
+        let sq = Square::new(1, 1);
+        assert_eq!(
+            err(pos.add_to_hand(Color::Black, PieceType::Gold, 1)),
+            "a standard set has only 4 KI"
+        );
+        assert_eq!(
+            err(pos.place(sq, Color::Black, PieceType::ProPawn)),
+            "a standard set has only 18 FU"
+        );
+        assert_eq!(
+            err(pos.place(sq, Color::White, PieceType::King)),
+            "a standard set has only 1 -OU"
+        );
+        pos.place(sq, Color::Black, PieceType::King).unwrap();
+        assert_eq!(
+            err(pos.place(Square::new(5, 3), Color::Black, PieceType::King)),
+            "53 already holds +FU"
+        );
+        assert!(pos
+            .place(Square::new(0, 0), Color::Black, PieceType::Pawn)
+            .is_err());
+        assert!(pos
+            .place(Square::new(1, 2), Color::Black, PieceType::All)
+            .is_err());
+        assert!(pos.add_to_hand(Color::Black, PieceType::King, 1).is_err());
+        assert!(pos.add_to_hand(Color::Black, PieceType::Horse, 1).is_err());
+
+        // Round trip through CSA.
+        let reparsed = parse_csa(&format!("V2.2\n{pos}")).unwrap();
+        assert_eq!(reparsed.start_pos, {
+            let mut normalized = pos.clone();
+            normalized.normalize();
+            normalized
+        });
+    }
+
+    #[test]
+    fn semantic_equality_across_representations() {
+        let games = [
+            "V2.2\nPI\n+\n+7776FU\n-3334FU\n+8822UM\n-3122GI\n+0045KA\n-8384FU\n",
+            "V2.2\nPI82HI22KA\nP+00HI00FU00FU\nP-00KA\n-\n-5142OU\n+0055HI\n",
+        ];
+        for csa in games {
+            let game = parse_csa(csa).unwrap();
+            let mut board = game.start_board().unwrap();
+            let mut boards = vec![board.clone()];
+            for record in &game.moves {
+                board.apply(&record.action).unwrap();
+                boards.push(board.clone());
+            }
+
+            for (ply, board) in boards.iter().enumerate() {
+                // The board as a grid, and as an empty grid with every piece
+                // placed by P+/P- lines.
+                let grid = board.to_position();
+                let mut placed = Position {
+                    bulk: Some(Box::default()),
+                    side_to_move: grid.side_to_move,
+                    ..Position::default()
+                };
+                for file in 1..=9 {
+                    for rank in 1..=9 {
+                        let sq = Square::new(file, rank);
+                        if let Some((color, pt)) = board.piece_at(sq) {
+                            placed.add_pieces.push((color, sq, pt));
+                        }
+                    }
+                }
+                placed.add_pieces.extend(grid.add_pieces.iter().copied());
+                let mut positions = vec![grid, placed];
+                if ply == 0 {
+                    positions.push(game.start_pos.clone());
+                }
+
+                let written: Vec<Position> = positions
+                    .iter()
+                    .map(|pos| {
+                        let record = GameRecord {
+                            start_pos: pos.clone(),
+                            ..GameRecord::default()
+                        };
+                        parse_csa(&record.to_string()).unwrap().start_pos
+                    })
+                    .collect();
+                for a in &written {
+                    for b in &written {
+                        assert!(a.semantically_equals(b).unwrap(), "{csa} ply {ply}");
+                    }
+                }
+                assert_ne!(written[0], written[1]);
+            }
+
+            // A different side to move is a different position.
+            let mut other = game.start_pos.clone();
+            other.side_to_move = flip(other.side_to_move);
+            assert!(!game.start_pos.semantically_equals(&other).unwrap());
+        }
+
+        // An empty 5x5 board is not an empty 9x9 board.
+        let empty_9x9 = Position {
+            bulk: Some(Box::default()),
+            ..Position::default()
+        };
+        let empty_5x5 = Position {
+            minishogi_bulk: Some(Box::default()),
+            ..Position::default()
+        };
+        assert!(!empty_9x9.semantically_equals(&empty_5x5).unwrap());
+
+        let bad = Position {
+            add_pieces: [(Color::Black, Square::new(0, 0), PieceType::King)]
+                .into_iter()
+                .collect(),
+            ..Position::default()
+        };
+        assert!(bad.semantically_equals(&bad).is_err());
+    }
+
+    #[test]
+    fn slice_round_trip() {
+        let game = parse_csa(
+            "V2.2\nPI\n+\n+7776FU\n-3334FU\n+8822UM\n-3122GI\n+0045KA\n-8384FU\n%TORYO\n",
+        )
+        .unwrap();
+        let hashes = game.position_hashes().unwrap();
+
+        for (from, to) in [(1, 6), (3, 6), (3, 4), (6, 6), (4, 3)] {
+            let slice = game.slice(from..=to).unwrap();
+            let reparsed = parse_csa(&slice.to_string()).unwrap();
+            assert_eq!(reparsed.moves, slice.moves);
+            let board = Board::from_position(&reparsed.start_pos).unwrap();
+            assert_eq!(board.position_hash(), hashes[from - 1]);
+            assert_eq!(
+                reparsed.position_hashes().unwrap(),
+                hashes[from - 1..=to.max(from - 1)]
+            );
+        }
+
+        // Only a slice that runs to the end keeps the result.
+        assert_eq!(
+            game.slice(3..).unwrap().moves.last().unwrap().action,
+            Action::Toryo
+        );
+        assert_eq!(game.slice(3..6).unwrap().ply_count(), 3);
+        assert_ne!(
+            game.slice(3..6).unwrap().moves.last().unwrap().action,
+            Action::Toryo
+        );
+        assert_eq!(game.slice(..).unwrap().moves, game.moves);
+
+        // Out of range plies are clamped.
+        assert_eq!(game.slice(0..100).unwrap().moves, game.moves);
+        let past_end = game.slice(10..).unwrap();
+        assert_eq!(past_end.ply_count(), 0);
+        assert_eq!(
+            Board::from_position(&past_end.start_pos)
+                .unwrap()
+                .position_hash(),
+            hashes[6]
+        );
+    }
+
+    #[test]
+    fn slice_keeps_special_moves_in_range() {
+        let game = parse_csa("V2.2\nPI\n+\n+7776FU\n%MATTA\n-3334FU\n+2726FU\n").unwrap();
+        let slice = game.slice(2..).unwrap();
+        assert_eq!(slice.moves, game.moves[1..]);
+        assert_eq!(slice.start_pos.side_to_move, Color::White);
+
+        let invalid = parse_csa("V2.2\nPI\n+\n+7776FU\n-5556FU\n+2726FU\n").unwrap();
+        assert!(matches!(
+            invalid.slice(3..),
+            Err(BoardError::InvalidMove(..))
+        ));
+    }
+
+    #[test]
+    fn captures() {
+        let game = parse_csa(
+            "V2.2\nPI\n+\n+7776FU\n-3334FU\n+8822UM\n-3122GI\n+0033KA\n-2133KE\n%MATTA\n+2726FU\n",
+        )
+        .unwrap();
+        assert_eq!(
+            game.captures().unwrap(),
+            [
+                None,
+                None,
+                Some(PieceType::Bishop),
+                Some(PieceType::Horse),
+                None,
+                Some(PieceType::Bishop),
+                None,
+                None,
+            ]
+        );
+
+        let inconsistent = parse_csa("V2.2\nPI\n+\n+7776FU\n-7776FU\n").unwrap();
+        assert!(matches!(
+            inconsistent.captures(),
+            Err(BoardError::InvalidMove(..))
+        ));
+    }
+
+    #[test]
+    fn truncate_when_first_capture() {
+        let game =
+            parse_csa("V2.2\nPI\n+\n+7776FU\n-3334FU\n%MATTA\n+8822UM\n-3122GI\n%TORYO\n").unwrap();
+        let mut seen = Vec::new();
+        let cut = game
+            .truncate_when(|m| {
+                seen.push(m.ply);
+                m.captured == Some(PieceType::Bishop)
+            })
+            .unwrap();
+        assert_eq!(seen, [1, 2, 3]);
+        assert_eq!(cut.moves, game.moves[..4]);
+
+        let after = game
+            .truncate_when(|m| m.board.hand(Color::White, PieceType::Bishop) > 0)
+            .unwrap();
+        // The last ply: nothing to cut.
+        assert_eq!(after, game);
+
+        assert_eq!(game.truncate_when(|_| false).unwrap(), game);
+    }
+
+    #[test]
+    fn perft_starting_position() {
+        let board = Board::from_position(&Position::default()).unwrap();
+        let counts: Vec<u64> = (0..=4).map(|depth| board.perft(depth)).collect();
+        assert_eq!(counts, vec![1, 30, 900, 25470, 719731]);
+
+        let divide = board.perft_divide(2);
+        assert_eq!(divide.len(), 30);
+        assert!(divide.iter().all(|&(_, n)| n == 30));
+        assert_eq!(divide.iter().map(|&(_, n)| n).sum::<u64>(), 900);
+    }
+
+    #[test]
+    fn perft_pinned_piece() {
+        // The gold on 58 is pinned by the rook and may only advance to 57.
+        let board = setup("+59OU +58KI -51HI -11OU", Color::Black);
+        let gold_moves: Vec<_> = board
+            .legal_moves()
+            .into_iter()
+            .filter(|m| matches!(m, Action::Move(_, from, ..) if *from == Square::new(5, 8)))
+            .collect();
+        assert_eq!(gold_moves, vec![mv("+5857KI")]);
+        assert_eq!(
+            (1..=3).map(|d| board.perft(d)).collect::<Vec<_>>(),
+            vec![5, 93, 841]
+        );
+    }
+
+    #[test]
+    fn perft_nifu() {
+        // No pawn may be dropped on file 5, which already has one.
+        let board = setup("+59OU -51OU +57FU +00FU", Color::Black);
+        let drops = board
+            .legal_moves()
+            .into_iter()
+            .filter(|m| matches!(m, Action::Move(_, from, ..) if from.file == 0))
+            .collect::<Vec<_>>();
+        assert_eq!(drops.len(), 64);
+        assert!(drops
+            .iter()
+            .all(|m| matches!(m, Action::Move(_, _, to, _) if to.file != 5 && to.rank != 1)));
+        assert_eq!(board.perft(1), 70);
+        assert_eq!(board.perft(2), 346);
+    }
+
+    #[test]
+    fn perft_uchifuzume() {
+        // Dropping the pawn on 12 would checkmate, so it is not allowed.
+        let board = setup("-11OU +23KI +32GI +59OU +00FU", Color::Black);
+        let moves = board.legal_moves();
+        assert!(!moves.contains(&mv("+0012FU")));
+        assert!(moves.contains(&mv("+0013FU")));
+        assert_eq!(board.perft(1), 86);
+        assert_eq!(board.perft(2), 12);
+
+        // Without the silver the king escapes to 21, and the drop is fine.
+        let board = setup("-11OU +23KI +59OU +00FU", Color::Black);
+        assert!(board.legal_moves().contains(&mv("+0012FU")));
+    }
+
+    #[test]
+    fn perft_drops() {
+        let record = parse_csa("V2.2\nPI\n+\n+7776FU\n-3334FU\n+8822UM\n-3122GI\n").unwrap();
+        let mut board = record.start_board().unwrap();
+        for m in &record.moves {
+            board.apply(&m.action).unwrap();
+        }
+        assert_eq!(board.perft(1), 77);
+        assert_eq!(board.perft(2), 5390);
+    }
+
+    #[test]
+    fn perft_most_moves() {
+        // The position with the largest known number of legal moves.
+        let mut pieces = String::from(
+            "+91HI +72OU +52GI +32GI +22GI -12OU +53KA +89KY +69KY +49KY \
+             +00HI +00KA +00KI +00GI +00KE +00KY +00FU \
+             -00KI -00KI -00KI -00KE -00KE -00KE",
+        );
+        pieces.push_str(&" -00FU".repeat(17));
+        let board = setup(&pieces, Color::Black);
+        assert_eq!(board.perft(1), 593);
+    }
+
+    #[test]
+    fn hash_components_add_up() {
+        let mut board = Board::from_position(&Position::default()).unwrap();
+        let start = board.hash_components();
+        assert_eq!(start.hands, 0);
+        assert_eq!(start.side, 0);
+        assert_eq!(board.position_hash(), start.pieces);
+
+        board.apply(&mv("+7776FU")).unwrap();
+        let parts = board.hash_components();
+        assert_eq!(parts.side, Board::side_key());
+        assert_eq!(
+            parts.pieces,
+            start.pieces
+                ^ Board::piece_key(Color::Black, PieceType::Pawn, Square::new(7, 7))
+                ^ Board::piece_key(Color::Black, PieceType::Pawn, Square::new(7, 6))
+        );
+        assert_eq!(
+            board.position_hash(),
+            parts.pieces ^ parts.hands ^ parts.side
+        );
+
+        assert_eq!(Board::hand_key(Color::Black, PieceType::Pawn, 0), 0);
+        assert_ne!(Board::hand_key(Color::Black, PieceType::Pawn, 1), 0);
+        assert_eq!(Board::hand_key(Color::Black, PieceType::King, 1), 0);
+        assert_eq!(Board::hand_key(Color::Black, PieceType::Pawn, 19), 0);
+    }
+
+    #[test]
+    fn transpositions_hash_equal() {
+        let a = parse_csa("V2.2\nPI\n+\n+7776FU\n-3334FU\n+2726FU\n").unwrap();
+        let b = parse_csa("V2.2\nPI\n+\n+2726FU\n-3334FU\n+7776FU\n").unwrap();
+        let (a, b) = (a.position_hashes().unwrap(), b.position_hashes().unwrap());
+        assert_eq!(a.len(), 4);
+        assert_eq!(a[0], b[0]);
+        assert_ne!(a[1], b[1]);
+        assert_eq!(a[3], b[3]);
+    }
+
+    #[test]
+    fn contains_position_transposition() {
+        let a = parse_csa("V2.2\nPI\n+\n+7776FU\n-3334FU\n+2726FU\n-8384FU\n").unwrap();
+        let b = parse_csa("V2.2\nPI\n+\n+2726FU\n-3334FU\n+7776FU\n%TORYO\n").unwrap();
+        let mut board = Board::from_position(&a.start_pos).unwrap();
+        for record in &a.moves[..3] {
+            board.apply(&record.action).unwrap();
+        }
+        assert_eq!(a.contains_position(&board).unwrap(), Some(3));
+        assert_eq!(b.contains_position(&board).unwrap(), Some(3));
+
+        let start = Board::from_position(&a.start_pos).unwrap();
+        assert_eq!(b.contains_position(&start).unwrap(), Some(0));
+
+        // Hands count: the same board after an exchange of bishops, with one
+        // side holding both, is another position.
+        let c = parse_csa("V2.2\nPI\n+\n+7776FU\n-3334FU\n+8822UM\n-3122GI\n").unwrap();
+        let mut board = Board::from_position(&c.start_pos).unwrap();
+        for record in &c.moves {
+            board.apply(&record.action).unwrap();
+        }
+        let mut other = board.clone();
+        other.hands[0][5] = 0;
+        other.hands[1][5] = 2;
+        assert_eq!(c.contains_position(&board).unwrap(), Some(4));
+        assert_eq!(c.contains_position(&other).unwrap(), None);
+    }
+
+    #[test]
+    fn repetition() {
+        let cycle = |start: &str, moves: &[&str], times: usize| {
+            let moves: String = (0..times)
+                .flat_map(|_| moves.iter())
+                .map(|m| format!("{m}\n"))
+                .collect();
+            parse_csa(&format!("V2.2\n{start}{moves}%SENNICHITE\n")).unwrap()
+        };
+
+        // The kings step back and forth: the starting position comes back
+        // every four plies.
+        let kings = ["+5958OU", "-5152OU", "+5859OU", "-5251OU"];
+        let game = cycle("PI\n+\n", &kings, 3);
+        let repetition = game.repetition().unwrap().unwrap();
+        assert_eq!(repetition.plies, [0, 4, 8, 12]);
+        assert_eq!(repetition.perpetual_check, None);
+        assert_eq!(cycle("PI\n+\n", &kings, 2).repetition().unwrap(), None);
+
+        // Black checks with every move but the first: the cycle starts after
+        // it.
+        let start = "P-12OU\nP+59OU98HI\n+\n+9892HI\n";
+        let checks = ["-1213OU", "+9293HI", "-1312OU", "+9392HI"];
+        let repetition = cycle(start, &checks, 3).repetition().unwrap().unwrap();
+        assert_eq!(repetition.plies, [1, 5, 9, 13]);
+        assert_eq!(repetition.perpetual_check, Some(Color::Black));
+
+        // One quiet move in the cycle is enough.
+        let quiet = ["-1213OU", "+9297HI", "-1312OU", "+9792HI"];
+        let repetition = cycle(start, &quiet, 3).repetition().unwrap().unwrap();
+        assert_eq!(repetition.plies, [1, 5, 9, 13]);
+        assert_eq!(repetition.perpetual_check, None);
+    }
+
+    #[test]
+    fn contains_sequence() {
+        let game = parse_csa("V2.2\nPI\n+\n+7776FU\n-3334FU\n%MATTA\n+2726FU\n-8384FU\n").unwrap();
+        let seq = |moves: &[&str]| -> Vec<Action> { moves.iter().map(|m| mv(m)).collect() };
+
+        assert_eq!(game.contains_sequence(&[]), Some(0));
+        assert_eq!(game.contains_sequence(&seq(&["+7776FU"])), Some(1));
+        // Special moves in between are skipped.
+        assert_eq!(
+            game.contains_sequence(&seq(&["-3334FU", "+2726FU"])),
+            Some(3)
+        );
+        assert_eq!(game.contains_sequence(&seq(&["+7776FU", "+2726FU"])), None);
+        assert_eq!(game.contains_sequence(&seq(&["%MATTA"])), None);
+
+        // Plies 2 and 3 with the sides swapped.
+        let swapped = seq(&["+7776FU", "-8384FU"]);
+        assert_eq!(game.contains_sequence(&swapped), None);
+        assert_eq!(game.contains_sequence_or_flipped(&swapped), Some(3));
+        let neither = seq(&["+7776FU", "+2726FU"]);
+        assert_eq!(game.contains_sequence_or_flipped(&neither), None);
+    }
+
+    /// Checks the hashes in `fixtures/hashes/position_hashes.txt`, which pin
+    /// the hashing scheme down. Each line holds a hash in hex, a `PI` line and
+    /// the moves played from it.
+    #[test]
+    fn hash_test_vectors() {
+        let vectors = include_str!("../fixtures/hashes/position_hashes.txt");
+        let mut checked = 0;
+        for line in vectors.lines() {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let expected = u64::from_str_radix(fields.next().unwrap(), 16).unwrap();
+            let pi = fields.next().unwrap();
+            let moves: Vec<&str> = fields.collect();
+
+            let side = moves.first().map_or("+", |m| &m[..1]);
+            let mut csa = format!("V2.2\n{pi}\n{side}\n");
+            for m in &moves {
+                csa.push_str(m);
+                csa.push('\n');
+            }
+            let record = parse_csa(&csa).unwrap();
+            let hashes = record.position_hashes().unwrap();
+            assert_eq!(*hashes.last().unwrap(), expected, "{line}");
+            checked += 1;
+        }
+        assert!(checked >= 5);
+    }
+}
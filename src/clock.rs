@@ -0,0 +1,655 @@
+//! Clocks computed from the time limits and the recorded move times, and the
+//! checks built on them and on the move limit.
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::board::flip;
+use crate::lint::SuppressionList;
+use crate::stats::GameRules;
+use crate::value::{Action, Color, GameRecord, TimeLimit, TimeUnit};
+
+/// The clock of the player who made a move, as computed by
+/// [`GameRecord::clocks`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct MoveClock {
+    /// Index of the move in `GameRecord::moves`.
+    pub index: usize,
+    pub color: Color,
+    /// Time charged for the move: the recorded `T`, or the per-move minimum
+    /// if that is longer.
+    pub spent: Duration,
+    /// Main time left after the move, including the increment it earned.
+    pub main_time_left: Duration,
+    /// Whether the move ran into byoyomi, either because the main time was
+    /// already used up or because it ran out during the move.
+    pub in_byoyomi: bool,
+    /// The part of `spent` taken from the byoyomi.
+    pub byoyomi_used: Duration,
+    /// Whether the move took longer than the main time left plus the byoyomi,
+    /// which the recorded times should never show: the record is wrong, or
+    /// the player lost on time and the game went on.
+    pub overrun: bool,
+}
+
+/// A problem found by [`GameRecord::validate`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ValidationIssue {
+    /// A move took longer than the time its player had left, see
+    /// [`MoveClock::overrun`].
+    ClockOverrun {
+        index: usize,
+        color: Color,
+        spent: Duration,
+        available: Duration,
+    },
+    /// The game ends in `%TIME_UP` with a recorded `T` that fits in the time
+    /// the player had left.
+    TimeUpWithTimeLeft {
+        index: usize,
+        color: Color,
+        spent: Duration,
+        available: Duration,
+    },
+    /// The game ends in `%TIME_UP` for a player without a time limit, or
+    /// with one that never runs out.
+    TimeUpWithoutTimeLimit { index: usize, color: Color },
+    /// The game ends in `%TIME_UP`, but a clock already ran out at ply `ply`,
+    /// where the game should have ended: the recorded times drifted, or the
+    /// server allows some grace.
+    TimeUpAfterFlagFall {
+        index: usize,
+        color: Color,
+        ply: usize,
+    },
+    /// The time of a move is not a whole number of [`GameRecord::time_unit`],
+    /// as a `T1.5` in a record counting in minutes: the unit is likely wrong.
+    TimeOffUnit {
+        index: usize,
+        time: Duration,
+        unit: TimeUnit,
+    },
+    /// A move is played past the move limit, see
+    /// [`GameRecord::max_moves`]: `ply` is `max_moves + 1`.
+    PastMaxMoves {
+        index: usize,
+        ply: usize,
+        max_moves: usize,
+    },
+    /// The game ends in `%MAX_MOVES` after only `plies` plies.
+    EarlyMaxMoves {
+        index: usize,
+        plies: usize,
+        max_moves: usize,
+    },
+    /// `bookmarks[bookmark]` marks the position after ply `ply`, but the
+    /// game has only `plies` plies.
+    BookmarkPastEnd {
+        bookmark: usize,
+        ply: usize,
+        plies: usize,
+    },
+}
+
+impl ValidationIssue {
+    /// Returns the id of the issue, the id of the [`LintRule`] that reports
+    /// it in [`lint_record`], such as `KIFU-CLOCK-001`.
+    ///
+    /// [`LintRule`]: crate::LintRule
+    /// [`lint_record`]: crate::lint_record
+    pub fn id(&self) -> &'static str {
+        match self {
+            ValidationIssue::ClockOverrun { .. } => "KIFU-CLOCK-001",
+            ValidationIssue::TimeUpWithTimeLeft { .. } => "KIFU-CLOCK-002",
+            ValidationIssue::TimeUpWithoutTimeLimit { .. } => "KIFU-CLOCK-003",
+            ValidationIssue::TimeUpAfterFlagFall { .. } => "KIFU-CLOCK-004",
+            ValidationIssue::TimeOffUnit { .. } => "KIFU-CLOCK-005",
+            ValidationIssue::PastMaxMoves { .. } => "KIFU-RESULT-003",
+            ValidationIssue::EarlyMaxMoves { .. } => "KIFU-RESULT-004",
+            ValidationIssue::BookmarkPastEnd { .. } => "KIFU-META-003",
+        }
+    }
+
+    /// Returns the numeric code of the issue, see [`LintRule::code`].
+    ///
+    /// [`LintRule::code`]: crate::LintRule::code
+    pub fn code(&self) -> u16 {
+        crate::LintRule::by_id(self.id()).map_or(0, |rule| rule.code)
+    }
+
+    /// Returns the index of the move the issue is about, if it is about a
+    /// move.
+    pub fn index(&self) -> Option<usize> {
+        match *self {
+            ValidationIssue::ClockOverrun { index, .. }
+            | ValidationIssue::TimeUpWithTimeLeft { index, .. }
+            | ValidationIssue::TimeUpWithoutTimeLimit { index, .. }
+            | ValidationIssue::TimeUpAfterFlagFall { index, .. }
+            | ValidationIssue::TimeOffUnit { index, .. }
+            | ValidationIssue::PastMaxMoves { index, .. }
+            | ValidationIssue::EarlyMaxMoves { index, .. } => Some(index),
+            ValidationIssue::BookmarkPastEnd { .. } => None,
+        }
+    }
+
+    /// Returns whether `suppressions` acknowledges the issue.
+    pub fn is_suppressed_by(&self, suppressions: &SuppressionList) -> bool {
+        suppressions.suppresses(self.id(), self.index())
+    }
+
+    /// Returns the issue as displayed, without its id.
+    pub(crate) fn message(&self) -> String {
+        Message(self).to_string()
+    }
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] {}", self.id(), Message(self))
+    }
+}
+
+struct Message<'a>(&'a ValidationIssue);
+
+impl fmt::Display for Message<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self.0 {
+            ValidationIssue::ClockOverrun {
+                index,
+                color,
+                spent,
+                available,
+            } => write!(
+                f,
+                "move {index}: {color} took {spent:?} with only {available:?} left"
+            ),
+            ValidationIssue::TimeUpWithTimeLeft {
+                index,
+                color,
+                spent,
+                available,
+            } => write!(
+                f,
+                "move {index}: %TIME_UP after {color} took {spent:?} with {available:?} left"
+            ),
+            ValidationIssue::TimeUpWithoutTimeLimit { index, color } => {
+                write!(f, "move {index}: %TIME_UP but {color} has no time limit")
+            }
+            ValidationIssue::TimeUpAfterFlagFall { index, color, ply } => write!(
+                f,
+                "move {index}: %TIME_UP but {color} already ran out of time at ply {ply}"
+            ),
+            ValidationIssue::TimeOffUnit { index, time, unit } => write!(
+                f,
+                "move {index}: {time:?} is not a whole number of the time unit {unit}"
+            ),
+            ValidationIssue::PastMaxMoves {
+                index,
+                ply,
+                max_moves,
+            } => write!(
+                f,
+                "move {index}: ply {ply} is played past the limit of {max_moves} moves"
+            ),
+            ValidationIssue::EarlyMaxMoves {
+                index,
+                plies,
+                max_moves,
+            } => write!(
+                f,
+                "move {index}: %MAX_MOVES after {plies} plies, short of the limit of {max_moves}"
+            ),
+            ValidationIssue::BookmarkPastEnd {
+                bookmark,
+                ply,
+                plies,
+            } => write!(
+                f,
+                "bookmark {bookmark}: ply {ply} is past the end of the game at ply {plies}"
+            ),
+        }
+    }
+}
+
+impl GameRecord {
+    /// Replays the clocks of both players and returns them after each move,
+    /// in the order of the moves.
+    ///
+    /// Each player starts with the main time of their time limit, see
+    /// [`GameRecord::time_limit_of`]; the moves of a player without one are
+    /// left out. Once the main time is used up, a move may take up to the
+    /// byoyomi without losing, and taking exactly the byoyomi is fine. A move
+    /// without a `T` counts as taking only the per-move minimum. Special moves
+    /// are left out too.
+    ///
+    /// With a Fischer increment, the increment is added to the main time after
+    /// each move: the time left is the time left before the move, less the
+    /// time spent (never below zero), plus the increment.
+    ///
+    /// A time limit that never runs out, see [`TimeLimit::is_unlimited`],
+    /// puts no move in byoyomi and lets every move take as long as it took.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use csa::parse_csa;
+    ///
+    /// let game = parse_csa(
+    ///     "V2.2\n$TIME_LIMIT:00:01+30\nPI\n+\n+7776FU\nT50\n-3334FU\nT5\n+2726FU\nT30\n",
+    /// )
+    /// .unwrap();
+    /// let clocks = game.clocks();
+    /// assert_eq!(clocks[0].main_time_left, Duration::from_secs(10));
+    /// assert!(clocks[2].in_byoyomi);
+    /// assert_eq!(clocks[2].byoyomi_used, Duration::from_secs(20));
+    /// assert!(!clocks[2].overrun);
+    /// ```
+    pub fn clocks(&self) -> Vec<MoveClock> {
+        let mut main_left = [Color::Black, Color::White]
+            .map(|color| self.time_limit_of(color).map(|limit| limit.main_time));
+        let mut clocks = Vec::new();
+        for (index, record) in self.moves.iter().enumerate() {
+            let Action::Move(color, ..) = record.action else {
+                continue;
+            };
+            let (Some(limit), Some(left)) = (
+                self.time_limit_of(color),
+                main_left[color as usize].as_mut(),
+            ) else {
+                continue;
+            };
+
+            let spent = charged(limit, record.time);
+            let unlimited = limit.is_unlimited();
+            let in_byoyomi = !unlimited && (spent > *left || left.is_zero());
+            let byoyomi_used = if unlimited {
+                Duration::ZERO
+            } else {
+                spent.saturating_sub(*left)
+            };
+            clocks.push(MoveClock {
+                index,
+                color,
+                spent,
+                main_time_left: left.saturating_sub(spent) + limit.increment,
+                in_byoyomi,
+                byoyomi_used,
+                overrun: byoyomi_used > limit.byoyomi,
+            });
+            *left = left.saturating_sub(spent) + limit.increment;
+        }
+        clocks
+    }
+
+    /// Checks the record for inconsistencies and returns what it found.
+    ///
+    /// Moves are checked against the clocks of [`GameRecord::clocks`]. A game
+    /// ending in `%TIME_UP` must not have gone on after a clock ran out, and is
+    /// checked against the clock of the player to move: a `T` recorded on the
+    /// `%TIME_UP` must not fit in the time left, and the player must have a
+    /// time limit that can run out at all. Without a `T`, the last think is unknown and the
+    /// loss on time is taken as recorded.
+    ///
+    /// With a move limit, see [`GameRecord::max_moves`], no move may follow
+    /// the ply reaching it, and a game ending in `%MAX_MOVES` must have
+    /// reached it.
+    ///
+    /// Bookmarks must mark a position the game reaches, and with a
+    /// [`GameRecord::time_unit`], move times must be whole numbers of it.
+    pub fn validate(&self) -> Vec<ValidationIssue> {
+        self.validate_with(&GameRules::default())
+    }
+
+    /// Checks the record as [`GameRecord::validate`] does, with the move
+    /// limit taken from `rules`.
+    pub fn validate_with(&self, rules: &GameRules) -> Vec<ValidationIssue> {
+        let clocks = self.clocks();
+        let mut issues: Vec<ValidationIssue> = clocks
+            .iter()
+            .filter(|clock| clock.overrun)
+            .map(|clock| ValidationIssue::ClockOverrun {
+                index: clock.index,
+                color: clock.color,
+                spent: clock.spent,
+                available: clock.spent - clock.byoyomi_used + self.byoyomi_of(clock.color),
+            })
+            .collect();
+
+        if let Some(unit) = self.time_unit {
+            let unit_millis = unit.duration().as_millis();
+            for (index, record) in self.moves.iter().enumerate() {
+                match record.time {
+                    Some(time) if time.as_millis() % unit_millis != 0 => {
+                        issues.push(ValidationIssue::TimeOffUnit { index, time, unit });
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if let Some(issue) = self.check_time_up(&clocks) {
+            issues.push(issue);
+        }
+        if let Some(max_moves) = rules.max_moves_of(self) {
+            issues.extend(self.check_max_moves(max_moves));
+        }
+        let plies = self.ply_count();
+        for (bookmark, b) in self.bookmarks.iter().enumerate() {
+            if b.ply > plies {
+                issues.push(ValidationIssue::BookmarkPastEnd {
+                    bookmark,
+                    ply: b.ply,
+                    plies,
+                });
+            }
+        }
+        issues
+    }
+
+    fn check_max_moves(&self, max_moves: usize) -> Option<ValidationIssue> {
+        if let Some(index) = self.index_of_ply(max_moves + 1) {
+            return Some(ValidationIssue::PastMaxMoves {
+                index,
+                ply: max_moves + 1,
+                max_moves,
+            });
+        }
+        let index = self.moves.len().checked_sub(1)?;
+        let plies = self.ply_count();
+        (self.moves[index].action == Action::MaxMoves && plies < max_moves).then_some(
+            ValidationIssue::EarlyMaxMoves {
+                index,
+                plies,
+                max_moves,
+            },
+        )
+    }
+
+    fn check_time_up(&self, clocks: &[MoveClock]) -> Option<ValidationIssue> {
+        let index = self.moves.len().checked_sub(1)?;
+        let last = &self.moves[index];
+        if last.action != Action::TimeUp {
+            return None;
+        }
+        if let Some(clock) = clocks.iter().find(|clock| clock.overrun) {
+            return Some(ValidationIssue::TimeUpAfterFlagFall {
+                index,
+                color: clock.color,
+                ply: self.ply_of(clock.index)?,
+            });
+        }
+
+        let color = self
+            .moves
+            .iter()
+            .rev()
+            .find_map(|m| match m.action {
+                Action::Move(color, ..) => Some(flip(color)),
+                _ => None,
+            })
+            .unwrap_or(self.start_pos.side_to_move);
+        let Some(limit) = self
+            .time_limit_of(color)
+            .filter(|limit| !limit.is_unlimited())
+        else {
+            return Some(ValidationIssue::TimeUpWithoutTimeLimit { index, color });
+        };
+
+        let main_left = clocks
+            .iter()
+            .rev()
+            .find(|clock| clock.color == color)
+            .map_or(limit.main_time, |clock| clock.main_time_left);
+        let available = main_left + limit.byoyomi;
+        let spent = charged(limit, Some(last.time?));
+        (spent <= available).then_some(ValidationIssue::TimeUpWithTimeLeft {
+            index,
+            color,
+            spent,
+            available,
+        })
+    }
+
+    fn byoyomi_of(&self, color: Color) -> Duration {
+        self.time_limit_of(color)
+            .map_or(Duration::ZERO, |limit| limit.byoyomi)
+    }
+}
+
+/// Returns the time charged for a move that took `time`.
+pub(crate) fn charged(limit: &TimeLimit, time: Option<Duration>) -> Duration {
+    time.unwrap_or_default().max(limit.least_time_per_move)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_csa;
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    fn byoyomi_accounting() {
+        let game = parse_csa(
+            "V2.2\n$TIME_LIMIT:00:01+30\nPI\n+\n\
+             +7776FU\nT40\n-3334FU\nT70\n+2726FU\nT20\n-8384FU\nT30\n+2625FU\nT30\n-8485FU\nT31\n",
+        )
+        .unwrap();
+        let clocks = game.clocks();
+        let summary: Vec<_> = clocks
+            .iter()
+            .map(|c| (c.main_time_left, c.in_byoyomi, c.byoyomi_used, c.overrun))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (secs(20), false, secs(0), false),
+                // Main time runs out during the move.
+                (secs(0), true, secs(10), false),
+                // Exactly the main time left.
+                (secs(0), false, secs(0), false),
+                // The full byoyomi.
+                (secs(0), true, secs(30), false),
+                (secs(0), true, secs(30), false),
+                (secs(0), true, secs(31), true),
+            ]
+        );
+        assert_eq!(
+            game.validate(),
+            [ValidationIssue::ClockOverrun {
+                index: 5,
+                color: Color::White,
+                spent: secs(31),
+                available: secs(30),
+            }]
+        );
+
+        // No time limit at all: the clock never runs out.
+        let game =
+            parse_csa("V2.2\n$TIME_LIMIT:00:00+00\nPI\n+\n+7776FU\nT5\n-3334FU\nT7\n").unwrap();
+        assert!(game.time_limit().unwrap().is_unlimited());
+        let summary: Vec<_> = game
+            .clocks()
+            .iter()
+            .map(|c| (c.spent, c.in_byoyomi, c.byoyomi_used, c.overrun))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (secs(5), false, secs(0), false),
+                (secs(7), false, secs(0), false)
+            ]
+        );
+        assert!(game.validate().is_empty());
+        assert_eq!(game.stats().first_flag_fall, [None, None]);
+        let game =
+            parse_csa("V2.2\n$TIME_LIMIT:00:00+00\nPI\n+\n+7776FU\nT5\n-3334FU\nT7\n%TIME_UP\n")
+                .unwrap();
+        assert_eq!(
+            game.validate(),
+            [ValidationIssue::TimeUpWithoutTimeLimit {
+                index: 2,
+                color: Color::Black,
+            }]
+        );
+    }
+
+    #[test]
+    fn clocks_per_player() {
+        let mut game =
+            parse_csa("V2.2\nPI\n+\n+7776FU\nT5\n%MATTA\n-3334FU\nT9\n+2726FU\n").unwrap();
+        assert!(game.clocks().is_empty());
+
+        game.time_limit_black = Some(TimeLimit {
+            main_time: secs(60),
+            least_time_per_move: secs(1),
+            ..TimeLimit::default()
+        });
+        let clocks = game.clocks();
+        assert_eq!(clocks.len(), 2);
+        assert_eq!((clocks[0].index, clocks[0].spent), (0, secs(5)));
+        // No `T`: only the per-move minimum.
+        assert_eq!((clocks[1].index, clocks[1].spent), (3, secs(1)));
+        assert_eq!(clocks[1].main_time_left, secs(54));
+        assert!(game.validate().is_empty());
+    }
+
+    #[test]
+    fn fischer_increment() {
+        let game = parse_csa(include_str!("../fixtures/v3_fischer.csa")).unwrap();
+        assert_eq!(game.time_limit().unwrap().increment, secs(5));
+
+        let clocks = game.clocks();
+        assert_eq!(clocks.len(), 11);
+        let left = |color| {
+            clocks
+                .iter()
+                .filter(|c| c.color == color)
+                .map(|c| c.main_time_left)
+                .collect::<Vec<_>>()
+        };
+        // 600 - 120 + 5, - 200 + 5, - 290 + 5, - 5 + 5, - 3 + 5, - 2 + 5
+        assert_eq!(
+            left(Color::Black),
+            [secs(485), secs(290), secs(5), secs(5), secs(7), secs(10)]
+        );
+        // 600 - 300 + 5, - 304 + 5, - 6 + 5, - 4.5 + 5, - 5.5 + 5
+        assert_eq!(
+            left(Color::White),
+            [
+                secs(305),
+                secs(6),
+                secs(5),
+                Duration::from_millis(5500),
+                secs(5)
+            ]
+        );
+        assert!(clocks.iter().all(|c| !c.in_byoyomi));
+        assert!(game.validate().is_empty());
+    }
+
+    #[test]
+    fn increment_after_the_move() {
+        // The increment cannot save a move that overruns the clock.
+        let game = parse_csa("V3.0\n$TIME:10+0+5\nPI\n+\n+7776FU\nT11\n-3334FU\nT1\n+2726FU\nT4\n")
+            .unwrap();
+        let clocks = game.clocks();
+        assert!(clocks[0].overrun);
+        assert_eq!(clocks[0].main_time_left, secs(5));
+        assert!(!clocks[2].overrun);
+        assert_eq!(clocks[2].main_time_left, secs(6));
+    }
+
+    #[test]
+    fn time_up_after_flag_fall() {
+        let game = parse_csa(
+            "V2.2\n$TIME_LIMIT:00:01+10\nPI\n+\n+7776FU\nT71\n-3334FU\nT1\n+2726FU\nT1\n%TIME_UP\n",
+        )
+        .unwrap();
+        let issues = game.validate();
+        assert_eq!(issues.len(), 2);
+        assert_eq!(
+            issues[1],
+            ValidationIssue::TimeUpAfterFlagFall {
+                index: 3,
+                color: Color::Black,
+                ply: 1,
+            }
+        );
+        assert_eq!(
+            issues[1].to_string(),
+            "[KIFU-CLOCK-004] move 3: %TIME_UP but + already ran out of time at ply 1"
+        );
+    }
+
+    #[test]
+    fn time_up_cross_check() {
+        let base = "V2.2\n$TIME_LIMIT:00:01+10\nPI\n+\n+7776FU\nT50\n-3334FU\nT1\n";
+        let game = parse_csa(&format!("{base}%TIME_UP\nT25\n")).unwrap();
+        assert!(game.validate().is_empty());
+
+        let game = parse_csa(&format!("{base}%TIME_UP\nT20\n")).unwrap();
+        assert_eq!(
+            game.validate(),
+            [ValidationIssue::TimeUpWithTimeLeft {
+                index: 2,
+                color: Color::Black,
+                spent: secs(20),
+                available: secs(20),
+            }]
+        );
+
+        // Nothing to check without a `T`.
+        let game = parse_csa(&format!("{base}%TIME_UP\n")).unwrap();
+        assert!(game.validate().is_empty());
+
+        let game = parse_csa("V2.2\nPI\n-\n%TIME_UP\n").unwrap();
+        assert_eq!(
+            game.validate(),
+            [ValidationIssue::TimeUpWithoutTimeLimit {
+                index: 0,
+                color: Color::White,
+            }]
+        );
+        assert_eq!(
+            game.validate()[0].to_string(),
+            "[KIFU-CLOCK-003] move 0: %TIME_UP but - has no time limit"
+        );
+    }
+
+    #[test]
+    fn bookmark_past_end() {
+        let mut game = parse_csa("V2.2\nPI\n+\n+7776FU\n'&opening\n").unwrap();
+        assert!(game.validate().is_empty());
+        game.bookmarks[0].ply = 3;
+        assert_eq!(
+            game.validate(),
+            [ValidationIssue::BookmarkPastEnd {
+                bookmark: 0,
+                ply: 3,
+                plies: 1,
+            }]
+        );
+        assert_eq!(
+            game.validate()[0].to_string(),
+            "[KIFU-META-003] bookmark 0: ply 3 is past the end of the game at ply 1"
+        );
+    }
+
+    #[test]
+    fn time_off_unit() {
+        let game = parse_csa("V3.0\n'Time_Unit:1min\nPI\n+\n+7776FU\nT2\n-3334FU\nT1.5\n").unwrap();
+        assert_eq!(
+            game.validate(),
+            [ValidationIssue::TimeOffUnit {
+                index: 1,
+                time: Duration::from_secs(90),
+                unit: TimeUnit::parse("1min").unwrap(),
+            }]
+        );
+        assert_eq!(
+            game.validate()[0].to_string(),
+            "[KIFU-CLOCK-005] move 1: 90s is not a whole number of the time unit 1min"
+        );
+    }
+}
@@ -0,0 +1,168 @@
+//! One error type for everything the crate can fail with.
+
+use std::fmt;
+
+#[cfg(feature = "binary")]
+use crate::binary::BinaryError;
+use crate::board::BoardError;
+use crate::parser::csa::{self, v2, v2_1, v2_2, v3};
+use crate::parser::{CsaError, CustomSpecialError, EventConventionError};
+use crate::value::{PromotionError, SquareError, WriteError};
+
+/// Any error of this crate, for callers that handle them alike.
+///
+/// The parse entry points such as [`parse_csa`](crate::parse_csa) return it,
+/// and every narrower error converts into it with `?`, including the errors
+/// of the version parsers, which all become [`Error::Csa`]. Match on the
+/// variant to get the narrow error back.
+///
+/// ```
+/// use csa::{parse_csa, CsaError, Error};
+///
+/// fn plies(csa: &str) -> Result<usize, Error> {
+///     let game = csa::parser::csa::v2_2::parse(csa)?;
+///     Ok(game.position_hashes()?.len() - 1)
+/// }
+///
+/// assert_eq!(plies("V2.2\nPI\n+\n+7776FU\n").unwrap(), 1);
+/// assert!(matches!(plies("V2.2\nPI\n+\n-3334FU\n"), Err(Error::Board(_))));
+/// assert!(matches!(parse_csa("PI\n"), Err(Error::Csa(CsaError::ParseError(_)))));
+/// ```
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The input could not be read as CSA.
+    Csa(CsaError),
+    /// A position could not be set up or a move played.
+    Board(BoardError),
+    Promotion(PromotionError),
+    Square(SquareError),
+    /// A custom special move could not be registered.
+    CustomSpecial(CustomSpecialError),
+    /// An event line could not be registered.
+    EventConvention(EventConventionError),
+    /// A record could not be written the way the options asked.
+    Write(WriteError),
+    /// A record could not be decoded from bytes.
+    #[cfg(feature = "binary")]
+    Binary(BinaryError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Csa(e) => e.fmt(f),
+            Error::Board(e) => e.fmt(f),
+            Error::Promotion(e) => e.fmt(f),
+            Error::Square(e) => e.fmt(f),
+            Error::CustomSpecial(e) => e.fmt(f),
+            Error::EventConvention(e) => e.fmt(f),
+            Error::Write(e) => e.fmt(f),
+            #[cfg(feature = "binary")]
+            Error::Binary(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Csa(e) => Some(e),
+            Error::Board(e) => Some(e),
+            Error::Promotion(e) => Some(e),
+            Error::Square(e) => Some(e),
+            Error::CustomSpecial(e) => Some(e),
+            Error::EventConvention(e) => Some(e),
+            Error::Write(e) => Some(e),
+            #[cfg(feature = "binary")]
+            Error::Binary(e) => Some(e),
+        }
+    }
+}
+
+impl From<CsaError> for Error {
+    fn from(e: CsaError) -> Error {
+        Error::Csa(e)
+    }
+}
+
+impl From<BoardError> for Error {
+    fn from(e: BoardError) -> Error {
+        Error::Board(e)
+    }
+}
+
+impl From<PromotionError> for Error {
+    fn from(e: PromotionError) -> Error {
+        Error::Promotion(e)
+    }
+}
+
+impl From<SquareError> for Error {
+    fn from(e: SquareError) -> Error {
+        Error::Square(e)
+    }
+}
+
+impl From<CustomSpecialError> for Error {
+    fn from(e: CustomSpecialError) -> Error {
+        Error::CustomSpecial(e)
+    }
+}
+
+impl From<EventConventionError> for Error {
+    fn from(e: EventConventionError) -> Error {
+        Error::EventConvention(e)
+    }
+}
+
+impl From<WriteError> for Error {
+    fn from(e: WriteError) -> Error {
+        Error::Write(e)
+    }
+}
+
+#[cfg(feature = "binary")]
+impl From<BinaryError> for Error {
+    fn from(e: BinaryError) -> Error {
+        Error::Binary(e)
+    }
+}
+
+/// The version parsers each have their own `ParseError`, all a message.
+macro_rules! from_parse_error {
+    ($($module:ident),*) => {$(
+        impl From<$module::ParseError> for Error {
+            fn from(e: $module::ParseError) -> Error {
+                Error::Csa(CsaError::ParseError(e.0))
+            }
+        }
+    )*};
+}
+
+from_parse_error!(csa, v2, v2_1, v2_2, v3);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as _;
+
+    #[test]
+    fn conversions() {
+        let input = "V3.0\nPI\n+\n+7776F\n";
+        let narrow = v3::parse(input).unwrap_err();
+        let error = Error::from(narrow);
+        assert_eq!(
+            error.to_string(),
+            crate::parse_csa(input).unwrap_err().to_string()
+        );
+        assert!(error.source().unwrap().is::<CsaError>());
+
+        let error: Error = csa::parse(input).unwrap_err().into();
+        assert!(matches!(error, Error::Csa(CsaError::ParseError(_))));
+
+        let error: Error = BoardError::InvalidPosition("no king".to_string()).into();
+        assert_eq!(error.to_string(), "invalid position: no king");
+        assert!(error.source().unwrap().is::<BoardError>());
+    }
+}
@@ -0,0 +1,248 @@
+//! A flat, chronological view of a game, for spectating frontends.
+
+use std::time::Duration;
+
+use crate::value::{Action, Color, GameRecord, InterMoveEvent, TimeLimit};
+
+/// One entry of [`GameRecord::events`].
+///
+/// With the `serde` feature, events serialize with their variant name in a
+/// `type` field, e.g. `{"type":"GameEnd","result":"Toryo"}`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "type")
+)]
+pub enum KifuEvent {
+    /// Always the first event.
+    GameStart {
+        black_player: Option<String>,
+        white_player: Option<String>,
+        /// Each side's time limit, indexed by `Color as usize`.
+        time_limits: [Option<TimeLimit>; 2],
+    },
+    /// A move played, or a `%MATTA` taking one back.
+    Move {
+        /// The ply number, see [`GameRecord::ply_of`]; `None` for `%MATTA`.
+        ply: Option<usize>,
+        action: Action,
+        /// The time charged for the move, see [`crate::MoveClock::spent`],
+        /// or the recorded `T` when the player has no time limit.
+        spent: Option<Duration>,
+        /// The main time the player has left after the move, see
+        /// [`crate::MoveClock::main_time_left`].
+        remaining: Option<Duration>,
+    },
+    /// The game was adjourned with `%CHUDAN`, see [`crate::Adjournment`].
+    Adjourn {
+        sealed_move: Option<Action>,
+        /// When play resumes, written as in CSA, e.g. `2024/01/06 10:00:00`.
+        resume_time: Option<String>,
+        continuation: Option<String>,
+    },
+    /// The game ended with the special move `result`.
+    GameEnd { result: Action },
+    /// Something noted between moves, such as a draw offer, from
+    /// [`crate::MoveRecord::events`].
+    InterMove {
+        /// The number of plies played when it happened.
+        ply: usize,
+        event: InterMoveEvent,
+    },
+}
+
+impl GameRecord {
+    /// Returns the game as a flat list of events in the order they happened:
+    /// a [`KifuEvent::GameStart`], a [`KifuEvent::Move`] for each move, and
+    /// for the special move ending the game, if any, a [`KifuEvent::GameEnd`],
+    /// or a [`KifuEvent::Adjourn`] for `%CHUDAN`. The events noted after a
+    /// move follow its event as [`KifuEvent::InterMove`]s. Times come from
+    /// [`GameRecord::clocks`].
+    ///
+    /// Comments are not kept in a `GameRecord` and have no event.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use csa::{parse_csa, Action, KifuEvent};
+    ///
+    /// let game = parse_csa("V2.2\n$TIME_LIMIT:00:10+00\nPI\n+\n+7776FU\nT12\n%TORYO\n").unwrap();
+    /// let events = game.events();
+    /// assert_eq!(events.len(), 3);
+    /// assert!(matches!(
+    ///     events[1],
+    ///     KifuEvent::Move { ply: Some(1), remaining: Some(left), .. }
+    ///         if left == Duration::from_secs(588)
+    /// ));
+    /// assert_eq!(events[2], KifuEvent::GameEnd { result: Action::Toryo });
+    /// ```
+    pub fn events(&self) -> Vec<KifuEvent> {
+        let mut events = vec![KifuEvent::GameStart {
+            black_player: self.black_player.as_deref().map(str::to_string),
+            white_player: self.white_player.as_deref().map(str::to_string),
+            time_limits: [Color::Black, Color::White]
+                .map(|color| self.time_limit_of(color).cloned()),
+        }];
+
+        let mut clocks = self.clocks().into_iter().peekable();
+        let mut plies = 0;
+        for (index, record) in self.moves.iter().enumerate() {
+            let event = match record.action {
+                Action::Chudan => {
+                    let adjournment = self.adjournment.clone().unwrap_or_default();
+                    KifuEvent::Adjourn {
+                        sealed_move: adjournment.sealed_move,
+                        resume_time: adjournment.resume_time.map(|time| time.to_string()),
+                        continuation: adjournment.continuation,
+                    }
+                }
+                action if action.is_terminal() => KifuEvent::GameEnd { result: action },
+                action => {
+                    let clock = clocks.next_if(|clock| clock.index == index);
+                    KifuEvent::Move {
+                        ply: self.ply_of(index),
+                        action,
+                        spent: clock.map(|clock| clock.spent).or(record.time),
+                        remaining: clock.map(|clock| clock.main_time_left),
+                    }
+                }
+            };
+            events.push(event);
+            if let Action::Move(..) = record.action {
+                plies += 1;
+            }
+            events.extend(
+                record
+                    .events
+                    .iter()
+                    .map(|&event| KifuEvent::InterMove { ply: plies, event }),
+            );
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_csa;
+    use crate::value::{PieceType, Square};
+
+    #[test]
+    fn events() {
+        let game = parse_csa(
+            "V2.2\nN+A\n$TIME_LIMIT:00:01+10\nPI\n+\n+7776FU\nT50\n-3334FU\nT5\n%MATTA\n-8384FU\n%CHUDAN\n'CHUDAN:SEALED_MOVE:+2726FU\n'CHUDAN:RESUME_TIME:2024/01/06 10:00\n",
+        )
+        .unwrap();
+        let limit = game.time_limit().cloned();
+        assert_eq!(
+            game.events(),
+            [
+                KifuEvent::GameStart {
+                    black_player: Some("A".to_string()),
+                    white_player: None,
+                    time_limits: [limit.clone(), limit],
+                },
+                KifuEvent::Move {
+                    ply: Some(1),
+                    action: Action::Move(
+                        Color::Black,
+                        Square::new(7, 7),
+                        Square::new(7, 6),
+                        PieceType::Pawn
+                    ),
+                    spent: Some(Duration::from_secs(50)),
+                    remaining: Some(Duration::from_secs(10)),
+                },
+                KifuEvent::Move {
+                    ply: Some(2),
+                    action: Action::Move(
+                        Color::White,
+                        Square::new(3, 3),
+                        Square::new(3, 4),
+                        PieceType::Pawn
+                    ),
+                    spent: Some(Duration::from_secs(5)),
+                    remaining: Some(Duration::from_secs(55)),
+                },
+                KifuEvent::Move {
+                    ply: None,
+                    action: Action::Matta,
+                    spent: None,
+                    remaining: None,
+                },
+                KifuEvent::Move {
+                    ply: Some(3),
+                    action: Action::Move(
+                        Color::White,
+                        Square::new(8, 3),
+                        Square::new(8, 4),
+                        PieceType::Pawn
+                    ),
+                    spent: Some(Duration::ZERO),
+                    remaining: Some(Duration::from_secs(55)),
+                },
+                KifuEvent::Adjourn {
+                    sealed_move: Action::parse("+2726FU"),
+                    resume_time: Some("2024/01/06 10:00:00".to_string()),
+                    continuation: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn no_time_limit() {
+        let game = parse_csa("V2.2\nPI\n+\n+7776FU\nT3\n").unwrap();
+        let events = game.events();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(
+            events[1],
+            KifuEvent::Move {
+                spent: Some(spent),
+                remaining: None,
+                ..
+            } if spent == Duration::from_secs(3)
+        ));
+    }
+
+    #[test]
+    fn inter_move_events() {
+        let convention = crate::EventConvention::standard();
+        let options = crate::ParseOptions {
+            event_convention: Some(&convention),
+            ..crate::ParseOptions::default()
+        };
+        let csa = "V2.2\nPI\n+\n+7776FU\n'-DRAW_OFFER\n%TORYO\n'PAUSE\n";
+        let game = crate::parse_csa_with_options(csa, &options).unwrap().record;
+        let events = game.events();
+        assert_eq!(
+            events[2..],
+            [
+                KifuEvent::InterMove {
+                    ply: 1,
+                    event: InterMoveEvent::DrawOffered(Color::White),
+                },
+                KifuEvent::GameEnd {
+                    result: Action::Toryo
+                },
+                KifuEvent::InterMove {
+                    ply: 1,
+                    event: InterMoveEvent::Pause,
+                },
+            ]
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize() {
+        let game = parse_csa("V2.2\nPI\n+\n+7776FU\n%TORYO\n").unwrap();
+        let events = game.events();
+        let json = serde_json::to_string(&events[2]).unwrap();
+        assert_eq!(json, r#"{"type":"GameEnd","result":"Toryo"}"#);
+        let json = serde_json::to_string(&events).unwrap();
+        let back: Vec<KifuEvent> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, events);
+    }
+}
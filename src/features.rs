@@ -0,0 +1,277 @@
+//! Piece values and material, and per-ply features of a game for statistics
+//! and machine learning.
+
+use crate::board::{flip, Board, BoardError, HAND_PIECES};
+use crate::value::{Action, Color, GameRecord, PieceType, Square};
+
+/// The value of each piece type, used to weigh material, see
+/// [`Board::material`]. The default is [`PieceValues::STANDARD`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct PieceValues([i32; 14]);
+
+impl Default for PieceValues {
+    fn default() -> Self {
+        PieceValues::STANDARD
+    }
+}
+
+impl PieceValues {
+    /// The values commonly taught to beginners: pawn 1, lance 3, knight 4,
+    /// silver 5, gold 6, bishop 8, rook 10, tokin 7, promoted lance, knight
+    /// and silver 6, horse 10, dragon 12, and 0 for the king.
+    pub const STANDARD: PieceValues = PieceValues([1, 3, 4, 5, 6, 8, 10, 0, 7, 6, 6, 6, 10, 12]);
+
+    /// The points counted when a king enters the opponent's camp: 5 for a
+    /// rook or bishop, promoted or not, 1 for any other piece but the king,
+    /// as in the 24-point rule and the 27-point `%KACHI` declaration.
+    pub const IMPASSE: PieceValues = PieceValues([1, 1, 1, 1, 1, 5, 5, 0, 1, 1, 1, 1, 5, 5]);
+
+    /// Makes a table from the values of `FU`, `KY`, `KE`, `GI`, `KI`, `KA`,
+    /// `HI`, `OU`, `TO`, `NY`, `NK`, `NG`, `UM` and `RY`, in that order.
+    pub const fn new(values: [i32; 14]) -> PieceValues {
+        PieceValues(values)
+    }
+
+    /// Returns the value of `pt`. `AL` is worth nothing.
+    pub fn get(&self, pt: PieceType) -> i32 {
+        self.0.get(pt as usize).copied().unwrap_or(0)
+    }
+
+    /// Sets the value of `pt`. Setting the value of `AL` does nothing.
+    pub fn set(&mut self, pt: PieceType, value: i32) {
+        if let Some(slot) = self.0.get_mut(pt as usize) {
+            *slot = value;
+        }
+    }
+}
+
+/// Figures describing one position of a game, see [`GameRecord::features`].
+///
+/// Per-player figures are indexed by `Color as usize`, Black first.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PlyFeatures {
+    /// The number of plies played to reach the position, 0 for the starting
+    /// position.
+    pub ply: usize,
+    pub side_to_move: Color,
+    /// The number of legal moves of the side to move, see
+    /// [`Board::legal_moves`].
+    pub legal_moves: usize,
+    /// The number of the opponent's pieces attacking each side's king, 0 for
+    /// a side without a king.
+    pub king_attackers: [u8; 2],
+    /// Black's material minus White's, pieces in hand included.
+    pub material: i32,
+    /// The number of each side's pieces standing in the zone where they
+    /// promote.
+    pub in_promotion_zone: [u8; 2],
+}
+
+impl PlyFeatures {
+    fn of(board: &Board, ply: usize, values: &PieceValues) -> PlyFeatures {
+        let mut features = PlyFeatures {
+            ply,
+            side_to_move: board.side_to_move(),
+            legal_moves: board.legal_moves().len(),
+            king_attackers: [0; 2],
+            material: 0,
+            in_promotion_zone: [0; 2],
+        };
+
+        let (files, ranks) = board.dimensions();
+        for file in 1..=files {
+            for rank in 1..=ranks {
+                let sq = Square::new(file, rank);
+                let Some((color, pt)) = board.piece_at(sq) else {
+                    continue;
+                };
+                if board.in_zone(color, sq) {
+                    features.in_promotion_zone[color as usize] += 1;
+                }
+                if pt == PieceType::King {
+                    let attackers = board.attackers_of(sq, flip(color)).len();
+                    features.king_attackers[color as usize] += attackers as u8;
+                }
+            }
+        }
+
+        features.material = board.material_balance(values);
+        features
+    }
+}
+
+impl Board {
+    /// Returns the value of `color`'s pieces, on the board and in hand,
+    /// weighed by `values`. Promoted pieces on the board count at their
+    /// promoted value.
+    ///
+    /// ```
+    /// use csa::{Board, Color, PieceValues, Position};
+    ///
+    /// let board = Board::from_position(&Position::default()).unwrap();
+    /// let material = board.material(Color::Black, &PieceValues::IMPASSE);
+    /// assert_eq!(material, 9 + 2 * 4 + 5 + 5);
+    /// ```
+    pub fn material(&self, color: Color, values: &PieceValues) -> i32 {
+        let (files, ranks) = self.dimensions();
+        let mut material = 0;
+        for file in 1..=files {
+            for rank in 1..=ranks {
+                if let Some((owner, pt)) = self.piece_at(Square::new(file, rank)) {
+                    if owner == color {
+                        material += values.get(pt);
+                    }
+                }
+            }
+        }
+        for pt in HAND_PIECES {
+            material += i32::from(self.hand(color, pt)) * values.get(pt);
+        }
+        material
+    }
+
+    /// Returns Black's material minus White's, see [`Board::material`].
+    pub fn material_balance(&self, values: &PieceValues) -> i32 {
+        self.material(Color::Black, values) - self.material(Color::White, values)
+    }
+}
+
+impl GameRecord {
+    /// Replays the game and returns the [`PlyFeatures`] of the starting
+    /// position followed by those of the position after each ply, so that
+    /// `features[n]` describes the position after ply `n`. Special moves do
+    /// not change the position and add no entry.
+    ///
+    /// ```
+    /// use csa::{parse_csa, PieceValues};
+    ///
+    /// let game = parse_csa("V2.2\nPI\n+\n+7776FU\n-3334FU\n+8822UM\n").unwrap();
+    /// let features = game.features(&PieceValues::default()).unwrap();
+    /// assert_eq!(features.len(), 4);
+    /// assert_eq!(features[0].legal_moves, 30);
+    /// // Black has taken a bishop and promoted its own.
+    /// assert_eq!(features[3].material, 8 + 8 + (10 - 8));
+    /// ```
+    pub fn features(&self, values: &PieceValues) -> Result<Vec<PlyFeatures>, BoardError> {
+        let mut board = self.start_board()?;
+        let mut features = vec![PlyFeatures::of(&board, 0, values)];
+        for record in &self.moves {
+            if let Action::Move(..) = record.action {
+                board.apply(&record.action)?;
+                features.push(PlyFeatures::of(&board, features.len(), values));
+            }
+        }
+        Ok(features)
+    }
+
+    /// Replays the game and returns Black's material minus White's, see
+    /// [`Board::material_balance`], for the starting position and after each
+    /// ply, indexed as [`GameRecord::features`].
+    ///
+    /// ```
+    /// use csa::{parse_csa, PieceValues};
+    ///
+    /// let game = parse_csa("V2.2\nPI\n+\n+7776FU\n-3334FU\n+8822UM\n-3122GI\n").unwrap();
+    /// let series = game.material_series(&PieceValues::STANDARD).unwrap();
+    /// assert_eq!(series, [0, 0, 0, 18, 0]);
+    /// ```
+    pub fn material_series(&self, values: &PieceValues) -> Result<Vec<i32>, BoardError> {
+        let mut board = self.start_board()?;
+        let mut series = vec![board.material_balance(values)];
+        for record in &self.moves {
+            if let Action::Move(..) = record.action {
+                board.apply(&record.action)?;
+                series.push(board.material_balance(values));
+            }
+        }
+        Ok(series)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_csa;
+
+    #[test]
+    fn features() {
+        let game = parse_csa("V2.2\nPI\n+\n+7776FU\n-3334FU\n+8822UM\n-3122GI\n+0045KA\n%TORYO\n")
+            .unwrap();
+        let features = game.features(&PieceValues::default()).unwrap();
+        assert_eq!(features.len(), 6);
+        assert_eq!(
+            features[0],
+            PlyFeatures {
+                ply: 0,
+                side_to_move: Color::Black,
+                legal_moves: 30,
+                king_attackers: [0, 0],
+                material: 0,
+                in_promotion_zone: [0, 0],
+            }
+        );
+        // +8822UM: a horse in White's camp, White a bishop down.
+        assert_eq!(features[3].side_to_move, Color::White);
+        assert_eq!(features[3].material, 18);
+        assert_eq!(features[3].in_promotion_zone, [1, 0]);
+        // -3122GI takes the horse back.
+        assert_eq!(features[4].material, 0);
+        assert_eq!(features[4].in_promotion_zone, [0, 0]);
+
+        let mut values = PieceValues::default();
+        values.set(PieceType::Bishop, 9);
+        values.set(PieceType::All, 100);
+        assert_eq!(values.get(PieceType::All), 0);
+        let features = game.features(&values).unwrap();
+        assert_eq!(features[4].material, 0);
+        assert_eq!(features[3].material, 9 + 9 + (10 - 9));
+    }
+
+    #[test]
+    fn king_attackers() {
+        // Black's gold and rook both attack White's king.
+        let game = parse_csa("V2.2\nP-51OU\nP+52KI11HI\nP+00AL\n-\n").unwrap();
+        let features = game.features(&PieceValues::default()).unwrap();
+        assert_eq!(features[0].king_attackers, [0, 2]);
+        assert_eq!(features[0].legal_moves, 1);
+    }
+
+    #[test]
+    fn material() {
+        // Black: dragon and gold on the board, a rook and two pawns in hand.
+        let game = parse_csa("V2.2\nP-51OU\nP+59OU28RY52KI\nP+00HI00FU00FU\nP-00KA\n+\n").unwrap();
+        let board = game.start_board().unwrap();
+        assert_eq!(
+            board.material(Color::Black, &PieceValues::STANDARD),
+            12 + 6 + 10 + 2
+        );
+        assert_eq!(board.material(Color::White, &PieceValues::STANDARD), 8);
+        assert_eq!(
+            board.material(Color::Black, &PieceValues::IMPASSE),
+            5 + 1 + 5 + 2
+        );
+        assert_eq!(board.material_balance(&PieceValues::IMPASSE), 13 - 5);
+
+        let mut values = PieceValues::new([1, 0, 0, 0, 0, 0, 0, 100, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(board.material(Color::Black, &values), 100 + 2);
+        values.set(PieceType::Dragon, 7);
+        assert_eq!(board.material(Color::Black, &values), 100 + 2 + 7);
+        assert_eq!(PieceValues::default(), PieceValues::STANDARD);
+        assert_eq!(game.material_series(&values).unwrap(), [109 - 100]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize() {
+        let game = parse_csa("V2.2\nPI\n+\n+7776FU\n").unwrap();
+        let features = game.features(&PieceValues::default()).unwrap();
+        let json = serde_json::to_string(&features[1]).unwrap();
+        assert_eq!(
+            json,
+            r#"{"ply":1,"side_to_move":"White","legal_moves":30,"king_attackers":[0,0],"material":0,"in_promotion_zone":[0,0]}"#
+        );
+        let back: PlyFeatures = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, features[1]);
+    }
+}
@@ -0,0 +1,307 @@
+//! Selecting records from a collection.
+
+use std::ops::Not;
+
+use time::Date as NativeDate;
+
+use crate::names::NameNormalizer;
+use crate::value::{Action, Color, GameRecord, PieceType, Position, Square};
+
+/// A condition on a [`GameRecord`], checked with [`RecordFilter::matches`].
+///
+/// Filters combine with [`RecordFilter::and`], [`RecordFilter::or`] and `!`:
+///
+/// ```
+/// use csa::{parse_csa, Action, RecordFilter};
+///
+/// let game = parse_csa("V2.2\nN+Habu\nN-Hoshi\nPI\n+\n+7776FU\n-3334FU\n%TORYO\n").unwrap();
+/// let filter = RecordFilter::Player("Habu".to_string())
+///     .and(RecordFilter::EndsWith(Action::Toryo))
+///     .and(!RecordFilter::Plies { min: Some(100), max: None });
+/// assert!(filter.matches(&game));
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum RecordFilter {
+    /// Black's or White's name contains the text, as parsed from `N+`/`N-`,
+    /// or one of their aliases does (see [`GameRecord::add_player_alias`]).
+    Player(String),
+    /// The event is exactly the text.
+    Event(String),
+    /// The game was played between the two dates, both included. The date is
+    /// that of `$START_TIME`, or of `$END_TIME` when the start is missing;
+    /// the time of day is not looked at. Records with neither date match
+    /// when `undated` is set.
+    Date {
+        from: Option<NativeDate>,
+        to: Option<NativeDate>,
+        undated: bool,
+    },
+    /// The game ends with the given special move, such as `%TORYO`.
+    EndsWith(Action),
+    /// The number of plies played lies between the bounds, both included.
+    Plies {
+        min: Option<usize>,
+        max: Option<usize>,
+    },
+    /// The game starts on a 9x9 board from the standard position with the
+    /// given pieces removed, in any order. An empty list matches even games.
+    Handicap(Vec<(Square, PieceType)>),
+    /// The game is played on a board of the given `(files, ranks)`, see
+    /// [`Position::dimensions`].
+    BoardSize(u8, u8),
+    /// Every filter matches. An empty list matches every record.
+    All(Vec<RecordFilter>),
+    /// Some filter matches. An empty list matches no record.
+    Any(Vec<RecordFilter>),
+    /// The filter does not match.
+    Not(Box<RecordFilter>),
+}
+
+impl RecordFilter {
+    /// Returns whether `record` satisfies the filter. Player names are
+    /// compared as written.
+    pub fn matches(&self, record: &GameRecord) -> bool {
+        self.check(record, None)
+    }
+
+    /// Returns whether `record` satisfies the filter, comparing player names
+    /// and the text looked for once both are run through `normalizer`.
+    ///
+    /// ```
+    /// use csa::{parse_csa, DefaultNormalizer, RecordFilter};
+    ///
+    /// let game = parse_csa("V2.2\nN+ＨＡＢＵ　Yoshiharu\nPI\n+\n").unwrap();
+    /// let filter = RecordFilter::Player("habu yoshiharu".to_string());
+    /// assert!(!filter.matches(&game));
+    /// assert!(filter.matches_with(&game, &DefaultNormalizer));
+    /// ```
+    pub fn matches_with(&self, record: &GameRecord, normalizer: &dyn NameNormalizer) -> bool {
+        self.check(record, Some(normalizer))
+    }
+
+    fn check(&self, record: &GameRecord, normalizer: Option<&dyn NameNormalizer>) -> bool {
+        match self {
+            RecordFilter::Player(text) => {
+                let names = [Color::Black, Color::White]
+                    .into_iter()
+                    .flat_map(|color| record.player_names(color));
+                match normalizer {
+                    Some(normalizer) => {
+                        let text = normalizer.normalize(text);
+                        names
+                            .into_iter()
+                            .any(|name| normalizer.normalize(name).contains(&text))
+                    }
+                    None => names.into_iter().any(|name| name.contains(text.as_str())),
+                }
+            }
+            RecordFilter::Event(text) => record.event.as_deref() == Some(text.as_str()),
+            RecordFilter::Date { from, to, undated } => {
+                let time = record.start_time.as_ref().or(record.end_time.as_ref());
+                match time {
+                    Some(time) => {
+                        from.is_none_or(|from| from <= time.date)
+                            && to.is_none_or(|to| time.date <= to)
+                    }
+                    None => *undated,
+                }
+            }
+            RecordFilter::EndsWith(action) => {
+                let last = record.moves.last().map(|record| record.action);
+                !matches!(action, Action::Move(..)) && last == Some(*action)
+            }
+            RecordFilter::Plies { min, max } => {
+                let plies = record.ply_count();
+                min.is_none_or(|min| min <= plies) && max.is_none_or(|max| plies <= max)
+            }
+            RecordFilter::Handicap(pieces) => match removals(&record.start_pos) {
+                Some(removed) => {
+                    removed.len() == pieces.len() && pieces.iter().all(|p| removed.contains(p))
+                }
+                None => false,
+            },
+            RecordFilter::BoardSize(files, ranks) => {
+                record.start_pos.dimensions() == (*files, *ranks)
+            }
+            RecordFilter::All(filters) => filters
+                .iter()
+                .all(|filter| filter.check(record, normalizer)),
+            RecordFilter::Any(filters) => filters
+                .iter()
+                .any(|filter| filter.check(record, normalizer)),
+            RecordFilter::Not(filter) => !filter.check(record, normalizer),
+        }
+    }
+
+    /// Returns a filter matching when both `self` and `other` match.
+    pub fn and(self, other: RecordFilter) -> RecordFilter {
+        match self {
+            RecordFilter::All(mut filters) => {
+                filters.push(other);
+                RecordFilter::All(filters)
+            }
+            filter => RecordFilter::All(vec![filter, other]),
+        }
+    }
+
+    /// Returns a filter matching when `self` or `other` matches.
+    pub fn or(self, other: RecordFilter) -> RecordFilter {
+        match self {
+            RecordFilter::Any(mut filters) => {
+                filters.push(other);
+                RecordFilter::Any(filters)
+            }
+            filter => RecordFilter::Any(vec![filter, other]),
+        }
+    }
+}
+
+impl Not for RecordFilter {
+    type Output = RecordFilter;
+
+    fn not(self) -> RecordFilter {
+        match self {
+            RecordFilter::Not(filter) => *filter,
+            filter => RecordFilter::Not(Box::new(filter)),
+        }
+    }
+}
+
+/// Returns the pieces missing from the standard position, or `None` if the
+/// position is not the standard one with pieces removed.
+fn removals(position: &Position) -> Option<Vec<(Square, PieceType)>> {
+    if position.dimensions() != (9, 9) {
+        return None;
+    }
+    let standard = Position::default();
+    let mut removed = Vec::new();
+    for file in 1..=9 {
+        for rank in 1..=9 {
+            let sq = Square::new(file, rank);
+            match (standard.piece_at(sq), position.piece_at(sq)) {
+                (expected, actual) if expected == actual => {}
+                (Some((_, pt)), None) => removed.push((sq, pt)),
+                _ => return None,
+            }
+        }
+    }
+    Some(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::names::DefaultNormalizer;
+    use crate::parse_csa;
+    use time::Month;
+
+    fn date(year: i32, month: Month, day: u8) -> NativeDate {
+        NativeDate::from_calendar_date(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn metadata_filters() {
+        let game = parse_csa(
+            "V2.2\nN+Habu Yoshiharu\nN-Hoshi\n$EVENT:Meijin\n$START_TIME:2024/04/09\nPI\n+\n+7776FU\n-3334FU\n%TORYO\n",
+        )
+        .unwrap();
+
+        assert!(RecordFilter::Player("Habu".into()).matches(&game));
+        assert!(RecordFilter::Player("Hoshi".into()).matches(&game));
+        assert!(!RecordFilter::Player("habu".into()).matches(&game));
+        assert!(RecordFilter::Event("Meijin".into()).matches(&game));
+        assert!(!RecordFilter::Event("Meij".into()).matches(&game));
+        assert!(RecordFilter::EndsWith(Action::Toryo).matches(&game));
+        assert!(!RecordFilter::EndsWith(Action::Chudan).matches(&game));
+        assert!(RecordFilter::Plies {
+            min: Some(2),
+            max: Some(2)
+        }
+        .matches(&game));
+        assert!(!RecordFilter::Plies {
+            min: Some(3),
+            max: None
+        }
+        .matches(&game));
+        assert!(RecordFilter::BoardSize(9, 9).matches(&game));
+        assert!(RecordFilter::Handicap(vec![]).matches(&game));
+    }
+
+    #[test]
+    fn player_aliases_and_normalization() {
+        let mut game = parse_csa("V2.2\nN+HABU Yoshiharu\nN-Hoshi\nPI\n+\n").unwrap();
+        let kanji = RecordFilter::Player("羽生".into());
+        assert!(!kanji.matches(&game));
+        game.add_player_alias(Color::Black, "羽生　善治");
+        assert!(kanji.matches(&game));
+
+        let spaced = RecordFilter::Player("羽生 善治".into());
+        assert!(!spaced.matches(&game));
+        assert!(spaced.matches_with(&game, &DefaultNormalizer));
+        // The normalizer reaches filters nested in combinators.
+        let both = RecordFilter::Player("habu".into()).and(kanji);
+        assert!(!both.matches(&game));
+        assert!(both.matches_with(&game, &DefaultNormalizer));
+    }
+
+    #[test]
+    fn date_range() {
+        let in_april = |undated| RecordFilter::Date {
+            from: Some(date(2024, Month::April, 1)),
+            to: Some(date(2024, Month::April, 30)),
+            undated,
+        };
+
+        let start = parse_csa("V2.2\n$START_TIME:2024/04/30 23:59\nPI\n+\n").unwrap();
+        assert!(in_april(false).matches(&start));
+
+        // The end time stands in for a missing start time.
+        let end = parse_csa("V2.2\n$END_TIME:2024/05/01\nPI\n+\n").unwrap();
+        assert!(!in_april(true).matches(&end));
+
+        let undated = parse_csa("V2.2\nPI\n+\n").unwrap();
+        assert!(!in_april(false).matches(&undated));
+        assert!(in_april(true).matches(&undated));
+    }
+
+    #[test]
+    fn handicap() {
+        let game = parse_csa("V2.2\nPI82HI22KA\n-\n").unwrap();
+        let two_pieces = vec![
+            (Square::new(2, 2), PieceType::Bishop),
+            (Square::new(8, 2), PieceType::Rook),
+        ];
+        assert!(RecordFilter::Handicap(two_pieces).matches(&game));
+        assert!(!RecordFilter::Handicap(vec![(Square::new(8, 2), PieceType::Rook)]).matches(&game));
+        assert!(!RecordFilter::Handicap(vec![]).matches(&game));
+
+        // A piece moved rather than removed is not a handicap.
+        let mut moved = game.clone();
+        moved.start_pos.add_pieces.push((
+            crate::value::Color::White,
+            Square::new(5, 5),
+            PieceType::Rook,
+        ));
+        assert!(
+            !RecordFilter::Handicap(vec![(Square::new(2, 2), PieceType::Bishop)]).matches(&moved)
+        );
+    }
+
+    #[test]
+    fn combinators() {
+        let game = parse_csa("V2.2\nN+A\nN-B\nPI\n+\n+7776FU\n").unwrap();
+        let a = RecordFilter::Player("A".into());
+        let c = RecordFilter::Player("C".into());
+
+        assert!(a.clone().or(c.clone()).matches(&game));
+        assert!(!a.clone().and(c.clone()).matches(&game));
+        assert!(a.clone().and(!c.clone()).matches(&game));
+        assert_eq!(!!a.clone(), a);
+        assert!(RecordFilter::All(vec![]).matches(&game));
+        assert!(!RecordFilter::Any(vec![]).matches(&game));
+        assert_eq!(
+            a.clone().and(c.clone()).and(a.clone()),
+            RecordFilter::All(vec![a.clone(), c, a])
+        );
+    }
+}
@@ -0,0 +1,304 @@
+//! The pieces one side holds in hand, and the ways they are written down.
+
+use std::fmt::Write;
+
+use crate::board::{hand_index, Board, HAND_PIECES};
+use crate::value::{Color, PieceType};
+
+/// Hand pieces in the order they are written: 飛角金銀桂香歩.
+const WRITING_ORDER: [PieceType; 7] = [
+    PieceType::Rook,
+    PieceType::Bishop,
+    PieceType::Gold,
+    PieceType::Silver,
+    PieceType::Knight,
+    PieceType::Lance,
+    PieceType::Pawn,
+];
+
+/// The pieces one side holds in hand, see [`Board::hand_of`].
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct Hand {
+    /// Indexed like `HAND_PIECES`.
+    counts: [u8; 7],
+}
+
+impl Hand {
+    /// Returns how many pieces of the given type the hand holds.
+    pub fn count(&self, pt: PieceType) -> u8 {
+        hand_index(pt).map_or(0, |slot| self.counts[slot])
+    }
+
+    /// Sets how many pieces of the given type the hand holds.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pt` cannot be held in hand.
+    pub fn set_count(&mut self, pt: PieceType, count: u8) {
+        let slot = hand_index(pt).unwrap_or_else(|| panic!("{pt} cannot be held in hand"));
+        self.counts[slot] = count;
+    }
+
+    /// Returns whether the hand holds no pieces.
+    pub fn is_empty(&self) -> bool {
+        self.counts.iter().all(|&n| n == 0)
+    }
+
+    /// Returns the pieces held and their counts, in writing order.
+    pub fn iter(&self) -> impl Iterator<Item = (PieceType, u8)> + '_ {
+        WRITING_ORDER
+            .iter()
+            .map(|&pt| (pt, self.count(pt)))
+            .filter(|&(_, n)| n > 0)
+    }
+
+    /// Writes the hand as in a BOD diagram or a KIF header, e.g. `角 歩三`,
+    /// or `なし` when it is empty. A count above one follows the piece in
+    /// kanji numerals, so eighteen pawns are `歩十八`.
+    pub fn to_japanese(&self) -> String {
+        if self.is_empty() {
+            return "なし".to_string();
+        }
+        let pieces: Vec<String> = self
+            .iter()
+            .map(|(pt, n)| {
+                let mut s = kanji_of(pt).to_string();
+                if n > 1 {
+                    s.push_str(&kanji_number(n));
+                }
+                s
+            })
+            .collect();
+        pieces.join(" ")
+    }
+
+    /// Reads the form written by [`Hand::to_japanese`]. Pieces may be
+    /// separated by ASCII or full-width spaces, or not at all.
+    pub fn parse_japanese(s: &str) -> Option<Hand> {
+        let s = s.trim_matches(|c: char| c.is_whitespace());
+        let mut hand = Hand::default();
+        if s == "なし" {
+            return Some(hand);
+        }
+        let mut chars = s.chars().filter(|c| !c.is_whitespace()).peekable();
+        while let Some(c) = chars.next() {
+            let pt = WRITING_ORDER.into_iter().find(|&pt| kanji_of(pt) == c)?;
+            let mut digits = String::new();
+            while let Some(&d) = chars.peek() {
+                if kanji_digit(d).is_none() {
+                    break;
+                }
+                digits.push(d);
+                chars.next();
+            }
+            let n = if digits.is_empty() {
+                1
+            } else {
+                parse_kanji_number(&digits)?
+            };
+            let total = hand.count(pt).checked_add(n)?;
+            hand.set_count(pt, total);
+        }
+        Some(hand)
+    }
+
+    /// Writes the hand as a CSA placement line such as `P+00HI00FU00FU`, or
+    /// an empty string when it is empty.
+    pub fn to_csa(&self, color: Color) -> String {
+        if self.is_empty() {
+            return String::new();
+        }
+        let mut s = format!("P{color}");
+        for (pt, n) in self.iter() {
+            for _ in 0..n {
+                write!(s, "00{pt}").unwrap();
+            }
+        }
+        s
+    }
+
+    /// Writes the hand as its part of an SFEN hand field, e.g. `B2P` for
+    /// Black or `b2p` for White. An empty hand gives an empty string; SFEN
+    /// writes `-` only when neither side holds anything.
+    pub fn to_sfen_fragment(&self, color: Color) -> String {
+        let mut s = String::new();
+        for (pt, n) in self.iter() {
+            if n > 1 {
+                write!(s, "{n}").unwrap();
+            }
+            let letter = sfen_letter(pt);
+            s.push(match color {
+                Color::Black => letter,
+                Color::White => letter.to_ascii_lowercase(),
+            });
+        }
+        s
+    }
+}
+
+impl Board {
+    /// Returns the pieces `color` holds in hand.
+    pub fn hand_of(&self, color: Color) -> Hand {
+        let mut hand = Hand::default();
+        for pt in HAND_PIECES {
+            hand.set_count(pt, self.hand(color, pt));
+        }
+        hand
+    }
+}
+
+fn kanji_of(pt: PieceType) -> char {
+    match pt {
+        PieceType::Rook => '飛',
+        PieceType::Bishop => '角',
+        PieceType::Gold => '金',
+        PieceType::Silver => '銀',
+        PieceType::Knight => '桂',
+        PieceType::Lance => '香',
+        _ => '歩',
+    }
+}
+
+fn sfen_letter(pt: PieceType) -> char {
+    match pt {
+        PieceType::Rook => 'R',
+        PieceType::Bishop => 'B',
+        PieceType::Gold => 'G',
+        PieceType::Silver => 'S',
+        PieceType::Knight => 'N',
+        PieceType::Lance => 'L',
+        _ => 'P',
+    }
+}
+
+const KANJI_DIGITS: [char; 9] = ['一', '二', '三', '四', '五', '六', '七', '八', '九'];
+
+fn kanji_digit(c: char) -> Option<u8> {
+    if c == '十' {
+        return Some(10);
+    }
+    KANJI_DIGITS
+        .iter()
+        .position(|&d| d == c)
+        .map(|i| i as u8 + 1)
+}
+
+/// Writes a number from 1 to 99 in kanji, e.g. `十八`.
+fn kanji_number(n: u8) -> String {
+    let (tens, ones) = (n / 10, n % 10);
+    let mut s = String::new();
+    if tens > 1 {
+        s.push(KANJI_DIGITS[tens as usize - 1]);
+    }
+    if tens > 0 {
+        s.push('十');
+    }
+    if ones > 0 {
+        s.push(KANJI_DIGITS[ones as usize - 1]);
+    }
+    s
+}
+
+/// Reads a number written by [`kanji_number`].
+fn parse_kanji_number(s: &str) -> Option<u8> {
+    let digits: Vec<u8> = s.chars().map(kanji_digit).collect::<Option<_>>()?;
+    let n = match digits[..] {
+        [ones] => ones,
+        [10, ones] if ones < 10 => 10 + ones,
+        [tens, 10] if tens < 10 => tens * 10,
+        [tens, 10, ones] if tens < 10 && ones < 10 => tens * 10 + ones,
+        _ => return None,
+    };
+    (n > 0).then_some(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::Position;
+
+    fn hand(pieces: &[(PieceType, u8)]) -> Hand {
+        let mut hand = Hand::default();
+        for &(pt, n) in pieces {
+            hand.set_count(pt, n);
+        }
+        hand
+    }
+
+    #[test]
+    fn japanese() {
+        use PieceType::*;
+
+        let cases = [
+            (hand(&[]), "なし"),
+            (hand(&[(Pawn, 3), (Lance, 1)]), "香 歩三"),
+            (hand(&[(Pawn, 1), (Knight, 2), (Rook, 1)]), "飛 桂二 歩"),
+            (hand(&[(Pawn, 10)]), "歩十"),
+            (hand(&[(Pawn, 18)]), "歩十八"),
+            (
+                hand(&[
+                    (Rook, 2),
+                    (Bishop, 2),
+                    (Gold, 4),
+                    (Silver, 4),
+                    (Knight, 4),
+                    (Lance, 4),
+                    (Pawn, 18),
+                ]),
+                "飛二 角二 金四 銀四 桂四 香四 歩十八",
+            ),
+        ];
+        for (hand, text) in cases {
+            assert_eq!(hand.to_japanese(), text);
+            assert_eq!(Hand::parse_japanese(text), Some(hand), "{text}");
+        }
+
+        assert_eq!(
+            Hand::parse_japanese("　歩三香　"),
+            Some(hand(&[(Pawn, 3), (Lance, 1)]))
+        );
+        assert_eq!(Hand::parse_japanese("王"), None);
+        assert_eq!(Hand::parse_japanese("歩十十"), None);
+        assert_eq!(Hand::parse_japanese("三"), None);
+    }
+
+    #[test]
+    fn csa_and_sfen() {
+        use PieceType::*;
+
+        let h = hand(&[(Pawn, 2), (Bishop, 1)]);
+        assert_eq!(h.to_csa(Color::Black), "P+00KA00FU00FU");
+        assert_eq!(h.to_csa(Color::White), "P-00KA00FU00FU");
+        assert_eq!(h.to_sfen_fragment(Color::Black), "B2P");
+        assert_eq!(h.to_sfen_fragment(Color::White), "b2p");
+        assert_eq!(hand(&[(Pawn, 18)]).to_sfen_fragment(Color::Black), "18P");
+
+        let empty = Hand::default();
+        assert_eq!(empty.to_csa(Color::Black), "");
+        assert_eq!(empty.to_sfen_fragment(Color::White), "");
+    }
+
+    #[test]
+    fn hand_of_board() {
+        let position = Position {
+            add_pieces: vec![
+                (
+                    Color::Black,
+                    crate::value::Square::new(0, 0),
+                    PieceType::Pawn,
+                ),
+                (
+                    Color::Black,
+                    crate::value::Square::new(0, 0),
+                    PieceType::Rook,
+                ),
+            ]
+            .into_iter()
+            .collect(),
+            ..Position::default()
+        };
+        let board = Board::from_position(&position).unwrap();
+        assert_eq!(board.hand_of(Color::Black).to_japanese(), "飛 歩");
+        assert!(board.hand_of(Color::White).is_empty());
+    }
+}
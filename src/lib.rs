@@ -28,7 +28,8 @@
 //! assert_eq!(game.event, Some("13th World Computer Shogi Championship".to_string()));
 //! assert_eq!(game.moves[0],  MoveRecord{
 //!     action: Action::Move(Color::Black, Square::new(2, 7), Square::new(2, 6), PieceType::Pawn),
-//!     time: Some(Duration::from_secs(12))
+//!     time: Some(Duration::from_secs(12)),
+//!     events: Vec::new(),
 //! });
 //! ```
 //!
@@ -50,11 +51,9 @@
 //!         PieceType::Pawn,
 //!     ),
 //!     time: Some(Duration::from_secs(5)),
+//!     events: Vec::new(),
 //! });
-//! g.moves.push(MoveRecord {
-//!     action: Action::Toryo,
-//!     time: None,
-//! });
+//! g.moves.push(MoveRecord::new(Action::Toryo));
 //!
 //! let csa_str = "\
 //! V2.2
@@ -71,10 +70,196 @@
 //! assert_eq!(csa_str, g.to_string());
 //! ```
 //!
+//! # Thread safety
+//!
+//! Every public type in this crate is `Send` and `Sync`, so records, boards and
+//! errors can be handed between threads freely, and with the `intern` feature
+//! one `Interner` can be shared by parsers running on a thread pool. Records
+//! own their data (interned strings are `Arc<str>`), and nothing uses `Rc` or
+//! unsynchronized interior mutability.
+//!
+//! Tables such as the Zobrist keys and opening names are `const`s, built at
+//! compile time, so nothing is initialized lazily or before `main`, and the
+//! crate works the same on `wasm32` without threads. The one global is the
+//! opening table, which the `table-override` feature lets a program replace
+//! once, before first use, with `set_opening_table`; a `OnceLock` guards it.
+//!
+//! # Determinism
+//!
+//! Parsing the same input always gives the same record, and writing, hashing
+//! or encoding a record always gives the same output, byte for byte, so
+//! records can be stored by content. Anything that feeds a writer or a hash
+//! keeps its order in a `Vec`, such as the extra attributes in the order they
+//! were read, or sorts it, as the ratings of [`compute_ratings`] are; hash
+//! maps and sets are only used for lookups. A test parses and writes every
+//! fixture repeatedly to hold this.
+//!
 //! [CSA]: http://www2.computer-shogi.org/protocol/record_v22.html
 
+pub mod analyzed;
+pub mod anonymize;
+pub mod archive;
+#[cfg(feature = "binary")]
+pub mod binary;
+pub mod board;
+pub mod clock;
+pub mod error;
+pub mod events;
+pub mod features;
+pub mod filter;
+pub mod hand;
+pub mod lint;
+pub mod names;
+pub mod opening;
 pub mod parser;
+pub mod rating;
+pub mod render;
+pub mod repair;
+pub mod search;
+pub mod sort;
+pub mod stats;
+pub mod summary;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod text;
+pub mod timeline;
+pub mod tournament;
 pub mod value;
+pub mod variant;
 
+pub use analyzed::*;
+pub use anonymize::*;
+pub use archive::*;
+#[cfg(feature = "binary")]
+pub use binary::*;
+pub use board::*;
+pub use clock::*;
+pub use error::*;
+pub use events::*;
+pub use features::*;
+pub use filter::*;
+pub use hand::*;
+pub use lint::*;
+pub use names::*;
+pub use opening::*;
 pub use parser::*;
+pub use rating::*;
+pub use render::*;
+pub use repair::*;
+pub use search::*;
+pub use sort::*;
+pub use stats::*;
+pub use summary::*;
+#[cfg(any(test, feature = "testing"))]
+pub use testing::*;
+pub use text::*;
+pub use timeline::*;
+pub use tournament::*;
 pub use value::*;
+pub use variant::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn public_types_are_send_sync() {
+        assert_send_sync::<GameRecord>();
+        assert_send_sync::<Layout>();
+        assert_send_sync::<Time>();
+        assert_send_sync::<TimeLimit>();
+        assert_send_sync::<GameAttribute>();
+        assert_send_sync::<AttrValue>();
+        assert_send_sync::<Position>();
+        assert_send_sync::<PositionRepr>();
+        assert_send_sync::<PositionStyle>();
+        assert_send_sync::<WriteOptions>();
+        assert_send_sync::<WritePreset>();
+        assert_send_sync::<WriteError>();
+        assert_send_sync::<PieceList<(Color, Square, PieceType)>>();
+        assert_send_sync::<Action>();
+        assert_send_sync::<Adjournment>();
+        assert_send_sync::<Bookmark>();
+        assert_send_sync::<MoveRecord>();
+        assert_send_sync::<PromotionStatus>();
+        assert_send_sync::<PromotionError>();
+
+        assert_send_sync::<Board>();
+        assert_send_sync::<BoardError>();
+        assert_send_sync::<HashComponents>();
+        assert_send_sync::<MoveClock>();
+        assert_send_sync::<ValidationIssue>();
+        assert_send_sync::<GameStats>();
+        assert_send_sync::<TimeStats>();
+        assert_send_sync::<Think>();
+        assert_send_sync::<GameSummary>();
+        assert_send_sync::<RecordFilter>();
+        assert_send_sync::<OpeningName>();
+        assert_send_sync::<OpeningTag>();
+        assert_send_sync::<Hand>();
+        assert_send_sync::<DefaultNormalizer>();
+        assert_send_sync::<Severity>();
+        assert_send_sync::<LintRule>();
+        assert_send_sync::<LintLocation>();
+        assert_send_sync::<LintFinding>();
+        assert_send_sync::<LintConfig>();
+        assert_send_sync::<SuppressionList>();
+        assert_send_sync::<Variant>();
+        assert_send_sync::<Suppression>();
+        assert_send_sync::<Repair>();
+        assert_send_sync::<RecordSortKey>();
+        assert_send_sync::<PieceValues>();
+        assert_send_sync::<PlyFeatures>();
+        assert_send_sync::<AttackMap>();
+        assert_send_sync::<KifuEvent>();
+        assert_send_sync::<GameResult>();
+        assert_send_sync::<NamePolicy>();
+        assert_send_sync::<AnonymizePolicy>();
+        assert_send_sync::<TimelinePoint>();
+        assert_send_sync::<ParseMetrics>();
+        assert_send_sync::<AnalyzedRecord>();
+        assert_send_sync::<CustomSpecials>();
+        assert_send_sync::<CustomSpecialError>();
+        assert_send_sync::<TimeUnit>();
+        assert_send_sync::<MaxMovesRule>();
+        assert_send_sync::<GameRules>();
+        assert_send_sync::<Outcome>();
+        assert_send_sync::<PlayerMatch>();
+        assert_send_sync::<PlayerTally>();
+        assert_send_sync::<PlayerRating>();
+        assert_send_sync::<RatingSkip>();
+        assert_send_sync::<RatingReport>();
+        assert_send_sync::<AppliedMove<'static>>();
+        assert_send_sync::<Repetition>();
+        assert_send_sync::<TournamentInfo>();
+        assert_send_sync::<DefaultEventParser>();
+        assert_send_sync::<FilenameConvention>();
+        assert_send_sync::<FilenameMeta>();
+
+        assert_send_sync::<CsaError>();
+        assert_send_sync::<Error>();
+        assert_send_sync::<SquareError>();
+        assert_send_sync::<InterMoveEvent>();
+        assert_send_sync::<EventConvention>();
+        assert_send_sync::<EventConventionError>();
+        assert_send_sync::<csa::ParseError>();
+        assert_send_sync::<ParseOptions<'static>>();
+        assert_send_sync::<ParseWarning>();
+        assert_send_sync::<ParseOutput>();
+        assert_send_sync::<GameHeader>();
+        assert_send_sync::<MaxMovesPolicy>();
+        assert_send_sync::<Units>();
+        assert_send_sync::<SourceFormat>();
+        assert_send_sync::<OpeningTable>();
+        assert_send_sync::<GenerateOptions>();
+        assert_send_sync::<CorpusExpectations<'static>>();
+        assert_send_sync::<CorpusReport>();
+
+        #[cfg(feature = "binary")]
+        assert_send_sync::<BinaryError>();
+        #[cfg(feature = "intern")]
+        assert_send_sync::<Interner>();
+    }
+}
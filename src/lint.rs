@@ -0,0 +1,719 @@
+//! Quality checks over a whole record, each reported under a stable rule id.
+
+use std::fmt;
+
+use crate::board::{HAND_PIECES, STANDARD_SET};
+use crate::clock::ValidationIssue;
+use crate::value::{Action, Color, GameRecord, PieceType, Square, Time};
+use crate::variant::Variant;
+
+/// How much a [`LintFinding`] matters.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+pub enum Severity {
+    /// Worth knowing, but nothing is wrong with the record.
+    Info,
+    /// The record is probably wrong, or was recorded carelessly.
+    Warning,
+    /// The record cannot be right.
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// A check run by [`lint_record`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct LintRule {
+    /// The id findings are reported under, which stays the same across
+    /// releases.
+    pub id: &'static str,
+    /// A number for the rule, for tools that sort or store findings by
+    /// number: the hundreds group the rules like the ids do. It stays the
+    /// same across releases too.
+    pub code: u16,
+    /// The severity findings get unless [`LintConfig::severities`] says
+    /// otherwise.
+    pub severity: Severity,
+    pub description: &'static str,
+}
+
+impl LintRule {
+    /// Returns the rule with id `id`.
+    ///
+    /// ```
+    /// use csa::LintRule;
+    ///
+    /// assert_eq!(LintRule::by_id("KIFU-CLOCK-001").unwrap().code, 301);
+    /// assert_eq!(LintRule::by_id("KIFU-CLOCK-999"), None);
+    /// ```
+    pub fn by_id(id: &str) -> Option<&'static LintRule> {
+        LINT_RULES.iter().find(|rule| rule.id == id)
+    }
+}
+
+/// Ids and codes of the rules that were removed. They are never given to
+/// another rule, so that a suppression or a stored report keeps meaning
+/// what it did.
+pub const RETIRED_RULES: &[(&str, u16)] = &[];
+
+/// Every rule [`lint_record`] checks.
+pub const LINT_RULES: [LintRule; 18] = [
+    LintRule {
+        id: "KIFU-POS-001",
+        code: 101,
+        severity: Severity::Error,
+        description: "the starting position cannot be set up",
+    },
+    LintRule {
+        id: "KIFU-POS-002",
+        code: 102,
+        severity: Severity::Error,
+        description: "the starting position holds more pieces of a type than a set has",
+    },
+    LintRule {
+        id: "KIFU-MOVE-001",
+        code: 201,
+        severity: Severity::Error,
+        description: "a move is made by the side not to move",
+    },
+    LintRule {
+        id: "KIFU-MOVE-002",
+        code: 202,
+        severity: Severity::Error,
+        description: "a move does not fit the board or breaks the rules of shogi",
+    },
+    LintRule {
+        id: "KIFU-MOVE-003",
+        code: 203,
+        severity: Severity::Error,
+        description: "a pawn is dropped on a file holding an unpromoted pawn of its side (nifu)",
+    },
+    LintRule {
+        id: "KIFU-CLOCK-001",
+        code: 301,
+        severity: Severity::Warning,
+        description: "a move took longer than its player had left",
+    },
+    LintRule {
+        id: "KIFU-CLOCK-002",
+        code: 302,
+        severity: Severity::Warning,
+        description: "the game is lost on time with time left on the clock",
+    },
+    LintRule {
+        id: "KIFU-CLOCK-003",
+        code: 303,
+        severity: Severity::Warning,
+        description: "the game is lost on time by a player without a time limit",
+    },
+    LintRule {
+        id: "KIFU-CLOCK-004",
+        code: 304,
+        severity: Severity::Warning,
+        description: "the game is lost on time after a clock already ran out",
+    },
+    LintRule {
+        id: "KIFU-CLOCK-005",
+        code: 305,
+        severity: Severity::Warning,
+        description: "a move time is not a whole number of the record's time unit",
+    },
+    LintRule {
+        id: "KIFU-RESULT-001",
+        code: 401,
+        severity: Severity::Error,
+        description: "moves follow a special move that ends the game",
+    },
+    LintRule {
+        id: "KIFU-RESULT-002",
+        code: 402,
+        severity: Severity::Warning,
+        description: "the game ends in %TSUMI but the side to move is not checkmated",
+    },
+    LintRule {
+        id: "KIFU-RESULT-003",
+        code: 403,
+        severity: Severity::Error,
+        description: "a move is played past the move limit",
+    },
+    LintRule {
+        id: "KIFU-RESULT-004",
+        code: 404,
+        severity: Severity::Warning,
+        description: "the game ends in %MAX_MOVES before reaching the move limit",
+    },
+    LintRule {
+        id: "KIFU-META-001",
+        code: 501,
+        severity: Severity::Warning,
+        description: "$END_TIME is before $START_TIME",
+    },
+    LintRule {
+        id: "KIFU-META-002",
+        code: 502,
+        severity: Severity::Info,
+        description: "a player has no name",
+    },
+    LintRule {
+        id: "KIFU-META-003",
+        code: 503,
+        severity: Severity::Warning,
+        description: "a bookmark marks a position past the end of the game",
+    },
+    LintRule {
+        id: "KIFU-OPENING-001",
+        code: 601,
+        severity: Severity::Info,
+        description: "$OPENING names another opening than the moves play",
+    },
+];
+
+/// Where in a record a [`LintFinding`] was made.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum LintLocation {
+    /// The starting position.
+    Position,
+    /// `moves[index]`, with its ply number unless it is a special move.
+    Move { index: usize, ply: Option<usize> },
+    /// A header line, named by its prefix, e.g. `$END_TIME` or `N+`.
+    Field(&'static str),
+    /// `bookmarks[index]`.
+    Bookmark(usize),
+}
+
+impl fmt::Display for LintLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LintLocation::Position => write!(f, "starting position"),
+            LintLocation::Move { ply: Some(ply), .. } => write!(f, "ply {ply}"),
+            LintLocation::Move { index, ply: None } => write!(f, "move {index}"),
+            LintLocation::Field(field) => write!(f, "{field}"),
+            LintLocation::Bookmark(index) => write!(f, "bookmark {index}"),
+        }
+    }
+}
+
+/// A problem found by [`lint_record`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct LintFinding {
+    /// The id of the [`LintRule`] that found the problem.
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub location: LintLocation,
+    pub message: String,
+}
+
+impl fmt::Display for LintFinding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}[{}] {}: {}",
+            self.severity, self.rule, self.location, self.message
+        )
+    }
+}
+
+impl LintFinding {
+    /// Returns the code of the rule that found the problem, see
+    /// [`LintRule::code`].
+    pub fn code(&self) -> u16 {
+        LintRule::by_id(self.rule).map_or(0, |rule| rule.code)
+    }
+
+    /// Returns whether `suppressions` acknowledges the finding.
+    pub fn is_suppressed_by(&self, suppressions: &SuppressionList) -> bool {
+        let index = match self.location {
+            LintLocation::Move { index, .. } => Some(index),
+            _ => None,
+        };
+        suppressions.suppresses(self.rule, index)
+    }
+}
+
+/// Findings acknowledged in one file, for
+/// [`LintFinding::is_suppressed_by`] and
+/// [`ValidationIssue::is_suppressed_by`](crate::ValidationIssue::is_suppressed_by).
+///
+/// With the `serde` feature it reads from a structure such as this JSON,
+/// where a suppression without an `index` covers the whole file:
+///
+/// ```json
+/// {
+///   "suppressions": [
+///     { "rule": "KIFU-META-002" },
+///     { "rule": "KIFU-CLOCK-001", "index": 41 }
+///   ]
+/// }
+/// ```
+///
+/// A list per file, such as a map from paths to lists, is left to the
+/// caller.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SuppressionList {
+    pub suppressions: Vec<Suppression>,
+}
+
+/// One entry of a [`SuppressionList`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Suppression {
+    /// The id of the rule, such as `KIFU-CLOCK-001`.
+    pub rule: String,
+    /// The index in `GameRecord::moves` of the move to suppress findings
+    /// on, as given by [`LintLocation::Move`] and the `index` of a
+    /// [`ValidationIssue`]; `None` suppresses the rule in the whole file.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub index: Option<usize>,
+}
+
+impl SuppressionList {
+    pub(crate) fn suppresses(&self, rule: &str, index: Option<usize>) -> bool {
+        self.suppressions
+            .iter()
+            .any(|s| s.rule == rule && (s.index.is_none() || s.index == index))
+    }
+}
+
+/// Which rules [`lint_record`] runs, and how severe their findings are.
+///
+/// The default runs every rule at its own severity.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct LintConfig {
+    /// Ids of the rules not to run.
+    pub disabled: Vec<String>,
+    /// Severities to report rules with instead of their own.
+    pub severities: Vec<(String, Severity)>,
+    /// Findings to leave out.
+    pub suppressions: SuppressionList,
+}
+
+impl LintConfig {
+    fn enabled(&self, rule: &str) -> bool {
+        !self.disabled.iter().any(|id| id == rule)
+    }
+
+    fn severity_of(&self, rule: &LintRule) -> Severity {
+        self.severities
+            .iter()
+            .rev()
+            .find(|(id, _)| id == rule.id)
+            .map_or(rule.severity, |(_, severity)| *severity)
+    }
+}
+
+/// Runs the checks of [`LINT_RULES`] over a record and returns what they
+/// found, in the order of the rules.
+///
+/// The moves are replayed from the starting position and checked against
+/// the rules of shogi, as [`Board::legal_moves`](crate::Board::legal_moves) knows them, up to the first
+/// one that breaks them; later moves cannot be checked. The clocks are
+/// checked as by [`GameRecord::validate`].
+///
+/// ```
+/// use csa::{lint_record, parse_csa, LintConfig, Severity};
+///
+/// let game = parse_csa("V2.2\nN+Sente\nPI\n+\n+7776FU\n+2726FU\n").unwrap();
+/// let findings = lint_record(&game, &LintConfig::default());
+/// assert_eq!(findings[0].rule, "KIFU-MOVE-001");
+/// assert_eq!(findings[0].severity, Severity::Error);
+/// assert_eq!(findings[0].to_string(), "error[KIFU-MOVE-001] ply 2: +2726FU is made by + with - to move");
+/// assert_eq!(findings[1].rule, "KIFU-META-002");
+///
+/// let config = LintConfig {
+///     disabled: vec!["KIFU-META-002".to_string()],
+///     ..LintConfig::default()
+/// };
+/// assert_eq!(lint_record(&game, &config).len(), 1);
+/// ```
+pub fn lint_record(record: &GameRecord, config: &LintConfig) -> Vec<LintFinding> {
+    let mut found = Vec::new();
+    check_moves(record, &mut found);
+    check_validation(record, &mut found);
+    check_result(record, &mut found);
+    check_metadata(record, &mut found);
+    check_opening(record, &mut found);
+
+    let mut findings = Vec::new();
+    for rule in &LINT_RULES {
+        if !config.enabled(rule.id) {
+            continue;
+        }
+        for (_, location, message) in found.iter().filter(|(id, ..)| *id == rule.id) {
+            let finding = LintFinding {
+                rule: rule.id,
+                severity: config.severity_of(rule),
+                location: *location,
+                message: message.clone(),
+            };
+            if !finding.is_suppressed_by(&config.suppressions) {
+                findings.push(finding);
+            }
+        }
+    }
+    findings
+}
+
+type Found = Vec<(&'static str, LintLocation, String)>;
+
+fn move_location(record: &GameRecord, index: usize) -> LintLocation {
+    LintLocation::Move {
+        index,
+        ply: record.ply_of(index),
+    }
+}
+
+fn check_moves(record: &GameRecord, found: &mut Found) {
+    let mut board = match record.start_board() {
+        Ok(board) => board,
+        Err(err) => {
+            found.push(("KIFU-POS-001", LintLocation::Position, err.to_string()));
+            return;
+        }
+    };
+    if board.dimensions() == (9, 9) {
+        let pieces = HAND_PIECES
+            .into_iter()
+            .zip(STANDARD_SET)
+            .chain([(PieceType::King, 2)]);
+        for (pt, total) in pieces {
+            let count = board.count(pt);
+            if count > total {
+                found.push((
+                    "KIFU-POS-002",
+                    LintLocation::Position,
+                    format!("{count} {pt} where a set has {total}"),
+                ));
+            }
+        }
+    }
+
+    for (index, record_move) in record.moves.iter().enumerate() {
+        let action = record_move.action;
+        let Action::Move(color, from, to, pt) = action else {
+            continue;
+        };
+        let location = move_location(record, index);
+        let to_move = board.side_to_move();
+        if color != to_move {
+            found.push((
+                "KIFU-MOVE-001",
+                location,
+                format!("{action} is made by {color} with {to_move} to move"),
+            ));
+            return;
+        }
+
+        let nifu = from.is_hand()
+            && pt == PieceType::Pawn
+            && board.variant() != Variant::Kyoto
+            && (1..=board.dimensions().1)
+                .any(|rank| board.piece_at(Square::new(to.file, rank)) == Some((color, pt)));
+        if nifu {
+            found.push((
+                "KIFU-MOVE-003",
+                location,
+                format!("{action} drops a second pawn on file {}", to.file),
+            ));
+            return;
+        }
+        let mut next = board.clone();
+        if let Err(err) = next.apply(&action) {
+            found.push(("KIFU-MOVE-002", location, err.to_string()));
+            return;
+        }
+        if !board.legal_moves().contains(&action) {
+            found.push((
+                "KIFU-MOVE-002",
+                location,
+                format!("{action} is not a legal move"),
+            ));
+            return;
+        }
+        board = next;
+    }
+}
+
+fn check_validation(record: &GameRecord, found: &mut Found) {
+    for issue in record.validate() {
+        let location = match issue {
+            ValidationIssue::BookmarkPastEnd { bookmark, .. } => LintLocation::Bookmark(bookmark),
+            _ => issue
+                .index()
+                .map_or(LintLocation::Position, |index| move_location(record, index)),
+        };
+        found.push((issue.id(), location, issue.message()));
+    }
+}
+
+fn check_result(record: &GameRecord, found: &mut Found) {
+    let end = record
+        .moves
+        .iter()
+        .position(|m| !matches!(m.action, Action::Move(..)));
+    if let Some(end) = end {
+        if let Some(index) = (end + 1..record.moves.len())
+            .find(|&i| matches!(record.moves[i].action, Action::Move(..)))
+        {
+            found.push((
+                "KIFU-RESULT-001",
+                move_location(record, index),
+                format!(
+                    "{} follows {}",
+                    record.moves[index].action, record.moves[end].action
+                ),
+            ));
+            return;
+        }
+    }
+
+    let Some(index) = record.moves.len().checked_sub(1) else {
+        return;
+    };
+    if record.moves[index].action != Action::Tsumi {
+        return;
+    }
+    let Ok(mut board) = record.start_board() else {
+        return;
+    };
+    for m in &record.moves[..index] {
+        if board.apply(&m.action).is_err() {
+            return;
+        }
+    }
+    let color = board.side_to_move();
+    if !board.is_checkmate() {
+        found.push((
+            "KIFU-RESULT-002",
+            move_location(record, index),
+            format!("{color} is not checkmated"),
+        ));
+    }
+}
+
+fn check_metadata(record: &GameRecord, found: &mut Found) {
+    if let (Some(start), Some(end)) = (&record.start_time, &record.end_time) {
+        if ends_before(start, end) {
+            found.push((
+                "KIFU-META-001",
+                LintLocation::Field("$END_TIME"),
+                format!("the game ends at {end}, before it starts at {start}"),
+            ));
+        }
+    }
+    for (color, field) in [(Color::Black, "N+"), (Color::White, "N-")] {
+        let name = match color {
+            Color::Black => &record.black_player,
+            Color::White => &record.white_player,
+        };
+        if name.as_deref().is_none_or(str::is_empty) {
+            found.push((
+                "KIFU-META-002",
+                LintLocation::Field(field),
+                format!("{color} has no name"),
+            ));
+        }
+    }
+}
+
+/// Returns whether `end` is before `start`. Without both times of day, only
+/// the dates are compared.
+fn ends_before(start: &Time, end: &Time) -> bool {
+    match (start.time, end.time) {
+        (Some(start_time), Some(end_time)) if start.date == end.date => end_time < start_time,
+        _ => end.date < start.date,
+    }
+}
+
+fn check_opening(record: &GameRecord, found: &mut Found) {
+    let (Some(declared), Some(played)) = (
+        record.opening_tag().and_then(|tag| tag.name),
+        record.classify_opening(),
+    ) else {
+        return;
+    };
+    if declared != played {
+        found.push((
+            "KIFU-OPENING-001",
+            LintLocation::Field("$OPENING"),
+            format!("{declared} is declared, but the moves play {played}"),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_csa;
+
+    fn rules(csa: &str) -> Vec<&'static str> {
+        let game = parse_csa(&format!("V2.2\nN+A\nN-B\n{csa}")).unwrap();
+        lint_record(&game, &LintConfig::default())
+            .into_iter()
+            .map(|finding| finding.rule)
+            .collect()
+    }
+
+    #[test]
+    fn rule_ids_are_unique() {
+        for (i, rule) in LINT_RULES.iter().enumerate() {
+            assert!(
+                LINT_RULES[..i]
+                    .iter()
+                    .all(|r| r.id != rule.id && r.code != rule.code),
+                "{}",
+                rule.id
+            );
+            assert!(
+                RETIRED_RULES
+                    .iter()
+                    .all(|&(id, code)| id != rule.id && code != rule.code),
+                "{} reuses a retired id or code",
+                rule.id
+            );
+        }
+    }
+
+    #[test]
+    fn clean_record() {
+        assert!(rules("PI\n+\n+7776FU\n-3334FU\n%TORYO\n").is_empty());
+    }
+
+    #[test]
+    fn position_and_moves() {
+        assert_eq!(rules("PI\nP+00OU\n+\n"), ["KIFU-POS-001"]);
+        assert_eq!(rules("PI\nP+00FU\n+\n"), ["KIFU-POS-002"]);
+        assert_eq!(rules("PI\n+\n-3334FU\n"), ["KIFU-MOVE-001"]);
+        assert_eq!(rules("PI\n+\n+5554FU\n"), ["KIFU-MOVE-002"]);
+        assert_eq!(rules("PI\n+\n+7775FU\n"), ["KIFU-MOVE-002"]);
+        assert_eq!(rules("PI17FU\nP+00FU\n+\n+0055FU\n"), ["KIFU-MOVE-003"]);
+        // Only the first broken move is reported.
+        assert_eq!(rules("PI\n+\n+7775FU\n+2725FU\n"), ["KIFU-MOVE-002"]);
+    }
+
+    #[test]
+    fn clocks_and_results() {
+        assert_eq!(
+            rules("$TIME_LIMIT:00:01+00\nPI\n+\n+7776FU\nT70\n"),
+            ["KIFU-CLOCK-001"]
+        );
+        assert_eq!(rules("PI\n+\n+7776FU\n%TIME_UP\n"), ["KIFU-CLOCK-003"]);
+        assert_eq!(rules("PI\n+\n+7776FU\n%TSUMI\n"), ["KIFU-RESULT-002"]);
+
+        let mut game = parse_csa("V2.2\nN+A\nN-B\nPI\n+\n+7776FU\n%CHUDAN\n").unwrap();
+        game.moves.push(crate::MoveRecord {
+            action: Action::Move(
+                Color::White,
+                Square::new(3, 3),
+                Square::new(3, 4),
+                PieceType::Pawn,
+            ),
+            time: None,
+            events: Vec::new(),
+        });
+        let findings = lint_record(&game, &LintConfig::default());
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].rule, "KIFU-RESULT-001");
+        assert_eq!(
+            findings[0].location,
+            LintLocation::Move {
+                index: 2,
+                ply: Some(2)
+            }
+        );
+    }
+
+    #[test]
+    fn metadata_and_opening() {
+        assert_eq!(
+            rules("$START_TIME:2024/01/02 10:00\n$END_TIME:2024/01/02 09:00\nPI\n+\n"),
+            ["KIFU-META-001"]
+        );
+        assert!(rules("$START_TIME:2024/01/02 10:00\n$END_TIME:2024/01/02\nPI\n+\n").is_empty());
+        assert_eq!(
+            rules("$OPENING:YAGURA\nPI\n+\n+7776FU\n-3334FU\n+2868HI\n"),
+            ["KIFU-OPENING-001"]
+        );
+        assert!(rules("$OPENING:SHIKENBISHA\nPI\n+\n+7776FU\n-3334FU\n+2868HI\n").is_empty());
+
+        let game = parse_csa("V2.2\nN+A\nPI\n+\n").unwrap();
+        let findings = lint_record(&game, &LintConfig::default());
+        assert_eq!(
+            findings[0].to_string(),
+            "info[KIFU-META-002] N-: - has no name"
+        );
+    }
+
+    #[test]
+    fn config() {
+        let game = parse_csa("V2.2\nPI\n+\n-3334FU\n").unwrap();
+        let config = LintConfig {
+            disabled: vec!["KIFU-MOVE-001".to_string()],
+            severities: vec![("KIFU-META-002".to_string(), Severity::Warning)],
+            ..LintConfig::default()
+        };
+        let findings = lint_record(&game, &config);
+        assert_eq!(findings.len(), 2);
+        assert!(findings
+            .iter()
+            .all(|f| f.rule == "KIFU-META-002" && f.severity == Severity::Warning));
+    }
+
+    #[test]
+    fn suppressions() {
+        let game =
+            parse_csa("V2.2\n$TIME_LIMIT:00:01+00\nPI\n+\n+7776FU\nT70\n-3334FU\nT70\n").unwrap();
+        let suppressions = SuppressionList {
+            suppressions: vec![
+                Suppression {
+                    rule: "KIFU-META-002".to_string(),
+                    index: None,
+                },
+                Suppression {
+                    rule: "KIFU-CLOCK-001".to_string(),
+                    index: Some(1),
+                },
+            ],
+        };
+        let config = LintConfig {
+            suppressions: suppressions.clone(),
+            ..LintConfig::default()
+        };
+        let findings = lint_record(&game, &config);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].rule, "KIFU-CLOCK-001");
+        assert_eq!(findings[0].code(), 301);
+        assert_eq!(
+            findings[0].location,
+            LintLocation::Move {
+                index: 0,
+                ply: Some(1)
+            }
+        );
+
+        let issues = game.validate();
+        assert_eq!(issues.len(), 2);
+        assert!(!issues[0].is_suppressed_by(&suppressions));
+        assert!(issues[1].is_suppressed_by(&suppressions));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn suppressions_from_json() {
+        let json = r#"{"suppressions": [
+            {"rule": "KIFU-META-002"},
+            {"rule": "KIFU-CLOCK-001", "index": 41}
+        ]}"#;
+        let suppressions: SuppressionList = serde_json::from_str(json).unwrap();
+        assert_eq!(suppressions.suppressions[0].index, None);
+        assert_eq!(suppressions.suppressions[1].index, Some(41));
+    }
+}
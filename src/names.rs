@@ -0,0 +1,189 @@
+//! Player aliases, and the normalization player names are compared under.
+
+use crate::value::{AttrValue, Color, GameRecord};
+
+/// Separates the aliases kept in one `$BLACK_ALIAS`/`$WHITE_ALIAS` attribute.
+const ALIAS_SEPARATOR: char = '|';
+
+fn alias_key(color: Color) -> &'static str {
+    match color {
+        Color::Black => "BLACK_ALIAS",
+        Color::White => "WHITE_ALIAS",
+    }
+}
+
+impl GameRecord {
+    /// Records another name a player is known by, such as `羽生善治` for
+    /// `HABU Yoshiharu`.
+    ///
+    /// Aliases are kept in the `$BLACK_ALIAS` or `$WHITE_ALIAS` attribute,
+    /// separated by `|`, so they are written with the record and read back by
+    /// any CSA reader. An alias already recorded, or containing `|`, a comma
+    /// or a line break, is not added.
+    ///
+    /// ```
+    /// use csa::{parse_csa, Color};
+    ///
+    /// let mut game = parse_csa("V2.2\nN+HABU Yoshiharu\nPI\n+\n").unwrap();
+    /// game.add_player_alias(Color::Black, "羽生善治");
+    /// assert!(game.to_string().contains("$BLACK_ALIAS:羽生善治\n"));
+    /// assert_eq!(parse_csa(&game.to_string()).unwrap().player_aliases(Color::Black), ["羽生善治"]);
+    /// ```
+    pub fn add_player_alias(&mut self, color: Color, alias: &str) {
+        let invalid = |c: char| c == ALIAS_SEPARATOR || c == ',' || c == '\r' || c == '\n';
+        if alias.is_empty()
+            || alias.contains(invalid)
+            || self.player_aliases(color).contains(&alias)
+        {
+            return;
+        }
+
+        let key = alias_key(color);
+        let value = match self.attr_str(key) {
+            Some(aliases) => format!("{aliases}{ALIAS_SEPARATOR}{alias}"),
+            None => alias.to_string(),
+        };
+        self.extra_attrs.retain(|(k, _)| k != key);
+        self.extra_attrs
+            .push((key.to_string(), AttrValue::Text(value)));
+    }
+
+    /// Returns the aliases recorded for a player by
+    /// [`GameRecord::add_player_alias`].
+    pub fn player_aliases(&self, color: Color) -> Vec<&str> {
+        self.attr_str(alias_key(color))
+            .map(|aliases| {
+                aliases
+                    .split(ALIAS_SEPARATOR)
+                    .filter(|alias| !alias.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns a player's name followed by their aliases.
+    pub fn player_names(&self, color: Color) -> Vec<&str> {
+        let name = match color {
+            Color::Black => self.black_player.as_deref(),
+            Color::White => self.white_player.as_deref(),
+        };
+        name.into_iter().chain(self.player_aliases(color)).collect()
+    }
+}
+
+/// Turns a player name into the form names are compared in, see
+/// [`crate::RecordFilter::matches_with`].
+///
+/// Implementations can go as far as they need, e.g. map romanized names to
+/// kanji; [`DefaultNormalizer`] only evens out how the same text is typed.
+pub trait NameNormalizer {
+    fn normalize(&self, name: &str) -> String;
+}
+
+/// Folds full-width ASCII and half-width katakana to their usual widths, as
+/// NFKC does for these characters, lowercases, and collapses runs of
+/// whitespace, the ideographic space included, into one ASCII space.
+///
+/// ```
+/// use csa::{DefaultNormalizer, NameNormalizer};
+///
+/// assert_eq!(DefaultNormalizer.normalize(" ＨＡＢＵ　 Yoshiharu "), "habu yoshiharu");
+/// assert_eq!(DefaultNormalizer.normalize("ﾊﾌﾞ"), "ハブ");
+/// ```
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefaultNormalizer;
+
+impl NameNormalizer for DefaultNormalizer {
+    fn normalize(&self, name: &str) -> String {
+        let mut folded = String::with_capacity(name.len());
+        for c in name.chars() {
+            fold_width(c, &mut folded);
+        }
+        let lowered = folded.to_lowercase();
+        lowered.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+}
+
+const HALF_WIDTH_KATAKANA: &str = "ｦｧｨｩｪｫｬｭｮｯｰｱｲｳｴｵｶｷｸｹｺｻｼｽｾｿﾀﾁﾂﾃﾄﾅﾆﾇﾈﾉﾊﾋﾌﾍﾎﾏﾐﾑﾒﾓﾔﾕﾖﾗﾘﾙﾚﾛﾜﾝ";
+const FULL_WIDTH_KATAKANA: &str = "ヲァィゥェォャュョッーアイウエオカキクケコサシスセソタチツテトナニヌネノハヒフヘホマミムメモヤユヨラリルレロワン";
+
+/// Pushes `c` onto `out` at its usual width, composing a half-width voiced
+/// sound mark with the kana before it.
+fn fold_width(c: char, out: &mut String) {
+    match c {
+        '\u{FF01}'..='\u{FF5E}' => out.push(char::from_u32(c as u32 - 0xFEE0).unwrap()),
+        '\u{3000}' => out.push(' '),
+        'ﾞ' | 'ﾟ' => {
+            let voiced = c == 'ﾞ';
+            let composed = out.chars().last().and_then(|prev| match prev {
+                'ウ' if voiced => Some('ヴ'),
+                _ if voiced && "カキクケコサシスセソタチツテト".contains(prev) => {
+                    char::from_u32(prev as u32 + 1)
+                }
+                'ハ' | 'ヒ' | 'フ' | 'ヘ' | 'ホ' => {
+                    char::from_u32(prev as u32 + if voiced { 1 } else { 2 })
+                }
+                _ => None,
+            });
+            match composed {
+                Some(composed) => {
+                    out.pop();
+                    out.push(composed);
+                }
+                None => out.push(if voiced { '\u{3099}' } else { '\u{309A}' }),
+            }
+        }
+        _ => match HALF_WIDTH_KATAKANA.chars().position(|h| h == c) {
+            Some(i) => out.push(FULL_WIDTH_KATAKANA.chars().nth(i).unwrap()),
+            None => out.push(c),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_csa;
+
+    #[test]
+    fn aliases() {
+        let mut game = parse_csa("V2.2\nN+HABU Yoshiharu\nN-Hoshi\nPI\n+\n").unwrap();
+        assert!(game.player_aliases(Color::Black).is_empty());
+
+        game.add_player_alias(Color::Black, "羽生善治");
+        game.add_player_alias(Color::Black, "羽生九段");
+        game.add_player_alias(Color::Black, "羽生善治");
+        game.add_player_alias(Color::Black, "a|b");
+        game.add_player_alias(Color::Black, "");
+        assert_eq!(game.player_aliases(Color::Black), ["羽生善治", "羽生九段"]);
+        assert!(game.player_aliases(Color::White).is_empty());
+        assert_eq!(
+            game.player_names(Color::Black),
+            ["HABU Yoshiharu", "羽生善治", "羽生九段"]
+        );
+
+        let reparsed = parse_csa(&game.to_string()).unwrap();
+        assert_eq!(reparsed, game);
+    }
+
+    #[test]
+    fn default_normalizer() {
+        let cases = [
+            ("Habu  Yoshiharu", "habu yoshiharu"),
+            (
+                "\u{3000}ＨＡＢＵ\u{3000}Ｙｏｓｈｉｈａｒｕ",
+                "habu yoshiharu",
+            ),
+            ("羽生　善治", "羽生 善治"),
+            ("ｶﾞﾝﾊﾞﾚ", "ガンバレ"),
+            ("ﾎﾟﾝﾀ", "ポンタ"),
+            ("ﾂﾞﾄﾞ", "ヅド"),
+            ("ｳﾞｨ", "ヴィ"),
+            ("ﾝﾞ", "ン\u{3099}"),
+            ("", ""),
+        ];
+        for (name, normalized) in cases {
+            assert_eq!(DefaultNormalizer.normalize(name), normalized, "{name}");
+        }
+    }
+}
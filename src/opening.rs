@@ -0,0 +1,386 @@
+//! Opening names, as declared by `$OPENING` and as told from the moves.
+
+use std::fmt;
+use std::sync::Arc;
+#[cfg(feature = "table-override")]
+use std::sync::OnceLock;
+
+use crate::board::Board;
+use crate::value::{Action, Color, GameRecord, PieceType, Position, Square};
+
+/// An opening with an agreed name.
+///
+/// CSA files name openings in romanized Japanese, as in `$OPENING:YAGURA`,
+/// while KIF files write the same name in Japanese in their `戦型` header.
+/// Each variant maps to one name of each kind.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum OpeningName {
+    /// 矢倉
+    Yagura,
+    /// 角換わり
+    Kakugawari,
+    /// 横歩取り
+    Yokofudori,
+    /// 相掛かり
+    Aigakari,
+    /// 中飛車
+    Nakabisha,
+    /// 四間飛車
+    Shikenbisha,
+    /// 三間飛車
+    Sankenbisha,
+    /// 向かい飛車
+    Mukaibisha,
+    /// 相振り飛車
+    Aifuribisha,
+}
+
+/// The CSA and Japanese names of each opening. The first CSA name is the one
+/// written; the others are spellings seen in the wild.
+const NAMES: [(OpeningName, &[&str], &str); 9] = [
+    (OpeningName::Yagura, &["YAGURA"], "矢倉"),
+    (
+        OpeningName::Kakugawari,
+        &["KAKUGAWARI", "KAKUKAWARI"],
+        "角換わり",
+    ),
+    (
+        OpeningName::Yokofudori,
+        &["YOKOFUDORI", "YOKOFUTORI"],
+        "横歩取り",
+    ),
+    (OpeningName::Aigakari, &["AIGAKARI"], "相掛かり"),
+    (OpeningName::Nakabisha, &["NAKABISHA"], "中飛車"),
+    (OpeningName::Shikenbisha, &["SHIKENBISHA"], "四間飛車"),
+    (OpeningName::Sankenbisha, &["SANKENBISHA"], "三間飛車"),
+    (
+        OpeningName::Mukaibisha,
+        &["MUKAIBISHA", "MUKAIHISHA"],
+        "向かい飛車",
+    ),
+    (
+        OpeningName::Aifuribisha,
+        &["AIFURIBISHA", "AIFURI"],
+        "相振り飛車",
+    ),
+];
+
+impl OpeningName {
+    /// Looks up a CSA name such as `YAGURA`, ignoring case, or a Japanese
+    /// name such as `矢倉`.
+    pub fn parse(s: &str) -> Option<OpeningName> {
+        let s = s.trim();
+        NAMES
+            .iter()
+            .find(|(_, csa, japanese)| {
+                csa.iter().any(|name| name.eq_ignore_ascii_case(s)) || *japanese == s
+            })
+            .map(|(name, ..)| *name)
+    }
+
+    /// Returns the name as written in `$OPENING`, e.g. `YAGURA`.
+    pub fn csa_name(self) -> &'static str {
+        self.entry().1[0]
+    }
+
+    /// Returns the name as written in a KIF `戦型` header, e.g. `矢倉`.
+    pub fn japanese_name(self) -> &'static str {
+        self.entry().2
+    }
+
+    fn entry(self) -> &'static (OpeningName, &'static [&'static str], &'static str) {
+        NAMES.iter().find(|(name, ..)| *name == self).unwrap()
+    }
+}
+
+impl fmt::Display for OpeningName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.csa_name())
+    }
+}
+
+/// An opening as declared in a record: the text as written, and the opening
+/// it names if it is one of [`OpeningName`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct OpeningTag {
+    pub raw: Arc<str>,
+    pub name: Option<OpeningName>,
+}
+
+impl OpeningTag {
+    /// Reads a declared opening, in either vocabulary.
+    pub fn parse(raw: impl Into<Arc<str>>) -> OpeningTag {
+        let raw = raw.into();
+        OpeningTag {
+            name: OpeningName::parse(&raw),
+            raw,
+        }
+    }
+
+    /// Returns the tag as `$OPENING` should hold it: the CSA name of a known
+    /// opening, or the text as written.
+    pub fn to_csa(&self) -> &str {
+        match self.name {
+            Some(name) => name.csa_name(),
+            None => &self.raw,
+        }
+    }
+
+    /// Returns the tag as a KIF `戦型` header should hold it: the Japanese
+    /// name of a known opening, or the text as written.
+    pub fn to_japanese(&self) -> &str {
+        match self.name {
+            Some(name) => name.japanese_name(),
+            None => &self.raw,
+        }
+    }
+}
+
+impl fmt::Display for OpeningTag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.raw)
+    }
+}
+
+/// What [`GameRecord::classify_opening_with`] tells openings by.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct OpeningTable {
+    /// The opening of a side whose rook first moves along its starting rank
+    /// to each file, the 1st to the 9th counted from the owner's side.
+    pub ranging: [Option<OpeningName>; 9],
+    /// The opening when both sides have one from `ranging`.
+    pub both_ranging: Option<OpeningName>,
+}
+
+impl OpeningTable {
+    /// The table of [`GameRecord::classify_opening`], unless replaced with
+    /// `set_opening_table` with the `table-override` feature.
+    pub const STANDARD: OpeningTable = OpeningTable {
+        ranging: [
+            None,
+            None,
+            None,
+            None,
+            Some(OpeningName::Nakabisha),
+            Some(OpeningName::Shikenbisha),
+            Some(OpeningName::Sankenbisha),
+            Some(OpeningName::Mukaibisha),
+            None,
+        ],
+        both_ranging: Some(OpeningName::Aifuribisha),
+    };
+}
+
+#[cfg(feature = "table-override")]
+static OPENING_TABLE: OnceLock<OpeningTable> = OnceLock::new();
+
+/// Returns the table [`GameRecord::classify_opening`] uses:
+/// [`OpeningTable::STANDARD`], or the one given to `set_opening_table`.
+pub fn opening_table() -> &'static OpeningTable {
+    #[cfg(feature = "table-override")]
+    return OPENING_TABLE.get_or_init(|| OpeningTable::STANDARD);
+    #[cfg(not(feature = "table-override"))]
+    &OpeningTable::STANDARD
+}
+
+/// Replaces the table [`GameRecord::classify_opening`] uses for the rest of
+/// the program, with the `table-override` feature.
+///
+/// The table can only be set once, before any opening is classified with
+/// it: later calls return the table given back as the error, and openings
+/// classified so far never change. To use several tables, pass them to
+/// [`GameRecord::classify_opening_with`] instead.
+///
+/// ```
+/// use csa::{opening_table, parse_csa, set_opening_table, OpeningName, OpeningTable};
+///
+/// let mut table = OpeningTable::STANDARD;
+/// table.ranging[5] = Some(OpeningName::Nakabisha);
+/// set_opening_table(table.clone()).unwrap();
+///
+/// let game = parse_csa("V2.2\nPI\n+\n+7776FU\n-3334FU\n+2868HI\n").unwrap();
+/// assert_eq!(game.classify_opening(), Some(OpeningName::Nakabisha));
+/// assert!(set_opening_table(OpeningTable::STANDARD).is_err());
+/// assert_eq!(opening_table(), &table);
+/// ```
+#[cfg(feature = "table-override")]
+pub fn set_opening_table(table: OpeningTable) -> Result<(), OpeningTable> {
+    OPENING_TABLE.set(table)
+}
+
+impl GameRecord {
+    /// Returns the opening declared by `$OPENING`.
+    pub fn opening_tag(&self) -> Option<OpeningTag> {
+        self.opening.clone().map(OpeningTag::parse)
+    }
+
+    /// Tells the opening from the moves, for comparison with
+    /// [`GameRecord::opening_tag`].
+    ///
+    /// Only ranging rook openings are told apart, by the file each rook is
+    /// first moved to along its starting rank, counted from its owner's side:
+    /// the 5th file is [`OpeningName::Nakabisha`], the 6th
+    /// [`OpeningName::Shikenbisha`], the 7th [`OpeningName::Sankenbisha`] and
+    /// the 8th [`OpeningName::Mukaibisha`]. When both sides range the rook the
+    /// game is [`OpeningName::Aifuribisha`]. Other games, and games that do
+    /// not start from the standard position, give `None`.
+    ///
+    /// ```
+    /// use csa::{parse_csa, OpeningName};
+    ///
+    /// let game =
+    ///     parse_csa("V2.2\n$OPENING:SHIKENBISHA\nPI\n+\n+7776FU\n-3334FU\n+2868HI\n").unwrap();
+    /// assert_eq!(game.classify_opening(), Some(OpeningName::Shikenbisha));
+    /// assert_eq!(game.opening_tag().unwrap().name, game.classify_opening());
+    /// ```
+    pub fn classify_opening(&self) -> Option<OpeningName> {
+        self.classify_opening_with(opening_table())
+    }
+
+    /// Tells the opening from the moves as [`GameRecord::classify_opening`]
+    /// does, by the files and names of `table`.
+    ///
+    /// ```
+    /// use csa::{parse_csa, OpeningName, OpeningTable};
+    ///
+    /// let game = parse_csa("V2.2\nPI\n+\n+7776FU\n-3334FU\n+2868HI\n").unwrap();
+    /// let mut table = OpeningTable::STANDARD;
+    /// table.ranging[5] = Some(OpeningName::Nakabisha);
+    /// assert_eq!(game.classify_opening_with(&table), Some(OpeningName::Nakabisha));
+    /// ```
+    pub fn classify_opening_with(&self, table: &OpeningTable) -> Option<OpeningName> {
+        let standard = Board::from_position(&Position::default()).ok();
+        if self.start_board().ok() != standard {
+            return None;
+        }
+        let mut files = [None; 2];
+        for record in &self.moves {
+            let Action::Move(color, from, to, PieceType::Rook) = record.action else {
+                continue;
+            };
+            let file = &mut files[color as usize];
+            let (home, rank) = match color {
+                Color::Black => (Square::new(2, 8), 8),
+                Color::White => (Square::new(8, 2), 2),
+            };
+            if file.is_none() && from == home {
+                // Counted from the owner's side, so Black's 28 rook is on
+                // the 2nd file and White's 82 rook too.
+                let own_file = match color {
+                    Color::Black => to.file,
+                    Color::White => 10 - to.file,
+                };
+                *file = Some(if to.rank == rank { own_file } else { 2 });
+            }
+            if files.iter().all(Option::is_some) {
+                break;
+            }
+        }
+
+        let ranging = |file: Option<u8>| *table.ranging.get(usize::from(file?).checked_sub(1)?)?;
+        match (ranging(files[0]), ranging(files[1])) {
+            (Some(_), Some(_)) => table.both_ranging,
+            (black, white) => black.or(white),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_csa;
+
+    #[test]
+    fn names_round_trip() {
+        for (name, csa, japanese) in NAMES {
+            assert_eq!(OpeningName::parse(name.csa_name()), Some(name));
+            assert_eq!(OpeningName::parse(name.japanese_name()), Some(name));
+            assert_eq!(name.csa_name(), csa[0]);
+            assert_eq!(name.japanese_name(), japanese);
+        }
+        assert_eq!(
+            OpeningName::parse("kakukawari"),
+            Some(OpeningName::Kakugawari)
+        );
+        assert_eq!(OpeningName::parse("UNKNOWN"), None);
+    }
+
+    #[test]
+    fn tags() {
+        let tag = OpeningTag::parse("矢倉");
+        assert_eq!(tag.name, Some(OpeningName::Yagura));
+        assert_eq!(tag.to_csa(), "YAGURA");
+        assert_eq!(tag.to_japanese(), "矢倉");
+        assert_eq!(tag.to_string(), "矢倉");
+
+        // Unknown names pass through as written.
+        let tag = OpeningTag::parse("GOKIGEN");
+        assert_eq!(tag.name, None);
+        assert_eq!(tag.to_csa(), "GOKIGEN");
+        assert_eq!(tag.to_japanese(), "GOKIGEN");
+
+        let game = parse_csa("V2.2\n$OPENING:YAGURA\nPI\n+\n").unwrap();
+        assert_eq!(game.opening_tag(), Some(OpeningTag::parse("YAGURA")));
+        assert_eq!(parse_csa("V2.2\nPI\n+\n").unwrap().opening_tag(), None);
+    }
+
+    #[test]
+    fn classify() {
+        let classify = |moves: &str| {
+            parse_csa(&format!("V2.2\nPI\n+\n{moves}"))
+                .unwrap()
+                .classify_opening()
+        };
+
+        assert_eq!(
+            classify("+7776FU\n-3334FU\n+2878HI\n"),
+            Some(OpeningName::Sankenbisha)
+        );
+        assert_eq!(
+            classify("+7776FU\n-3334FU\n+2726FU\n-8252HI\n"),
+            Some(OpeningName::Nakabisha)
+        );
+        assert_eq!(
+            classify("+7776FU\n-3334FU\n+2858HI\n-8222HI\n"),
+            Some(OpeningName::Aifuribisha)
+        );
+        // A rook moved up its file first stays a static rook.
+        assert_eq!(classify("+2726FU\n-8384FU\n+2827HI\n"), None);
+        assert_eq!(classify("+7776FU\n-3334FU\n"), None);
+
+        let handicap = parse_csa("V2.2\nPI82HI\n-\n-3334FU\n+2868HI\n").unwrap();
+        assert_eq!(handicap.classify_opening(), None);
+    }
+
+    #[test]
+    fn tables() {
+        let game = parse_csa("V2.2\nPI\n+\n+7776FU\n-3334FU\n+2858HI\n-8222HI\n").unwrap();
+        let mut table = OpeningTable::STANDARD;
+        assert_eq!(
+            game.classify_opening_with(&table),
+            Some(OpeningName::Aifuribisha)
+        );
+        table.both_ranging = None;
+        assert_eq!(game.classify_opening_with(&table), None);
+        // White's rook went to its own 8th file.
+        table.ranging[4] = None;
+        table.ranging[7] = Some(OpeningName::Yagura);
+        assert_eq!(
+            game.classify_opening_with(&table),
+            Some(OpeningName::Yagura)
+        );
+        assert_eq!(game.classify_opening(), Some(OpeningName::Aifuribisha));
+    }
+
+    #[cfg(feature = "table-override")]
+    #[test]
+    fn set_table() {
+        // Other tests may have classified an opening already, which fixes the
+        // table, so the standard one is set again here.
+        let _ = set_opening_table(OpeningTable::STANDARD);
+        let mut custom = OpeningTable::STANDARD;
+        custom.both_ranging = None;
+        assert_eq!(set_opening_table(custom.clone()), Err(custom));
+        assert_eq!(opening_table(), &OpeningTable::STANDARD);
+    }
+}
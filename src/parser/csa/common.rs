@@ -0,0 +1,847 @@
+//! Helpers shared by the version-specific CSA parsers
+
+use std::borrow::Cow;
+use std::ops::ControlFlow;
+use std::time::{Duration, Instant};
+
+use pest::error::LineColLocation;
+use pest::iterators::Pair;
+use pest::RuleType;
+
+use super::visitor::Visitor;
+use super::{
+    CommentedAttrPolicy, DuplicatePolicy, GridSplitPolicy, MaxMovesPolicy, ParseMetrics,
+    ParseOptions, ParseOutput, ParseWarning, RemovalPolicy, SeparatorPolicy, Version,
+    VersionPolicy,
+};
+use crate::value::{parse_duration, removal_problem};
+use crate::value::{
+    Action, Adjournment, AttrValue, Bookmark, Color, GameRecord, InterMoveEvent, MoveRecord,
+    PieceList, PieceType, Position, RecordText, SourceFormat, Square, Time, TimeLimit, TimeUnit,
+    MAX_TIME,
+};
+
+/// Returns the 1-based line number a statement starts on.
+pub(crate) fn line_of<R: RuleType>(pair: &Pair<R>) -> usize {
+    pair.line_col().0
+}
+
+/// A statement that only some CSA versions allow, from `since` to `until`.
+pub(crate) struct Construct {
+    /// Plural noun phrase used in messages, e.g. "millisecond time values".
+    what: &'static str,
+    since: Version,
+    until: Version,
+}
+
+impl Construct {
+    const fn new(what: &'static str, since: Version, until: Version) -> Construct {
+        Construct { what, since, until }
+    }
+
+    fn allows(&self, version: Version) -> bool {
+        (self.since..=self.until).contains(&version)
+    }
+
+    fn requirement(&self) -> String {
+        match (self.since, self.until) {
+            (since, until) if since == until => format!("{} require {since}", self.what),
+            (since, Version::V3) => format!("{} require {since} or later", self.what),
+            (_, until) => format!("{} require {until} or earlier", self.what),
+        }
+    }
+}
+
+const MILLISECOND_TIME: Construct =
+    Construct::new("millisecond time values", Version::V3, Version::V3);
+
+/// Special moves that are not in every version.
+const SPECIAL_MOVES: &[(&str, Construct)] = &[
+    (
+        "%TIME_UP",
+        Construct::new("%TIME_UP moves", Version::V2_1, Version::V3),
+    ),
+    (
+        "%ILLEGAL_MOVE",
+        Construct::new("%ILLEGAL_MOVE moves", Version::V2_1, Version::V3),
+    ),
+    (
+        "%+ILLEGAL_ACTION",
+        Construct::new("%+ILLEGAL_ACTION moves", Version::V2_2, Version::V3),
+    ),
+    (
+        "%-ILLEGAL_ACTION",
+        Construct::new("%-ILLEGAL_ACTION moves", Version::V2_2, Version::V3),
+    ),
+    (
+        "%KACHI",
+        Construct::new("%KACHI moves", Version::V2_1, Version::V3),
+    ),
+    (
+        "%HIKIWAKE",
+        Construct::new("%HIKIWAKE moves", Version::V2_1, Version::V3),
+    ),
+    (
+        "%MATTA",
+        Construct::new("%MATTA moves", Version::V2, Version::V2_2),
+    ),
+    (
+        "%MAX_MOVES",
+        Construct::new("%MAX_MOVES moves", Version::V3, Version::V3),
+    ),
+];
+
+/// Reports the time of a `T` line, given the text after the `T`, such as `15`
+/// or, from V3.0, `15.123`. A time that is malformed or over [`MAX_TIME`] is
+/// reported as a warning instead, so that no time is made up for the move.
+pub(crate) fn visit_time(visitor: &mut impl Visitor, text: &str, line: usize) -> ControlFlow<()> {
+    if let Some(time) = parse_duration(text) {
+        return visitor.on_time(time, line);
+    }
+    let message = if !text.is_empty() && text.bytes().all(|b| b.is_ascii_digit() || b == b'.') {
+        format!(
+            "time consumed `T{text}` over the maximum of {} seconds, ignoring it",
+            MAX_TIME.as_secs()
+        )
+    } else {
+        format!("malformed time consumed `T{text}`, ignoring it")
+    };
+    visitor.on_warning(ParseWarning { line, message })
+}
+
+/// Returns why `version` does not allow a move or time statement, such as
+/// "%KACHI moves require V2.1 or later", or `None` when it does. The writer
+/// checks the statements it writes with this too.
+pub(crate) fn statement_requirement(version: Version, statement: &str) -> Option<String> {
+    let statement = statement.trim_end();
+    let construct = if statement.starts_with('T') && statement.contains('.') {
+        &MILLISECOND_TIME
+    } else {
+        &SPECIAL_MOVES.iter().find(|(s, _)| *s == statement)?.1
+    };
+    (!construct.allows(version)).then(|| construct.requirement())
+}
+
+/// Checks that a move or time statement is allowed by `version`. The grammars
+/// accept the statements of every version so that this can name the version
+/// that does allow it. When the policy lets the statement through, the
+/// warning goes to `visitor`.
+pub(crate) fn check_statement(
+    visitor: &mut impl Visitor,
+    options: &ParseOptions,
+    version: Version,
+    statement: &str,
+    line: usize,
+) -> Result<ControlFlow<()>, String> {
+    let Some(requirement) = statement_requirement(version, statement) else {
+        return Ok(ControlFlow::Continue(()));
+    };
+
+    let message = format!("{requirement}, but this file is {version}");
+    match options.other_versions {
+        VersionPolicy::Error => Err(format!("line {line}: {message}")),
+        VersionPolicy::Warn => Ok(visitor.on_warning(ParseWarning { line, message })),
+    }
+}
+
+/// Reads the two digits of a square, `00` for [`Square::HAND`] when `hand`
+/// allows it, failing for a square off the board.
+pub(crate) fn square_at(text: &str, hand: bool, line: usize) -> Result<Square, String> {
+    let square = raw_square(text);
+    if hand && square.is_hand() {
+        return Ok(square);
+    }
+    Square::try_new(square.file, square.rank).map_err(|e| format!("line {line}: {e}"))
+}
+
+/// Reads the two digits of a square without checking it, for the `PI`
+/// removals [`ParseOptions::bad_removals`] judges.
+pub(crate) fn raw_square(text: &str) -> Square {
+    let mut digits = text.bytes().map(|b| b.wrapping_sub(b'0'));
+    Square::new(digits.next().unwrap_or(0), digits.next().unwrap_or(0))
+}
+
+/// Reads a special move CSA does not define, such as `%DRAW_OFFER`, as
+/// registered in [`ParseOptions::custom_specials`].
+pub(crate) fn custom_special(
+    options: &ParseOptions,
+    statement: &str,
+    line: usize,
+) -> Result<Action, String> {
+    let token = &statement[1..];
+    options
+        .custom_specials
+        .and_then(|specials| specials.get(token))
+        .ok_or_else(|| format!("line {line}: unknown special move {statement}"))
+}
+
+/// Rejects or warns about an attribute found on `line`, between the rows of a
+/// grid, as [`ParseOptions::split_grids`] says.
+pub(crate) fn check_grid_split(
+    visitor: &mut impl Visitor,
+    options: &ParseOptions,
+    line: usize,
+) -> Result<ControlFlow<()>, String> {
+    let message = "attribute line splits the position grid".to_string();
+    match options.split_grids {
+        GridSplitPolicy::Error => Err(format!("line {line}: {message}")),
+        GridSplitPolicy::Warn => Ok(visitor.on_warning(ParseWarning { line, message })),
+    }
+}
+
+/// Returns whether `line` is a decorative separator, see [`SeparatorPolicy`].
+pub(crate) fn is_separator(line: &str) -> bool {
+    let line = line.trim();
+    let decorative = |c: char| {
+        c.is_ascii_punctuation() || ('\u{2500}'..='\u{257f}').contains(&c) || "－＝＊".contains(c)
+    };
+    !line.starts_with('\'')
+        && line.chars().filter(|c| !c.is_whitespace()).count() >= 3
+        && line.chars().all(|c| c.is_whitespace() || decorative(c))
+}
+
+/// Under [`SeparatorPolicy::Warn`], returns `input` with each separator line
+/// emptied to a bare `'` comment, keeping the line numbers, and warns
+/// `visitor` about each.
+pub(crate) fn skip_separators<'i>(
+    visitor: &mut impl Visitor,
+    options: &ParseOptions,
+    input: &'i str,
+) -> (Cow<'i, str>, ControlFlow<()>) {
+    if options.separators == SeparatorPolicy::Error || !input.lines().any(is_separator) {
+        return (Cow::Borrowed(input), ControlFlow::Continue(()));
+    }
+    let mut output = String::with_capacity(input.len());
+    let mut flow = ControlFlow::Continue(());
+    for (i, line) in input.split_inclusive('\n').enumerate() {
+        let text = line.trim_end_matches(['\r', '\n']);
+        if flow.is_continue() && is_separator(text) {
+            output.push('\'');
+            output.push_str(&line[text.len()..]);
+            flow = visitor.on_warning(ParseWarning {
+                line: i + 1,
+                message: "skipped a decorative separator line".to_string(),
+            });
+        } else {
+            output.push_str(line);
+        }
+    }
+    (Cow::Owned(output), flow)
+}
+
+/// Describes a grammar error, pointing out a separator line the grammar
+/// stopped on or at.
+pub(crate) fn grammar_error<R: RuleType>(input: &str, err: pest::error::Error<R>) -> String {
+    let (LineColLocation::Pos((line, _)) | LineColLocation::Span((line, _), _)) = err.line_col;
+    let separator = input
+        .lines()
+        .enumerate()
+        .take(line)
+        .find(|(_, text)| is_separator(text));
+    match separator {
+        Some((i, text)) => format!(
+            "line {}: `{}` looks like a decorative separator line, which CSA does not \
+             allow; SeparatorPolicy::Warn skips such lines",
+            i + 1,
+            text.trim()
+        ),
+        None => err.to_string(),
+    }
+}
+
+/// Times the phases of a parse, when [`ParseOptions::metrics`] asks for it;
+/// otherwise it never reads the clock.
+pub(crate) struct Stopwatch {
+    last: Option<Instant>,
+}
+
+impl Stopwatch {
+    pub fn new(enabled: bool) -> Self {
+        Stopwatch {
+            last: enabled.then(Instant::now),
+        }
+    }
+
+    /// Returns the time since the last lap, or since the start.
+    pub fn lap(&mut self) -> Duration {
+        match self.last.as_mut() {
+            Some(last) => {
+                let now = Instant::now();
+                let lap = now - *last;
+                *last = now;
+                lap
+            }
+            None => Duration::ZERO,
+        }
+    }
+}
+
+/// State threaded through a single parse: the options, the warnings raised so
+/// far and the line each once-only statement was first seen on.
+pub(crate) struct Context<'a> {
+    pub options: &'a ParseOptions<'a>,
+    pub warnings: Vec<ParseWarning>,
+    version: Version,
+    seen: Vec<(String, usize)>,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(options: &'a ParseOptions<'a>, version: Version) -> Self {
+        Context {
+            options,
+            warnings: Vec::new(),
+            version,
+            seen: Vec::new(),
+        }
+    }
+
+    /// Records an occurrence of a statement that should appear only once, such
+    /// as `$START_TIME` or `N+`, and returns whether it should be applied
+    /// according to the duplicate policy.
+    pub fn once(&mut self, what: &str, line: usize) -> Result<bool, String> {
+        let first = match self.seen.iter().find(|(w, _)| w == what) {
+            Some((_, first)) => *first,
+            None => {
+                self.seen.push((what.to_string(), line));
+                return Ok(true);
+            }
+        };
+
+        let (apply, keeping) = match self.options.duplicates {
+            DuplicatePolicy::KeepFirst => (false, "first"),
+            DuplicatePolicy::KeepLast => (true, "last"),
+            DuplicatePolicy::Error => {
+                return Err(format!(
+                    "duplicate {what} on line {line} (first on line {first})"
+                ));
+            }
+        };
+        self.warnings.push(ParseWarning {
+            line,
+            message: format!("duplicate {what} (first on line {first}), keeping the {keeping} one"),
+        });
+        Ok(apply)
+    }
+
+    /// Returns whether a once-only statement has been seen.
+    pub fn seen(&self, what: &str) -> bool {
+        self.seen.iter().any(|(w, _)| w == what)
+    }
+
+    /// Returns `s` as a record string, shared through the interner if any.
+    pub fn text(&self, s: &str) -> RecordText {
+        #[cfg(feature = "intern")]
+        if let Some(interner) = self.options.intern {
+            return interner.intern(s);
+        }
+        s.into()
+    }
+
+    /// Records a warning against `line`.
+    pub fn warn(&mut self, line: usize, message: String) {
+        self.warnings.push(ParseWarning { line, message });
+    }
+
+    /// Parses the value of a structured attribute, warning when it is not
+    /// recognized so the field is not dropped silently.
+    pub fn attr<T>(
+        &mut self,
+        key: &str,
+        value: &str,
+        line: usize,
+        parse: impl FnOnce(&str) -> Option<T>,
+    ) -> Option<T> {
+        let parsed = parse(value);
+        if parsed.is_none() {
+            self.warn(
+                line,
+                format!("unrecognized {key} value `{value}`, ignoring it"),
+            );
+        }
+        parsed
+    }
+}
+
+/// Returns the key and the value of a `$KEY:value` statement.
+pub(crate) fn attr_parts<'i, R: RuleType>(pair: Pair<'i, R>) -> (&'i str, &'i str) {
+    let mut inner = pair.into_inner();
+    let key = inner.next().map_or("", |key| key.as_str());
+    let value = inner.next().map_or("", |value| value.as_str());
+    (key, value)
+}
+
+/// The visitor every parser builds its `GameRecord` with.
+///
+/// `$EVENT`, `$SITE`, `$START_TIME`, `$END_TIME`, `$TIME_LIMIT` and `$OPENING`
+/// go to their own fields; any other attribute is kept in
+/// `GameRecord::extra_attrs`.
+pub(crate) struct RecordBuilder<'a> {
+    ctx: Context<'a>,
+    record: GameRecord,
+    /// `'CHUDAN:` comments since the last move, read into the adjournment
+    /// details if the game ends in `%CHUDAN`.
+    adjournment_comments: Vec<(String, usize)>,
+    /// A `'summary:` comment, checked against the end of the game.
+    summary: Option<(String, usize)>,
+    /// The number of plies read so far.
+    plies: usize,
+    /// Set once the version line is read, ending the header comments.
+    seen_version: bool,
+    /// Set when the last statement was a special move read as an event, so
+    /// a `T` line after it has no move to go to.
+    after_event: bool,
+    /// Set when a callback fails the parse.
+    error: Option<String>,
+    /// The number of comments read so far.
+    comments: usize,
+    stopwatch: Stopwatch,
+    grammar_time: Duration,
+}
+
+impl<'a> RecordBuilder<'a> {
+    pub fn new(options: &'a ParseOptions<'a>, version: Version) -> Self {
+        let mut record = GameRecord::default();
+        record.layout.source_format = Some(SourceFormat::Csa(version));
+        RecordBuilder {
+            ctx: Context::new(options, version),
+            record,
+            adjournment_comments: Vec::new(),
+            summary: None,
+            plies: 0,
+            seen_version: false,
+            after_event: false,
+            error: None,
+            comments: 0,
+            stopwatch: Stopwatch::new(options.metrics),
+            grammar_time: Duration::ZERO,
+        }
+    }
+
+    /// Marks the end of the grammar phase; what follows until
+    /// [`RecordBuilder::finish`] is the build phase.
+    pub fn end_grammar(&mut self) {
+        self.grammar_time = self.stopwatch.lap();
+    }
+
+    /// Returns the record read from `input`, or the error that stopped the
+    /// parse.
+    pub fn finish(mut self, input: &str) -> Result<ParseOutput, String> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        if let Some((summary, line)) = self.summary.take() {
+            self.check_summary(&summary, line);
+        }
+        let adjourned = matches!(self.record.moves.last(), Some(m) if m.action == Action::Chudan);
+        if adjourned {
+            for (text, line) in std::mem::take(&mut self.adjournment_comments) {
+                self.read_adjournment_comment(&text, line);
+            }
+        }
+        let metrics = self.ctx.options.metrics.then(|| ParseMetrics {
+            input_bytes: input.len(),
+            lines: input.lines().count(),
+            moves: self.record.moves.len(),
+            comments: self.comments,
+            warnings: self.ctx.warnings.len(),
+            detect_time: Duration::ZERO,
+            grammar_time: self.grammar_time,
+            build_time: self.stopwatch.lap(),
+        });
+        Ok(ParseOutput {
+            record: self.record,
+            warnings: self.ctx.warnings,
+            metrics,
+        })
+    }
+
+    /// Applies the duplicate policy to a once-only statement, stopping the
+    /// parse if it fails.
+    fn once(&mut self, what: &str, line: usize) -> ControlFlow<(), bool> {
+        match self.ctx.once(what, line) {
+            Ok(apply) => ControlFlow::Continue(apply),
+            Err(error) => {
+                self.error = Some(error);
+                ControlFlow::Break(())
+            }
+        }
+    }
+
+    fn push_move(&mut self, action: Action) -> ControlFlow<()> {
+        self.adjournment_comments.clear();
+        self.after_event = false;
+        self.record.moves.push(MoveRecord::new(action));
+        ControlFlow::Continue(())
+    }
+
+    /// Returns the event `line` is read as, see [`ParseOptions::event_convention`].
+    /// Before the first move there is no move to note it after, and the line
+    /// is read as usual.
+    fn event(&self, line: &str) -> Option<InterMoveEvent> {
+        self.record.moves.last()?;
+        self.ctx.options.event_convention?.event(line)
+    }
+
+    /// Notes `event` after the last move, keeping the convention it was read
+    /// with for the writer.
+    fn push_event(&mut self, event: InterMoveEvent) {
+        if let Some(convention) = self.ctx.options.event_convention {
+            let kept = &mut self.record.layout.event_convention;
+            kept.get_or_insert_with(|| convention.clone());
+        }
+        if let Some(last) = self.record.moves.last_mut() {
+            last.events.push(event);
+        }
+    }
+
+    /// Fails the parse when the game has gone past its move limit, see
+    /// [`MaxMovesPolicy`].
+    fn check_max_moves(&mut self, line: usize) -> ControlFlow<()> {
+        let max_moves = match self.ctx.options.max_moves {
+            MaxMovesPolicy::FromRecord => self.record.max_moves(),
+            MaxMovesPolicy::Limit(max_moves) => Some(max_moves),
+            MaxMovesPolicy::Unlimited => None,
+        };
+        match max_moves {
+            Some(max_moves) if self.plies > max_moves => {
+                self.error = Some(format!(
+                    "line {line}: ply {} is past the limit of {max_moves} moves",
+                    max_moves + 1
+                ));
+                ControlFlow::Break(())
+            }
+            _ => ControlFlow::Continue(()),
+        }
+    }
+
+    /// Reads a `'CHUDAN:` comment following a final `%CHUDAN` into the
+    /// record's adjournment details.
+    fn read_adjournment_comment(&mut self, text: &str, line: usize) {
+        let mut adjournment = self.record.adjournment.take().unwrap_or_default();
+        if !adjournment.read_comment(text) {
+            self.ctx
+                .warn(line, format!("unrecognized adjournment comment `{text}`"));
+        }
+        if adjournment != Adjournment::default() {
+            self.record.adjournment = Some(adjournment);
+        }
+    }
+
+    /// Reads a `'$KEY:value` comment as the attribute, unless a `$KEY` line
+    /// gives it. Other comments are left alone.
+    fn read_commented_attr(&mut self, text: &str, line: usize) {
+        let Some((key, value)) = text
+            .strip_prefix("'$")
+            .and_then(|attr| attr.split_once(':'))
+        else {
+            return;
+        };
+        let is_key = |c: char| c.is_ascii_uppercase() || c.is_ascii_digit() || "_+-".contains(c);
+        if key.is_empty() || !key.chars().all(is_key) || self.ctx.seen(&format!("${key}")) {
+            return;
+        }
+        self.record.extra_attrs.retain(|(k, _)| k != key);
+        self.apply_attr(key, value, line);
+    }
+
+    /// Warns when a `'summary:reason:name result:name result` comment states
+    /// another ending than the record, either another special move or, for a
+    /// resignation or a loss on time, another loser.
+    fn check_summary(&mut self, summary: &str, line: usize) {
+        let mut fields = summary.trim_start_matches("'summary:").split(':');
+        let reason = fields.next().unwrap_or_default();
+        let expected: &[Action] = match reason {
+            "toryo" => &[Action::Toryo],
+            "time up" => &[Action::TimeUp],
+            "sennichite" => &[Action::Sennichite],
+            "kachi" => &[Action::Kachi],
+            "abnormal" => &[Action::Chudan],
+            "illegal move" => &[
+                Action::IllegalMove,
+                Action::IllegalAction(Color::Black),
+                Action::IllegalAction(Color::White),
+            ],
+            _ => return,
+        };
+
+        let last = self.record.moves.last().map(|m| m.action);
+        let ending = match last {
+            Some(action) if !matches!(action, Action::Move(..)) => action,
+            _ => {
+                self.ctx.warn(
+                    line,
+                    format!("summary says `{reason}`, but the game ends without a special move"),
+                );
+                return;
+            }
+        };
+        if !expected.contains(&ending) {
+            self.ctx.warn(
+                line,
+                format!("summary says `{reason}`, but the game ends with {ending}"),
+            );
+            return;
+        }
+
+        if !matches!(ending, Action::Toryo | Action::TimeUp) {
+            return;
+        }
+        let loser = match self.record.ply_count() % 2 {
+            0 => self.record.start_pos.side_to_move,
+            _ => crate::board::flip(self.record.start_pos.side_to_move),
+        };
+        let (loser_name, winner_name) = match loser {
+            Color::Black => (&self.record.black_player, &self.record.white_player),
+            Color::White => (&self.record.white_player, &self.record.black_player),
+        };
+        let (Some(loser_name), Some(winner_name)) = (loser_name, winner_name) else {
+            return;
+        };
+        let said_to_lose = fields.any(|field| field == format!("{winner_name} lose"));
+        if loser_name != winner_name && said_to_lose {
+            let message = format!(
+                "summary has {winner_name} losing, but {loser_name} is the side to move at {ending}"
+            );
+            self.ctx.warn(line, message);
+        }
+    }
+
+    /// Reads the `Time_Unit` the `T` lines count in. It must come before
+    /// the moves, so that every time is read in it.
+    fn read_time_unit(&mut self, unit: &str, line: usize) {
+        let message = match TimeUnit::parse(unit) {
+            _ if !self.record.moves.is_empty() => {
+                format!("time unit `{unit}` after the first move, ignoring it")
+            }
+            Some(_) if self.record.time_unit.is_some() => {
+                format!("second time unit `{unit}`, ignoring it")
+            }
+            Some(unit) => {
+                self.record.time_unit = Some(unit);
+                return;
+            }
+            None => format!("unknown time unit `{unit}`, reading times in seconds"),
+        };
+        self.ctx.warn(line, message);
+    }
+
+    fn apply_attr(&mut self, key: &str, value: &str, line: usize) {
+        let ctx = &mut self.ctx;
+        let record = &mut self.record;
+        match key {
+            // Free-text attributes take the raw value, even when it happens to
+            // look like a date or a time limit.
+            "EVENT" => record.event = Some(ctx.text(value)),
+            "SITE" => record.site = Some(ctx.text(value)),
+            "OPENING" => record.opening = Some(ctx.text(value)),
+            "START_TIME" => record.start_time = ctx.attr(key, value, line, Time::parse),
+            "END_TIME" => record.end_time = ctx.attr(key, value, line, Time::parse),
+            "TIME_LIMIT" => record.set_time_limit(ctx.attr(key, value, line, TimeLimit::parse)),
+            "RATED" => {
+                let value = AttrValue::parse(value);
+                record.rated = ctx.attr(key, value.raw(), line, |_| value.as_bool());
+                record.extra_attrs.push((key.to_string(), value));
+            }
+            _ => {
+                // `$TIME` is also kept as written: `$TIME_LIMIT` cannot carry
+                // the increment when the record is written back.
+                let time_control = matches!(key, "TIME" | "TIME+" | "TIME-");
+                if time_control && ctx.version == Version::V3 {
+                    if let Some(limit) = ctx.attr(key, value, line, TimeLimit::parse_time_control) {
+                        match key {
+                            "TIME+" => record.time_limit_black = Some(limit),
+                            "TIME-" => record.time_limit_white = Some(limit),
+                            _ => record.set_time_limit(Some(limit)),
+                        }
+                    }
+                }
+                record
+                    .extra_attrs
+                    .push((key.to_string(), AttrValue::parse(value)));
+            }
+        }
+    }
+}
+
+impl Visitor for RecordBuilder<'_> {
+    fn on_encoding(&mut self, _encoding: &str, _line: usize) -> ControlFlow<()> {
+        self.record.layout.encoding_line = true;
+        ControlFlow::Continue(())
+    }
+
+    fn on_version(&mut self, _version: Version, _line: usize) -> ControlFlow<()> {
+        self.seen_version = true;
+        ControlFlow::Continue(())
+    }
+
+    fn on_player(&mut self, color: Color, name: Option<&str>, line: usize) -> ControlFlow<()> {
+        let (what, player, bare) = match color {
+            Color::Black => (
+                "N+",
+                &mut self.record.black_player,
+                &mut self.record.layout.bare_black_player,
+            ),
+            Color::White => (
+                "N-",
+                &mut self.record.white_player,
+                &mut self.record.layout.bare_white_player,
+            ),
+        };
+        match self.ctx.once(what, line) {
+            Ok(true) => {
+                *player = name.map(|name| self.ctx.text(name));
+                *bare = name.is_none();
+                ControlFlow::Continue(())
+            }
+            Ok(false) => ControlFlow::Continue(()),
+            Err(error) => {
+                self.error = Some(error);
+                ControlFlow::Break(())
+            }
+        }
+    }
+
+    fn on_attr(&mut self, key: &str, value: &str, line: usize) -> ControlFlow<()> {
+        if self.once(&format!("${key}"), line)? {
+            self.record.extra_attrs.retain(|(k, _)| k != key);
+            self.apply_attr(key, value, line);
+            let trailing = &mut self.record.layout.trailing_attrs;
+            trailing.retain(|k| k != key);
+            if !self.record.moves.is_empty() {
+                trailing.push(key.to_string());
+            }
+            if key == "MAX_MOVES" {
+                return self.check_max_moves(line);
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn on_position(&mut self, mut position: Position, line: usize) -> ControlFlow<()> {
+        let mut kept = PieceList::new();
+        for i in 0..position.drop_pieces.len() {
+            let Some(problem) = removal_problem(&position.drop_pieces, i) else {
+                kept.push(position.drop_pieces[i]);
+                continue;
+            };
+            match self.ctx.options.bad_removals {
+                RemovalPolicy::Error => {
+                    self.error = Some(format!("line {line}: {problem}"));
+                    return ControlFlow::Break(());
+                }
+                RemovalPolicy::Warn => self.ctx.warn(line, format!("{problem}, ignoring it")),
+            }
+        }
+        position.drop_pieces = kept;
+
+        if self.once("position block", line)? {
+            self.record.start_pos = position;
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn on_side_to_move(&mut self, color: Color, _line: usize) -> ControlFlow<()> {
+        self.record.start_pos.side_to_move = color;
+        ControlFlow::Continue(())
+    }
+
+    fn on_late_placement(
+        &mut self,
+        placements: PieceList<(Color, Square, PieceType)>,
+        line: usize,
+    ) -> ControlFlow<()> {
+        self.ctx.warn(
+            line,
+            "P+/P- line after the side to move, applied to the position".to_string(),
+        );
+        self.record.start_pos.add_pieces.extend(placements);
+        ControlFlow::Continue(())
+    }
+
+    fn on_move(&mut self, action: Action, line: usize) -> ControlFlow<()> {
+        self.plies += 1;
+        self.push_move(action)?;
+        self.check_max_moves(line)
+    }
+
+    fn on_special(&mut self, action: Action, _line: usize) -> ControlFlow<()> {
+        if let Action::Custom { token, .. } = action {
+            if let Some(event) = self.event(&format!("%{}", token.as_str())) {
+                self.push_event(event);
+                self.after_event = true;
+                return ControlFlow::Continue(());
+            }
+        }
+        self.push_move(action)
+    }
+
+    fn on_time(&mut self, time: Duration, line: usize) -> ControlFlow<()> {
+        if self.after_event {
+            self.ctx.warn(
+                line,
+                "time consumed after an event, ignoring it".to_string(),
+            );
+            return ControlFlow::Continue(());
+        }
+        let time = match self.record.time_unit {
+            Some(unit) => match unit.real(time) {
+                Some(time) => time,
+                None => {
+                    let message = format!(
+                        "time consumed over the maximum of {} seconds in units of {unit}, \
+                         ignoring it",
+                        MAX_TIME.as_secs()
+                    );
+                    self.ctx.warn(line, message);
+                    return ControlFlow::Continue(());
+                }
+            },
+            None => time,
+        };
+        if let Some(last) = self.record.moves.last_mut() {
+            last.time = Some(time);
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn on_comment(&mut self, text: &str, line: usize) -> ControlFlow<()> {
+        self.comments += 1;
+        if !self.seen_version {
+            self.record.header_comments.push(text.to_string());
+            return ControlFlow::Continue(());
+        }
+        if let Some(event) = self.event(text) {
+            self.push_event(event);
+            return ControlFlow::Continue(());
+        }
+        if let Some(bookmark) = Bookmark::from_comment(text, self.plies) {
+            self.record.bookmarks.push(bookmark);
+        }
+        if text.starts_with("'CHUDAN:") {
+            self.adjournment_comments.push((text.to_string(), line));
+        }
+        if let Some(unit) = text.strip_prefix("'Time_Unit:") {
+            self.read_time_unit(unit, line);
+        }
+        if let Some((color, rating)) = self.record.rating_comment(text) {
+            match color {
+                Color::Black => self.record.black_rating = Some(rating),
+                Color::White => self.record.white_rating = Some(rating),
+            }
+            self.record.layout.rating_comments.push(text.to_string());
+        }
+        if self.ctx.options.commented_attrs == CommentedAttrPolicy::Read {
+            if text.starts_with("'summary:") {
+                self.summary = Some((text.to_string(), line));
+            } else {
+                self.read_commented_attr(text, line);
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn on_warning(&mut self, warning: ParseWarning) -> ControlFlow<()> {
+        self.ctx.warnings.push(warning);
+        ControlFlow::Continue(())
+    }
+}
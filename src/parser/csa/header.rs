@@ -0,0 +1,189 @@
+//! Reading the header of a CSA file without parsing the rest.
+
+use std::ops::ControlFlow;
+
+use super::common::RecordBuilder;
+use super::visitor::Visitor;
+use super::{detect_version, ParseError, ParseOptions, ParseWarning, Version};
+use crate::value::{Color, GameRecord};
+
+/// The header of a CSA file: its version, players and attributes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameHeader {
+    pub version: Version,
+    /// The record with only the header applied: the players and the
+    /// attributes are filled in the same way the full parse fills them, while
+    /// the position and the moves are left at their defaults.
+    pub record: GameRecord,
+    /// Whether the input ended before the position, as a truncated file does.
+    /// The header then holds what was found up to that point.
+    pub truncated: bool,
+    pub warnings: Vec<ParseWarning>,
+}
+
+/// Reads the header of a CSA file, stopping at the position.
+///
+/// Only the comment, version, `N+`/`N-` and `$` lines at the start of the file
+/// are looked at, up to the first statement of another kind, so this stays
+/// fast on long games and works on files cut short in the header. The
+/// statements are applied by the same code as in the full parse, duplicate
+/// policy and warnings included. `$` lines that come after the position,
+/// which the full parse also reads, are not seen.
+pub fn parse_header(input: &str, options: &ParseOptions) -> Result<GameHeader, ParseError> {
+    let version = detect_version(input)
+        .ok_or_else(|| ParseError("No version found or unsupported version".to_string()))?;
+    let mut builder = RecordBuilder::new(options, version);
+
+    let mut truncated = true;
+    let mut lines = input.split('\n').enumerate().peekable();
+    'lines: while let Some((index, line)) = lines.next() {
+        let (number, line) = (index + 1, line.strip_suffix('\r').unwrap_or(line));
+        let last = lines.peek().is_none();
+        let encoding = line
+            .strip_prefix("'CSA encoding=")
+            .filter(|encoding| matches!(*encoding, "UTF-8" | "SHIFT_JIS"));
+        if let Some(encoding) = encoding.filter(|_| number == 1 && version == Version::V3) {
+            let _ = builder.on_encoding(encoding, number);
+            continue;
+        }
+        if line.starts_with('\'') {
+            let _ = builder.on_comment(line, number);
+            continue;
+        }
+        for statement in line.split(',') {
+            let flow = match header_statement(&mut builder, statement, number) {
+                Some(flow) => flow,
+                // A line the input ends in may be cut short.
+                None if last => break 'lines,
+                None => {
+                    truncated = false;
+                    break 'lines;
+                }
+            };
+            if flow.is_break() {
+                break 'lines;
+            }
+        }
+    }
+
+    let output = builder.finish(input).map_err(ParseError)?;
+    Ok(GameHeader {
+        version,
+        record: output.record,
+        truncated,
+        warnings: output.warnings,
+    })
+}
+
+/// Applies one header statement, or returns `None` if `statement` is not one.
+fn header_statement(
+    visitor: &mut impl Visitor,
+    statement: &str,
+    line: usize,
+) -> Option<ControlFlow<()>> {
+    if statement.is_empty() {
+        return Some(ControlFlow::Continue(()));
+    }
+    let version = match statement {
+        "V2" => Some(Version::V2),
+        "V2.1" => Some(Version::V2_1),
+        "V2.2" => Some(Version::V2_2),
+        "V3.0" => Some(Version::V3),
+        _ => None,
+    };
+    if let Some(version) = version {
+        return Some(visitor.on_version(version, line));
+    }
+    if let Some(name) = statement.strip_prefix("N+") {
+        let name = Some(name).filter(|name| !name.is_empty());
+        return Some(visitor.on_player(Color::Black, name, line));
+    }
+    if let Some(name) = statement.strip_prefix("N-") {
+        let name = Some(name).filter(|name| !name.is_empty());
+        return Some(visitor.on_player(Color::White, name, line));
+    }
+    let (key, value) = statement.strip_prefix('$')?.split_once(':')?;
+    if key.is_empty() {
+        return None;
+    }
+    Some(visitor.on_attr(key, value, line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_csa;
+    use crate::parser::csa::DuplicatePolicy;
+
+    #[test]
+    fn header_matches_full_parse() {
+        for fixture in [
+            include_str!("../../../fixtures/v2_2.csa"),
+            include_str!("../../../fixtures/v3_fischer.csa"),
+        ] {
+            let header = parse_header(fixture, &ParseOptions::default()).unwrap();
+            assert!(!header.truncated);
+            let full = parse_csa(fixture).unwrap();
+            let expected = GameRecord {
+                black_player: full.black_player,
+                white_player: full.white_player,
+                event: full.event,
+                site: full.site,
+                start_time: full.start_time,
+                end_time: full.end_time,
+                time_limit_black: full.time_limit_black,
+                time_limit_white: full.time_limit_white,
+                opening: full.opening,
+                extra_attrs: full.extra_attrs,
+                header_comments: full.header_comments,
+                layout: full.layout,
+                ..GameRecord::default()
+            };
+            assert_eq!(header.record, expected);
+        }
+    }
+
+    #[test]
+    fn header_stops_at_the_position() {
+        // Nothing after the header is looked at, valid or not.
+        let header = parse_header(
+            "V2.2\nN+A,N-B\n$EVENT:X\nPI\n+\n+9999XX\n",
+            &ParseOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(header.version, Version::V2_2);
+        assert_eq!(header.record.black_player, Some("A".into()));
+        assert_eq!(header.record.white_player, Some("B".into()));
+        assert_eq!(header.record.event, Some("X".into()));
+        assert!(!header.truncated);
+    }
+
+    #[test]
+    fn truncated_header() {
+        let header =
+            parse_header("V2.2\nN+A\n$EVENT:X\n$SITE:Y", &ParseOptions::default()).unwrap();
+        assert!(header.truncated);
+        assert_eq!(header.record.site, Some("Y".into()));
+
+        let header = parse_header("V2.2\nN+A\n$EVE", &ParseOptions::default()).unwrap();
+        assert!(header.truncated);
+        assert_eq!(header.record.black_player, Some("A".into()));
+        assert_eq!(header.record.event, None);
+
+        assert!(parse_header("N+A\n", &ParseOptions::default()).is_err());
+    }
+
+    #[test]
+    fn header_duplicates_and_warnings() {
+        let csa = "V2.2\n$START_TIME:yesterday\nN+A\nN+B\nPI\n";
+        let header = parse_header(csa, &ParseOptions::default()).unwrap();
+        assert_eq!(header.record.black_player, Some("B".into()));
+        assert_eq!(header.warnings.len(), 2);
+
+        let options = ParseOptions {
+            duplicates: DuplicatePolicy::Error,
+            ..ParseOptions::default()
+        };
+        assert!(parse_header(csa, &options).is_err());
+    }
+}
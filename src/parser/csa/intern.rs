@@ -0,0 +1,41 @@
+//! Sharing of repeated record strings between parses
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+/// A thread-safe pool of strings handed out as shared `Arc<str>`s.
+///
+/// Records parsed in bulk tend to repeat the same metadata over and over, so
+/// passing one interner to every parse keeps a single copy of each value.
+#[derive(Debug, Default)]
+pub struct Interner {
+    strings: Mutex<HashSet<Arc<str>>>,
+}
+
+impl Interner {
+    pub fn new() -> Interner {
+        Interner::default()
+    }
+
+    /// Returns the shared copy of `s`, adding it to the pool if needed.
+    pub fn intern(&self, s: &str) -> Arc<str> {
+        let mut strings = self.strings.lock().unwrap_or_else(|e| e.into_inner());
+        match strings.get(s) {
+            Some(shared) => Arc::clone(shared),
+            None => {
+                let shared: Arc<str> = Arc::from(s);
+                strings.insert(Arc::clone(&shared));
+                shared
+            }
+        }
+    }
+
+    /// Returns the number of distinct strings in the pool.
+    pub fn len(&self) -> usize {
+        self.strings.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
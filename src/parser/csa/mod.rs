@@ -3,15 +3,31 @@
 //! Each version is a separate parser with its own grammar.
 //! All parsers output to the common `crate::value::GameRecord` type.
 
+mod common;
+mod header;
+#[cfg(feature = "intern")]
+mod intern;
 pub mod v2;
 pub mod v2_1;
 pub mod v2_2;
 pub mod v3;
+mod variant;
+mod visitor;
 
-use crate::value::GameRecord;
+use std::ops::ControlFlow;
+use std::time::{Duration, Instant};
+
+use crate::value::{Action, GameRecord, InterMoveEvent, SpecialToken};
+
+pub(crate) use common::statement_requirement;
+pub use header::{parse_header, GameHeader};
+#[cfg(feature = "intern")]
+pub use intern::Interner;
+pub(crate) use variant::detect_large_variant;
+pub use visitor::Visitor;
 
 /// CSA format version
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Version {
     V2,
     V2_1,
@@ -19,6 +35,18 @@ pub enum Version {
     V3,
 }
 
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Version::V2 => "V2",
+            Version::V2_1 => "V2.1",
+            Version::V2_2 => "V2.2",
+            Version::V3 => "V3.0",
+        };
+        f.write_str(s)
+    }
+}
+
 /// Detect the CSA version from the input string
 pub fn detect_version(input: &str) -> Option<Version> {
     for line in input.lines() {
@@ -55,6 +83,417 @@ pub fn detect_version(input: &str) -> Option<Version> {
     None
 }
 
+/// What to do when a statement that may appear only once is repeated, such as
+/// a second `$START_TIME` line, a second `N+` line, or a second position block.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Keep the first occurrence and ignore the later ones.
+    KeepFirst,
+    /// Let each occurrence replace the previous one.
+    #[default]
+    KeepLast,
+    /// Fail the parse.
+    Error,
+}
+
+/// What to do with a statement that the file's CSA version does not allow but
+/// another version does, such as `T15.123` in a V2.2 file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum VersionPolicy {
+    /// Fail the parse, naming the version that allows the statement.
+    #[default]
+    Error,
+    /// Accept the statement with a warning.
+    Warn,
+}
+
+/// What to do with a `PI` removal that names a piece the standard position
+/// does not have on that square, such as `PI82KA`, or a square already
+/// removed, as in `PI82HI82HI`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RemovalPolicy {
+    /// Fail the parse.
+    #[default]
+    Error,
+    /// Drop the removal with a warning.
+    Warn,
+}
+
+/// What to do with a `$` line between the rows of a position grid, as in a
+/// `$EVENT` line between `P4` and `P5`. Rows must otherwise be contiguous.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum GridSplitPolicy {
+    /// Fail the parse.
+    #[default]
+    Error,
+    /// Read the attribute and the grid as if the attribute had come after
+    /// the grid, with a warning.
+    Warn,
+}
+
+/// What to do with metadata written as comments, such as the
+/// `'$END_TIME:2013/07/15 20:01:44` and `'summary:toryo:player1 win:player2 lose`
+/// lines older shogi-server builds write.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CommentedAttrPolicy {
+    /// Treat them as any other comment.
+    #[default]
+    Ignore,
+    /// Read a `'$KEY:value` comment as the attribute when no `$KEY` line
+    /// gives it, and check the result a `'summary:` comment states against
+    /// the way the game ends, warning when they disagree.
+    Read,
+}
+
+/// What to do with a decorative separator line, such as `--------` or
+/// `========` between the header and the moves, as hand-edited files and some
+/// club software write. A separator is a line of at least three punctuation
+/// or box-drawing characters, spaces aside, that is not a comment.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SeparatorPolicy {
+    /// Fail the parse, saying the line looks like a separator.
+    #[default]
+    Error,
+    /// Skip the line with a warning.
+    Warn,
+}
+
+/// How many moves a game may have, checked as the moves are read. The move
+/// reaching the limit is read; a move past it fails the parse.
+///
+/// By default every move is read, and a game run past its `$MAX_MOVES` is
+/// left to [`GameRecord::validate`](crate::GameRecord::validate) to report.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MaxMovesPolicy {
+    /// Read every move.
+    #[default]
+    Unlimited,
+    /// Apply `$MAX_MOVES` when the record has it, see
+    /// [`GameRecord::max_moves`](crate::GameRecord::max_moves).
+    FromRecord,
+    /// Apply this limit whatever `$MAX_MOVES` says.
+    Limit(usize),
+}
+
+/// Options controlling how lenient the parser is.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ParseOptions<'a> {
+    pub duplicates: DuplicatePolicy,
+    pub other_versions: VersionPolicy,
+    pub bad_removals: RemovalPolicy,
+    pub commented_attrs: CommentedAttrPolicy,
+    pub split_grids: GridSplitPolicy,
+    pub max_moves: MaxMovesPolicy,
+    pub separators: SeparatorPolicy,
+    /// Measures the parse, see [`ParseOutput::metrics`].
+    pub metrics: bool,
+    /// Shares player names, `$EVENT`, `$SITE` and `$OPENING` values between
+    /// records parsed with the same interner instead of allocating them anew.
+    #[cfg(feature = "intern")]
+    pub intern: Option<&'a Interner>,
+    /// Special moves read besides those CSA defines. Without them, an
+    /// unknown special move fails the parse.
+    pub custom_specials: Option<&'a CustomSpecials>,
+    /// The lines read as [`InterMoveEvent`]s rather than as comments or
+    /// custom special moves. Without it, no events are read.
+    pub event_convention: Option<&'a EventConvention>,
+}
+
+/// Special moves a file may use besides those CSA defines, such as the
+/// `%DRAW_OFFER` of a private server, for [`ParseOptions::custom_specials`].
+///
+/// A token is read either as [`Action::Custom`] with a tag of the caller's
+/// choosing, or as one of the special moves CSA defines. The writer writes
+/// custom actions back as their token, so they survive a round trip.
+///
+/// ```
+/// use csa::{parse_csa_with_options, Action, CustomSpecials, ParseOptions};
+///
+/// let mut specials = CustomSpecials::new();
+/// specials.register("DRAW_OFFER", 1).unwrap();
+/// specials.register_as("DRAW", Action::Hikiwake).unwrap();
+/// let options = ParseOptions {
+///     custom_specials: Some(&specials),
+///     ..ParseOptions::default()
+/// };
+/// let csa = "V2.2\nPI\n+\n+7776FU\n%DRAW_OFFER\n-3334FU\n%DRAW\n";
+/// let game = parse_csa_with_options(csa, &options).unwrap().record;
+/// assert!(matches!(game.moves[1].action, Action::Custom { tag: 1, .. }));
+/// assert_eq!(game.moves[3].action, Action::Hikiwake);
+/// assert!(game.to_string().contains("%DRAW_OFFER\n"));
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct CustomSpecials {
+    tokens: Vec<(SpecialToken, Action)>,
+}
+
+impl CustomSpecials {
+    pub fn new() -> CustomSpecials {
+        CustomSpecials::default()
+    }
+
+    /// Reads `%token` as [`Action::Custom`] with `tag`.
+    pub fn register(&mut self, token: &str, tag: u16) -> Result<(), CustomSpecialError> {
+        let token = self.check(token)?;
+        self.tokens.push((token, Action::Custom { token, tag }));
+        Ok(())
+    }
+
+    /// Reads `%token` as `action`, a special move CSA defines, such as
+    /// [`Action::Hikiwake`] for a server's `%DRAW`. It is written back as
+    /// the token CSA defines.
+    pub fn register_as(&mut self, token: &str, action: Action) -> Result<(), CustomSpecialError> {
+        if matches!(action, Action::Move(..) | Action::Custom { .. }) {
+            return Err(CustomSpecialError::NotSpecial(action));
+        }
+        let token = self.check(token)?;
+        self.tokens.push((token, action));
+        Ok(())
+    }
+
+    /// Returns the action `%token` is read as, if it is registered.
+    pub fn get(&self, token: &str) -> Option<Action> {
+        self.tokens
+            .iter()
+            .find(|(registered, _)| registered.as_str() == token)
+            .map(|&(_, action)| action)
+    }
+
+    fn check(&self, token: &str) -> Result<SpecialToken, CustomSpecialError> {
+        let Some(special) = SpecialToken::new(token) else {
+            return Err(CustomSpecialError::InvalidToken(token.to_string()));
+        };
+        if Action::parse(&format!("%{token}")).is_some() {
+            return Err(CustomSpecialError::BuiltIn(token.to_string()));
+        }
+        if self.get(token).is_some() {
+            return Err(CustomSpecialError::Duplicate(token.to_string()));
+        }
+        Ok(special)
+    }
+}
+
+/// Why [`CustomSpecials`] refused a token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CustomSpecialError {
+    /// The token is not one to [`SpecialToken::CAPACITY`] ASCII capital
+    /// letters, digits, `_`, `+` or `-`.
+    InvalidToken(String),
+    /// CSA defines the token already, e.g. `TORYO`.
+    BuiltIn(String),
+    /// The token is registered already.
+    Duplicate(String),
+    /// [`CustomSpecials::register_as`] was given an action that is not a
+    /// special move CSA defines.
+    NotSpecial(Action),
+}
+
+impl std::fmt::Display for CustomSpecialError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CustomSpecialError::InvalidToken(token) => {
+                write!(f, "invalid special move token {token:?}")
+            }
+            CustomSpecialError::BuiltIn(token) => {
+                write!(f, "%{token} is a special move CSA defines")
+            }
+            CustomSpecialError::Duplicate(token) => write!(f, "%{token} is registered already"),
+            CustomSpecialError::NotSpecial(action) => {
+                write!(f, "{action} is not a special move CSA defines")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CustomSpecialError {}
+
+/// How a server or a person notes [`InterMoveEvent`]s, for
+/// [`ParseOptions::event_convention`]: the comment or special move lines
+/// read as each event.
+///
+/// A line is matched whole, and only after the first move; the event goes to
+/// the [`MoveRecord`](crate::MoveRecord) before it. A special move line is
+/// read as an event once the parser reads it, so its token must be in
+/// [`ParseOptions::custom_specials`] too.
+///
+/// The record keeps the convention in
+/// [`Layout::event_convention`](crate::Layout::event_convention), and the
+/// writer writes each event as the first line registered for it. Events the
+/// convention has no line for are written in their `Display` form, the lines
+/// of [`EventConvention::standard`].
+///
+/// ```
+/// use csa::{
+///     parse_csa_with_options, Color, CustomSpecials, EventConvention, InterMoveEvent,
+///     ParseOptions,
+/// };
+///
+/// let mut specials = CustomSpecials::new();
+/// specials.register("DRAW_OFFER", 1).unwrap();
+/// let mut convention = EventConvention::new();
+/// convention
+///     .register("%DRAW_OFFER", InterMoveEvent::DrawOffered(Color::White))
+///     .unwrap();
+/// convention
+///     .register("'draw declined", InterMoveEvent::DrawDeclined(Color::Black))
+///     .unwrap();
+/// let options = ParseOptions {
+///     custom_specials: Some(&specials),
+///     event_convention: Some(&convention),
+///     ..ParseOptions::default()
+/// };
+/// let csa = "V2.2\nPI\n+\n+7776FU\n-3334FU\n%DRAW_OFFER\n'draw declined\n+2726FU\n";
+/// let game = parse_csa_with_options(csa, &options).unwrap().record;
+/// assert_eq!(game.moves.len(), 3);
+/// assert_eq!(
+///     game.moves[1].events,
+///     [
+///         InterMoveEvent::DrawOffered(Color::White),
+///         InterMoveEvent::DrawDeclined(Color::Black)
+///     ]
+/// );
+/// assert!(game.to_string().contains("-3334FU\n%DRAW_OFFER\n'draw declined\n"));
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct EventConvention {
+    lines: Vec<(String, InterMoveEvent)>,
+}
+
+impl EventConvention {
+    pub fn new() -> EventConvention {
+        EventConvention::default()
+    }
+
+    /// The comments `'+DRAW_OFFER`, `'-DRAW_OFFER`, `'+DRAW_DECLINE`,
+    /// `'-DRAW_DECLINE`, `'PAUSE` and `'RESUME`, which the writer falls back
+    /// to.
+    pub fn standard() -> EventConvention {
+        EventConvention {
+            lines: InterMoveEvent::ALL
+                .into_iter()
+                .map(|event| (event.to_string(), event))
+                .collect(),
+        }
+    }
+
+    /// Reads `line` as `event`. The line is a comment with its leading `'`,
+    /// or a special move with its `%`.
+    pub fn register(
+        &mut self,
+        line: &str,
+        event: InterMoveEvent,
+    ) -> Result<(), EventConventionError> {
+        let valid = match line.strip_prefix('%') {
+            Some(token) => SpecialToken::new(token).is_some() && Action::parse(line).is_none(),
+            None => line.starts_with('\'') && !line.contains(['\r', '\n']),
+        };
+        if !valid {
+            return Err(EventConventionError::InvalidLine(line.to_string()));
+        }
+        if self.event(line).is_some() {
+            return Err(EventConventionError::Duplicate(line.to_string()));
+        }
+        self.lines.push((line.to_string(), event));
+        Ok(())
+    }
+
+    /// Returns the event `line` is read as, if it is registered.
+    pub fn event(&self, line: &str) -> Option<InterMoveEvent> {
+        self.lines
+            .iter()
+            .find(|(registered, _)| registered == line)
+            .map(|&(_, event)| event)
+    }
+
+    /// Returns the line `event` is written as, the first registered for it.
+    pub fn line(&self, event: &InterMoveEvent) -> Option<&str> {
+        self.lines
+            .iter()
+            .find(|(_, registered)| registered == event)
+            .map(|(line, _)| line.as_str())
+    }
+
+    /// The registered lines and their events, in the order registered.
+    pub fn lines(&self) -> impl Iterator<Item = (&str, InterMoveEvent)> {
+        self.lines
+            .iter()
+            .map(|(line, event)| (line.as_str(), *event))
+    }
+}
+
+/// Why [`EventConvention`] refused a line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventConventionError {
+    /// The line is neither a comment nor a custom special move, or spans
+    /// several lines.
+    InvalidLine(String),
+    /// The line is registered already.
+    Duplicate(String),
+}
+
+impl std::fmt::Display for EventConventionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventConventionError::InvalidLine(line) => {
+                write!(f, "{line:?} is not a comment or custom special move")
+            }
+            EventConventionError::Duplicate(line) => write!(f, "{line:?} is registered already"),
+        }
+    }
+}
+
+impl std::error::Error for EventConventionError {}
+
+/// A problem in the input that did not stop the parse.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseWarning {
+    /// 1-based line number of the statement the warning is about.
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// A parsed record together with the warnings raised while parsing it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseOutput {
+    pub record: GameRecord,
+    pub warnings: Vec<ParseWarning>,
+    /// Figures about the parse, when [`ParseOptions::metrics`] is set.
+    pub metrics: Option<ParseMetrics>,
+}
+
+/// Sizes and timings of one parse, see [`ParseOptions::metrics`].
+///
+/// The parse runs in three phases: detecting the version, running the
+/// grammar of that version over the file, separator lines skipped first, and
+/// building the record from what the grammar found. A version's own parser,
+/// such as [`v2_2::parse_with_options`], starts at the grammar and reports no
+/// detection time.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ParseMetrics {
+    pub input_bytes: usize,
+    pub lines: usize,
+    /// The entries of `GameRecord::moves`, special moves included.
+    pub moves: usize,
+    pub comments: usize,
+    pub warnings: usize,
+    pub detect_time: Duration,
+    pub grammar_time: Duration,
+    pub build_time: Duration,
+}
+
+impl ParseMetrics {
+    /// The time the whole parse took.
+    pub fn total_time(&self) -> Duration {
+        self.detect_time + self.grammar_time + self.build_time
+    }
+}
+
 /// Parse error type
 #[derive(Debug)]
 pub struct ParseError(pub String);
@@ -69,14 +508,44 @@ impl std::error::Error for ParseError {}
 
 /// Parse a CSA file, auto-detecting the version
 pub fn parse(input: &str) -> Result<GameRecord, ParseError> {
+    parse_with_options(input, &ParseOptions::default()).map(|output| output.record)
+}
+
+/// Parse a CSA file, auto-detecting the version, with the given options
+pub fn parse_with_options(input: &str, options: &ParseOptions) -> Result<ParseOutput, ParseError> {
+    let start = options.metrics.then(Instant::now);
+    let version = detect_version(input)
+        .ok_or_else(|| ParseError("No version found or unsupported version".to_string()))?;
+    let detect_time = start.map(|start| start.elapsed());
+
+    let mut output = match version {
+        Version::V2 => v2::parse_with_options(input, options).map_err(|e| ParseError(e.0)),
+        Version::V2_1 => v2_1::parse_with_options(input, options).map_err(|e| ParseError(e.0)),
+        Version::V2_2 => v2_2::parse_with_options(input, options).map_err(|e| ParseError(e.0)),
+        Version::V3 => v3::parse_with_options(input, options).map_err(|e| ParseError(e.0)),
+    }?;
+    if let (Some(metrics), Some(detect_time)) = (output.metrics.as_mut(), detect_time) {
+        metrics.detect_time = detect_time;
+    }
+    Ok(output)
+}
+
+/// Parse a CSA file, auto-detecting the version, reporting each statement to
+/// `visitor` instead of building a record. Returns `ControlFlow::Break` if the
+/// visitor stopped the parse early.
+pub fn visit(
+    input: &str,
+    options: &ParseOptions,
+    visitor: &mut impl Visitor,
+) -> Result<ControlFlow<()>, ParseError> {
     let version = detect_version(input)
         .ok_or_else(|| ParseError("No version found or unsupported version".to_string()))?;
 
     match version {
-        Version::V2 => v2::parse(input).map_err(|e| ParseError(e.0)),
-        Version::V2_1 => v2_1::parse(input).map_err(|e| ParseError(e.0)),
-        Version::V2_2 => v2_2::parse(input).map_err(|e| ParseError(e.0)),
-        Version::V3 => v3::parse(input).map_err(|e| ParseError(e.0)),
+        Version::V2 => v2::visit(input, options, visitor).map_err(|e| ParseError(e.0)),
+        Version::V2_1 => v2_1::visit(input, options, visitor).map_err(|e| ParseError(e.0)),
+        Version::V2_2 => v2_2::visit(input, options, visitor).map_err(|e| ParseError(e.0)),
+        Version::V3 => v3::visit(input, options, visitor).map_err(|e| ParseError(e.0)),
     }
 }
 
@@ -84,6 +553,11 @@ pub fn parse(input: &str) -> Result<GameRecord, ParseError> {
 mod tests {
     use super::*;
     use crate::parse_csa;
+    use crate::value::{
+        Action, Color, InterMoveEvent, PieceType, Position, Square, TimeLimit, TimeUnit,
+        WriteOptions,
+    };
+    use std::time::Duration;
 
     #[test]
     fn test_detect_version_v2() {
@@ -118,6 +592,1032 @@ mod tests {
         assert_eq!(detect_version("PI\n+\n"), None);
     }
 
+    fn parse_with_policy(
+        input: &str,
+        duplicates: DuplicatePolicy,
+    ) -> Result<ParseOutput, ParseError> {
+        parse_with_options(
+            input,
+            &ParseOptions {
+                duplicates,
+                ..ParseOptions::default()
+            },
+        )
+    }
+
+    #[test]
+    fn test_duplicate_attributes() {
+        let csa = concat!(
+            "V2.2\n",
+            "$START_TIME:2024/01/05 10:00:00\n",
+            "$NOTE:server\n",
+            "$START_TIME:2024/01/06 10:00:00\n",
+            "$NOTE:archiver\n",
+            "PI\n",
+            "+\n",
+        );
+
+        let output = parse_with_policy(csa, DuplicatePolicy::KeepLast).unwrap();
+        assert_eq!(output.record.start_time.as_ref().unwrap().date.day(), 6);
+        assert_eq!(output.record.attr_str("NOTE"), Some("archiver"));
+        assert_eq!(output.record.extra_attrs.len(), 1);
+        assert_eq!(output.warnings.len(), 2);
+        assert_eq!(output.warnings[0].line, 4);
+        assert!(output.warnings[0].message.contains("$START_TIME"));
+        assert!(output.warnings[0].message.contains("line 2"));
+
+        let output = parse_with_policy(csa, DuplicatePolicy::KeepFirst).unwrap();
+        assert_eq!(output.record.start_time.as_ref().unwrap().date.day(), 5);
+        assert_eq!(output.record.attr_str("NOTE"), Some("server"));
+        assert_eq!(output.warnings.len(), 2);
+
+        let err = parse_with_policy(csa, DuplicatePolicy::Error).unwrap_err();
+        assert!(err.0.contains("line 4"), "{}", err.0);
+        assert!(err.0.contains("line 2"), "{}", err.0);
+    }
+
+    #[test]
+    fn test_duplicate_player_lines() {
+        let csa = "V2\nN+first\nN-white\nN+second\nPI\n+\n";
+
+        let output = parse_with_policy(csa, DuplicatePolicy::KeepLast).unwrap();
+        assert_eq!(output.record.black_player, Some("second".to_string()));
+        assert_eq!(output.record.white_player, Some("white".to_string()));
+        assert_eq!(
+            output.warnings,
+            vec![ParseWarning {
+                line: 4,
+                message: "duplicate N+ (first on line 2), keeping the last one".to_string(),
+            }]
+        );
+
+        let output = parse_with_policy(csa, DuplicatePolicy::KeepFirst).unwrap();
+        assert_eq!(output.record.black_player, Some("first".to_string()));
+
+        assert!(parse_with_policy(csa, DuplicatePolicy::Error).is_err());
+    }
+
+    #[test]
+    fn test_duplicate_position_blocks() {
+        let csa = "V2.1\nPI\nP+00FU\nPI82HI\n+\n+7776FU\n";
+
+        let output = parse_with_policy(csa, DuplicatePolicy::KeepLast).unwrap();
+        let pos = &output.record.start_pos;
+        assert_eq!(
+            pos.drop_pieces[..],
+            [(crate::Square::new(8, 2), crate::PieceType::Rook)]
+        );
+        assert!(pos.add_pieces.is_empty());
+        assert_eq!(output.warnings.len(), 1);
+        assert_eq!(output.warnings[0].line, 4);
+        assert_eq!(output.record.moves.len(), 1);
+
+        let output = parse_with_policy(csa, DuplicatePolicy::KeepFirst).unwrap();
+        let pos = &output.record.start_pos;
+        assert!(pos.drop_pieces.is_empty());
+        assert_eq!(pos.add_pieces.len(), 1);
+
+        let err = parse_with_policy(csa, DuplicatePolicy::Error).unwrap_err();
+        assert!(err.0.contains("position block"), "{}", err.0);
+    }
+
+    #[test]
+    fn test_pi_removals() {
+        use crate::{PieceType, Square};
+
+        let output = parse_with_options("V2.2\nPI\n+\n", &ParseOptions::default()).unwrap();
+        assert!(output.record.start_pos.drop_pieces.is_empty());
+        let output = parse_with_options("V2.2\nPI82HI22KA\n-\n", &ParseOptions::default()).unwrap();
+        assert_eq!(output.record.start_pos.drop_pieces.len(), 2);
+        assert!(output.warnings.is_empty());
+
+        for csa in [
+            "V2.2\nPI82KA\n-\n",
+            "V2.2\nPI82HI82HI\n-\n",
+            "V2.2\nPI55FU\n-\n",
+        ] {
+            let err = parse_with_options(csa, &ParseOptions::default()).unwrap_err();
+            assert!(err.0.contains("line 2"), "{}", err.0);
+        }
+
+        let lenient = ParseOptions {
+            bad_removals: RemovalPolicy::Warn,
+            ..ParseOptions::default()
+        };
+        let output = parse_with_options("V2.2\nPI82HI82HI22HI\n-\n", &lenient).unwrap();
+        assert_eq!(
+            output.record.start_pos.drop_pieces[..],
+            [(Square::new(8, 2), PieceType::Rook)]
+        );
+        assert_eq!(output.warnings.len(), 2);
+        assert!(output.warnings.iter().all(|w| w.line == 2));
+    }
+
+    #[test]
+    fn test_squares_off_the_board() {
+        for version in ["V2", "V2.1", "V2.2", "V3.0"] {
+            let errors = [
+                ("PI\n+\n+1077FU\n", "line 4: square 10 is off the board"),
+                ("PI\n+\n+7700FU\n", "line 4: square 00 is off the board"),
+                (
+                    "PI\n+\n+7776FU\n-3034FU\n",
+                    "line 5: square 30 is off the board",
+                ),
+                ("P+05OU\nP-51OU\n+\n", "line 2: square 05 is off the board"),
+                (
+                    "P+59OU\nP-51OU\nP+00KI\nP-90FU\n+\n",
+                    "line 5: square 90 is off the board",
+                ),
+            ];
+            for (csa, message) in errors {
+                let csa = format!("{version}\n{csa}");
+                let err = parse_with_options(&csa, &ParseOptions::default()).unwrap_err();
+                assert!(err.0.starts_with(message), "{csa}: {}", err.0);
+            }
+
+            let csa = format!("{version}\nP+59OU\nP-51OU\nP+00KI\n+\n+0052KI\n");
+            let game = parse_with_options(&csa, &ParseOptions::default())
+                .unwrap()
+                .record;
+            assert_eq!(
+                game.moves[0].action,
+                Action::Move(
+                    Color::Black,
+                    Square::HAND,
+                    Square::new(5, 2),
+                    PieceType::Gold
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_attrs_after_the_position() {
+        let grid = concat!(
+            "P1-KY-KE-GI-KI-OU-KI-GI-KE-KY\n",
+            "P2 * -HI *  *  *  *  * -KA * \n",
+            "P3-FU-FU-FU-FU-FU-FU-FU-FU-FU\n",
+            "P4 *  *  *  *  *  *  *  *  * \n",
+            "P5 *  *  *  *  *  *  *  *  * \n",
+            "P6 *  *  *  *  *  *  *  *  * \n",
+            "P7+FU+FU+FU+FU+FU+FU+FU+FU+FU\n",
+            "P8 * +KA *  *  *  *  * +HI * \n",
+            "P9+KY+KE+GI+KI+OU+KI+GI+KE+KY\n",
+        );
+        let after_grid = format!("{grid}$EVENT:x\n'c\n+\n");
+        for version in ["V2", "V2.1", "V2.2", "V3.0"] {
+            for body in [
+                "PI\n$EVENT:x\n+\n",
+                &after_grid,
+                "PI\n+\n$EVENT:x\n",
+                "PI\n+\nP+00FU\n$EVENT:x\nP-00FU\n",
+                "$SITE:y\nPI\n$EVENT:x\nPI\n+\n",
+            ] {
+                let csa = format!("{version}\n{body}+7776FU\n");
+                let output = parse_with_options(&csa, &ParseOptions::default()).unwrap();
+                assert_eq!(output.record.event, Some("x".to_string()), "{csa}");
+                assert_eq!(output.record.moves.len(), 1);
+                assert!(output.warnings.iter().all(|w| !w.message.contains("grid")));
+            }
+
+            // A `$` line may not split the grid itself.
+            let split = grid.replacen("P5", "$EVENT:x\nP5", 1);
+            let csa = format!("{version}\n{split}+\n+7776FU\n");
+            let err = parse_with_options(&csa, &ParseOptions::default()).unwrap_err();
+            assert!(err.0.contains("line 6"), "{}", err.0);
+
+            let lenient = ParseOptions {
+                split_grids: GridSplitPolicy::Warn,
+                ..ParseOptions::default()
+            };
+            let output = parse_with_options(&csa, &lenient).unwrap();
+            let unsplit = parse_csa(&format!("{version}\n{grid}$EVENT:x\n+\n+7776FU\n")).unwrap();
+            assert_eq!(output.record.start_pos, unsplit.start_pos);
+            assert_eq!(output.record.event, Some("x".to_string()));
+            assert_eq!(output.warnings.len(), 1);
+            assert_eq!(output.warnings[0].line, 6);
+        }
+    }
+
+    #[test]
+    fn test_separator_lines() {
+        let lenient = ParseOptions {
+            separators: SeparatorPolicy::Warn,
+            ..ParseOptions::default()
+        };
+        let fixtures = [
+            ("dashes_and_equals", 7, vec![7, 10, 20], 4),
+            ("box_drawing", 5, vec![5, 10], 2),
+        ];
+        for (name, first, lines, plies) in fixtures {
+            let csa = std::fs::read_to_string(format!("fixtures/separators/{name}.csa")).unwrap();
+            let err = parse(&csa).unwrap_err();
+            assert!(
+                err.0.contains(&format!("line {first}: `"))
+                    && err.0.contains("decorative separator")
+                    && err.0.contains("SeparatorPolicy::Warn"),
+                "{name}: {}",
+                err.0
+            );
+
+            let output = parse_with_options(&csa, &lenient).unwrap();
+            let warned: Vec<_> = output.warnings.iter().map(|w| w.line).collect();
+            assert_eq!(warned, lines, "{name}");
+            assert_eq!(output.record.ply_count(), plies, "{name}");
+        }
+
+        // Errors before a separator are reported as they were.
+        let err = parse("V2.2\nN*bad\n--------\nPI\n+\n").unwrap_err();
+        assert!(!err.0.contains("separator"), "{}", err.0);
+        // Lines of the game are never taken for separators.
+        for line in ["+", "-", "'-----", "'**", "%TORYO", "-3334FU"] {
+            assert!(!common::is_separator(line), "{line}");
+        }
+    }
+
+    #[test]
+    fn test_metrics() {
+        let csa = "V2.2\n'by hand\nPI\n+\n+7776FU\nT3\n'a comment\n-3334FU\n%TORYO\n";
+        assert_eq!(
+            parse_with_options(csa, &ParseOptions::default())
+                .unwrap()
+                .metrics,
+            None
+        );
+
+        let options = ParseOptions {
+            metrics: true,
+            duplicates: DuplicatePolicy::KeepFirst,
+            ..ParseOptions::default()
+        };
+        let metrics = parse_with_options(&csa.replace("PI", "$SITE:a\n$SITE:b\nPI"), &options)
+            .unwrap()
+            .metrics
+            .unwrap();
+        let counts = (
+            metrics.input_bytes,
+            metrics.lines,
+            metrics.moves,
+            metrics.comments,
+            metrics.warnings,
+        );
+        assert_eq!(counts, (csa.len() + 16, 11, 3, 2, 1));
+        assert_eq!(
+            metrics.total_time(),
+            metrics.detect_time + metrics.grammar_time + metrics.build_time
+        );
+
+        // A version's own parser does not detect the version.
+        let metrics = v2_2::parse_with_options(csa, &options)
+            .unwrap()
+            .metrics
+            .unwrap();
+        assert_eq!((metrics.detect_time, metrics.moves), (Duration::ZERO, 3));
+    }
+
+    #[test]
+    fn test_custom_specials() {
+        let mut specials = CustomSpecials::new();
+        specials.register("DRAW_OFFER", 7).unwrap();
+        specials.register_as("DRAW", Action::Hikiwake).unwrap();
+        assert_eq!(
+            specials.register("TORYO", 1),
+            Err(CustomSpecialError::BuiltIn("TORYO".to_string()))
+        );
+        assert_eq!(
+            specials.register_as("+ILLEGAL_ACTION", Action::Toryo),
+            Err(CustomSpecialError::BuiltIn("+ILLEGAL_ACTION".to_string()))
+        );
+        assert_eq!(
+            specials.register("DRAW", 2),
+            Err(CustomSpecialError::Duplicate("DRAW".to_string()))
+        );
+        assert_eq!(
+            specials.register("draw_offer_", 2),
+            Err(CustomSpecialError::InvalidToken("draw_offer_".to_string()))
+        );
+        assert!(specials.register("A_VERY_LONG_TOKEN", 2).is_err());
+        let draw_offer = specials.get("DRAW_OFFER").unwrap();
+        assert!(matches!(
+            specials.register_as("OFFER", draw_offer),
+            Err(CustomSpecialError::NotSpecial(_))
+        ));
+
+        let csa = "V2.2\nPI\n+\n+7776FU\nT3\n%DRAW_OFFER\n-3334FU\n%TORYOX\n%DRAW\n";
+        let options = ParseOptions {
+            custom_specials: Some(&specials),
+            ..ParseOptions::default()
+        };
+        // Registering a token does not make a longer one known.
+        let err = parse_with_options(csa, &options).unwrap_err();
+        assert_eq!(err.0, "line 8: unknown special move %TORYOX");
+
+        let csa = csa.replace("%TORYOX\n", "");
+        for version in ["V2", "V2.1", "V2.2", "V3.0"] {
+            let csa = csa.replace("V2.2", version);
+            let game = parse_with_options(&csa, &options).unwrap().record;
+            let actions: Vec<_> = game.moves.iter().map(|m| m.action).collect();
+            assert_eq!(actions[1], draw_offer, "{version}");
+            assert_eq!(actions[3], Action::Hikiwake, "{version}");
+            assert_eq!(game.ply_count(), 2);
+
+            let written = game.to_string();
+            assert!(
+                written.contains("%DRAW_OFFER\n-3334FU\n%HIKIWAKE\n"),
+                "{written}"
+            );
+            let reparsed = parse_with_options(&written, &options).unwrap().record;
+            assert_eq!(reparsed, game);
+
+            // Without the registration the token is rejected as before.
+            let err = parse_with_options(&csa, &ParseOptions::default()).unwrap_err();
+            assert_eq!(err.0, "line 6: unknown special move %DRAW_OFFER");
+        }
+    }
+
+    #[test]
+    fn test_inter_move_events() {
+        use InterMoveEvent::*;
+
+        let standard = EventConvention::standard();
+        let options = ParseOptions {
+            event_convention: Some(&standard),
+            ..ParseOptions::default()
+        };
+        let csa = "V2.2\nPI\n+\n'PAUSE\n+7776FU\nT3\n'-DRAW_OFFER\n'+DRAW_DECLINE\n-3334FU\n'PAUSE\n'RESUME\n";
+        for version in ["V2", "V2.1", "V2.2", "V3.0"] {
+            let csa = csa.replace("V2.2", version);
+            let game = parse_with_options(&csa, &options).unwrap().record;
+            // Before the first move there is no move to note it after.
+            assert_eq!(game.moves.len(), 2);
+            assert_eq!(
+                game.moves[0].events,
+                [DrawOffered(Color::White), DrawDeclined(Color::Black)]
+            );
+            assert_eq!(game.moves[1].events, [Pause, Resume]);
+            assert_eq!(game.layout.event_convention.as_ref(), Some(&standard));
+            let written = game.to_string();
+            assert!(
+                written.contains(
+                    "+7776FU\nT3\n'-DRAW_OFFER\n'+DRAW_DECLINE\n-3334FU\n'PAUSE\n'RESUME\n"
+                ),
+                "{written}"
+            );
+            assert_eq!(parse_with_options(&written, &options).unwrap().record, game);
+
+            // Without a convention they are ordinary comments.
+            let game = parse_csa(&csa).unwrap();
+            assert!(game.moves.iter().all(|m| m.events.is_empty()));
+            assert_eq!(game.layout.event_convention, None);
+        }
+
+        // Special moves are read as events once registered as custom ones,
+        // and written back as read.
+        let mut specials = CustomSpecials::new();
+        specials.register("DRAW", 1).unwrap();
+        specials.register("NO_DRAW", 2).unwrap();
+        let mut convention = EventConvention::new();
+        convention
+            .register("%DRAW", DrawOffered(Color::Black))
+            .unwrap();
+        convention
+            .register("%NO_DRAW", DrawDeclined(Color::White))
+            .unwrap();
+        let options = ParseOptions {
+            custom_specials: Some(&specials),
+            event_convention: Some(&convention),
+            ..ParseOptions::default()
+        };
+        let csa = "V2.2\nPI\n+\n+7776FU\nT3\n%DRAW\nT1\n%NO_DRAW\n-3334FU\n";
+        let output = parse_with_options(csa, &options).unwrap();
+        let mut game = output.record;
+        assert_eq!(game.moves.len(), 2);
+        assert_eq!(game.moves[0].time, Some(Duration::from_secs(3)));
+        assert_eq!(
+            game.moves[0].events,
+            [DrawOffered(Color::Black), DrawDeclined(Color::White)]
+        );
+        assert_eq!(output.warnings.len(), 1);
+        assert_eq!(output.warnings[0].line, 7);
+        assert_eq!(
+            output.warnings[0].message,
+            "time consumed after an event, ignoring it"
+        );
+
+        // An event the convention has no line for falls back to the standard
+        // one, as do events in a record built in code.
+        game.moves[1].events.push(Pause);
+        let written = game.to_string();
+        assert!(
+            written.ends_with("+7776FU\nT3\n%DRAW\n%NO_DRAW\n-3334FU\n'PAUSE\n"),
+            "{written}"
+        );
+        game.layout.event_convention = None;
+        assert!(game
+            .to_string()
+            .contains("T3\n'+DRAW_OFFER\n'-DRAW_DECLINE\n"));
+
+        for (line, error) in [
+            (
+                "DRAW",
+                EventConventionError::InvalidLine("DRAW".to_string()),
+            ),
+            (
+                "%TORYO",
+                EventConventionError::InvalidLine("%TORYO".to_string()),
+            ),
+            (
+                "%draw",
+                EventConventionError::InvalidLine("%draw".to_string()),
+            ),
+            (
+                "'a\nb",
+                EventConventionError::InvalidLine("'a\nb".to_string()),
+            ),
+            (
+                "%DRAW",
+                EventConventionError::Duplicate("%DRAW".to_string()),
+            ),
+        ] {
+            assert_eq!(convention.register(line, Pause), Err(error));
+        }
+        assert_eq!(convention.line(&Pause), None);
+    }
+
+    #[test]
+    fn test_no_duplicates_no_warnings() {
+        let csa = "V3.0\nN+a\nN-b\n$EVENT:x\n$SITE:y\nPI\n+\n";
+        let output = parse_with_policy(csa, DuplicatePolicy::Error).unwrap();
+        assert!(output.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_lenient_datetimes() {
+        let csa = concat!(
+            "V2.2\n",
+            "$START_TIME:24-01-05 19:00\n",
+            "$END_TIME:2024年1月5日 21時30分\n",
+            "PI\n",
+            "+\n",
+        );
+        let output = parse_with_options(csa, &ParseOptions::default()).unwrap();
+        assert_eq!(
+            output.record.start_time.unwrap().to_string(),
+            "2024/01/05 19:00:00"
+        );
+        assert_eq!(
+            output.record.end_time.as_ref().unwrap().to_string(),
+            "2024/01/05 21:30:00"
+        );
+        assert!(output.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_unrecognized_datetime_warns() {
+        let csa = "V2\n$START_TIME:sometime in January\nPI\n+\n";
+        let output = parse_with_options(csa, &ParseOptions::default()).unwrap();
+        assert_eq!(output.record.start_time, None);
+        assert_eq!(
+            output.warnings,
+            vec![ParseWarning {
+                line: 2,
+                message: "unrecognized START_TIME value `sometime in January`, ignoring it"
+                    .to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_time_limit_variants() {
+        let cases = [
+            ("V2", "+30"),
+            ("V2.1", "0:25"),
+            ("V2.2", "0:25+00"),
+            ("V3.0", "1:00+60"),
+        ];
+        for (version, raw) in cases {
+            let csa = format!("{version}\n$TIME_LIMIT:{raw}\nPI\n+\n");
+            let output = parse_with_options(&csa, &ParseOptions::default()).unwrap();
+            let limit = TimeLimit::parse(raw).unwrap();
+            assert_eq!(output.record.time_limit(), Some(&limit), "{raw:?}");
+            assert_eq!(output.record.time_limit_white, Some(limit), "{raw:?}");
+            assert!(output.warnings.is_empty());
+        }
+
+        let csa = "V2.2\n$TIME_LIMIT:25 minutes\nPI\n+\n";
+        let output = parse_with_options(csa, &ParseOptions::default()).unwrap();
+        assert_eq!(output.record.time_limit_black, None);
+        assert_eq!(output.warnings.len(), 1);
+        assert_eq!(output.warnings[0].line, 2);
+    }
+
+    #[test]
+    fn test_adjournment_round_trip() {
+        let csa = concat!(
+            "V2.2\n",
+            "PI\n",
+            "+\n",
+            "+7776FU\n",
+            "'CHUDAN:CONTINUATION:not adjourned yet\n",
+            "%CHUDAN\n",
+            "'CHUDAN:SEALED_MOVE:-3334FU\n",
+            "'an ordinary comment\n",
+            "'CHUDAN:RESUME_TIME:2024/01/06 10:00:00\n",
+            "'CHUDAN:CONTINUATION:game-2",
+        );
+        let output = parse_with_options(csa, &ParseOptions::default()).unwrap();
+        assert!(output.warnings.is_empty());
+        let adjournment = output.record.adjournment.clone().unwrap();
+        assert_eq!(adjournment.sealed_move, Action::parse("-3334FU"));
+        assert_eq!(
+            adjournment.resume_time.unwrap().to_string(),
+            "2024/01/06 10:00:00"
+        );
+        assert_eq!(adjournment.continuation.as_deref(), Some("game-2"));
+
+        let written = output.record.to_string();
+        assert!(written.ends_with(concat!(
+            "%CHUDAN\n",
+            "'CHUDAN:SEALED_MOVE:-3334FU\n",
+            "'CHUDAN:RESUME_TIME:2024/01/06 10:00:00\n",
+            "'CHUDAN:CONTINUATION:game-2\n",
+        )));
+        assert_eq!(parse_csa(&written).unwrap(), output.record);
+    }
+
+    #[test]
+    fn test_adjournment_comment_warnings() {
+        let csa = "V3.0\nPI\n+\n%CHUDAN\n'CHUDAN:SEALED_MOVE:7776FU\n";
+        let output = parse_with_options(csa, &ParseOptions::default()).unwrap();
+        assert_eq!(output.warnings.len(), 1);
+        assert_eq!(output.warnings[0].line, 5);
+        assert_eq!(output.record.adjournment, None);
+
+        let csa = "V2\nPI\n+\n%TORYO\n'CHUDAN:SEALED_MOVE:+7776FU\n";
+        let output = parse_with_options(csa, &ParseOptions::default()).unwrap();
+        assert!(output.warnings.is_empty());
+        assert_eq!(output.record.adjournment, None);
+    }
+
+    #[test]
+    fn test_comments_run_to_end_of_line() {
+        for version in ["V2", "V2.1", "V2.2", "V3.0"] {
+            let csa = format!(
+                "{version}\nPI\n+\n+7776FU\n'eval 120, pv -3334FU,+8822UM\n-3334FU\n'a, b\n"
+            );
+            let record = parse_csa(&csa).unwrap_or_else(|e| panic!("{version}: {e:?}"));
+            assert_eq!(record.moves.len(), 2, "{version}");
+        }
+    }
+
+    /// Whether a construct is accepted by the V2, V2.1, V2.2 and V3.0 parsers.
+    type Support = [bool; 4];
+
+    const ALL: Support = [true, true, true, true];
+    const NONE: Support = [false, false, false, false];
+    const FROM_V2_1: Support = [false, true, true, true];
+    const FROM_V2_2: Support = [false, false, true, true];
+    const UNTIL_V2_2: Support = [true, true, true, false];
+    const ONLY_V2_2: Support = [false, false, true, false];
+    const ONLY_V3: Support = [false, false, false, true];
+
+    const MINISHOGI_GRID: &str = "\
+P1-HI-KA-GI-KI-OU
+P2 *  *  *  * -FU
+P3 *  *  *  *  * 
+P4+FU *  *  *  * 
+P5+OU+KI+GI+KA+HI
+";
+
+    const WILDCAT_GRID: &str = "\
+P1-GI-OU-GI
+P2 * -FU * 
+P3 *  *  * 
+P4 * +FU * 
+P5+GI+OU+GI
+";
+
+    const STANDARD_GRID: &str = "\
+P1-KY-KE-GI-KI-OU-KI-GI-KE-KY
+P2 * -HI *  *  *  *  * -KA * 
+P3-FU-FU-FU-FU-FU-FU-FU-FU-FU
+P4 *  *  *  *  *  *  *  *  * 
+P5 *  *  *  *  *  *  *  *  * 
+P6 *  *  *  *  *  *  *  *  * 
+P7+FU+FU+FU+FU+FU+FU+FU+FU+FU
+P8 * +KA *  *  *  *  * +HI * 
+P9+KY+KE+GI+KI+OU+KI+GI+KE+KY
+";
+
+    /// CSA constructs and the versions whose parser accepts them. Each body
+    /// follows the version line.
+    const CONFORMANCE: &[(&str, &str, Support)] = &[
+        // Special moves
+        ("%TORYO", "PI\n+\n+7776FU\n%TORYO\n", ALL),
+        ("%CHUDAN", "PI\n+\n+7776FU\n%CHUDAN\n", ALL),
+        ("%SENNICHITE", "PI\n+\n+7776FU\n%SENNICHITE\n", ALL),
+        ("%TIME_UP", "PI\n+\n+7776FU\n%TIME_UP\n", FROM_V2_1),
+        ("%ILLEGAL_MOVE", "PI\n+\n+7776FU\n%ILLEGAL_MOVE\n", FROM_V2_1),
+        ("%+ILLEGAL_ACTION", "PI\n+\n+7776FU\n%+ILLEGAL_ACTION\n", FROM_V2_2),
+        ("%-ILLEGAL_ACTION", "PI\n+\n+7776FU\n%-ILLEGAL_ACTION\n", FROM_V2_2),
+        ("%JISHOGI", "PI\n+\n+7776FU\n%JISHOGI\n", ALL),
+        ("%KACHI", "PI\n+\n+7776FU\n%KACHI\n", FROM_V2_1),
+        ("%HIKIWAKE", "PI\n+\n+7776FU\n%HIKIWAKE\n", FROM_V2_1),
+        ("%MATTA", "PI\n+\n+7776FU\n%MATTA\n", UNTIL_V2_2),
+        ("%MAX_MOVES", "PI\n+\n+7776FU\n%MAX_MOVES\n", ONLY_V3),
+        ("%TSUMI", "PI\n+\n+7776FU\n%TSUMI\n", ALL),
+        ("%FUZUMI", "PI\n+\n+7776FU\n%FUZUMI\n", ALL),
+        ("%ERROR", "PI\n+\n+7776FU\n%ERROR\n", ALL),
+        ("unknown special move", "PI\n+\n%RESIGN\n", NONE),
+        // Positions
+        ("PI", "PI\n+\n", ALL),
+        ("PI with removals", "PI82HI22KA\n+\n", ALL),
+        ("placements", "PI\nP+00FU\nP-55KA00KI\n+\n", ALL),
+        ("AL placement", "PI82HI22KA\nP-00AL\n+\n", ALL),
+        ("9x9 grid", "P1-KY-KE-GI-KI-OU-KI-GI-KE-KY\nP2 * -HI *  *  *  *  * -KA * \nP3-FU-FU-FU-FU-FU-FU-FU-FU-FU\nP4 *  *  *  *  *  *  *  *  * \nP5 *  *  *  *  *  *  *  *  * \nP6 *  *  *  *  *  *  *  *  * \nP7+FU+FU+FU+FU+FU+FU+FU+FU+FU\nP8 * +KA *  *  *  *  * +HI * \nP9+KY+KE+GI+KI+OU+KI+GI+KE+KY\n+\n", ALL),
+        ("minishogi grid", "MINISHOGI\n", ONLY_V2_2),
+        ("wildcat grid", "WILDCAT\n", ONLY_V2_2),
+        ("missing side to move", "PI\n+7776FU\n", NONE),
+        // Comments
+        ("comment between moves", "PI\n+\n+7776FU\n'a comment\n-3334FU\n", ALL),
+        ("comment with commas", "PI\n+\n+7776FU\n'a, b, c\n", ALL),
+        ("trailing comment without newline", "PI\n+\n+7776FU\n'end", ALL),
+        ("program comment", "PI\n+\n+7776FU\n'* 30 -3334FU\n", ALL),
+        ("evaluation comment", "PI\n+\n+7776FU\n'** 30 -3334FU #1000\n", ALL),
+        // Comma-separated statements
+        ("comma statements", "PI,+\n+7776FU,T12,-3334FU,T3\n", ALL),
+        // Times
+        ("whole seconds", "PI\n+\n+7776FU\nT12\n", ALL),
+        ("millisecond time", "PI\n+\n+7776FU\nT12.345\n", ONLY_V3),
+        // Malformed times are skipped with a warning, but a fraction still
+        // needs V3.0.
+        ("four fraction digits", "PI\n+\n+7776FU\nT12.3456\n", ONLY_V3),
+        ("negative time", "PI\n+\n+7776FU\nT-1\n", ALL),
+    ];
+
+    #[test]
+    fn test_conformance_matrix() {
+        let accepts = |version: &str, csa: &str| match version {
+            "V2" => v2::parse(csa).is_ok(),
+            "V2.1" => v2_1::parse(csa).is_ok(),
+            "V2.2" => v2_2::parse(csa).is_ok(),
+            _ => v3::parse(csa).is_ok(),
+        };
+        for &(name, body, support) in CONFORMANCE {
+            let body = body
+                .replace("MINISHOGI\n", &format!("{MINISHOGI_GRID}+\n"))
+                .replace("WILDCAT\n", &format!("{WILDCAT_GRID}+\n"));
+            for (version, expected) in ["V2", "V2.1", "V2.2", "V3.0"].into_iter().zip(support) {
+                let csa = format!("{version}\n{body}");
+                assert_eq!(
+                    accepts(version, &csa),
+                    expected,
+                    "{name} should be {} by {version}",
+                    if expected { "accepted" } else { "rejected" }
+                );
+            }
+        }
+
+        // The encoding declaration is V3.0 only; earlier parsers read it as
+        // an ordinary comment.
+        let csa = "'CSA encoding=UTF-8\nV3.0\nPI\n+\n";
+        assert!(v3::parse(csa).is_ok());
+        let csa = "'CSA encoding=UTF-8\nV2.2\nPI\n+\n";
+        assert!(v2_2::parse(csa).is_ok());
+    }
+
+    #[test]
+    fn test_grid_padding() {
+        type Restyle = fn(&str) -> String;
+        let styles: [(&str, Restyle); 5] = [
+            ("padded", |row| row.to_string()),
+            ("trimmed", |row| row.trim_end().to_string()),
+            ("unpadded", |row| row.replace(" * ", "*")),
+            ("half padded", |row| row.replace(" * ", " *")),
+            ("trailing whitespace", |row| format!("{row}  \t")),
+        ];
+        for (grid, versions) in [
+            (STANDARD_GRID, &["V2", "V2.1", "V2.2", "V3.0"][..]),
+            (MINISHOGI_GRID, &["V2.2"][..]),
+            (WILDCAT_GRID, &["V2.2"][..]),
+        ] {
+            for version in versions {
+                let canonical = parse_csa(&format!("{version}\n{grid}+\n")).unwrap();
+                for (style, restyle) in styles {
+                    let rows: String = grid.lines().map(|row| restyle(row) + "\n").collect();
+                    let csa = format!("{version}\n{rows}+\n");
+                    let game = parse_csa(&csa).unwrap_or_else(|e| panic!("{style}: {e}\n{csa}"));
+                    assert_eq!(game.start_pos, canonical.start_pos, "{style}\n{csa}");
+
+                    let written = game.to_string();
+                    assert!(written.contains(grid), "{style}\n{written}");
+                    assert_eq!(parse_csa(&written).unwrap(), game, "{style}");
+                }
+            }
+        }
+
+        // Anything else in a cell is still an error.
+        let csa = WILDCAT_GRID.replace("P2 * -FU", "P2 . -FU");
+        assert!(parse_csa(&format!("V2.2\n{csa}+\n")).is_err());
+    }
+
+    #[test]
+    fn test_cross_version_constructs() {
+        let lenient = ParseOptions {
+            other_versions: VersionPolicy::Warn,
+            ..ParseOptions::default()
+        };
+        let cases = [
+            (
+                "V2.2",
+                "+7776FU\nT15.123\n%TORYO\n",
+                "millisecond time values require V3.0",
+            ),
+            (
+                "V2",
+                "+7776FU\n%TIME_UP\n",
+                "%TIME_UP moves require V2.1 or later",
+            ),
+            (
+                "V2",
+                "+7776FU\n%ILLEGAL_MOVE\n",
+                "%ILLEGAL_MOVE moves require V2.1 or later",
+            ),
+            (
+                "V2",
+                "+7776FU\n%KACHI\n",
+                "%KACHI moves require V2.1 or later",
+            ),
+            (
+                "V2",
+                "+7776FU\n%HIKIWAKE\n",
+                "%HIKIWAKE moves require V2.1 or later",
+            ),
+            (
+                "V2.1",
+                "+7776FU\n%+ILLEGAL_ACTION\n",
+                "%+ILLEGAL_ACTION moves require V2.2 or later",
+            ),
+            (
+                "V2",
+                "+7776FU\n%-ILLEGAL_ACTION",
+                "%-ILLEGAL_ACTION moves require V2.2 or later",
+            ),
+            (
+                "V3.0",
+                "+7776FU\n%MATTA\n",
+                "%MATTA moves require V2.2 or earlier",
+            ),
+            (
+                "V2.2",
+                "+7776FU\n%MAX_MOVES\n",
+                "%MAX_MOVES moves require V3.0",
+            ),
+        ];
+        for (version, moves, message) in cases {
+            let csa = format!("{version}\nPI\n+\n{moves}");
+
+            let err = parse(&csa).unwrap_err();
+            assert!(err.0.contains(message), "{}", err.0);
+            assert!(err.0.starts_with("line 5:"), "{}", err.0);
+
+            let output = parse_with_options(&csa, &lenient).unwrap();
+            assert_eq!(output.record.moves.len(), 2);
+            assert_eq!(output.warnings.len(), 1, "{csa}");
+            assert_eq!(output.warnings[0].line, 5);
+            assert_eq!(
+                output.warnings[0].message,
+                format!("{message}, but this file is {version}")
+            );
+        }
+
+        let output = parse_with_options("V2.1\nPI\n+\n+7776FU\nT15.5\n", &lenient).unwrap();
+        assert_eq!(
+            output.record.moves[0].time,
+            Some(std::time::Duration::from_millis(15_500))
+        );
+
+        // Within their own versions the same statements raise nothing.
+        for csa in [
+            "V3.0\nPI\n+\n+7776FU\nT15.123\n%MAX_MOVES\n",
+            "V2.1\nPI\n+\n+7776FU\n%TIME_UP\n",
+            "V2.2\nPI\n+\n+7776FU\n%-ILLEGAL_ACTION\n",
+            "V2\nPI\n+\n+7776FU\n%MATTA\n",
+        ] {
+            let output = parse_with_options(csa, &ParseOptions::default()).unwrap();
+            assert!(output.warnings.is_empty(), "{csa}");
+        }
+    }
+
+    #[test]
+    fn test_version_writer_matrix() {
+        let versions = [Version::V2, Version::V2_1, Version::V2_2, Version::V3];
+        for entry in std::fs::read_dir("fixtures/").unwrap() {
+            let path = entry.unwrap().path();
+            let Ok(record) = parse_csa(&std::fs::read_to_string(&path).unwrap_or_default()) else {
+                continue;
+            };
+            for version in versions {
+                let options = WriteOptions {
+                    version: Some(version),
+                    ..WriteOptions::default()
+                };
+                let written = record.to_csa(&options);
+                assert_eq!(detect_version(&written), Some(version), "{path:?}");
+                let reparsed = parse_with_options(&written, &ParseOptions::default())
+                    .unwrap_or_else(|e| panic!("{path:?} as {version}: {e}\n{written}"));
+                assert!(reparsed.warnings.is_empty(), "{path:?} as {version}");
+                let reparsed = reparsed.record;
+                assert_eq!(reparsed.to_csa(&options), written, "{path:?} as {version}");
+                if record.write_losses_with(&options).is_empty() {
+                    assert_eq!(reparsed.moves, record.moves, "{path:?} as {version}");
+                    let same_position = reparsed.start_pos.semantically_equals(&record.start_pos);
+                    assert_eq!(same_position, Ok(true), "{path:?} as {version}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_downgrade_losses() {
+        let record = parse_csa("V3.0\nPI\n+\n+7776FU\nT1.5\n-3334FU\nT2\n%HIKIWAKE\n").unwrap();
+        let at = |version| WriteOptions {
+            version: Some(version),
+            ..WriteOptions::default()
+        };
+
+        assert_eq!(
+            record.to_csa(&at(Version::V3)),
+            "V3.0\nPI\n+\n+7776FU\nT1.500\n-3334FU\nT2\n%HIKIWAKE\n"
+        );
+        assert!(record.write_losses_with(&at(Version::V3)).is_empty());
+
+        assert_eq!(
+            record.write_losses_with(&at(Version::V2_1)),
+            ["times rounded down to the second: millisecond time values require V3.0"]
+        );
+        assert_eq!(
+            record.write_losses(),
+            record.write_losses_with(&at(Version::V2_2))
+        );
+
+        let written = record.to_csa(&at(Version::V2));
+        assert_eq!(written, "V2\nPI\n+\n+7776FU\nT1\n-3334FU\nT2\n");
+        assert_eq!(
+            record.write_losses_with(&at(Version::V2)),
+            [
+                "%HIKIWAKE omitted: %HIKIWAKE moves require V2.1 or later",
+                "times rounded down to the second: millisecond time values require V3.0",
+            ]
+        );
+        assert_eq!(parse_csa(&written).unwrap().moves.len(), 2);
+    }
+
+    #[test]
+    fn test_result_without_moves() {
+        for version in ["V2", "V2.1", "V2.2", "V3.0"] {
+            for ending in ["%TORYO\n", "%CHUDAN\n", "%TORYO", "%CHUDAN"] {
+                let csa = format!("{version}\nPI\n+\n{ending}");
+                let record = parse_csa(&csa).unwrap_or_else(|e| panic!("{csa:?}: {e}"));
+                assert_eq!(record.moves.len(), 1, "{csa:?}");
+                assert_eq!(record.ply_count(), 0, "{csa:?}");
+            }
+        }
+
+        let toryo = parse_csa(include_str!("../../../fixtures/forfeit_toryo.csa")).unwrap();
+        assert_eq!(toryo.result(), Some(crate::GameResult::Win(Color::White)));
+        assert_eq!(toryo.stats().plies, 0);
+        assert!(toryo.clocks().is_empty());
+        assert!(toryo.validate().is_empty());
+        assert_eq!(toryo.time_stats(&[]).histogram, [vec![0], vec![0]]);
+        assert_eq!(toryo.events().len(), 2);
+        let features = toryo.features(&crate::PieceValues::default()).unwrap();
+        assert_eq!(features.len(), 1);
+        let summary = toryo.summary(true);
+        assert_eq!(summary.result.as_deref(), Some("white"));
+        assert_eq!(summary.duration_secs, Some(0));
+        assert_eq!(summary.moves, Some(vec![]));
+
+        let chudan = parse_csa(include_str!("../../../fixtures/forfeit_chudan.csa")).unwrap();
+        assert_eq!(chudan.result(), None);
+        assert!(chudan.adjournment.as_ref().unwrap().resume_time.is_some());
+        assert!(matches!(
+            chudan.events().last(),
+            Some(crate::KifuEvent::Adjourn { .. })
+        ));
+        assert_eq!(
+            chudan
+                .material_series(&crate::PieceValues::default())
+                .unwrap()
+                .len(),
+            1
+        );
+
+        for record in [&toryo, &chudan] {
+            let findings = crate::lint_record(record, &crate::LintConfig::default());
+            assert!(findings.is_empty(), "{findings:?}");
+            for version in [Version::V2, Version::V2_1, Version::V2_2, Version::V3] {
+                let options = WriteOptions {
+                    version: Some(version),
+                    ..WriteOptions::default()
+                };
+                let written = record.to_csa(&options);
+                assert_eq!(
+                    parse_csa(&written).unwrap().moves,
+                    record.moves,
+                    "{written}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_max_moves() {
+        // The kings step back and forth, so every prefix is a legal game.
+        let shuffle = |header: &str, plies: usize, ending: &str| {
+            let steps = ["+5958OU", "-5152OU", "+5859OU", "-5251OU"];
+            let moves: String = (0..plies).map(|i| format!("{}\n", steps[i % 4])).collect();
+            format!("V3.0\n{header}PI\n+\n{moves}{ending}")
+        };
+
+        // Ply 256 is the last one played.
+        let record = parse_csa(&shuffle("$MAX_MOVES:256\n", 256, "%MAX_MOVES\n")).unwrap();
+        assert_eq!(record.max_moves(), Some(256));
+        assert_eq!(record.ply_count(), 256);
+        assert_eq!(record.result(), Some(crate::GameResult::Draw));
+        assert!(record.validate().is_empty());
+        let findings = crate::lint_record(&record, &crate::LintConfig::default());
+        assert!(
+            findings.iter().all(|f| f.severity == crate::Severity::Info),
+            "{findings:?}"
+        );
+
+        // Moves past the limit are read, and reported by `validate`.
+        let csa = shuffle("$MAX_MOVES:256\n", 257, "");
+        let record = parse_csa(&csa).unwrap();
+        assert_eq!(record.ply_count(), 257);
+        assert_eq!(
+            record.validate(),
+            [crate::ValidationIssue::PastMaxMoves {
+                index: 256,
+                ply: 257,
+                max_moves: 256,
+            }]
+        );
+        let findings = crate::lint_record(&record, &crate::LintConfig::default());
+        assert!(
+            findings.iter().any(|f| f.rule == "KIFU-RESULT-003"),
+            "{findings:?}"
+        );
+
+        // Asked to, the parser applies `$MAX_MOVES` itself.
+        let from_record = ParseOptions {
+            max_moves: MaxMovesPolicy::FromRecord,
+            ..ParseOptions::default()
+        };
+        let err = parse_with_options(&csa, &from_record).unwrap_err();
+        assert_eq!(err.0, "line 261: ply 257 is past the limit of 256 moves");
+        assert!(parse_with_options(&shuffle("", 300, ""), &from_record).is_ok());
+
+        // A limit given to the parser applies whatever the record says.
+        let limited = ParseOptions {
+            max_moves: MaxMovesPolicy::Limit(100),
+            ..ParseOptions::default()
+        };
+        assert!(parse_with_options(&shuffle("", 100, "%TORYO\n"), &limited).is_ok());
+        let err = parse_with_options(&shuffle("$MAX_MOVES:256\n", 101, ""), &limited).unwrap_err();
+        assert!(
+            err.0.contains("ply 101 is past the limit of 100 moves"),
+            "{}",
+            err.0
+        );
+
+        // A `$MAX_MOVES` line after the moves still limits them.
+        let csa = format!("{}$MAX_MOVES:3\n", shuffle("", 4, ""));
+        let err = parse_with_options(&csa, &from_record).unwrap_err();
+        assert_eq!(err.0, "line 8: ply 4 is past the limit of 3 moves");
+        assert_eq!(parse_csa(&csa).unwrap().ply_count(), 4);
+        let record = parse_csa("V2.2\n$MAX_MOVES:1\nPI\n+\n+7776FU\n-3334FU\n").unwrap();
+        assert_eq!(
+            record.validate(),
+            [crate::ValidationIssue::PastMaxMoves {
+                index: 1,
+                ply: 2,
+                max_moves: 1
+            }]
+        );
+
+        let record = parse_csa(&shuffle("$MAX_MOVES:256\n", 255, "%MAX_MOVES\n")).unwrap();
+        assert_eq!(
+            record.validate(),
+            [crate::ValidationIssue::EarlyMaxMoves {
+                index: 255,
+                plies: 255,
+                max_moves: 256,
+            }]
+        );
+    }
+
     #[test]
     fn test_tsumi_without_trailing_newline() {
         // Test that %TSUMI at end of file (no trailing newline) is parsed correctly
@@ -147,4 +1647,610 @@ mod tests {
             record.moves[1].action
         );
     }
+
+    /// Records every statement as a line of text.
+    #[derive(Default)]
+    struct Events {
+        events: Vec<String>,
+        stop_after: Option<usize>,
+    }
+
+    impl Events {
+        fn push(&mut self, event: String) -> ControlFlow<()> {
+            self.events.push(event);
+            match self.stop_after {
+                Some(n) if self.events.len() >= n => ControlFlow::Break(()),
+                _ => ControlFlow::Continue(()),
+            }
+        }
+    }
+
+    impl Visitor for Events {
+        fn on_version(&mut self, version: Version, line: usize) -> ControlFlow<()> {
+            self.push(format!("{line} version {version}"))
+        }
+        fn on_player(&mut self, color: Color, name: Option<&str>, line: usize) -> ControlFlow<()> {
+            self.push(format!("{line} player {color} {name:?}"))
+        }
+        fn on_attr(&mut self, key: &str, value: &str, line: usize) -> ControlFlow<()> {
+            self.push(format!("{line} attr {key}={value}"))
+        }
+        fn on_position(&mut self, position: Position, line: usize) -> ControlFlow<()> {
+            self.push(format!("{line} position {:?}", position.grid().is_some()))
+        }
+        fn on_side_to_move(&mut self, color: Color, line: usize) -> ControlFlow<()> {
+            self.push(format!("{line} side {color}"))
+        }
+        fn on_move(&mut self, action: Action, line: usize) -> ControlFlow<()> {
+            self.push(format!("{line} move {action}"))
+        }
+        fn on_special(&mut self, action: Action, line: usize) -> ControlFlow<()> {
+            self.push(format!("{line} special {action}"))
+        }
+        fn on_time(&mut self, time: Duration, line: usize) -> ControlFlow<()> {
+            self.push(format!("{line} time {time:?}"))
+        }
+        fn on_comment(&mut self, text: &str, line: usize) -> ControlFlow<()> {
+            self.push(format!("{line} comment {text}"))
+        }
+        fn on_warning(&mut self, warning: ParseWarning) -> ControlFlow<()> {
+            self.push(format!("warning {warning}"))
+        }
+    }
+
+    #[test]
+    fn test_visitor_events() {
+        let csa =
+            "'header\nV2.2\nN+A\nN-\n$EVENT:Test\nPI\n+\n+7776FU\nT5\n'nice\n-3334FU\n%TORYO\n";
+        let mut events = Events::default();
+        let flow = visit(csa, &ParseOptions::default(), &mut events).unwrap();
+        assert_eq!(flow, ControlFlow::Continue(()));
+        assert_eq!(
+            events.events,
+            [
+                "1 comment 'header",
+                "2 version V2.2",
+                "3 player + Some(\"A\")",
+                "4 player - None",
+                "5 attr EVENT=Test",
+                "6 position false",
+                "7 side +",
+                "8 move +7776FU",
+                "9 time 5s",
+                "10 comment 'nice",
+                "11 move -3334FU",
+                "12 special %TORYO",
+            ]
+        );
+
+        // V3.0 program comments are comments too.
+        let mut events = Events::default();
+        let csa = "V3.0\nPI\n+\n+7776FU\nT1.5\n'** 30 -3334FU\n";
+        assert!(visit(csa, &ParseOptions::default(), &mut events)
+            .unwrap()
+            .is_continue());
+        assert_eq!(
+            events.events[3..],
+            ["4 move +7776FU", "5 time 1.5s", "6 comment '** 30 -3334FU"]
+        );
+    }
+
+    #[test]
+    fn test_visitor_stops_early() {
+        let csa = "V2.2\nN+A\nN-B\nPI\n+\n+7776FU\n-3334FU\n";
+        let mut events = Events {
+            stop_after: Some(3),
+            ..Events::default()
+        };
+        let flow = visit(csa, &ParseOptions::default(), &mut events).unwrap();
+        assert_eq!(flow, ControlFlow::Break(()));
+        assert_eq!(events.events.len(), 3);
+
+        // Syntax errors still fail the parse, wherever they are.
+        let mut events = Events {
+            stop_after: Some(1),
+            ..Events::default()
+        };
+        assert!(visit(
+            "V2.2\nPI\n+\n+7776XX\n",
+            &ParseOptions::default(),
+            &mut events
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_visitor_warnings() {
+        let csa = "V2.2\nPI\n+\n+7776FU\nT1.5\n";
+        let mut events = Events::default();
+        assert!(visit(csa, &ParseOptions::default(), &mut events).is_err());
+
+        let options = ParseOptions {
+            other_versions: VersionPolicy::Warn,
+            ..ParseOptions::default()
+        };
+        let mut events = Events::default();
+        assert!(visit(csa, &options, &mut events).unwrap().is_continue());
+        assert_eq!(
+            events.events[3..],
+            [
+                "4 move +7776FU",
+                "warning line 5: millisecond time values require V3.0, but this file is V2.2",
+                "5 time 1.5s",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ratings() {
+        let csa = concat!(
+            "V2\n",
+            "N+player1\n",
+            "N-player2\n",
+            "$RATED:yes\n",
+            "'black_rate:player1+0123abcd:1834.5\n",
+            "'rating:player2:1502\n",
+            "PI\n",
+            "+\n",
+        );
+        let record = parse(csa).unwrap();
+        assert_eq!(record.rated, Some(true));
+        assert_eq!(record.attr_str("RATED"), Some("yes"));
+        assert_eq!(record.black_rating, Some(1835));
+        assert_eq!(record.white_rating, Some(1502));
+        let written = record.to_string();
+        assert!(
+            written.contains(
+                "$RATED:yes\n'black_rate:player1+0123abcd:1834.5\n'rating:player2:1502\n"
+            ),
+            "{written}"
+        );
+        assert_eq!(parse(&written).unwrap(), record);
+
+        // Ratings changed since parsing are written anew.
+        let mut changed = record.clone();
+        changed.white_rating = Some(1600);
+        changed.extra_attrs.clear();
+        changed.rated = Some(false);
+        let written = changed.to_string();
+        assert!(
+            written.contains("$RATED:NO\n'black_rate:player1:1835\n'white_rate:player2:1600\n"),
+            "{written}"
+        );
+        let reparsed = parse(&written).unwrap();
+        assert_eq!(reparsed.rated, Some(false));
+        assert_eq!(
+            (reparsed.black_rating, reparsed.white_rating),
+            (Some(1835), Some(1600))
+        );
+
+        // A `'rating:` comment for nobody in the record is just a comment.
+        let record = parse("V2.2\nN+a\n'rating:b:1500\n'black_rate:a:-1\nPI\n+\n").unwrap();
+        assert_eq!((record.black_rating, record.white_rating), (None, None));
+        assert!(record.layout.rating_comments.is_empty());
+    }
+
+    #[test]
+    fn test_commented_attrs() {
+        let csa = include_str!("../../../fixtures/commented_attrs.csa");
+        let read = ParseOptions {
+            commented_attrs: CommentedAttrPolicy::Read,
+            ..ParseOptions::default()
+        };
+
+        let output = parse_with_options(csa, &ParseOptions::default()).unwrap();
+        assert_eq!(output.record.end_time, None);
+
+        let output = parse_with_options(csa, &read).unwrap();
+        let end = output.record.end_time.unwrap();
+        assert_eq!(end.to_string(), "2013/07/15 20:01:44");
+        assert!(output.warnings.is_empty(), "{:?}", output.warnings);
+
+        // A `$KEY` line wins over the comment, wherever the comment is.
+        let csa = "V2.2\n'$END_TIME:2013/07/15 20:01:44\n$END_TIME:2013/07/16\n'$NOTE:x\nPI\n+\n";
+        let output = parse_with_options(csa, &read).unwrap();
+        assert_eq!(
+            output.record.end_time.as_ref().unwrap().to_string(),
+            "2013/07/16"
+        );
+        assert_eq!(output.record.attr_str("NOTE"), Some("x"));
+        assert!(output.warnings.is_empty());
+        let csa = "V2.2\n$END_TIME:2013/07/16\nPI\n+\n'$END_TIME:2013/07/15\n";
+        let output = parse_with_options(csa, &read).unwrap();
+        assert_eq!(
+            output.record.end_time.as_ref().unwrap().to_string(),
+            "2013/07/16"
+        );
+
+        let summaries = [
+            ("%TORYO\n'summary:toryo:a lose:b win\n", None),
+            (
+                "%TORYO\n'summary:time up:a lose:b win\n",
+                Some("ends with %TORYO"),
+            ),
+            (
+                "'summary:toryo:a lose:b win\n",
+                Some("without a special move"),
+            ),
+            ("%TORYO\n'summary:toryo:a win:b lose\n", Some("b losing")),
+            ("%TORYO\n'summary:something new:a win:b lose\n", None),
+        ];
+        for (ending, warning) in summaries {
+            let csa = format!("V2.2\nN+a\nN-b\nPI\n+\n+7776FU\n-3334FU\n{ending}");
+            let output = parse_with_options(&csa, &read).unwrap();
+            match warning {
+                Some(text) => {
+                    assert_eq!(output.warnings.len(), 1, "{ending}");
+                    assert!(
+                        output.warnings[0].message.contains(text),
+                        "{:?}",
+                        output.warnings
+                    );
+                    assert_eq!(output.warnings[0].line, csa.lines().count());
+                }
+                None => assert!(output.warnings.is_empty(), "{:?}", output.warnings),
+            }
+        }
+    }
+
+    #[test]
+    fn test_trailing_attrs() {
+        let csa = include_str!("../../../fixtures/trailing_attrs.csa");
+        let mut record = parse_csa(csa).unwrap();
+        // V2.2 does not write the header comments back.
+        record.header_comments.clear();
+        assert_eq!(
+            record.end_time.as_ref().unwrap().to_string(),
+            "2024/01/06 10:01:12"
+        );
+        assert_eq!(record.moves.len(), 3);
+        assert_eq!(record.layout.trailing_attrs, ["END_TIME"]);
+
+        let written = record.to_string();
+        assert!(
+            written.ends_with("%TORYO\n$END_TIME:2024/01/06 10:01:12\n"),
+            "{written}"
+        );
+        assert_eq!(written.matches("$END_TIME").count(), 1);
+        assert_eq!(parse_csa(&written).unwrap(), record);
+
+        // Without the layout the attribute goes back to the header.
+        let mut plain = record.clone();
+        plain.layout.trailing_attrs.clear();
+        let written = plain.to_string();
+        assert!(written.ends_with("%TORYO\n"), "{written}");
+        assert!(
+            written.contains("$END_TIME:2024/01/06 10:01:12\n"),
+            "{written}"
+        );
+        let attrs_first = WriteOptions {
+            attrs_first: true,
+            ..WriteOptions::default()
+        };
+        assert_eq!(record.to_csa(&attrs_first), written);
+
+        for version in ["V2", "V2.1", "V2.2", "V3.0"] {
+            // After the last move, after comments, and at the very end without a newline.
+            let csa = format!("{version}\nPI\n+\n+7776FU\n'c\n$NOTE:x\n'd\n$END_TIME:2024/01/06");
+            let record = parse_csa(&csa).unwrap();
+            assert_eq!(record.attr_str("NOTE"), Some("x"));
+            assert!(record.end_time.is_some());
+            assert_eq!(record.layout.trailing_attrs, ["NOTE", "END_TIME"]);
+            assert_eq!(parse_csa(&record.to_string()).unwrap(), record);
+
+            // Moves may not follow them.
+            assert!(parse_csa(&format!("{version}\nPI\n+\n+7776FU\n$NOTE:x\n-3334FU\n")).is_err());
+        }
+
+        // The last occurrence decides where a repeated attribute goes.
+        let record = parse_csa("V2.2\n$NOTE:x\nPI\n+\n+7776FU\n$NOTE:y\n").unwrap();
+        assert_eq!(record.attr_str("NOTE"), Some("y"));
+        assert_eq!(record.layout.trailing_attrs, ["NOTE"]);
+    }
+
+    #[test]
+    fn test_late_placements() {
+        let before = parse_with_options(
+            include_str!("../../../fixtures/hands_before_side.csa"),
+            &ParseOptions::default(),
+        )
+        .unwrap();
+        let after = parse_with_options(
+            include_str!("../../../fixtures/hands_after_side.csa"),
+            &ParseOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(after.record.start_pos, before.record.start_pos);
+        assert_eq!(after.record.moves, before.record.moves);
+        assert!(before.warnings.is_empty());
+        assert_eq!(
+            after.warnings.iter().map(|w| w.line).collect::<Vec<_>>(),
+            [8, 10]
+        );
+
+        // Every version takes them.
+        for version in ["V2", "V2.1", "V2.2", "V3.0"] {
+            let csa = format!("{version}\nPI\n+\nP+00FU\n'\nP-00FU\n+0055FU\n");
+            let record = parse_with_options(&csa, &ParseOptions::default())
+                .unwrap()
+                .record;
+            assert_eq!(record.start_pos.add_pieces.len(), 2, "{version}");
+            assert_eq!(record.moves.len(), 1, "{version}");
+        }
+    }
+
+    #[test]
+    fn test_huge_times() {
+        for csa in [
+            "V2.2\nPI\n+\n+7776FU\nT99999999999999999999\n",
+            "V2.2\nPI\n+\n+7776FU\nT315360001\n",
+            "V3.0\nPI\n+\n+7776FU\nT315360000.001\n",
+        ] {
+            let output = parse_with_options(csa, &ParseOptions::default()).unwrap();
+            assert_eq!(output.record.moves[0].time, None, "{csa}");
+            assert_eq!(output.warnings.len(), 1, "{csa}");
+            assert_eq!(output.warnings[0].line, 5, "{csa}");
+        }
+
+        let output = parse_with_options(
+            "V2.2\n$TIME_LIMIT:99999999999999999999:00+00\nPI\n+\n",
+            &ParseOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(output.record.time_limit(), None);
+        assert_eq!(output.warnings.len(), 1);
+
+        let output = parse_with_options(
+            "V3.0\nPI\n+\n+7776FU\nT315360000\n",
+            &ParseOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(output.record.moves[0].time, Some(crate::value::MAX_TIME));
+    }
+
+    #[test]
+    fn test_long_games() {
+        // The kings walk up and back, 1500 plies with times adding up to
+        // well over 99 hours, one of them over 99 hours on its own.
+        let walk = ["+5958OU", "-5152OU", "+5859OU", "-5251OU"];
+        let mut csa = String::from("V3.0\n$TIME_LIMIT:200:00+60\nPI\n+\n");
+        for ply in 0..1500 {
+            csa.push_str(walk[ply % 4]);
+            match ply {
+                1000 => csa.push_str("\nT360000\n"),
+                _ => csa.push_str(&format!("\nT{}.{:03}\n", ply % 600, ply % 1000)),
+            }
+        }
+        csa.push_str("%MAX_MOVES\n");
+
+        let game = parse_with_options(&csa, &ParseOptions::default())
+            .unwrap()
+            .record;
+        assert_eq!(game.ply_count(), 1500);
+        assert_eq!(game.moves[1000].time, Some(Duration::from_secs(360_000)));
+        assert_eq!(game.moves[1499].time, Some(Duration::from_millis(299_499)));
+        let total: Duration = game.moves.iter().filter_map(|m| m.time).sum();
+        assert!(total > Duration::from_secs(99 * 3600 + 360_000));
+        assert_eq!(game.clocks().len(), 1500);
+        assert_eq!(game.position_hashes().unwrap().len(), 1501);
+
+        let written = game.to_csa(&WriteOptions {
+            same_version: true,
+            ..WriteOptions::default()
+        });
+        assert!(written.contains("$TIME_LIMIT:200:00+60\n"));
+        let reparsed = parse_with_options(&written, &ParseOptions::default())
+            .unwrap()
+            .record;
+        assert_eq!(reparsed, game);
+    }
+
+    #[test]
+    fn test_time_unit() {
+        let csa = "V2.2\n$TIME_LIMIT:00:10+00\n'Time_Unit:1min\nPI\n+\n+7776FU\nT2\n-3334FU\nT1\n";
+        let game = parse_csa(csa).unwrap();
+        assert_eq!(game.time_unit, TimeUnit::parse("1min"));
+        assert_eq!(game.moves[0].time, Some(Duration::from_secs(120)));
+        assert_eq!(game.clocks()[1].main_time_left, Duration::from_secs(540));
+        let written = game.to_string();
+        assert!(
+            written.contains("'Time_Unit:1min\nPI\n+\n+7776FU\nT2\n-3334FU\nT1\n"),
+            "{written}"
+        );
+        assert_eq!(parse_csa(&written).unwrap(), game);
+
+        let game =
+            parse_csa("V3.0\n'Time_Unit:200msec\nPI\n+\n+7776FU\nT3\n-3334FU\nT0.5\n").unwrap();
+        assert_eq!(game.moves[0].time, Some(Duration::from_millis(600)));
+        assert_eq!(game.moves[1].time, Some(Duration::from_millis(100)));
+        let written = game.to_csa(&WriteOptions {
+            same_version: true,
+            ..WriteOptions::default()
+        });
+        assert!(written.contains("\nT3\n-3334FU\nT0.500\n"), "{written}");
+        assert_eq!(parse_csa(&written).unwrap(), game);
+
+        for (csa, message) in [
+            (
+                "V2.2\n'Time_Unit:1hour\nPI\n+\n+7776FU\nT2\n",
+                "unknown time unit `1hour`, reading times in seconds",
+            ),
+            (
+                "V2.2\n'Time_Unit:1min\n'Time_Unit:1sec\nPI\n+\n+7776FU\nT2\n",
+                "second time unit `1sec`, ignoring it",
+            ),
+            (
+                "V2.2\nPI\n+\n+7776FU\nT2\n'Time_Unit:1min\n",
+                "time unit `1min` after the first move, ignoring it",
+            ),
+        ] {
+            let output = parse_with_options(csa, &ParseOptions::default()).unwrap();
+            assert_eq!(output.warnings.len(), 1, "{csa}");
+            assert_eq!(output.warnings[0].message, message);
+            let seconds = if csa.contains("1min\n'") { 120 } else { 2 };
+            assert_eq!(
+                output.record.moves[0].time,
+                Some(Duration::from_secs(seconds))
+            );
+        }
+
+        let output = parse_with_options(
+            "V2.2\n'Time_Unit:1min\nPI\n+\n+7776FU\nT315360000\n",
+            &ParseOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(output.record.moves[0].time, None);
+        assert_eq!(output.warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_header_comments() {
+        let same_version = WriteOptions {
+            same_version: true,
+            ..WriteOptions::default()
+        };
+        let header =
+            "'CSA encoding=UTF-8\n'Floodgate record\n'\n'https://wdoor.c.u-tokyo.ac.jp/shogi/\n";
+        let csa = format!("{header}V3.0\nN+sente\nN-gote\n'a comment\nPI\n+\n+7776FU\n%TORYO\n");
+        let game = parse_csa(&csa).unwrap();
+        assert!(game.layout.encoding_line);
+        assert_eq!(
+            game.header_comments,
+            [
+                "'Floodgate record",
+                "'",
+                "'https://wdoor.c.u-tokyo.ac.jp/shogi/"
+            ]
+        );
+        let written = game.to_csa(&same_version);
+        assert!(written.starts_with(&format!("{header}V3.0\n")), "{written}");
+        assert_eq!(parse_csa(&written).unwrap(), game);
+
+        // Other versions start with the version line, and report the
+        // comments they leave out.
+        let written = game.to_string();
+        assert!(written.starts_with("V2.2\n"), "{written}");
+        assert_eq!(
+            game.write_losses(),
+            ["header comments omitted: only V3.0 writes them, but the record is written as V2.2"]
+        );
+        let game = parse_csa(&written).unwrap();
+        assert!(!game.layout.encoding_line);
+        assert!(game.header_comments.is_empty());
+
+        // The encoding line is only added on request.
+        let game = parse_csa("V3.0\nPI\n+\n+7776FU\n").unwrap();
+        assert!(game.to_csa(&same_version).starts_with("V3.0\n"));
+        let written = game.to_csa(&WriteOptions {
+            encoding_line: true,
+            ..same_version
+        });
+        assert!(
+            written.starts_with("'CSA encoding=UTF-8\nV3.0\n"),
+            "{written}"
+        );
+        assert!(parse_csa(&written).unwrap().layout.encoding_line);
+        let written = game.to_csa(&WriteOptions {
+            encoding_line: true,
+            ..WriteOptions::default()
+        });
+        assert!(written.starts_with("V2.2\n"), "{written}");
+
+        let fixture = include_str!("../../../fixtures/v2_2.csa");
+        let game = parse_csa(fixture).unwrap();
+        assert!(!game.header_comments.is_empty());
+        assert!(game.to_string().starts_with("V2.2\n"));
+    }
+
+    #[test]
+    fn test_time_consumed_forms() {
+        let cases = [
+            ("T0\n", Some(Duration::ZERO), 0),
+            ("T12\n", Some(Duration::from_secs(12)), 0),
+            ("T\n", None, 1),
+            ("Tabc\n", None, 1),
+            ("T1x\n", None, 1),
+            ("", None, 0),
+        ];
+        for version in ["V2.2", "V3.0"] {
+            for (time, expected, warnings) in cases {
+                let csa = format!("{version}\nPI\n+\n+7776FU\n{time}-3334FU\nT5\n");
+                let output = parse_with_options(&csa, &ParseOptions::default()).unwrap();
+                let record = output.record;
+                assert_eq!(record.moves[0].time, expected, "{csa}");
+                assert_eq!(record.moves[1].time, Some(Duration::from_secs(5)), "{csa}");
+                assert_eq!(output.warnings.len(), warnings, "{csa}");
+            }
+        }
+
+        let output =
+            parse_with_options("V2.2\nPI\n+\n+7776FU\nTabc\n", &ParseOptions::default()).unwrap();
+        assert_eq!(output.warnings[0].line, 5);
+        assert!(
+            output.warnings[0].message.contains("`Tabc`"),
+            "{:?}",
+            output.warnings
+        );
+    }
+
+    #[test]
+    fn test_source_format() {
+        use crate::value::SourceFormat;
+
+        let same_version = WriteOptions {
+            same_version: true,
+            ..WriteOptions::default()
+        };
+        let mut checked = Vec::new();
+        for entry in std::fs::read_dir("fixtures/").unwrap() {
+            let path = entry.unwrap().path();
+            let Ok(csa) = std::fs::read_to_string(&path) else {
+                continue;
+            };
+            let Ok(record) = parse_csa(&csa) else {
+                continue;
+            };
+            let version = detect_version(&csa).unwrap();
+            assert_eq!(
+                record.layout.source_format,
+                Some(SourceFormat::Csa(version)),
+                "{path:?}"
+            );
+            let header = crate::parse_csa_header(&csa).unwrap();
+            assert_eq!(
+                header.record.layout.source_format,
+                record.layout.source_format
+            );
+
+            let written = record.to_csa(&same_version);
+            assert_eq!(detect_version(&written), Some(version), "{path:?}");
+            let reread = parse_csa(&written).unwrap();
+            let mut expected = record.normalized();
+            if version != Version::V3 {
+                // Only V3.0 writes the header comments back.
+                expected.header_comments.clear();
+            }
+            assert_eq!(reread.normalized(), expected, "{path:?}");
+            assert_eq!(reread.layout.source_format, record.layout.source_format);
+            checked.push(version);
+        }
+        for version in [Version::V2, Version::V2_1, Version::V2_2, Version::V3] {
+            assert!(checked.contains(&version), "no {version} fixture");
+        }
+
+        // The version read is not part of equality, nor of the default output.
+        let v2 = parse_csa("V2\nPI\n+\n+7776FU\n").unwrap();
+        let v2_2 = parse_csa("V2.2\nPI\n+\n+7776FU\n").unwrap();
+        assert_eq!(v2, v2_2);
+        assert_eq!(v2.to_string(), v2_2.to_string());
+        assert!(v2.to_csa(&same_version).starts_with("V2\n"));
+        let v3 = WriteOptions {
+            version: Some(Version::V3),
+            ..same_version
+        };
+        assert!(v2.to_csa(&v3).starts_with("V3.0\n"));
+        assert!(GameRecord::default()
+            .to_csa(&same_version)
+            .starts_with("V2.2\n"));
+    }
 }
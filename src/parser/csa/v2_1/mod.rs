@@ -1,11 +1,19 @@
 //! CSA V2.1 format parser
+//!
+//! V2.1 adds `%TIME_UP`, `%ILLEGAL_MOVE`, `%KACHI` and `%HIKIWAKE` to V2, but
+//! not the `%+ILLEGAL_ACTION` and `%-ILLEGAL_ACTION` of V2.2.
+//! [`ParseOptions::other_versions`] says what to do with those.
 
 use pest::Parser;
 use pest_derive::Parser;
-use std::convert::TryFrom;
-use std::time::Duration;
-use time::{Date as NativeDate, Month, Time as NativeTime};
-
+use std::ops::ControlFlow;
+
+use super::common::{
+    attr_parts, check_grid_split, check_statement, custom_special, grammar_error, line_of,
+    raw_square, skip_separators, square_at, visit_time, RecordBuilder,
+};
+use super::visitor::{emit, visit_action, Visitor};
+use super::{ParseOptions, ParseOutput, Version};
 use crate::value::*;
 
 #[derive(Debug)]
@@ -23,159 +31,147 @@ impl std::error::Error for ParseError {}
 #[grammar = "parser/csa/v2_1/grammar.pest"]
 struct CsaParser;
 
-type Grid = [[Option<(Color, PieceType)>; 9]; 9];
-
 pub fn parse(input: &str) -> Result<GameRecord, ParseError> {
-    let pairs = CsaParser::parse(Rule::game_record, input)
-        .map_err(|e| ParseError(e.to_string()))?;
-
-    let mut record = GameRecord::default();
+    parse_with_options(input, &ParseOptions::default()).map(|output| output.record)
+}
 
-    for pair in pairs {
-        if pair.as_rule() == Rule::game_record {
-            for inner in pair.into_inner() {
-                match inner.as_rule() {
-                    Rule::black_player => record.black_player = parse_player_name(inner),
-                    Rule::white_player => record.white_player = parse_player_name(inner),
-                    Rule::game_attr => parse_game_attr(inner, &mut record),
-                    Rule::position => record.start_pos = parse_position(inner),
-                    Rule::side_to_move => record.start_pos.side_to_move = parse_side_to_move(inner),
-                    Rule::move_records => record.moves = parse_move_records(inner),
-                    Rule::final_move => {
-                        let action = parse_move_record_action(inner);
-                        record.moves.push(MoveRecord { action, time: None });
-                    }
-                    _ => {}
-                }
-            }
-        }
+pub fn parse_with_options(input: &str, options: &ParseOptions) -> Result<ParseOutput, ParseError> {
+    let mut builder = RecordBuilder::new(options, Version::V2_1);
+    let (text, flow) = skip_separators(&mut builder, options, input);
+    if flow.is_continue() {
+        let pairs = grammar(&text)?;
+        builder.end_grammar();
+        // The builder only stops the parse on an error, which `finish` returns.
+        let _ = walk(pairs, options, &mut builder)?;
     }
-
-    Ok(record)
+    builder.finish(input).map_err(ParseError)
 }
 
-fn parse_player_name(pair: pest::iterators::Pair<Rule>) -> Option<String> {
-    for inner in pair.into_inner() {
-        if inner.as_rule() == Rule::player_name {
-            let name = inner.as_str();
-            if !name.is_empty() { return Some(name.to_string()); }
-        }
-    }
-    None
+/// Parses `input`, reporting each statement to `visitor`. Returns
+/// `ControlFlow::Break` if the visitor stopped the parse early.
+pub fn visit(
+    input: &str,
+    options: &ParseOptions,
+    visitor: &mut impl Visitor,
+) -> Result<ControlFlow<()>, ParseError> {
+    let (input, flow) = skip_separators(visitor, options, input);
+    emit!(flow);
+    walk(grammar(&input)?, options, visitor)
 }
 
-fn parse_game_attr(pair: pest::iterators::Pair<Rule>, record: &mut GameRecord) {
-    let mut key = String::new();
+/// Runs the grammar over the whole of `input`.
+fn grammar(input: &str) -> Result<pest::iterators::Pairs<'_, Rule>, ParseError> {
+    CsaParser::parse(Rule::game_record, input).map_err(|e| ParseError(grammar_error(input, e)))
+}
 
-    for inner in pair.into_inner() {
-        match inner.as_rule() {
-            Rule::attr_key => key = inner.as_str().to_string(),
-            Rule::attr_value => {
-                for value_inner in inner.into_inner() {
-                    match value_inner.as_rule() {
-                        Rule::datetime => {
-                            let time = parse_datetime(value_inner);
-                            match key.as_str() {
-                                "START_TIME" => record.start_time = time,
-                                "END_TIME" => record.end_time = time,
-                                _ => {}
-                            }
-                        }
-                        Rule::timelimit => {
-                            if key == "TIME_LIMIT" {
-                                record.time_limit = Some(parse_timelimit(value_inner));
-                            }
-                        }
-                        Rule::attr_text => {
-                            let text = value_inner.as_str().to_string();
-                            match key.as_str() {
-                                "EVENT" => record.event = Some(text),
-                                "SITE" => record.site = Some(text),
-                                "OPENING" => record.opening = Some(text),
-                                _ => {}
-                            }
-                        }
-                        _ => {}
+/// Reports the statements the grammar found to `visitor`, in order.
+fn walk(
+    pairs: pest::iterators::Pairs<'_, Rule>,
+    options: &ParseOptions,
+    visitor: &mut impl Visitor,
+) -> Result<ControlFlow<()>, ParseError> {
+    for pair in pairs {
+        if pair.as_rule() != Rule::game_record {
+            continue;
+        }
+        for inner in pair.into_inner() {
+            let line = line_of(&inner);
+            match inner.as_rule() {
+                Rule::version => emit!(visitor.on_version(Version::V2_1, line)),
+                Rule::black_player => {
+                    emit!(visitor.on_player(Color::Black, parse_player_name(inner), line));
+                }
+                Rule::white_player => {
+                    emit!(visitor.on_player(Color::White, parse_player_name(inner), line));
+                }
+                Rule::game_attr => {
+                    let (key, value) = attr_parts(inner);
+                    emit!(visitor.on_attr(key, value, line));
+                }
+                Rule::position => {
+                    // Attributes splitting a grid, reported after the position
+                    let split_attrs: Vec<_> = inner
+                        .clone()
+                        .into_inner()
+                        .flatten()
+                        .filter(|pair| pair.as_rule() == Rule::game_attr)
+                        .collect();
+                    emit!(visitor.on_position(parse_position(inner)?, line));
+                    for attr in split_attrs {
+                        let line = line_of(&attr);
+                        emit!(check_grid_split(visitor, options, line).map_err(ParseError)?);
+                        let (key, value) = attr_parts(attr);
+                        emit!(visitor.on_attr(key, value, line));
                     }
                 }
+                Rule::side_to_move => {
+                    emit!(visitor.on_side_to_move(parse_side_to_move(inner), line));
+                }
+                Rule::late_placement => {
+                    emit!(visitor.on_late_placement(parse_piece_placements(inner)?, line));
+                }
+                Rule::move_records => emit!(visit_move_records(inner, options, visitor)?),
+                Rule::final_move => {
+                    emit!(check(visitor, options, &inner, line)?);
+                    emit!(visit_action(
+                        visitor,
+                        parse_move_record_action(inner, options)?,
+                        line
+                    ));
+                }
+                Rule::comment => emit!(visitor.on_comment(inner.as_str(), line)),
+                _ => {}
             }
-            _ => {}
         }
     }
-}
-
-fn parse_datetime(pair: pest::iterators::Pair<Rule>) -> Option<Time> {
-    let mut date_str = None;
-    let mut time_str = None;
 
-    for inner in pair.into_inner() {
-        match inner.as_rule() {
-            Rule::date => date_str = Some(inner.as_str()),
-            Rule::time => time_str = Some(inner.as_str()),
-            _ => {}
-        }
-    }
-
-    date_str.and_then(|d| {
-        let date_parts: Vec<&str> = d.split('/').collect();
-        if date_parts.len() != 3 { return None; }
-
-        let year: i32 = date_parts[0].parse().ok()?;
-        let month: u8 = date_parts[1].parse().ok()?;
-        let day: u8 = date_parts[2].parse().ok()?;
-        let month = Month::try_from(month).ok()?;
-        let date = NativeDate::from_calendar_date(year, month, day).ok()?;
-
-        let time = time_str.and_then(|t| {
-            let parts: Vec<&str> = t.split(':').collect();
-            if parts.len() != 3 { return None; }
-            let hour: u8 = parts[0].parse().ok()?;
-            let minute: u8 = parts[1].parse().ok()?;
-            let second: u8 = parts[2].parse().ok()?;
-            NativeTime::from_hms(hour, minute, second).ok()
-        });
-
-        Some(Time { date, time })
-    })
+    Ok(ControlFlow::Continue(()))
 }
 
-fn parse_timelimit(pair: pest::iterators::Pair<Rule>) -> TimeLimit {
-    let mut hours: u64 = 0;
-    let mut minutes: u64 = 0;
-    let mut byoyomi: u64 = 0;
+/// Checks that the version allows a move or time statement.
+fn check(
+    visitor: &mut impl Visitor,
+    options: &ParseOptions,
+    statement: &pest::iterators::Pair<Rule>,
+    line: usize,
+) -> Result<ControlFlow<()>, ParseError> {
+    check_statement(visitor, options, Version::V2_1, statement.as_str(), line).map_err(ParseError)
+}
 
+fn parse_player_name(pair: pest::iterators::Pair<'_, Rule>) -> Option<&str> {
     for inner in pair.into_inner() {
-        match inner.as_rule() {
-            Rule::timelimit_hours => hours = inner.as_str().parse().unwrap_or(0),
-            Rule::timelimit_minutes => minutes = inner.as_str().parse().unwrap_or(0),
-            Rule::timelimit_byoyomi => byoyomi = inner.as_str().parse().unwrap_or(0),
-            _ => {}
+        if inner.as_rule() == Rule::player_name {
+            let name = inner.as_str();
+            if !name.is_empty() {
+                return Some(name);
+            }
         }
     }
-
-    TimeLimit {
-        main_time: Duration::from_secs(hours * 3600 + minutes * 60),
-        byoyomi: Duration::from_secs(byoyomi),
-    }
+    None
 }
 
-fn parse_position(pair: pest::iterators::Pair<Rule>) -> Position {
+fn parse_position(pair: pest::iterators::Pair<Rule>) -> Result<Position, ParseError> {
     let mut pos = Position::default();
 
     for inner in pair.into_inner() {
         match inner.as_rule() {
             Rule::handicap => pos.drop_pieces = parse_handicap(inner),
-            Rule::grid => pos.bulk = Some(parse_grid(inner)),
-            Rule::piece_placement_lines => pos.add_pieces = parse_piece_placements(inner),
+            Rule::grid => pos.bulk = Some(Box::new(parse_grid(inner))),
+            Rule::piece_placement_lines => pos.add_pieces = parse_piece_placements(inner)?,
+            Rule::placements_only => {
+                pos.bulk = Some(Box::new([[None; 9]; 9]));
+                pos.placements_only = true;
+                pos.add_pieces = parse_piece_placements(inner)?;
+            }
             _ => {}
         }
     }
 
-    pos
+    Ok(pos)
 }
 
-fn parse_handicap(pair: pest::iterators::Pair<Rule>) -> Vec<(Square, PieceType)> {
-    let mut pieces = Vec::new();
+fn parse_handicap(pair: pest::iterators::Pair<Rule>) -> PieceList<(Square, PieceType)> {
+    let mut pieces = PieceList::new();
 
     for inner in pair.into_inner() {
         if inner.as_rule() == Rule::handicap_piece {
@@ -184,7 +180,7 @@ fn parse_handicap(pair: pest::iterators::Pair<Rule>) -> Vec<(Square, PieceType)>
 
             for piece_inner in inner.into_inner() {
                 match piece_inner.as_rule() {
-                    Rule::square => square = parse_square(piece_inner.as_str()),
+                    Rule::square => square = raw_square(piece_inner.as_str()),
                     Rule::piece_type => piece_type = parse_piece_type(piece_inner.as_str()),
                     _ => {}
                 }
@@ -243,8 +239,10 @@ fn parse_grid_cell(pair: pest::iterators::Pair<Rule>) -> Option<(Color, PieceTyp
     None
 }
 
-fn parse_piece_placements(pair: pest::iterators::Pair<Rule>) -> Vec<(Color, Square, PieceType)> {
-    let mut placements = Vec::new();
+fn parse_piece_placements(
+    pair: pest::iterators::Pair<Rule>,
+) -> Result<PieceList<(Color, Square, PieceType)>, ParseError> {
+    let mut placements = PieceList::new();
 
     for inner in pair.into_inner() {
         if inner.as_rule() == Rule::piece_placement {
@@ -257,7 +255,7 @@ fn parse_piece_placements(pair: pest::iterators::Pair<Rule>) -> Vec<(Color, Squa
                         let mut pt = PieceType::Pawn;
                         for pp in p.into_inner() {
                             match pp.as_rule() {
-                                Rule::square => sq = parse_square(pp.as_str()),
+                                Rule::square => sq = parse_square(pp, true)?,
                                 Rule::piece_type => pt = parse_piece_type(pp.as_str()),
                                 _ => {}
                             }
@@ -270,7 +268,7 @@ fn parse_piece_placements(pair: pest::iterators::Pair<Rule>) -> Vec<(Color, Squa
         }
     }
 
-    placements
+    Ok(placements)
 }
 
 fn parse_side_to_move(pair: pest::iterators::Pair<Rule>) -> Color {
@@ -280,49 +278,55 @@ fn parse_side_to_move(pair: pest::iterators::Pair<Rule>) -> Color {
     Color::Black
 }
 
-fn parse_move_records(pair: pest::iterators::Pair<Rule>) -> Vec<MoveRecord> {
-    let mut moves = Vec::new();
-    let mut pending_action: Option<Action> = None;
-
+fn visit_move_records(
+    pair: pest::iterators::Pair<Rule>,
+    options: &ParseOptions,
+    visitor: &mut impl Visitor,
+) -> Result<ControlFlow<()>, ParseError> {
     for inner in pair.into_inner() {
+        let line = line_of(&inner);
         match inner.as_rule() {
             Rule::move_record => {
-                if let Some(action) = pending_action.take() {
-                    moves.push(MoveRecord { action, time: None });
-                }
-                pending_action = Some(parse_move_record_action(inner));
+                emit!(check(visitor, options, &inner, line)?);
+                emit!(visit_action(
+                    visitor,
+                    parse_move_record_action(inner, options)?,
+                    line
+                ));
             }
             Rule::time_consumed => {
-                if let Some(action) = pending_action.take() {
-                    let time = parse_time_consumed(inner);
-                    moves.push(MoveRecord { action, time: Some(time) });
-                }
+                emit!(check(visitor, options, &inner, line)?);
+                emit!(visit_time(visitor, parse_time_consumed(inner), line));
             }
+            Rule::comment => emit!(visitor.on_comment(inner.as_str(), line)),
             _ => {}
         }
     }
 
-    if let Some(action) = pending_action {
-        moves.push(MoveRecord { action, time: None });
-    }
-
-    moves
+    Ok(ControlFlow::Continue(()))
 }
 
-fn parse_move_record_action(pair: pest::iterators::Pair<Rule>) -> Action {
+fn parse_move_record_action(
+    pair: pest::iterators::Pair<Rule>,
+    options: &ParseOptions,
+) -> Result<Action, ParseError> {
     for inner in pair.into_inner() {
         match inner.as_rule() {
             Rule::normal_move => return parse_normal_move(inner),
-            Rule::special_move => return parse_special_move(inner.as_str()),
+            Rule::special_move => return Ok(parse_special_move(inner.as_str())),
+            Rule::custom_special => {
+                let line = line_of(&inner);
+                return custom_special(options, inner.as_str(), line).map_err(ParseError);
+            }
             // Handle nested move_record (e.g., from final_move which contains move_record)
-            Rule::move_record => return parse_move_record_action(inner),
+            Rule::move_record => return parse_move_record_action(inner, options),
             _ => {}
         }
     }
-    Action::Error
+    Ok(Action::Error)
 }
 
-fn parse_normal_move(pair: pest::iterators::Pair<Rule>) -> Action {
+fn parse_normal_move(pair: pest::iterators::Pair<Rule>) -> Result<Action, ParseError> {
     let mut color = Color::Black;
     let mut from = Square::new(0, 0);
     let mut to = Square::new(0, 0);
@@ -333,8 +337,11 @@ fn parse_normal_move(pair: pest::iterators::Pair<Rule>) -> Action {
         match inner.as_rule() {
             Rule::color => color = parse_color(inner.as_str()),
             Rule::square => {
-                if sq_count == 0 { from = parse_square(inner.as_str()); }
-                else { to = parse_square(inner.as_str()); }
+                if sq_count == 0 {
+                    from = parse_square(inner, true)?;
+                } else {
+                    to = parse_square(inner, false)?;
+                }
                 sq_count += 1;
             }
             Rule::piece_type => piece = parse_piece_type(inner.as_str()),
@@ -342,44 +349,25 @@ fn parse_normal_move(pair: pest::iterators::Pair<Rule>) -> Action {
         }
     }
 
-    Action::Move(color, from, to, piece)
+    Ok(Action::Move(color, from, to, piece))
 }
 
-// V2.1 special moves (added TIME_UP, ILLEGAL_MOVE; no ILLEGAL_ACTION)
 fn parse_special_move(s: &str) -> Action {
-    if s.contains("TORYO") { Action::Toryo }
-    else if s.contains("CHUDAN") { Action::Chudan }
-    else if s.contains("SENNICHITE") { Action::Sennichite }
-    else if s.contains("TIME_UP") { Action::TimeUp }
-    else if s.contains("ILLEGAL_MOVE") { Action::IllegalMove }
-    else if s.contains("JISHOGI") { Action::Jishogi }
-    else if s.contains("KACHI") { Action::Kachi }
-    else if s.contains("HIKIWAKE") { Action::Hikiwake }
-    else if s.contains("MATTA") { Action::Matta }
-    else if s.contains("TSUMI") { Action::Tsumi }
-    else if s.contains("FUZUMI") { Action::Fuzumi }
-    else { Action::Error }
+    Action::parse(s).unwrap_or(Action::Error)
 }
 
-fn parse_time_consumed(pair: pest::iterators::Pair<Rule>) -> Duration {
-    for inner in pair.into_inner() {
-        if inner.as_rule() == Rule::seconds_consumed {
-            return Duration::from_secs(inner.as_str().parse().unwrap_or(0));
-        }
-    }
-    Duration::from_secs(0)
+fn parse_time_consumed(pair: pest::iterators::Pair<'_, Rule>) -> &str {
+    pair.into_inner().next().map_or("", |inner| inner.as_str())
 }
 
 fn parse_color(s: &str) -> Color {
     match s { "+" => Color::Black, "-" => Color::White, _ => Color::Black }
 }
 
-fn parse_square(s: &str) -> Square {
-    let chars: Vec<char> = s.chars().collect();
-    Square::new(
-        chars[0].to_digit(10).unwrap_or(0) as u8,
-        chars[1].to_digit(10).unwrap_or(0) as u8,
-    )
+/// Reads a square, `00` included when `hand` allows a piece in hand.
+fn parse_square(pair: pest::iterators::Pair<Rule>, hand: bool) -> Result<Square, ParseError> {
+    let line = line_of(&pair);
+    square_at(pair.as_str(), hand, line).map_err(ParseError)
 }
 
 fn parse_piece_type(s: &str) -> PieceType {
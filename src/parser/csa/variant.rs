@@ -0,0 +1,84 @@
+//! Recognizing records of the large shogi variants, which CSA does not cover,
+//! to reject them by name instead of with a syntax error.
+
+/// Two-letter codes of pieces found in chu shogi and dai shogi records, such
+/// as `FK` (free king) and `LN` (lion), none of which CSA has.
+const LARGE_VARIANT_PIECES: [&str; 15] = [
+    "BT", "CG", "DE", "DH", "DK", "FK", "FL", "GB", "KR", "LN", "PH", "RC", "RN", "SM", "VM",
+];
+
+/// Returns what marks `input` as a record of a variant on a board larger than
+/// 9x9: grid rows past `P9` or longer than nine cells, or the piece codes of
+/// those variants. Returns `None` when nothing does.
+pub(crate) fn detect_large_variant(input: &str) -> Option<String> {
+    let mut ranks = 0;
+    let mut files = 0;
+    let mut piece = None;
+    for line in input.lines().map(|line| line.trim_end_matches('\r')) {
+        let Some(rest) = line.strip_prefix('P') else {
+            if line.starts_with(['+', '-']) && piece.is_none() {
+                piece = large_piece(line);
+            }
+            continue;
+        };
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        if let Ok(rank) = rest[..digits].parse::<usize>() {
+            ranks = ranks.max(rank);
+            files = files.max(rest[digits..].len() / 3);
+        }
+        if piece.is_none() {
+            piece = large_piece(line);
+        }
+    }
+
+    if ranks > 9 || files > 9 {
+        let board = format!("a {files}x{ranks} board");
+        return Some(match (files, ranks) {
+            (12, 12) => format!("{board}, as in chu shogi"),
+            (15, 15) => format!("{board}, as in dai shogi"),
+            _ => board,
+        });
+    }
+    piece.map(|code| format!("the piece code {code}, as in chu shogi or dai shogi"))
+}
+
+/// Returns the first code of [`LARGE_VARIANT_PIECES`] in `line` that follows
+/// a square or a side, where a piece code is written.
+fn large_piece(line: &str) -> Option<&'static str> {
+    let bytes = line.as_bytes();
+    (1..bytes.len()).find_map(|i| {
+        if !matches!(bytes[i - 1], b'0'..=b'9' | b'+' | b'-') {
+            return None;
+        }
+        let code = line.get(i..i + 2)?;
+        LARGE_VARIANT_PIECES
+            .into_iter()
+            .find(|&piece| piece == code)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_boards_and_pieces() {
+        let chu = include_str!("../../../fixtures/unsupported/chu_shogi.csa");
+        assert_eq!(
+            detect_large_variant(chu).as_deref(),
+            Some("a 12x12 board, as in chu shogi")
+        );
+        assert_eq!(
+            detect_large_variant("V2.2\nPI\n+\n+5958LN\n").as_deref(),
+            Some("the piece code LN, as in chu shogi or dai shogi")
+        );
+        assert_eq!(
+            detect_large_variant("V2.2\nPI\nP+00FU\n+\n+7776FU\n'LN FK\n"),
+            None
+        );
+        assert_eq!(
+            detect_large_variant(include_str!("../../../fixtures/v2_2.csa")),
+            None
+        );
+    }
+}
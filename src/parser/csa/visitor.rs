@@ -0,0 +1,111 @@
+//! Callbacks for the statements of a CSA file, in the order they are parsed.
+
+use std::ops::ControlFlow;
+use std::time::Duration;
+
+use super::{ParseWarning, Version};
+use crate::value::{Action, Color, PieceList, PieceType, Position, Square};
+
+/// Receives the statements of a CSA file as they are parsed, see
+/// [`crate::parse_csa_with_visitor`].
+///
+/// Every callback gets the 1-based line the statement starts on, and does
+/// nothing by default. Returning `ControlFlow::Break(())` stops the parse
+/// there, so a visitor can skip the rest of a file once it has what it needs.
+///
+/// The record returned by [`crate::parse_csa`] is built by a visitor too, so
+/// the two see exactly the same statements.
+pub trait Visitor {
+    /// A V3.0 `'CSA encoding=` line, with the encoding it names, such as
+    /// `UTF-8`. Earlier versions report the line as a comment.
+    fn on_encoding(&mut self, _encoding: &str, _line: usize) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// The version line.
+    fn on_version(&mut self, _version: Version, _line: usize) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// An `N+` or `N-` line. The name is `None` when the line is bare.
+    fn on_player(&mut self, _color: Color, _name: Option<&str>, _line: usize) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// A `$KEY:value` line, with the value as written.
+    fn on_attr(&mut self, _key: &str, _value: &str, _line: usize) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// A position block: a grid or `PI` line and the `P+`/`P-` lines after it.
+    /// The side to move is reported separately.
+    fn on_position(&mut self, _position: Position, _line: usize) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// The `+` or `-` line giving the side to move first.
+    fn on_side_to_move(&mut self, _color: Color, _line: usize) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// A `P+` or `P-` line found after the side to move rather than in the
+    /// position block, as some generators write hand pieces.
+    fn on_late_placement(
+        &mut self,
+        _placements: PieceList<(Color, Square, PieceType)>,
+        _line: usize,
+    ) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// A move such as `+7776FU`.
+    fn on_move(&mut self, _action: Action, _line: usize) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// A special move such as `%TORYO`.
+    fn on_special(&mut self, _action: Action, _line: usize) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// A `T` line giving the time the move or special move before it took.
+    fn on_time(&mut self, _time: Duration, _line: usize) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// A comment, with its leading `'`. V3.0 `'*` and `'**` comments are
+    /// reported here too.
+    fn on_comment(&mut self, _text: &str, _line: usize) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// A problem that did not stop the parse, such as a statement of another
+    /// CSA version under [`super::VersionPolicy::Warn`].
+    fn on_warning(&mut self, _warning: ParseWarning) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+}
+
+/// Reports `action` as a move or a special move.
+pub(crate) fn visit_action(
+    visitor: &mut impl Visitor,
+    action: Action,
+    line: usize,
+) -> ControlFlow<()> {
+    match action {
+        Action::Move(..) => visitor.on_move(action, line),
+        _ => visitor.on_special(action, line),
+    }
+}
+
+/// Returns from the enclosing walk with `Ok(ControlFlow::Break(()))` when a
+/// callback asks to stop.
+macro_rules! emit {
+    ($callback:expr) => {
+        if let std::ops::ControlFlow::Break(()) = $callback {
+            return Ok(std::ops::ControlFlow::Break(()));
+        }
+    };
+}
+
+pub(crate) use emit;
@@ -3,17 +3,50 @@ pub mod csa;
 use std::error::Error;
 use std::fmt;
 
+use crate::error::Error as CrateError;
 use crate::value::GameRecord;
 
+#[cfg(feature = "intern")]
+pub use csa::Interner;
+pub use csa::{
+    CommentedAttrPolicy, CustomSpecialError, CustomSpecials, DuplicatePolicy, EventConvention,
+    EventConventionError, GameHeader, GridSplitPolicy, MaxMovesPolicy, ParseMetrics, ParseOptions,
+    ParseOutput, ParseWarning, RemovalPolicy, SeparatorPolicy, Version, VersionPolicy, Visitor,
+};
+
+/// Why a CSA file could not be parsed, as [`Error::Csa`](crate::Error::Csa)
+/// carries it.
 #[derive(Debug)]
 pub enum CsaError {
     ParseError(String),
+    /// The input is a record of a variant CSA does not cover, such as chu
+    /// shogi; `detected` names what gave it away, e.g. `a 12x12 board, as
+    /// in chu shogi`.
+    UnsupportedVariant {
+        detected: String,
+    },
+}
+
+impl CsaError {
+    /// Turns the error of a parse of `input` into a `CsaError`, naming the
+    /// variant when the input is of one CSA does not cover.
+    fn from_parse(input: &str, error: csa::ParseError) -> CsaError {
+        match csa::detect_large_variant(input) {
+            Some(detected) => CsaError::UnsupportedVariant { detected },
+            None => CsaError::ParseError(error.0),
+        }
+    }
 }
 
 impl fmt::Display for CsaError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             CsaError::ParseError(msg) => write!(f, "failed to parse: {}", msg),
+            CsaError::UnsupportedVariant { detected } => write!(
+                f,
+                "unsupported variant: found {}, but only boards up to 9x9 with the CSA pieces are supported",
+                detected
+            ),
         }
     }
 }
@@ -23,44 +56,123 @@ impl Error for CsaError {}
 ////////////////////////////////////////////////////////////////////////////////
 
 /// Parse a CSA file with automatic version detection.
-pub fn parse_csa(s: &str) -> Result<GameRecord, CsaError> {
-    csa::parse(s).map_err(|e| CsaError::ParseError(e.0))
+pub fn parse_csa(s: &str) -> Result<GameRecord, CrateError> {
+    csa::parse(s).map_err(|e| CsaError::from_parse(s, e).into())
+}
+
+/// Parse a CSA file with automatic version detection, returning the warnings
+/// raised along with the record.
+pub fn parse_csa_with_options(s: &str, options: &ParseOptions) -> Result<ParseOutput, CrateError> {
+    csa::parse_with_options(s, options).map_err(|e| CsaError::from_parse(s, e).into())
+}
+
+/// Read the players and attributes at the start of a CSA file, with
+/// automatic version detection, without parsing the position or the moves.
+///
+/// ```
+/// use csa::parse_csa_header;
+///
+/// let header = parse_csa_header("V2.2\nN+NAKAHARA\nN-YONENAGA\nPI\n+\n").unwrap();
+/// assert_eq!(header.record.black_player, Some("NAKAHARA".into()));
+/// assert!(!header.truncated);
+/// ```
+pub fn parse_csa_header(s: &str) -> Result<GameHeader, CrateError> {
+    csa::parse_header(s, &ParseOptions::default()).map_err(|e| CsaError::from_parse(s, e).into())
+}
+
+/// Parse a CSA file with automatic version detection, reporting each
+/// statement to `visitor` instead of building a record.
+///
+/// ```
+/// use std::ops::ControlFlow;
+/// use csa::{parse_csa_with_visitor, Color, Visitor};
+///
+/// /// Collects the player names and stops at the first move.
+/// #[derive(Default)]
+/// struct Players(Vec<String>);
+///
+/// impl Visitor for Players {
+///     fn on_player(&mut self, _: Color, name: Option<&str>, _: usize) -> ControlFlow<()> {
+///         self.0.extend(name.map(str::to_string));
+///         ControlFlow::Continue(())
+///     }
+///
+///     fn on_move(&mut self, _: csa::Action, _: usize) -> ControlFlow<()> {
+///         ControlFlow::Break(())
+///     }
+/// }
+///
+/// let mut players = Players::default();
+/// parse_csa_with_visitor("V2.2\nN+NAKAHARA\nN-YONENAGA\nPI\n+\n+2726FU\n", &mut players).unwrap();
+/// assert_eq!(players.0, ["NAKAHARA", "YONENAGA"]);
+/// ```
+pub fn parse_csa_with_visitor(s: &str, visitor: &mut impl Visitor) -> Result<(), CrateError> {
+    csa::visit(s, &ParseOptions::default(), visitor)
+        .map(|_| ())
+        .map_err(|e| CsaError::from_parse(s, e).into())
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
 #[cfg(test)]
 mod tests {
-    use super::*;
-
-    use std::fs::{self, File};
-    use std::io::Read;
     use std::path::Path;
 
+    use super::CsaError;
+    use crate::testing::{run_corpus, CorpusExpectations, Expectation};
+
     #[test]
     fn load_fixtures() {
-        let fixtures_dir = Path::new("fixtures/");
-        let dir_entries = fs::read_dir(fixtures_dir).unwrap();
+        // v1.csa has no version line - we don't support versionless files
+        let expectations = CorpusExpectations {
+            patterns: vec![("v1.csa".to_string(), Expectation::Fail)],
+            ..CorpusExpectations::default()
+        };
+        let report = run_corpus(Path::new("fixtures/"), &expectations).unwrap();
+        assert!(report.checked > 0);
+        assert!(report.is_ok(), "{report}");
+    }
 
-        for entry in dir_entries {
+    #[test]
+    fn unsupported_variants() {
+        for name in ["chu_shogi", "chu_shogi_moves"] {
+            let csa = std::fs::read_to_string(format!("fixtures/unsupported/{name}.csa")).unwrap();
+            assert!(
+                matches!(
+                    crate::parse_csa(&csa),
+                    Err(crate::Error::Csa(CsaError::UnsupportedVariant { .. }))
+                ),
+                "{name}"
+            );
+        }
+        let csa = include_str!("../../fixtures/unsupported/chu_shogi.csa");
+        assert_eq!(
+            crate::parse_csa(csa).unwrap_err().to_string(),
+            "unsupported variant: found a 12x12 board, as in chu shogi, but only boards up to 9x9 with the CSA pieces are supported"
+        );
+    }
+
+    /// Parsing and writing a fixture gives the same bytes every time.
+    #[test]
+    fn repeated_parses_are_identical() {
+        let output = |csa: &str| {
+            let record = crate::parse_csa(csa).ok()?;
+            let output = format!("{record}{:016x}\n", record.canonical_hash());
+            #[cfg(feature = "serde")]
+            let output = output + &serde_json::to_string(&record.summary(true)).unwrap();
+            #[cfg(feature = "binary")]
+            let output = output + &format!("{:?}", record.to_bytes());
+            Some(output)
+        };
+        for entry in std::fs::read_dir("fixtures").unwrap() {
             let path = entry.unwrap().path();
             if !path.is_file() {
                 continue;
             }
-
-            let filename = path.file_name().unwrap().to_str().unwrap();
-
-            let mut file = File::open(&path).unwrap();
-            let mut contents = String::new();
-            file.read_to_string(&mut contents)
-                .expect("failed to load a fixture content");
-            let res = parse_csa(&contents);
-
-            // v1.csa has no version line - we don't support versionless files
-            if filename == "v1.csa" {
-                assert!(res.is_err(), "v1.csa should fail (no version)");
-            } else {
-                assert!(res.is_ok(), "Failed to parse {:?}: {:?}", path, res);
+            let csa = std::fs::read_to_string(&path).unwrap();
+            let first = output(&csa);
+            for _ in 0..100 {
+                assert_eq!(output(&csa), first, "{}", path.display());
             }
         }
     }
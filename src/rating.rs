@@ -0,0 +1,208 @@
+//! Elo ratings computed over a collection of records.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::names::{DefaultNormalizer, NameNormalizer};
+use crate::stats::GameResult;
+use crate::value::{Color, GameRecord};
+
+/// Options for [`compute_ratings`].
+#[derive(Clone, Copy)]
+pub struct EloConfig<'a> {
+    /// How far one game moves a rating, 32 by default.
+    pub k: f64,
+    /// The rating of a player before their first game, 1500 by default.
+    pub initial: f64,
+    /// Whether draws count, as half a win for each side. When not set, drawn
+    /// games are skipped. Set by default.
+    pub count_draws: bool,
+    /// The normalization under which two names are the same player.
+    pub normalizer: &'a dyn NameNormalizer,
+}
+
+impl Default for EloConfig<'_> {
+    fn default() -> Self {
+        EloConfig {
+            k: 32.0,
+            initial: 1500.0,
+            count_draws: true,
+            normalizer: &DefaultNormalizer,
+        }
+    }
+}
+
+impl fmt::Debug for EloConfig<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EloConfig")
+            .field("k", &self.k)
+            .field("initial", &self.initial)
+            .field("count_draws", &self.count_draws)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A player's rating after all their games, see [`compute_ratings`].
+#[derive(Debug, PartialEq, Clone)]
+pub struct PlayerRating {
+    /// The name as written in the first game counted.
+    pub name: String,
+    pub rating: f64,
+    pub wins: usize,
+    pub losses: usize,
+    pub draws: usize,
+}
+
+/// Why [`compute_ratings`] left a record out.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum RatingSkip {
+    /// A side has no name.
+    MissingPlayer,
+    /// Both sides are the same player once normalized.
+    SamePlayer,
+    /// The game has no result, see [`GameRecord::result`].
+    NoResult,
+    /// The game is a draw and [`EloConfig::count_draws`] is not set.
+    Draw,
+}
+
+/// The outcome of [`compute_ratings`].
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct RatingReport {
+    /// The ratings, keyed by normalized player name, in name order.
+    pub ratings: BTreeMap<String, PlayerRating>,
+    /// The records left out, by index in the slice given, in the order the
+    /// games were played.
+    pub skipped: Vec<(usize, RatingSkip)>,
+}
+
+/// Rates the players of `records` with the Elo system, going through the
+/// games in the order of [`GameRecord::sort_key`], that is by start time
+/// with undated games last.
+///
+/// ```
+/// use csa::{compute_ratings, parse_csa, EloConfig, RatingSkip};
+///
+/// let records = [
+///     parse_csa("V2.2\nN+Habu\nN-Sato\n$START_TIME:2024/01/06\nPI\n+\n%TORYO\n").unwrap(),
+///     parse_csa("V2.2\nN+Sato\nN-HABU\n$START_TIME:2024/01/05\nPI\n+\n%TORYO\n").unwrap(),
+///     parse_csa("V2.2\nN+Sato\nPI\n+\n%TORYO\n").unwrap(),
+/// ];
+/// let report = compute_ratings(&records, &EloConfig::default());
+/// // Habu wins on the 5th, then loses on the 6th.
+/// assert_eq!(report.ratings["habu"].rating.round(), 1499.0);
+/// assert_eq!(report.ratings["sato"].rating.round(), 1501.0);
+/// assert_eq!(report.skipped, [(2, RatingSkip::MissingPlayer)]);
+/// ```
+pub fn compute_ratings(records: &[GameRecord], config: &EloConfig) -> RatingReport {
+    let mut order: Vec<usize> = (0..records.len()).collect();
+    order.sort_by_cached_key(|&i| records[i].sort_key());
+
+    let mut report = RatingReport::default();
+    for index in order {
+        let record = &records[index];
+        let (Some(black), Some(white)) = (&record.black_player, &record.white_player) else {
+            report.skipped.push((index, RatingSkip::MissingPlayer));
+            continue;
+        };
+        let keys = [black, white].map(|name| config.normalizer.normalize(name));
+        if keys[0] == keys[1] {
+            report.skipped.push((index, RatingSkip::SamePlayer));
+            continue;
+        }
+        let black_score = match record.result() {
+            Some(GameResult::Win(Color::Black)) => 1.0,
+            Some(GameResult::Win(Color::White)) => 0.0,
+            Some(GameResult::Draw) if config.count_draws => 0.5,
+            Some(GameResult::Draw) => {
+                report.skipped.push((index, RatingSkip::Draw));
+                continue;
+            }
+            None => {
+                report.skipped.push((index, RatingSkip::NoResult));
+                continue;
+            }
+        };
+
+        for (key, name) in keys.iter().zip([black, white]) {
+            report
+                .ratings
+                .entry(key.clone())
+                .or_insert_with(|| PlayerRating {
+                    name: name.to_string(),
+                    rating: config.initial,
+                    wins: 0,
+                    losses: 0,
+                    draws: 0,
+                });
+        }
+        let before = keys.clone().map(|key| report.ratings[&key].rating);
+        for (side, key) in keys.iter().enumerate() {
+            let score = if side == 0 {
+                black_score
+            } else {
+                1.0 - black_score
+            };
+            let opponent = before[1 - side];
+            let expected = 1.0 / (1.0 + 10f64.powf((opponent - before[side]) / 400.0));
+            let player = report.ratings.get_mut(key).unwrap();
+            player.rating += config.k * (score - expected);
+            if score > 0.5 {
+                player.wins += 1;
+            } else if score < 0.5 {
+                player.losses += 1;
+            } else {
+                player.draws += 1;
+            }
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_csa;
+
+    #[test]
+    fn ratings() {
+        let records = [
+            "V2.2\nN+A\nN-B\n$START_TIME:2024/01/01\nPI\n+\n%TORYO\n",
+            "V2.2\nN+A\nN-C\n$START_TIME:2024/01/02\nPI\n+\n+7776FU\n%TORYO\n",
+            "V2.2\nN+B\nN-C\n$START_TIME:2024/01/03\nPI\n+\n+7776FU\n%SENNICHITE\n",
+            "V2.2\nN+B\nN-C\n$START_TIME:2024/01/04\nPI\n+\n+7776FU\n%CHUDAN\n",
+            "V2.2\nN+A\nN-ａ\n$START_TIME:2024/01/05\nPI\n+\n%TORYO\n",
+        ];
+        let records: Vec<GameRecord> = records.iter().map(|csa| parse_csa(csa).unwrap()).collect();
+
+        let report = compute_ratings(&records, &EloConfig::default());
+        assert_eq!(
+            report.skipped,
+            [(3, RatingSkip::NoResult), (4, RatingSkip::SamePlayer)]
+        );
+        let a = &report.ratings["a"];
+        let b = &report.ratings["b"];
+        let c = &report.ratings["c"];
+        assert_eq!((a.wins, a.losses, a.draws), (1, 1, 0));
+        assert_eq!((b.wins, b.losses, b.draws), (1, 0, 1));
+        assert_eq!((c.wins, c.losses, c.draws), (0, 1, 1));
+        // B beats A at 1500 each: +16.
+        // A at 1484 beats C at 1500: A gains 16.74, C loses it.
+        // B at 1516 draws with C at 1483.26: B loses 1.51.
+        assert!((a.rating - 1500.74).abs() < 0.01, "{}", a.rating);
+        assert!((b.rating - 1514.49).abs() < 0.01, "{}", b.rating);
+        assert!((c.rating - 1484.77).abs() < 0.01, "{}", c.rating);
+        let total: f64 = report.ratings.values().map(|p| p.rating).sum();
+        assert!((total - 4500.0).abs() < 1e-9);
+
+        let config = EloConfig {
+            k: 16.0,
+            initial: 1000.0,
+            count_draws: false,
+            ..EloConfig::default()
+        };
+        let report = compute_ratings(&records, &config);
+        assert_eq!(report.skipped[0], (2, RatingSkip::Draw));
+        assert_eq!(report.ratings["b"].rating, 1008.0);
+    }
+}
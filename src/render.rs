@@ -0,0 +1,203 @@
+//! CSA text annotated with lint findings, for sending corrections back to
+//! the people who wrote a record.
+
+use std::collections::BTreeMap;
+use std::ops::ControlFlow;
+use std::time::Duration;
+
+use crate::lint::{LintFinding, LintLocation};
+use crate::parser::{parse_csa_with_visitor, Version, Visitor};
+use crate::value::{Action, Bookmark, Color, Position};
+
+/// The prefix of the comments written by [`annotate_with_findings`].
+pub const FINDING_COMMENT_PREFIX: &str = "'!! ";
+
+/// Returns `input` with each finding written as a comment after the line it
+/// is about, such as `'!! KIFU-MOVE-002: invalid move -7776FU: - has no piece
+/// on 77`. Only comments are added, so the result reads as the same game.
+///
+/// `findings` should come from linting a record parsed from `input`, see
+/// [`lint_record`](crate::lint_record). A finding about a move goes after the
+/// move's `T` line, one about the starting position after the side to move,
+/// and one about a header line after that line. Findings about lines the file
+/// does not have, such as a missing `N+`, go after the version line, or at
+/// the top of a file without one. Findings on the same line keep their order.
+///
+/// ```
+/// use csa::{annotate_with_findings, lint_record, parse_csa, LintConfig};
+///
+/// let csa = "V2.2\nN+Sente\nN-Gote\nPI\n+\n+7776FU\nT3\n-7776FU\nT5\n";
+/// let findings = lint_record(&parse_csa(csa).unwrap(), &LintConfig::default());
+/// let annotated = annotate_with_findings(csa, &findings);
+/// assert!(annotated.ends_with("-7776FU\nT5\n'!! KIFU-MOVE-002: invalid move -7776FU: - has no piece on 77\n"));
+/// assert_eq!(parse_csa(&annotated).unwrap(), parse_csa(csa).unwrap());
+/// ```
+pub fn annotate_with_findings(input: &str, findings: &[LintFinding]) -> String {
+    let mut lines = LineFinder::default();
+    // A file that fails to parse is annotated as far as it was read.
+    let _ = parse_csa_with_visitor(input, &mut lines);
+
+    let mut comments: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+    for finding in findings {
+        let line = match finding.location {
+            LintLocation::Position => lines.position,
+            LintLocation::Move { index, .. } => lines.moves.get(index).copied(),
+            LintLocation::Field(field) => lines.fields.get(field).copied(),
+            LintLocation::Bookmark(index) => lines.bookmarks.get(index).copied(),
+        };
+        let message = finding.message.replace(['\r', '\n'], " ");
+        comments
+            .entry(line.unwrap_or(lines.version))
+            .or_default()
+            .push(format!(
+                "{FINDING_COMMENT_PREFIX}{}: {message}",
+                finding.rule
+            ));
+    }
+
+    let mut output = String::with_capacity(input.len());
+    let mut write_comments = |output: &mut String, line: usize, newline: &str| {
+        for comment in comments.remove(&line).into_iter().flatten() {
+            output.push_str(&comment);
+            output.push_str(newline);
+        }
+    };
+    write_comments(&mut output, 0, "\n");
+    for (i, text) in input.split_inclusive('\n').enumerate() {
+        output.push_str(text);
+        let newline = if text.ends_with("\r\n") { "\r\n" } else { "\n" };
+        if !text.ends_with('\n') {
+            output.push('\n');
+        }
+        write_comments(&mut output, i + 1, newline);
+    }
+    output
+}
+
+/// Collects the lines findings are placed after.
+#[derive(Default)]
+struct LineFinder {
+    /// The version line, 0 before the first line when there is none.
+    version: usize,
+    position: Option<usize>,
+    /// The last line of each entry of `GameRecord::moves`, its `T` included.
+    moves: Vec<usize>,
+    /// The line of each header field, by its prefix, such as `N+` or `$EVENT`.
+    fields: BTreeMap<String, usize>,
+    bookmarks: Vec<usize>,
+}
+
+impl Visitor for LineFinder {
+    fn on_version(&mut self, _version: Version, line: usize) -> ControlFlow<()> {
+        self.version = line;
+        ControlFlow::Continue(())
+    }
+
+    fn on_player(&mut self, color: Color, _name: Option<&str>, line: usize) -> ControlFlow<()> {
+        let field = match color {
+            Color::Black => "N+",
+            Color::White => "N-",
+        };
+        self.fields.insert(field.to_string(), line);
+        ControlFlow::Continue(())
+    }
+
+    fn on_attr(&mut self, key: &str, _value: &str, line: usize) -> ControlFlow<()> {
+        self.fields.insert(format!("${key}"), line);
+        ControlFlow::Continue(())
+    }
+
+    fn on_position(&mut self, _position: Position, line: usize) -> ControlFlow<()> {
+        self.position = Some(line);
+        ControlFlow::Continue(())
+    }
+
+    fn on_side_to_move(&mut self, _color: Color, line: usize) -> ControlFlow<()> {
+        self.position = Some(line);
+        ControlFlow::Continue(())
+    }
+
+    fn on_move(&mut self, _action: Action, line: usize) -> ControlFlow<()> {
+        self.moves.push(line);
+        ControlFlow::Continue(())
+    }
+
+    fn on_special(&mut self, _action: Action, line: usize) -> ControlFlow<()> {
+        self.moves.push(line);
+        ControlFlow::Continue(())
+    }
+
+    fn on_time(&mut self, _time: Duration, line: usize) -> ControlFlow<()> {
+        if let Some(last) = self.moves.last_mut() {
+            *last = line;
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn on_comment(&mut self, text: &str, line: usize) -> ControlFlow<()> {
+        if Bookmark::from_comment(text, 0).is_some() {
+            self.bookmarks.push(line);
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lint_record, parse_csa, LintConfig};
+
+    fn annotate(csa: &str) -> String {
+        let findings = lint_record(&parse_csa(csa).unwrap(), &LintConfig::default());
+        let annotated = annotate_with_findings(csa, &findings);
+        assert_eq!(parse_csa(&annotated).unwrap(), parse_csa(csa).unwrap());
+        annotated
+    }
+
+    #[test]
+    fn findings_follow_their_lines() {
+        let csa = "V2.2\r\nN+Sente\r\n$END_TIME:2020/01/01\r\n$START_TIME:2024/01/06\r\n\
+                   PI\r\n+\r\n+7776FU,T3\r\n-7776FU\r\nT5\r\n%TORYO\r\n";
+        let annotated = annotate(csa);
+        let added: Vec<_> = annotated
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| line.starts_with(FINDING_COMMENT_PREFIX))
+            .map(|(i, line)| (i, line.split(':').next().unwrap()))
+            .collect();
+        assert_eq!(
+            added,
+            [
+                (1, "'!! KIFU-META-002"),
+                (4, "'!! KIFU-META-001"),
+                (11, "'!! KIFU-MOVE-002"),
+            ]
+        );
+        assert!(annotated
+            .split_inclusive('\n')
+            .all(|line| line.ends_with("\r\n")));
+        let original: Vec<_> = annotated
+            .lines()
+            .filter(|line| !line.starts_with(FINDING_COMMENT_PREFIX))
+            .collect();
+        assert_eq!(original, csa.lines().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn unplaced_findings() {
+        let csa = "V2.2\nPI\n+\n+7776FU";
+        let annotated = annotate(csa);
+        assert_eq!(
+            annotated,
+            "V2.2\n'!! KIFU-META-002: + has no name\n'!! KIFU-META-002: - has no name\n\
+             PI\n+\n+7776FU\n"
+        );
+
+        // Without a version line the file does not parse, and everything
+        // goes at the top.
+        let findings = lint_record(&parse_csa(csa).unwrap(), &LintConfig::default());
+        let annotated = annotate_with_findings(&csa[5..], &findings);
+        assert!(annotated.starts_with("'!! KIFU-META-002: + has no name\n"));
+        assert!(annotated.ends_with("'!! KIFU-META-002: - has no name\nPI\n+\n+7776FU\n"));
+    }
+}
@@ -0,0 +1,266 @@
+//! Fixes for defects that damaged records commonly show, suggested but never
+//! applied on their own.
+
+use std::fmt;
+use std::ops::ControlFlow;
+use std::time::Duration;
+
+use crate::board::flip;
+use crate::clock::ValidationIssue;
+use crate::error::Error;
+use crate::parser::{parse_csa, parse_csa_with_visitor, Visitor};
+use crate::value::{Action, GameRecord, MoveRecord};
+
+/// A change that would fix a defect of a record, see [`suggest_repairs`].
+///
+/// Indices refer to the record the repair was suggested for. Applying a
+/// repair can shift the moves after it, so suggest again after each one
+/// rather than applying a whole list.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Repair {
+    /// `moves[index]` repeats the move before it, as when a client sends a
+    /// move twice. Remove it.
+    RemoveDuplicate { index: usize },
+    /// `moves[index]` is made by the side not to move, but would be legal
+    /// for the side to move. Give it to that side.
+    SwapColor { index: usize },
+    /// The recorded times are a thousand times too large, as when
+    /// milliseconds are written as seconds. Divide them.
+    TimesInMilliseconds,
+    /// The game ends without a special move, but the comments after the last
+    /// move say the game was resigned. Add `%TORYO`.
+    AppendToryo,
+}
+
+impl Repair {
+    /// Performs the repair.
+    pub fn apply(&self, record: &mut GameRecord) {
+        match *self {
+            Repair::RemoveDuplicate { index } => {
+                if index < record.moves.len() {
+                    record.moves.remove(index);
+                }
+            }
+            Repair::SwapColor { index } => {
+                if let Some(MoveRecord {
+                    action: Action::Move(color, ..),
+                    ..
+                }) = record.moves.get_mut(index)
+                {
+                    *color = flip(*color);
+                }
+            }
+            Repair::TimesInMilliseconds => {
+                for record in &mut record.moves {
+                    if let Some(ref mut time) = record.time {
+                        *time /= 1000;
+                    }
+                }
+            }
+            Repair::AppendToryo => record.moves.push(MoveRecord::new(Action::Toryo)),
+        }
+    }
+}
+
+impl fmt::Display for Repair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Repair::RemoveDuplicate { index } => {
+                write!(f, "remove move {index}, a repeat of the move before it")
+            }
+            Repair::SwapColor { index } => {
+                write!(f, "give move {index} to the side to move")
+            }
+            Repair::TimesInMilliseconds => {
+                write!(f, "divide every recorded time by 1000")
+            }
+            Repair::AppendToryo => write!(f, "add %TORYO after the last move"),
+        }
+    }
+}
+
+/// Looks for defects a repair can fix: moves sent twice, moves given to the
+/// wrong side, and times recorded in milliseconds.
+///
+/// The moves are replayed up to the first one that neither a repair nor the
+/// rules explain. Times are taken to be in milliseconds when the clocks run
+/// out with the times as recorded but not with them divided by 1000, or,
+/// without a time limit, when every move took at least 1000 seconds.
+///
+/// ```
+/// use csa::{parse_csa, suggest_repairs, Repair};
+///
+/// let mut game = parse_csa("V2.2\nPI\n+\n+7776FU\n+7776FU\n-3334FU\n").unwrap();
+/// let repairs = suggest_repairs(&game);
+/// assert_eq!(repairs, [Repair::RemoveDuplicate { index: 1 }]);
+/// repairs[0].apply(&mut game);
+/// assert!(suggest_repairs(&game).is_empty());
+/// ```
+pub fn suggest_repairs(record: &GameRecord) -> Vec<Repair> {
+    let mut repairs = Vec::new();
+    suggest_move_repairs(record, &mut repairs);
+    if times_in_milliseconds(record) {
+        repairs.push(Repair::TimesInMilliseconds);
+    }
+    repairs
+}
+
+/// Parses a CSA file and looks for defects as [`suggest_repairs`] does, and
+/// also for a missing `%TORYO` where a comment after the last move says
+/// `投了` (resigned). Comments are not kept in a [`GameRecord`], which is why
+/// that defect is only found from the text.
+///
+/// ```
+/// use csa::{suggest_csa_repairs, Repair};
+///
+/// let (game, repairs) = suggest_csa_repairs("V2.2\nPI\n+\n+7776FU\n'まで1手で先手の勝ち 投了\n").unwrap();
+/// assert_eq!(repairs, [Repair::AppendToryo]);
+/// assert_eq!(game.moves.len(), 1);
+/// ```
+pub fn suggest_csa_repairs(s: &str) -> Result<(GameRecord, Vec<Repair>), Error> {
+    let record = parse_csa(s)?;
+    let mut repairs = suggest_repairs(&record);
+
+    let mut comments = TrailingComments::default();
+    parse_csa_with_visitor(s, &mut comments)?;
+    let ends_with_move = matches!(
+        record.moves.last(),
+        Some(MoveRecord {
+            action: Action::Move(..),
+            ..
+        })
+    );
+    if ends_with_move && comments.0.iter().any(|comment| comment.contains("投了")) {
+        repairs.push(Repair::AppendToryo);
+    }
+    Ok((record, repairs))
+}
+
+/// Collects the comments after the last move or special move.
+#[derive(Default)]
+struct TrailingComments(Vec<String>);
+
+impl Visitor for TrailingComments {
+    fn on_move(&mut self, _action: Action, _line: usize) -> ControlFlow<()> {
+        self.0.clear();
+        ControlFlow::Continue(())
+    }
+
+    fn on_special(&mut self, _action: Action, _line: usize) -> ControlFlow<()> {
+        self.0.clear();
+        ControlFlow::Continue(())
+    }
+
+    fn on_comment(&mut self, text: &str, _line: usize) -> ControlFlow<()> {
+        self.0.push(text.to_string());
+        ControlFlow::Continue(())
+    }
+}
+
+fn suggest_move_repairs(record: &GameRecord, repairs: &mut Vec<Repair>) {
+    let Ok(mut board) = record.start_board() else {
+        return;
+    };
+    for (index, m) in record.moves.iter().enumerate() {
+        let Action::Move(color, from, to, pt) = m.action else {
+            continue;
+        };
+        if index > 0 && record.moves[index - 1].action == m.action {
+            repairs.push(Repair::RemoveDuplicate { index });
+            continue;
+        }
+
+        let to_move = board.side_to_move();
+        let action = if color == to_move {
+            m.action
+        } else {
+            let swapped = Action::Move(to_move, from, to, pt);
+            if !board.legal_moves().contains(&swapped) {
+                return;
+            }
+            repairs.push(Repair::SwapColor { index });
+            swapped
+        };
+        if board.apply(&action).is_err() {
+            return;
+        }
+    }
+}
+
+fn times_in_milliseconds(record: &GameRecord) -> bool {
+    let times: Vec<Duration> = record.moves.iter().filter_map(|m| m.time).collect();
+    if times.is_empty() {
+        return false;
+    }
+    let has_limit = record.time_limit_black.is_some() || record.time_limit_white.is_some();
+    if !has_limit {
+        return times.len() > 1 && times.iter().all(|&t| t >= Duration::from_secs(1000));
+    }
+
+    let overruns = |record: &GameRecord| {
+        record
+            .validate()
+            .iter()
+            .any(|issue| matches!(issue, ValidationIssue::ClockOverrun { .. }))
+    };
+    let mut fixed = record.clone();
+    Repair::TimesInMilliseconds.apply(&mut fixed);
+    overruns(record) && !overruns(&fixed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duplicates_and_swapped_colors() {
+        let game = parse_csa("V2.2\nPI\n+\n+7776FU\n+3334FU\n+2726FU\n").unwrap();
+        assert_eq!(suggest_repairs(&game), [Repair::SwapColor { index: 1 }]);
+        let mut fixed = game.clone();
+        Repair::SwapColor { index: 1 }.apply(&mut fixed);
+        assert_eq!(fixed.moves[1].to_string(), "-3334FU\n");
+        assert!(suggest_repairs(&fixed).is_empty());
+
+        // A move that is not legal for the other side either is left alone.
+        let game = parse_csa("V2.2\nPI\n+\n+7776FU\n+2726FU\n").unwrap();
+        assert!(suggest_repairs(&game).is_empty());
+
+        let game = parse_csa("V2.2\nPI\n+\n+7776FU\n-3334FU\n-3334FU\n+2726FU\n").unwrap();
+        assert_eq!(
+            suggest_repairs(&game),
+            [Repair::RemoveDuplicate { index: 2 }]
+        );
+    }
+
+    #[test]
+    fn millisecond_times() {
+        let game = parse_csa(
+            "V2.2\n$TIME_LIMIT:00:10+10\nPI\n+\n+7776FU\nT12000\n-3334FU\nT9500\n+2726FU\nT30000\n",
+        )
+        .unwrap();
+        assert_eq!(suggest_repairs(&game), [Repair::TimesInMilliseconds]);
+        let mut fixed = game.clone();
+        Repair::TimesInMilliseconds.apply(&mut fixed);
+        assert_eq!(fixed.moves[1].time, Some(Duration::from_millis(9500)));
+        assert!(suggest_repairs(&fixed).is_empty());
+
+        let untimed = parse_csa("V2.2\nPI\n+\n+7776FU\nT5000\n-3334FU\nT1000\n").unwrap();
+        assert_eq!(suggest_repairs(&untimed), [Repair::TimesInMilliseconds]);
+        let slow = parse_csa("V2.2\nPI\n+\n+7776FU\nT5000\n-3334FU\nT10\n").unwrap();
+        assert!(suggest_repairs(&slow).is_empty());
+    }
+
+    #[test]
+    fn missing_resignation() {
+        let (mut game, repairs) = suggest_csa_repairs("V2.2\nPI\n+\n+7776FU\n'投了\n").unwrap();
+        assert_eq!(repairs, [Repair::AppendToryo]);
+        repairs[0].apply(&mut game);
+        assert_eq!(game.moves.last().unwrap().action, Action::Toryo);
+
+        // A comment before the last move does not count.
+        let (_, repairs) = suggest_csa_repairs("V2.2\nPI\n+\n'投了\n+7776FU\n").unwrap();
+        assert!(repairs.is_empty());
+        let (_, repairs) = suggest_csa_repairs("V2.2\nPI\n+\n+7776FU\n%TORYO\n'投了\n").unwrap();
+        assert!(repairs.is_empty());
+    }
+}
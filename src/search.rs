@@ -0,0 +1,266 @@
+//! Finding a player's games in a collection of records.
+
+use std::fmt;
+
+use crate::filter::RecordFilter;
+use crate::names::{DefaultNormalizer, NameNormalizer};
+use crate::stats::GameResult;
+use crate::value::{Color, GameRecord};
+
+/// Options for [`find_player`].
+#[derive(Clone, Copy)]
+pub struct PlayerSearchOptions<'a> {
+    /// The normalization names and the query are compared under.
+    pub normalizer: &'a dyn NameNormalizer,
+    /// The largest number of characters inserted, removed or replaced for a
+    /// normalized name to still match the normalized query. 0 by default.
+    pub max_distance: usize,
+}
+
+impl Default for PlayerSearchOptions<'_> {
+    fn default() -> Self {
+        PlayerSearchOptions {
+            normalizer: &DefaultNormalizer,
+            max_distance: 0,
+        }
+    }
+}
+
+impl fmt::Debug for PlayerSearchOptions<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PlayerSearchOptions")
+            .field("max_distance", &self.max_distance)
+            .finish_non_exhaustive()
+    }
+}
+
+/// How a game went for the player looked for.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Outcome {
+    Win,
+    Loss,
+    Draw,
+}
+
+/// A record in which [`find_player`] found the player.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PlayerMatch {
+    /// Index of the record in the slice searched.
+    pub index: usize,
+    /// The side the player had.
+    pub color: Color,
+    /// The name or alias that matched, as written.
+    pub name: String,
+    /// The edit distance between the normalized name and query.
+    pub distance: usize,
+    /// How the game went for the player, `None` when it has no result, see
+    /// [`GameRecord::result`].
+    pub outcome: Option<Outcome>,
+    /// Whether the game started from another position than the standard one,
+    /// as a handicap game does.
+    pub handicap: bool,
+}
+
+/// Wins, losses and draws over a set of [`PlayerMatch`]es.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct PlayerTally {
+    pub wins: usize,
+    pub losses: usize,
+    pub draws: usize,
+}
+
+impl PlayerTally {
+    /// Counts the outcomes of `matches`. Games without a result, such as
+    /// adjourned ones, are never counted; handicap games only when
+    /// `count_handicap` is set.
+    pub fn of(matches: &[PlayerMatch], count_handicap: bool) -> PlayerTally {
+        let mut tally = PlayerTally::default();
+        for m in matches {
+            if m.handicap && !count_handicap {
+                continue;
+            }
+            match m.outcome {
+                Some(Outcome::Win) => tally.wins += 1,
+                Some(Outcome::Loss) => tally.losses += 1,
+                Some(Outcome::Draw) => tally.draws += 1,
+                None => {}
+            }
+        }
+        tally
+    }
+}
+
+/// Returns the games in `records` played by `query`, in the order of the
+/// records, with the side the player had and how the game went for them.
+///
+/// A side matches when its name or one of its aliases (see
+/// [`GameRecord::add_player_alias`]) is within
+/// [`PlayerSearchOptions::max_distance`] edits of the query once both are
+/// normalized. The closest of them is reported. A player found on both
+/// sides of a record, as in a game against themselves, gives one match per
+/// side.
+///
+/// ```
+/// use csa::{find_player, parse_csa, PlayerSearchOptions, PlayerTally};
+///
+/// let records = [
+///     parse_csa("V2.2\nN+Habu Yoshiharu\nN-Sato\nPI\n+\n+7776FU\n%TORYO\n").unwrap(),
+///     parse_csa("V2.2\nN+Sato\nN-ＨＡＢＵ　Yoshiharu\nPI\n+\n+7776FU\n%TORYO\n").unwrap(),
+///     parse_csa("V2.2\nN+Habu Yoshiharo\nN-Sato\nPI\n+\n%TORYO\n").unwrap(),
+/// ];
+/// let options = PlayerSearchOptions {
+///     max_distance: 1,
+///     ..PlayerSearchOptions::default()
+/// };
+/// let matches = find_player(&records, "habu yoshiharu", &options);
+/// assert_eq!(matches.len(), 3);
+/// let tally = PlayerTally::of(&matches, false);
+/// assert_eq!((tally.wins, tally.losses), (1, 2));
+/// ```
+pub fn find_player(
+    records: &[GameRecord],
+    query: &str,
+    options: &PlayerSearchOptions,
+) -> Vec<PlayerMatch> {
+    let query: Vec<char> = options.normalizer.normalize(query).chars().collect();
+    let even = RecordFilter::Handicap(Vec::new());
+    let mut matches = Vec::new();
+    for (index, record) in records.iter().enumerate() {
+        for color in [Color::Black, Color::White] {
+            let closest = record
+                .player_names(color)
+                .into_iter()
+                .map(|name| {
+                    let normalized: Vec<char> =
+                        options.normalizer.normalize(name).chars().collect();
+                    (edit_distance(&normalized, &query), name)
+                })
+                .min_by_key(|&(distance, _)| distance);
+            let Some((distance, name)) = closest else {
+                continue;
+            };
+            if distance > options.max_distance {
+                continue;
+            }
+            let outcome = record.result().map(|result| match result {
+                GameResult::Win(winner) if winner == color => Outcome::Win,
+                GameResult::Win(_) => Outcome::Loss,
+                GameResult::Draw => Outcome::Draw,
+            });
+            matches.push(PlayerMatch {
+                index,
+                color,
+                name: name.to_string(),
+                distance,
+                outcome,
+                handicap: !even.matches(record),
+            });
+        }
+    }
+    matches
+}
+
+/// The Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let replaced = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = replaced.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_csa;
+
+    #[test]
+    fn edit_distances() {
+        let distance = |a: &str, b: &str| {
+            let a: Vec<char> = a.chars().collect();
+            let b: Vec<char> = b.chars().collect();
+            edit_distance(&a, &b)
+        };
+        assert_eq!(distance("", ""), 0);
+        assert_eq!(distance("habu", ""), 4);
+        assert_eq!(distance("kitten", "sitting"), 3);
+        assert_eq!(distance("羽生善治", "羽生義治"), 1);
+        assert_eq!(distance("habu", "hbau"), 2);
+    }
+
+    #[test]
+    fn find_player_tally() {
+        let records = [
+            "V2.2\nN+Habu\nN-Sato\nPI\n+\n+7776FU\n%TORYO\n",
+            "V2.2\nN+Sato\nN-habu\nPI\n+\n+7776FU\n-3334FU\n%SENNICHITE\n",
+            // An adjourned game has no result.
+            "V2.2\nN+Habu\nN-Sato\nPI\n+\n+7776FU\n%CHUDAN\n",
+            // Habu gives a rook and loses.
+            "V2.2\nN+Sato\nN-Habu\nPI82HI\n-\n-3334FU\n+7776FU\n%TORYO\n",
+            "V2.2\nN+Moriuchi\nN-Sato\nPI\n+\n%TORYO\n",
+            "V2.2\nN+Sato\nPI\n+\n%TORYO\n",
+        ];
+        let records: Vec<GameRecord> = records.iter().map(|csa| parse_csa(csa).unwrap()).collect();
+        let matches = find_player(&records, "HABU", &PlayerSearchOptions::default());
+        let found: Vec<_> = matches
+            .iter()
+            .map(|m| (m.index, m.color, m.outcome, m.handicap))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (0, Color::Black, Some(Outcome::Win), false),
+                (1, Color::White, Some(Outcome::Draw), false),
+                (2, Color::Black, None, false),
+                (3, Color::White, Some(Outcome::Loss), true),
+            ]
+        );
+        assert_eq!(matches[1].name, "habu");
+        assert_eq!(
+            PlayerTally::of(&matches, false),
+            PlayerTally {
+                wins: 1,
+                losses: 0,
+                draws: 1
+            }
+        );
+        assert_eq!(PlayerTally::of(&matches, true).losses, 1);
+
+        let sato = find_player(&records, "sato", &PlayerSearchOptions::default());
+        assert_eq!(sato.len(), 6);
+        assert_eq!(
+            PlayerTally::of(&sato, true),
+            PlayerTally {
+                wins: 2,
+                losses: 2,
+                draws: 1
+            }
+        );
+    }
+
+    #[test]
+    fn aliases_and_distance() {
+        let mut record = parse_csa("V2.2\nN+HABU Yoshiharu\nN-Sato\nPI\n+\n").unwrap();
+        record.add_player_alias(Color::Black, "羽生善治");
+        let records = [record];
+        let matches = find_player(&records, "羽生善治", &PlayerSearchOptions::default());
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].name, "羽生善治");
+        assert_eq!(matches[0].outcome, None);
+
+        assert!(find_player(&records, "羽生義治", &PlayerSearchOptions::default()).is_empty());
+        let lenient = PlayerSearchOptions {
+            max_distance: 1,
+            ..PlayerSearchOptions::default()
+        };
+        let matches = find_player(&records, "羽生義治", &lenient);
+        assert_eq!(matches[0].distance, 1);
+        assert!(find_player(&records, "", &lenient).is_empty());
+    }
+}
@@ -0,0 +1,182 @@
+//! Ordering records in a collection.
+
+use std::cmp::Ordering;
+
+use time::{Date as NativeDate, Time as NativeTime};
+
+use crate::value::{GameRecord, Layout, RecordText};
+
+/// The key [`GameRecord::sort_key`] returns, ordering records
+/// chronologically.
+///
+/// Keys compare by, in turn:
+///
+/// 1. the start time, earliest first. A date without a time of day sorts
+///    before any time on that date, and records without `$START_TIME` sort
+///    after all the dated ones;
+/// 2. the event, then Black's name, then White's name, by byte order, each
+///    sorting after every record that has one when missing;
+/// 3. the number of moves played, see [`GameRecord::ply_count`];
+/// 4. the [`GameRecord::canonical_hash`], so that two keys are only equal
+///    for records of the same game.
+///
+/// `GameRecord` itself does not implement `Ord`: a record has no single
+/// natural order, and this key makes the choice explicit.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct RecordSortKey {
+    start: Option<(NativeDate, Option<NativeTime>)>,
+    event: Option<RecordText>,
+    black_player: Option<RecordText>,
+    white_player: Option<RecordText>,
+    ply_count: usize,
+    hash: u64,
+}
+
+impl Ord for RecordSortKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        none_last(&self.start, &other.start)
+            .then_with(|| none_last(&self.event, &other.event))
+            .then_with(|| none_last(&self.black_player, &other.black_player))
+            .then_with(|| none_last(&self.white_player, &other.white_player))
+            .then(self.ply_count.cmp(&other.ply_count))
+            .then(self.hash.cmp(&other.hash))
+    }
+}
+
+impl PartialOrd for RecordSortKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn none_last<T: Ord>(a: &Option<T>, b: &Option<T>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.cmp(b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+impl GameRecord {
+    /// Returns the key to sort records by, see [`RecordSortKey`].
+    ///
+    /// ```
+    /// use csa::parse_csa;
+    ///
+    /// let mut games = vec![
+    ///     parse_csa("V2.2\nPI\n+\n").unwrap(),
+    ///     parse_csa("V2.2\n$START_TIME:2024/01/06\nPI\n+\n").unwrap(),
+    ///     parse_csa("V2.2\n$START_TIME:2024/01/05 19:00\nPI\n+\n").unwrap(),
+    /// ];
+    /// games.sort_by_cached_key(|game| game.sort_key());
+    /// let dates: Vec<_> = games
+    ///     .iter()
+    ///     .map(|game| game.start_time.as_ref().map(|t| t.date.day()))
+    ///     .collect();
+    /// assert_eq!(dates, [Some(5), Some(6), None]);
+    /// ```
+    pub fn sort_key(&self) -> RecordSortKey {
+        RecordSortKey {
+            start: self.start_time.as_ref().map(|time| (time.date, time.time)),
+            event: self.event.clone(),
+            black_player: self.black_player.clone(),
+            white_player: self.white_player.clone(),
+            ply_count: self.ply_count(),
+            hash: self.canonical_hash(),
+        }
+    }
+
+    /// Returns a hash of the game the record holds.
+    ///
+    /// The hash is the 64-bit FNV-1a of the record written as CSA, after
+    /// putting the starting position in canonical form and dropping the
+    /// header comments and the [`Layout`], so records that differ only in
+    /// how they were written hash the same. It does not change between runs
+    /// or platforms.
+    pub fn canonical_hash(&self) -> u64 {
+        let mut record = self.normalized();
+        record.header_comments.clear();
+        record.layout = Layout::default();
+        record
+            .to_string()
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_csa;
+
+    #[test]
+    fn sort_order() {
+        let fixtures = [
+            (
+                "commented_attrs",
+                include_str!("../fixtures/commented_attrs.csa"),
+            ),
+            (
+                "hands_after_side",
+                include_str!("../fixtures/hands_after_side.csa"),
+            ),
+            (
+                "placements_only",
+                include_str!("../fixtures/placements_only.csa"),
+            ),
+            ("v2_2", include_str!("../fixtures/v2_2.csa")),
+            ("v3_fischer", include_str!("../fixtures/v3_fischer.csa")),
+            ("undated", "V2.2\nN+A\nN-B\nPI\n+\n+7776FU\n"),
+            ("undated_anonymous", "V2.2\nPI\n+\n+7776FU\n"),
+            ("date_only", "V2.2\n$START_TIME:2003/05/03\nPI\n+\n"),
+            ("no_event", "V2.2\n$START_TIME:2003/05/03 10:30:00\nPI\n+\n"),
+        ];
+        let mut records: Vec<(&str, GameRecord)> = fixtures
+            .iter()
+            .map(|&(name, csa)| (name, parse_csa(csa).unwrap()))
+            .collect();
+        // The same game as v3_fischer, cut short.
+        let mut shorter = records
+            .iter()
+            .find(|(name, _)| *name == "v3_fischer")
+            .unwrap()
+            .1
+            .clone();
+        shorter.moves.truncate(1);
+        records.push(("shorter", shorter));
+        records.sort_by_cached_key(|(_, record)| record.sort_key());
+        let order: Vec<&str> = records.iter().map(|&(name, _)| name).collect();
+        assert_eq!(
+            order,
+            [
+                "date_only",
+                "v2_2",
+                "no_event",
+                "commented_attrs",
+                "shorter",
+                "v3_fischer",
+                "undated",
+                "hands_after_side",
+                "placements_only",
+                "undated_anonymous",
+            ]
+        );
+    }
+
+    #[test]
+    fn equal_keys_for_the_same_game() {
+        let a = parse_csa("V2.2\nN+A\nPI\n+\n+7776FU\n").unwrap();
+        let b = parse_csa("V2.1\nN+A\nPI\n+\n+7776FU\n%TORYO\n").unwrap();
+        let c = parse_csa("V2.2\nN+A\nPI\n+\n+7776FU\n-3334FU\n").unwrap();
+        // Only the layout differs: a bare `N-` line.
+        let bare = parse_csa("V2.2\nN+A\nN-\nPI\n+\n+7776FU\n").unwrap();
+        assert_ne!(a, bare);
+        assert_eq!(a.sort_key(), bare.sort_key());
+        assert_ne!(a.sort_key(), b.sort_key());
+        assert!(a.sort_key() < c.sort_key());
+        assert!(b.sort_key() < c.sort_key());
+    }
+}
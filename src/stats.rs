@@ -0,0 +1,437 @@
+//! Figures computed over a whole game.
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::board::{flip, Repetition};
+use crate::features::PieceValues;
+use crate::value::{Action, Color, GameRecord};
+
+/// Figures computed over a whole game by [`GameRecord::stats`].
+///
+/// Per-player figures are indexed by `Color as usize`, Black first.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct GameStats {
+    /// Number of plies played.
+    pub plies: usize,
+    /// Sum of the recorded move times of each player.
+    pub time_spent: [Duration; 2],
+    /// The ply at which each player's clock first ran out, as computed by
+    /// [`GameRecord::clocks`], or `None` if it never did.
+    pub first_flag_fall: [Option<usize>; 2],
+}
+
+impl GameRecord {
+    /// Computes the [`GameStats`] of the game.
+    ///
+    /// ```
+    /// use csa::{parse_csa, Color};
+    ///
+    /// let game = parse_csa(
+    ///     "V2.2\n$TIME_LIMIT:00:01+00\nPI\n+\n+7776FU\nT30\n-3334FU\nT70\n+2726FU\nT40\n",
+    /// )
+    /// .unwrap();
+    /// let stats = game.stats();
+    /// assert_eq!(stats.first_flag_fall[Color::White as usize], Some(2));
+    /// assert_eq!(stats.first_flag_fall[Color::Black as usize], Some(3));
+    /// ```
+    pub fn stats(&self) -> GameStats {
+        let mut stats = GameStats::default();
+        let mut plies_at = Vec::with_capacity(self.moves.len());
+        for record in &self.moves {
+            if let Action::Move(color, ..) = record.action {
+                stats.plies += 1;
+                stats.time_spent[color as usize] += record.time.unwrap_or_default();
+            }
+            plies_at.push(stats.plies);
+        }
+
+        for clock in self.clocks() {
+            let flag_fall = &mut stats.first_flag_fall[clock.color as usize];
+            if clock.overrun && flag_fall.is_none() {
+                *flag_fall = Some(plies_at[clock.index]);
+            }
+        }
+        stats
+    }
+}
+
+/// How long the players thought, computed by [`GameRecord::time_stats`] from
+/// the recorded move times.
+///
+/// Per-player figures are indexed by `Color as usize`, Black first. Moves
+/// without a recorded time are only counted in `untimed`.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct TimeStats {
+    /// The number of moves of each player whose time falls in each bucket.
+    /// With edges `e[0] < e[1] < ...`, bucket 0 holds the times below `e[0]`,
+    /// bucket `i` those from `e[i - 1]` up to but not including `e[i]`, and
+    /// the last bucket those of `e[e.len() - 1]` and longer.
+    pub histogram: [Vec<usize>; 2],
+    /// The longest recorded think of the game, the earliest one on a tie.
+    pub longest_think: Option<Think>,
+    /// The number of moves of each player played in under a second.
+    pub quick_moves: [usize; 2],
+    /// The number of moves of each player without a recorded time.
+    pub untimed: [usize; 2],
+}
+
+/// A move and the time spent on it, see [`TimeStats::longest_think`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Think {
+    pub ply: usize,
+    pub action: Action,
+    pub time: Duration,
+}
+
+impl GameRecord {
+    /// Computes the [`TimeStats`] of the game, sorting the move times into
+    /// the buckets bounded by `edges`, which should be increasing.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use csa::{parse_csa, Color};
+    ///
+    /// let game = parse_csa("V2.2\nPI\n+\n+7776FU\nT0\n-3334FU\nT45\n+2726FU\nT8\n").unwrap();
+    /// let edges = [Duration::from_secs(10), Duration::from_secs(60)];
+    /// let stats = game.time_stats(&edges);
+    /// assert_eq!(stats.histogram[Color::Black as usize], [2, 0, 0]);
+    /// assert_eq!(stats.histogram[Color::White as usize], [0, 1, 0]);
+    /// assert_eq!(stats.longest_think.unwrap().ply, 2);
+    /// assert_eq!(stats.quick_moves, [1, 0]);
+    /// ```
+    pub fn time_stats(&self, edges: &[Duration]) -> TimeStats {
+        let mut stats = TimeStats {
+            histogram: [vec![0; edges.len() + 1], vec![0; edges.len() + 1]],
+            ..TimeStats::default()
+        };
+        let mut ply = 0;
+        for record in &self.moves {
+            let Action::Move(color, ..) = record.action else {
+                continue;
+            };
+            ply += 1;
+            let Some(time) = record.time else {
+                stats.untimed[color as usize] += 1;
+                continue;
+            };
+
+            let bucket = edges.iter().take_while(|&&edge| edge <= time).count();
+            stats.histogram[color as usize][bucket] += 1;
+            if time < Duration::from_secs(1) {
+                stats.quick_moves[color as usize] += 1;
+            }
+            if stats
+                .longest_think
+                .is_none_or(|longest| time > longest.time)
+            {
+                stats.longest_think = Some(Think {
+                    ply,
+                    action: record.action,
+                    time,
+                });
+            }
+        }
+        stats
+    }
+}
+
+/// How a game ended, see [`GameRecord::result`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum GameResult {
+    Win(Color),
+    Draw,
+}
+
+/// Displays the winner as `black` or `white`, or `draw`, as in
+/// [`GameSummary::result`](crate::GameSummary::result).
+impl fmt::Display for GameResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GameResult::Win(Color::Black) => write!(f, "black"),
+            GameResult::Win(Color::White) => write!(f, "white"),
+            GameResult::Draw => write!(f, "draw"),
+        }
+    }
+}
+
+/// How a game that reaches its move limit ends, see [`GameRules`].
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum MaxMovesRule {
+    /// The game is a draw.
+    #[default]
+    Draw,
+    /// The game is decided on impasse points, each side's pieces on the board
+    /// and in hand counted with [`PieceValues::IMPASSE`]: a side with fewer
+    /// points than this loses, and the game is a draw when both or neither
+    /// do. 24 under the 24-point rule.
+    Points(i32),
+}
+
+/// Rules applied on top of what a record states, see
+/// [`GameRecord::result_with`] and [`GameRecord::validate_with`].
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct GameRules {
+    /// The most plies the game may have, in place of
+    /// [`GameRecord::max_moves`].
+    pub max_moves: Option<usize>,
+    /// How the game ends when it reaches the limit.
+    pub at_max_moves: MaxMovesRule,
+}
+
+impl GameRules {
+    /// The move limit of `record` under these rules.
+    pub fn max_moves_of(&self, record: &GameRecord) -> Option<usize> {
+        self.max_moves.or_else(|| record.max_moves())
+    }
+}
+
+impl GameRecord {
+    /// Returns the most plies the game may have, from `$MAX_MOVES`.
+    ///
+    /// The game ends once ply `max_moves` is played: that ply is part of the
+    /// game and the next one is not. With `$MAX_MOVES:256`, ply 256 is
+    /// played and the game ends, recorded with `%MAX_MOVES` in V3.0.
+    pub fn max_moves(&self) -> Option<usize> {
+        self.attr_int("MAX_MOVES")
+            .and_then(|max| usize::try_from(max).ok())
+            .filter(|&max| max > 0)
+    }
+
+    /// Returns who won, from the special move ending the game. `None` when
+    /// the game has no result: it has no special move, or was adjourned
+    /// (`%CHUDAN`), stopped by an `%ERROR`, or is a `%FUZUMI` problem.
+    ///
+    /// The special move is read as made by the side to move: it loses by
+    /// `%TORYO`, `%TIME_UP` and `%TSUMI`, and wins by `%KACHI` and by
+    /// `%ILLEGAL_MOVE`, which follows the opponent's illegal move.
+    /// `%SENNICHITE`, `%JISHOGI` and `%HIKIWAKE` are draws, except for a
+    /// `%SENNICHITE` in which one side gave check with every move: it loses,
+    /// see [`GameRecord::repetition`]. Some shogi-server releases record
+    /// that loss as `%+ILLEGAL_ACTION` or `%-ILLEGAL_ACTION` instead.
+    ///
+    /// A game ending in `%MAX_MOVES`, or without a special move once it has
+    /// reached [`GameRecord::max_moves`] plies, is a draw. See
+    /// [`GameRecord::result_with`] for other rules.
+    ///
+    /// ```
+    /// use csa::{parse_csa, Color, GameResult};
+    ///
+    /// let game = parse_csa("V2.2\nPI\n+\n+7776FU\n%TORYO\n").unwrap();
+    /// assert_eq!(game.result(), Some(GameResult::Win(Color::Black)));
+    /// ```
+    pub fn result(&self) -> Option<GameResult> {
+        self.result_with(&GameRules::default())
+    }
+
+    /// Returns who won as [`GameRecord::result`] does, with the move limit
+    /// and its outcome taken from `rules`.
+    ///
+    /// ```
+    /// use csa::{parse_csa, GameResult, GameRules, MaxMovesRule};
+    ///
+    /// let game = parse_csa("V2.2\nPI\n+\n+7776FU\n-3334FU\n").unwrap();
+    /// assert_eq!(game.result(), None);
+    /// let rules = GameRules {
+    ///     max_moves: Some(2),
+    ///     at_max_moves: MaxMovesRule::Points(24),
+    /// };
+    /// // Both sides still have 27 points.
+    /// assert_eq!(game.result_with(&rules), Some(GameResult::Draw));
+    /// ```
+    pub fn result_with(&self, rules: &GameRules) -> Option<GameResult> {
+        let at_limit = rules
+            .max_moves_of(self)
+            .is_some_and(|max| self.ply_count() >= max);
+        let to_move = match self.ply_count() % 2 {
+            0 => self.start_pos.side_to_move,
+            _ => flip(self.start_pos.side_to_move),
+        };
+        let result = match self.moves.last()?.action {
+            Action::Toryo | Action::TimeUp | Action::Tsumi => GameResult::Win(flip(to_move)),
+            Action::Kachi | Action::IllegalMove => GameResult::Win(to_move),
+            Action::IllegalAction(offender) => GameResult::Win(flip(offender)),
+            Action::Sennichite => match self.repetition() {
+                Ok(Some(Repetition {
+                    perpetual_check: Some(checking),
+                    ..
+                })) => GameResult::Win(flip(checking)),
+                _ => GameResult::Draw,
+            },
+            Action::Jishogi | Action::Hikiwake => GameResult::Draw,
+            Action::MaxMoves => return self.max_moves_result(rules.at_max_moves),
+            Action::Move(..) if at_limit => return self.max_moves_result(rules.at_max_moves),
+            Action::Move(..)
+            | Action::Matta
+            | Action::Chudan
+            | Action::Fuzumi
+            | Action::Error
+            | Action::Custom { .. } => return None,
+        };
+        Some(result)
+    }
+
+    /// The result of the game ended at its move limit under `rule`, `None`
+    /// when the points are needed but the moves cannot be replayed.
+    fn max_moves_result(&self, rule: MaxMovesRule) -> Option<GameResult> {
+        let least = match rule {
+            MaxMovesRule::Draw => return Some(GameResult::Draw),
+            MaxMovesRule::Points(least) => least,
+        };
+        let mut board = self.start_board().ok()?;
+        for record in &self.moves {
+            if let Action::Move(..) = record.action {
+                board.apply(&record.action).ok()?;
+            }
+        }
+        let short = [Color::Black, Color::White]
+            .map(|color| board.material(color, &PieceValues::IMPASSE) < least);
+        let result = match short {
+            [true, false] => GameResult::Win(Color::White),
+            [false, true] => GameResult::Win(Color::Black),
+            _ => GameResult::Draw,
+        };
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_csa;
+
+    #[test]
+    fn stats() {
+        let game = parse_csa(
+            "V2.2\n$TIME_LIMIT:00:01+10\nPI\n+\n+7776FU\nT50\n%MATTA\n-3334FU\nT5\n\
+             +2726FU\nT21\n-8384FU\nT3\n+2625FU\nT30\n%TIME_UP\n",
+        )
+        .unwrap();
+        let stats = game.stats();
+        assert_eq!(stats.plies, 5);
+        assert_eq!(
+            stats.time_spent,
+            [Duration::from_secs(101), Duration::from_secs(8)]
+        );
+        assert_eq!(stats.first_flag_fall, [Some(3), None]);
+
+        assert_eq!(GameRecord::default().stats(), GameStats::default());
+    }
+
+    #[test]
+    fn result() {
+        let result = |moves: &str| {
+            parse_csa(&format!("V2.2\nPI\n+\n{moves}"))
+                .unwrap()
+                .result()
+        };
+        assert_eq!(result("+7776FU\n"), None);
+        assert_eq!(result("%TORYO\n"), Some(GameResult::Win(Color::White)));
+        assert_eq!(
+            result("+7776FU\n%TIME_UP\n"),
+            Some(GameResult::Win(Color::Black))
+        );
+        assert_eq!(
+            result("+7776FU\n%KACHI\n"),
+            Some(GameResult::Win(Color::White))
+        );
+        assert_eq!(
+            result("+7776FU\n%ILLEGAL_MOVE\n"),
+            Some(GameResult::Win(Color::White))
+        );
+        assert_eq!(
+            result("+7776FU\n%+ILLEGAL_ACTION\n"),
+            Some(GameResult::Win(Color::White))
+        );
+        assert_eq!(result("+7776FU\n%SENNICHITE\n"), Some(GameResult::Draw));
+        assert_eq!(result("+7776FU\n%CHUDAN\n"), None);
+
+        // White moves first in a handicap game.
+        let game = parse_csa("V2.2\nPI82HI\n-\n-3334FU\n%TORYO\n").unwrap();
+        assert_eq!(game.result(), Some(GameResult::Win(Color::White)));
+    }
+
+    #[test]
+    fn result_of_perpetual_check() {
+        let start = "V2.2\nP-12OU\nP+59OU98HI\n+\n+9892HI\n";
+        let game = |cycle: &str, ending: &str| {
+            parse_csa(&format!("{start}{}{ending}", cycle.repeat(3))).unwrap()
+        };
+        let checks = "-1213OU\n+9293HI\n-1312OU\n+9392HI\n";
+        let quiet = "-1213OU\n+9297HI\n-1312OU\n+9792HI\n";
+
+        assert_eq!(
+            game(checks, "%SENNICHITE\n").result(),
+            Some(GameResult::Win(Color::White))
+        );
+        assert_eq!(
+            game(checks, "%+ILLEGAL_ACTION\n").result(),
+            Some(GameResult::Win(Color::White))
+        );
+        assert_eq!(
+            game(quiet, "%SENNICHITE\n").result(),
+            Some(GameResult::Draw)
+        );
+        // Without a fourth time, the record is taken at its word.
+        let short = parse_csa(&format!("{start}{checks}%SENNICHITE\n")).unwrap();
+        assert_eq!(short.result(), Some(GameResult::Draw));
+    }
+
+    #[test]
+    fn result_at_max_moves() {
+        let game = parse_csa("V2.2\n$MAX_MOVES:2\nPI\n+\n+7776FU\n").unwrap();
+        assert_eq!(game.result(), None);
+        let game = parse_csa("V2.2\n$MAX_MOVES:2\nPI\n+\n+7776FU\n-3334FU\n").unwrap();
+        assert_eq!(game.result(), Some(GameResult::Draw));
+        let game = parse_csa("V3.0\nPI\n+\n+7776FU\n%MAX_MOVES\n").unwrap();
+        assert_eq!(game.max_moves(), None);
+        assert_eq!(game.result(), Some(GameResult::Draw));
+
+        // White gave a rook and a bishop and has 17 points.
+        let game = parse_csa("V2.2\nPI82HI22KA\n-\n-3334FU\n+7776FU\n").unwrap();
+        let rules = GameRules {
+            max_moves: Some(2),
+            at_max_moves: MaxMovesRule::Points(24),
+        };
+        assert_eq!(
+            game.result_with(&rules),
+            Some(GameResult::Win(Color::Black))
+        );
+        let rules = GameRules {
+            max_moves: Some(3),
+            ..rules
+        };
+        assert_eq!(game.result_with(&rules), None);
+        // An explicit result stands.
+        let game = parse_csa("V2.2\n$MAX_MOVES:1\nPI\n+\n+7776FU\n%TORYO\n").unwrap();
+        assert_eq!(game.result(), Some(GameResult::Win(Color::Black)));
+    }
+
+    #[test]
+    fn time_stats() {
+        let game = parse_csa(
+            "V2.2\nPI\n+\n+7776FU\nT10\n-3334FU\n+2726FU\nT30\n%MATTA\n-8384FU\nT30\n\
+             +2625FU\nT0\n%TORYO\n",
+        )
+        .unwrap();
+        let edges = [Duration::from_secs(1), Duration::from_secs(10)];
+        let stats = game.time_stats(&edges);
+        // 10 seconds falls in the bucket starting at 10.
+        assert_eq!(stats.histogram, [vec![1, 0, 2], vec![0, 0, 1]]);
+        assert_eq!(stats.untimed, [0, 1]);
+        assert_eq!(stats.quick_moves, [1, 0]);
+        // White's 30 seconds tie with Black's earlier ones.
+        assert_eq!(
+            stats.longest_think,
+            Some(Think {
+                ply: 3,
+                action: Action::parse("+2726FU").unwrap(),
+                time: Duration::from_secs(30),
+            })
+        );
+
+        let stats = game.time_stats(&[]);
+        assert_eq!(stats.histogram, [vec![3], vec![1]]);
+        assert_eq!(GameRecord::default().time_stats(&edges).longest_think, None);
+    }
+}
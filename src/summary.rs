@@ -0,0 +1,191 @@
+//! One-line summaries of games, for indexing an archive.
+
+use time::PrimitiveDateTime;
+
+use crate::filter::RecordFilter;
+use crate::value::{Action, GameRecord};
+
+/// The version of the [`GameSummary`] layout, raised whenever a field is
+/// added, removed or changes meaning.
+pub const SUMMARY_SCHEMA_VERSION: u32 = 1;
+
+/// A flat summary of a game, see [`GameRecord::summary`].
+///
+/// With the `serde` feature it serializes as one JSON object with these
+/// fields, in this order, missing values as `null`:
+///
+/// | Field | Type | Value |
+/// |---|---|---|
+/// | `schema_version` | number | [`SUMMARY_SCHEMA_VERSION`] |
+/// | `id` | string | [`GameRecord::canonical_hash`], 16 hex digits |
+/// | `black_player`, `white_player` | string | the `N+`/`N-` names |
+/// | `event`, `site` | string | `$EVENT`, `$SITE` |
+/// | `date` | string | the date of `$START_TIME`, as `2024-01-06` |
+/// | `handicap` | bool | the game starts from another position than the standard one |
+/// | `variant` | string | [`GameRecord::variant`], such as `shogi` or `kyoto` |
+/// | `move_count` | number | [`GameRecord::ply_count`] |
+/// | `result` | string | `black`, `white` or `draw`, see [`GameRecord::result`] |
+/// | `ending` | string | the special move ending the game, such as `TORYO` |
+/// | `opening` | string | `$OPENING` in CSA form, else [`GameRecord::classify_opening`] |
+/// | `duration_secs` | number | from `$START_TIME` to `$END_TIME`, when both have a time of day |
+/// | `moves` | array of strings | the moves in CSA, such as `+7776FU`, when asked for |
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GameSummary {
+    pub schema_version: u32,
+    pub id: String,
+    pub black_player: Option<String>,
+    pub white_player: Option<String>,
+    pub event: Option<String>,
+    pub site: Option<String>,
+    pub date: Option<String>,
+    pub handicap: bool,
+    pub variant: String,
+    pub move_count: usize,
+    pub result: Option<String>,
+    pub ending: Option<String>,
+    pub opening: Option<String>,
+    pub duration_secs: Option<u64>,
+    pub moves: Option<Vec<String>>,
+}
+
+impl GameRecord {
+    /// Returns the [`GameSummary`] of the game, listing the moves played
+    /// when `with_moves` is set.
+    ///
+    /// ```
+    /// use csa::parse_csa;
+    ///
+    /// let game = parse_csa("V2.2\nN+Habu\n$START_TIME:2024/01/06\nPI\n+\n+7776FU\n%TORYO\n").unwrap();
+    /// let summary = game.summary(true);
+    /// assert_eq!(summary.date.as_deref(), Some("2024-01-06"));
+    /// assert_eq!(summary.result.as_deref(), Some("black"));
+    /// assert_eq!(summary.moves.unwrap(), ["+7776FU"]);
+    /// ```
+    pub fn summary(&self, with_moves: bool) -> GameSummary {
+        let text = |s: &Option<crate::value::RecordText>| s.as_deref().map(str::to_string);
+        let ending = self.moves.last().and_then(|m| m.action.code());
+        let opening = match self.opening_tag() {
+            Some(tag) => Some(tag.to_csa().to_string()),
+            None => self
+                .classify_opening()
+                .map(|name| name.csa_name().to_string()),
+        };
+        let duration_secs = self
+            .start_time
+            .as_ref()
+            .zip(self.end_time.as_ref())
+            .and_then(|(start, end)| {
+                let start = PrimitiveDateTime::new(start.date, start.time?);
+                let end = PrimitiveDateTime::new(end.date, end.time?);
+                u64::try_from((end - start).whole_seconds()).ok()
+            });
+        let moves = with_moves.then(|| {
+            self.moves
+                .iter()
+                .filter(|m| matches!(m.action, Action::Move(..)))
+                .map(|m| m.action.to_string())
+                .collect()
+        });
+
+        GameSummary {
+            schema_version: SUMMARY_SCHEMA_VERSION,
+            id: format!("{:016x}", self.canonical_hash()),
+            black_player: text(&self.black_player),
+            white_player: text(&self.white_player),
+            event: text(&self.event),
+            site: text(&self.site),
+            date: self.start_time.as_ref().map(|time| {
+                let date = time.date;
+                format!(
+                    "{:04}-{:02}-{:02}",
+                    date.year(),
+                    u8::from(date.month()),
+                    date.day()
+                )
+            }),
+            handicap: !RecordFilter::Handicap(Vec::new()).matches(self),
+            variant: self.variant().name().to_string(),
+            move_count: self.ply_count(),
+            result: self.result().map(|result| result.to_string()),
+            ending: ending.map(str::to_string),
+            opening,
+            duration_secs,
+            moves,
+        }
+    }
+}
+
+/// Writes the [`GameSummary`] of each record as JSON Lines: one JSON object
+/// per line, in the order of the records.
+#[cfg(feature = "serde")]
+pub fn write_summaries<'a>(
+    records: impl IntoIterator<Item = &'a GameRecord>,
+    with_moves: bool,
+    writer: &mut impl std::io::Write,
+) -> std::io::Result<()> {
+    for record in records {
+        serde_json::to_writer(&mut *writer, &record.summary(with_moves))?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse_csa;
+
+    #[test]
+    fn summary() {
+        let game = parse_csa(include_str!("../fixtures/v2_2.csa")).unwrap();
+        let summary = game.summary(false);
+        assert_eq!(summary.schema_version, 1);
+        assert_eq!(summary.id.len(), 16);
+        assert_eq!(summary.black_player.as_deref(), Some("NAKAHARA"));
+        assert_eq!(summary.date.as_deref(), Some("2003-05-03"));
+        assert_eq!(summary.duration_secs, Some(41 * 60 + 5));
+        assert_eq!(summary.opening.as_deref(), Some("YAGURA"));
+        assert_eq!(summary.variant, "shogi");
+        assert!(!summary.handicap);
+        assert_eq!(summary.moves, None);
+
+        let handicap = parse_csa("V2.2\nPI82HI\n-\n-3334FU\n%CHUDAN\n").unwrap();
+        let summary = handicap.summary(true);
+        assert!(summary.handicap);
+        assert_eq!(summary.result, None);
+        assert_eq!(summary.ending.as_deref(), Some("CHUDAN"));
+        assert_eq!(summary.date, None);
+        assert_eq!(summary.moves, Some(vec!["-3334FU".to_string()]));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn golden_summaries() {
+        use super::*;
+
+        let names = [
+            "hands_after_side",
+            "placements_only",
+            "trailing_attrs",
+            "tsume_atamakin",
+            "v2_2",
+            "v3_fischer",
+        ];
+        let records: Vec<GameRecord> = names
+            .iter()
+            .map(|name| {
+                let csa = std::fs::read_to_string(format!("fixtures/{name}.csa")).unwrap();
+                parse_csa(&csa).unwrap()
+            })
+            .collect();
+        let mut jsonl = Vec::new();
+        write_summaries(&records, true, &mut jsonl).unwrap();
+        let jsonl = String::from_utf8(jsonl).unwrap();
+        assert_eq!(jsonl, include_str!("../fixtures/summaries/golden.jsonl"));
+
+        for (line, record) in jsonl.lines().zip(&records) {
+            let summary: GameSummary = serde_json::from_str(line).unwrap();
+            assert_eq!(summary, record.summary(true));
+        }
+    }
+}
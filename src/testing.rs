@@ -0,0 +1,524 @@
+//! Tools for testing code built on this crate: synthetic games, and
+//! directories of records checked against expected outcomes.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use time::{Date as NativeDate, Month};
+
+use crate::board::BoardError;
+use crate::parser::{parse_csa_with_options, ParseOptions};
+use crate::value::{
+    Action, Color, GameRecord, MoveRecord, PieceList, PieceType, RecordText, Square, Time,
+    TimeLimit,
+};
+
+/// Options for [`generate_game`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct GenerateOptions {
+    /// Pieces removed from the standard starting position, as by `PI82HI`.
+    /// White moves first when any are. Empty by default, for an even game.
+    pub handicap: Vec<(Square, PieceType)>,
+    /// The longest the game may go, in plies, before a player resigns or
+    /// runs out of time. 150 by default.
+    pub max_plies: usize,
+    /// The clock of both players, `None` for a game without times. 10
+    /// minutes with 10 seconds of byoyomi by default.
+    pub time_limit: Option<TimeLimit>,
+}
+
+impl Default for GenerateOptions {
+    fn default() -> Self {
+        GenerateOptions {
+            handicap: Vec::new(),
+            max_plies: 150,
+            time_limit: Some(TimeLimit {
+                main_time: Duration::from_secs(600),
+                byoyomi: Duration::from_secs(10),
+                ..TimeLimit::default()
+            }),
+        }
+    }
+}
+
+/// Plays a game of random legal moves and returns its record.
+///
+/// The game starts from the standard position less
+/// [`GenerateOptions::handicap`] and ends in checkmate (`%TSUMI`), fourfold
+/// repetition (`%SENNICHITE`), or, at a length picked at random, with a
+/// resignation or a loss on time. The players, event and start and end times
+/// are made up, and with a time limit every move has a time that keeps
+/// within the clock, so the record has no [`GameRecord::validate`] issues.
+///
+/// The same `seed` and options always give the same record, on every
+/// platform and in every version of the crate that keeps this function's
+/// output as it is.
+///
+/// ```
+/// use csa::testing::{generate_game, GenerateOptions};
+///
+/// let game = generate_game(7, &GenerateOptions::default()).unwrap();
+/// assert_eq!(game, generate_game(7, &GenerateOptions::default()).unwrap());
+/// assert!(game.validate().is_empty());
+/// assert!(game.result().is_some());
+/// ```
+pub fn generate_game(seed: u64, options: &GenerateOptions) -> Result<GameRecord, BoardError> {
+    let mut rng = Rng(seed);
+    let mut record = GameRecord::default();
+    record.start_pos.drop_pieces = PieceList::from(&options.handicap[..]);
+    if !options.handicap.is_empty() {
+        record.start_pos.side_to_move = Color::White;
+    }
+    let black = rng.below(10_000);
+    let white = (black + 1 + rng.below(9_999)) % 10_000;
+    record.black_player = Some(RecordText::from(format!("player{black:04}")));
+    record.white_player = Some(RecordText::from(format!("player{white:04}")));
+    record.event = Some("synthetic".into());
+    record.set_time_limit(options.time_limit.clone());
+
+    let mut board = record.start_board()?;
+    let length = options.max_plies / 2 + rng.below(options.max_plies / 2 + 1);
+    let mut left = [0, 1].map(|_| {
+        options
+            .time_limit
+            .as_ref()
+            .map_or(Duration::ZERO, |limit| limit.main_time)
+    });
+    let mut seen = HashMap::from([(board.clone(), 1)]);
+    let mut elapsed = 0;
+    let ending = loop {
+        let moves = board.legal_moves();
+        if moves.is_empty() {
+            break if board.in_check(board.side_to_move()) {
+                Action::Tsumi
+            } else {
+                Action::Toryo
+            };
+        }
+        if record.moves.len() >= length {
+            break if options.time_limit.is_some() && rng.below(10) == 0 {
+                Action::TimeUp
+            } else {
+                Action::Toryo
+            };
+        }
+
+        let action = moves[rng.below(moves.len())];
+        let color = board.side_to_move() as usize;
+        let time = options.time_limit.as_ref().map(|limit| {
+            let available = (left[color] + limit.byoyomi).as_secs();
+            let least = limit.least_time_per_move.as_secs().min(available);
+            let most = available.min(60).max(least);
+            let spent = Duration::from_secs(least + rng.below((most - least + 1) as usize) as u64);
+            elapsed += spent.as_secs();
+            left[color] = left[color].saturating_sub(spent) + limit.increment;
+            spent
+        });
+        board.apply(&action)?;
+        record.moves.push(MoveRecord {
+            time,
+            ..MoveRecord::new(action)
+        });
+
+        let count = seen.entry(board.clone()).or_insert(0);
+        *count += 1;
+        if *count == 4 {
+            break Action::Sennichite;
+        }
+    };
+    let time = match (ending, &options.time_limit) {
+        (Action::TimeUp, Some(limit)) => {
+            let spent =
+                left[board.side_to_move() as usize] + limit.byoyomi + Duration::from_secs(1);
+            elapsed += spent.as_secs();
+            Some(spent)
+        }
+        _ => None,
+    };
+    record.moves.push(MoveRecord {
+        time,
+        ..MoveRecord::new(ending)
+    });
+
+    let start = NativeDate::from_calendar_date(2024, Month::January, 1)
+        .expect("valid date")
+        .midnight()
+        + time::Duration::days(rng.below(366) as i64)
+        + time::Duration::seconds(rng.below(86_400) as i64);
+    record.start_time = Some(Time {
+        date: start.date(),
+        time: Some(start.time()),
+    });
+    if options.time_limit.is_some() {
+        let end = start + time::Duration::seconds(elapsed as i64);
+        record.end_time = Some(Time {
+            date: end.date(),
+            time: Some(end.time()),
+        });
+    }
+    Ok(record)
+}
+
+/// What parsing a file of a corpus should give, see [`run_corpus`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Expectation {
+    /// The file parses, with or without warnings.
+    Pass,
+    /// The file fails to parse.
+    Fail,
+    /// The file parses with exactly this many warnings.
+    ParseWarningCount(usize),
+}
+
+impl fmt::Display for Expectation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expectation::Pass => write!(f, "to parse"),
+            Expectation::Fail => write!(f, "to fail"),
+            Expectation::ParseWarningCount(count) => write!(f, "to parse with {count} warnings"),
+        }
+    }
+}
+
+/// The expected outcomes of the files of a corpus, see [`run_corpus`].
+#[derive(Debug, Default, Clone)]
+pub struct CorpusExpectations<'a> {
+    /// Expectations by file name pattern, the first that matches applying.
+    /// In a pattern, `*` stands for any run of characters and `?` for any
+    /// one character.
+    pub patterns: Vec<(String, Expectation)>,
+    /// The expectation of files no pattern matches, [`Expectation::Pass`]
+    /// by default.
+    pub default: Option<Expectation>,
+    /// The options the files are parsed with.
+    pub options: ParseOptions<'a>,
+}
+
+impl CorpusExpectations<'_> {
+    /// Returns the expectation for the file `name`, and the index in
+    /// `patterns` of the pattern that gave it.
+    pub fn expectation_of(&self, name: &str) -> (Expectation, Option<usize>) {
+        match self
+            .patterns
+            .iter()
+            .position(|(pattern, _)| matches_pattern(pattern, name))
+        {
+            Some(i) => (self.patterns[i].1, Some(i)),
+            None => (self.default.unwrap_or(Expectation::Pass), None),
+        }
+    }
+}
+
+/// A file whose outcome was not the one expected, see [`CorpusReport`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CorpusMismatch {
+    pub file: String,
+    pub expected: Expectation,
+    /// What happened instead: the parse error, or the warnings raised.
+    pub actual: String,
+}
+
+impl fmt::Display for CorpusMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: expected {}, but {}",
+            self.file, self.expected, self.actual
+        )
+    }
+}
+
+/// The outcome of [`run_corpus`].
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct CorpusReport {
+    /// The number of files parsed.
+    pub checked: usize,
+    /// The files that did not do as expected, by file name.
+    pub mismatches: Vec<CorpusMismatch>,
+    /// The patterns of [`CorpusExpectations::patterns`] that matched no
+    /// file, which are most likely stale.
+    pub unused_patterns: Vec<String>,
+}
+
+impl CorpusReport {
+    /// Whether every file did as expected and every pattern was used.
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty() && self.unused_patterns.is_empty()
+    }
+}
+
+/// Lists the mismatches and unused patterns, one per line.
+impl fmt::Display for CorpusReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for mismatch in &self.mismatches {
+            writeln!(f, "{mismatch}")?;
+        }
+        for pattern in &self.unused_patterns {
+            writeln!(f, "{pattern}: matches no file")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses every file directly in `dir`, in file name order, and checks each
+/// outcome against `expectations`. Subdirectories are skipped, and a file
+/// that is not UTF-8 counts as failing to parse.
+///
+/// ```no_run
+/// use std::path::Path;
+/// use csa::testing::{run_corpus, CorpusExpectations, Expectation};
+///
+/// let expectations = CorpusExpectations {
+///     patterns: vec![("broken_*.csa".to_string(), Expectation::Fail)],
+///     ..CorpusExpectations::default()
+/// };
+/// let report = run_corpus(Path::new("fixtures"), &expectations).unwrap();
+/// assert!(report.is_ok(), "{report}");
+/// ```
+pub fn run_corpus(dir: &Path, expectations: &CorpusExpectations) -> io::Result<CorpusReport> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    let mut report = CorpusReport::default();
+    let mut used = vec![false; expectations.patterns.len()];
+    for path in files {
+        let file = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        let (expected, pattern) = expectations.expectation_of(&file);
+        if let Some(i) = pattern {
+            used[i] = true;
+        }
+        let outcome = match String::from_utf8(std::fs::read(&path)?) {
+            Ok(contents) => parse_csa_with_options(&contents, &expectations.options)
+                .map(|output| output.warnings)
+                .map_err(|e| e.to_string()),
+            Err(_) => Err("the file is not UTF-8".to_string()),
+        };
+        report.checked += 1;
+
+        let actual = match (expected, outcome) {
+            (Expectation::Fail, Err(_)) | (Expectation::Pass, Ok(_)) => continue,
+            (Expectation::ParseWarningCount(count), Ok(warnings)) if warnings.len() == count => {
+                continue
+            }
+            (_, Err(error)) => format!("it failed: {error}"),
+            (_, Ok(warnings)) if warnings.is_empty() => "it parsed without warnings".to_string(),
+            (_, Ok(warnings)) => {
+                let warnings: Vec<String> = warnings.iter().map(ToString::to_string).collect();
+                format!(
+                    "it parsed with {} warnings: {}",
+                    warnings.len(),
+                    warnings.join("; ")
+                )
+            }
+        };
+        report.mismatches.push(CorpusMismatch {
+            file,
+            expected,
+            actual,
+        });
+    }
+    report.unused_patterns = expectations
+        .patterns
+        .iter()
+        .zip(used)
+        .filter(|(_, used)| !used)
+        .map(|((pattern, _), _)| pattern.clone())
+        .collect();
+    Ok(report)
+}
+
+/// Whether `name` matches `pattern`, where `*` stands for any run of
+/// characters and `?` for any one character.
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // The positions after the last `*` and the name character it stands up
+    // to, to go back to when the rest of the pattern does not match.
+    let (mut p, mut n) = (0, 0);
+    let mut star = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p + 1, n));
+            p += 1;
+        } else if let Some((after, matched)) = star {
+            p = after;
+            n = matched + 1;
+            star = Some((after, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// SplitMix64, kept here rather than taken from a crate so that the games
+/// generated for a seed never change under a dependency update.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number below `n`, or 0 when `n` is 0.
+    fn below(&mut self, n: usize) -> usize {
+        if n == 0 {
+            return 0;
+        }
+        (self.next() % n as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lint::{lint_record, LintConfig, Severity};
+    use crate::parse_csa;
+
+    #[test]
+    fn rng_is_pinned() {
+        let mut rng = Rng(0);
+        assert_eq!(rng.next(), 0xe220_a839_7b1d_cdaf);
+        assert_eq!(rng.next(), 0x6e78_9e6a_a1b9_65f4);
+    }
+
+    #[test]
+    fn deterministic() {
+        let options = GenerateOptions {
+            max_plies: 40,
+            ..GenerateOptions::default()
+        };
+        let game = generate_game(7, &options).unwrap();
+        assert_eq!(game, generate_game(7, &options).unwrap());
+        assert_ne!(game, generate_game(8, &options).unwrap());
+        assert_eq!(
+            game.to_string(),
+            include_str!("../fixtures/testing/seed_7.csa")
+        );
+        assert_eq!(parse_csa(&game.to_string()).unwrap().moves, game.moves);
+    }
+
+    #[test]
+    fn valid_by_construction() {
+        let config = LintConfig::default();
+        for seed in 0..50 {
+            let options = GenerateOptions {
+                time_limit: if seed % 5 == 0 {
+                    None
+                } else {
+                    GenerateOptions::default().time_limit
+                },
+                ..GenerateOptions::default()
+            };
+            let game = generate_game(seed, &options).unwrap();
+            assert!(game.validate().is_empty(), "seed {seed}");
+            assert!(game.result().is_some(), "seed {seed}");
+            assert!(game.ply_count() <= options.max_plies, "seed {seed}");
+            let findings = lint_record(&game, &config);
+            assert!(
+                findings.iter().all(|f| f.severity == Severity::Info),
+                "seed {seed}: {findings:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn handicap_and_short_clock() {
+        let options = GenerateOptions {
+            handicap: vec![(Square::new(8, 2), PieceType::Rook)],
+            max_plies: 60,
+            time_limit: Some(TimeLimit {
+                byoyomi: Duration::from_secs(5),
+                least_time_per_move: Duration::from_secs(1),
+                ..TimeLimit::default()
+            }),
+        };
+        for seed in 0..20 {
+            let game = generate_game(seed, &options).unwrap();
+            assert!(matches!(
+                game.moves[0].action,
+                Action::Move(Color::White, ..)
+            ));
+            assert!(game.validate().is_empty(), "seed {seed}");
+        }
+        let invalid = GenerateOptions {
+            handicap: vec![(Square::new(5, 5), PieceType::Rook)],
+            ..GenerateOptions::default()
+        };
+        assert!(generate_game(0, &invalid).is_err());
+    }
+
+    #[test]
+    fn patterns() {
+        assert!(matches_pattern("v1.csa", "v1.csa"));
+        assert!(!matches_pattern("v1.csa", "v1.csa.bak"));
+        assert!(matches_pattern("*.csa", "v2_2.csa"));
+        assert!(matches_pattern("*", ""));
+        assert!(matches_pattern("v?_*.csa", "v3_fischer.csa"));
+        assert!(matches_pattern("*_*_*.csa", "a_b_c_d.csa"));
+        assert!(!matches_pattern("*.kif", "v2_2.csa"));
+        assert!(matches_pattern("棋譜*", "棋譜1.csa"));
+    }
+
+    #[test]
+    fn corpus() {
+        let dir = std::env::temp_dir().join(format!("csa-corpus-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        std::fs::write(dir.join("ok.csa"), "V2.2\nPI\n+\n+7776FU\n").unwrap();
+        std::fs::write(dir.join("bad.csa"), "PI\n+\n").unwrap();
+        std::fs::write(dir.join("binary.csa"), [0xff, 0xfe]).unwrap();
+        std::fs::write(dir.join("nested/skipped.csa"), "").unwrap();
+
+        let mut expectations = CorpusExpectations {
+            patterns: vec![
+                ("b*.csa".to_string(), Expectation::Fail),
+                ("*.kif".to_string(), Expectation::Pass),
+            ],
+            ..CorpusExpectations::default()
+        };
+        let report = run_corpus(&dir, &expectations).unwrap();
+        assert_eq!(report.checked, 3);
+        assert!(report.mismatches.is_empty());
+        assert_eq!(report.unused_patterns, ["*.kif"]);
+        assert!(!report.is_ok());
+
+        expectations.patterns = vec![("ok.csa".to_string(), Expectation::ParseWarningCount(1))];
+        let report = run_corpus(&dir, &expectations).unwrap();
+        let mismatches: Vec<String> = report.mismatches.iter().map(ToString::to_string).collect();
+        assert_eq!(mismatches.len(), 3);
+        assert!(mismatches[0]
+            .starts_with("bad.csa: expected to parse, but it failed: failed to parse: "));
+        assert_eq!(
+            mismatches[1],
+            "binary.csa: expected to parse, but it failed: the file is not UTF-8"
+        );
+        assert_eq!(
+            mismatches[2],
+            "ok.csa: expected to parse with 1 warnings, but it parsed without warnings"
+        );
+        assert_eq!(report.to_string().lines().count(), 3);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(run_corpus(&dir, &expectations).is_err());
+    }
+}
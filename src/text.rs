@@ -0,0 +1,142 @@
+//! Cutting text to the length limits of software that reads records.
+
+use std::fmt;
+
+/// What [`truncate_display`] counts.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Units {
+    /// UTF-8 bytes, for readers with a byte buffer per field.
+    Bytes,
+    /// Unicode scalar values.
+    Chars,
+}
+
+impl fmt::Display for Units {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Units::Bytes => write!(f, "bytes"),
+            Units::Chars => write!(f, "chars"),
+        }
+    }
+}
+
+/// Returns the longest start of `s` that is at most `max` units long and
+/// ends between two characters as they are displayed.
+///
+/// A code point is never split, and neither is a character from the marks
+/// that change how it is displayed: combining marks such as the voicing
+/// mark of `か\u{3099}` or of half-width `ｶﾞ`, variation selectors,
+/// including the ideographic ones some names are written with, emoji
+/// modifiers and tags, and the characters joined by a zero width joiner. This follows the grapheme
+/// clusters of Unicode for the text found in records without their full
+/// tables, so a few scripts may still be cut where a cluster goes on.
+///
+/// ```
+/// use csa::{truncate_display, Units};
+///
+/// assert_eq!(truncate_display("名人戦第一局", 9, Units::Bytes), "名人戦");
+/// assert_eq!(truncate_display("名人戦第一局", 3, Units::Chars), "名人戦");
+/// // The voicing mark stays with its kana, or both go.
+/// assert_eq!(truncate_display("か\u{3099}か\u{3099}", 3, Units::Chars), "か\u{3099}");
+/// ```
+pub fn truncate_display(s: &str, max: usize, units: Units) -> &str {
+    let mut end = 0;
+    let mut chars = 0;
+    let mut joined = false;
+    for (i, c) in s.char_indices() {
+        if i > 0 && !joined && !extends_cluster(c) {
+            // A cluster ends before `c`.
+            let length = match units {
+                Units::Bytes => i,
+                Units::Chars => chars,
+            };
+            if length > max {
+                return &s[..end];
+            }
+            end = i;
+        }
+        joined = c == ZERO_WIDTH_JOINER;
+        chars += 1;
+    }
+    let length = match units {
+        Units::Bytes => s.len(),
+        Units::Chars => chars,
+    };
+    if length > max {
+        &s[..end]
+    } else {
+        s
+    }
+}
+
+const ZERO_WIDTH_JOINER: char = '\u{200d}';
+
+/// Whether `c` belongs to the cluster of the character before it.
+fn extends_cluster(c: char) -> bool {
+    matches!(
+        c,
+        // Combining diacritical marks, with their extensions and those for
+        // symbols and half marks.
+        '\u{0300}'..='\u{036f}'
+            | '\u{1ab0}'..='\u{1aff}'
+            | '\u{1dc0}'..='\u{1dff}'
+            | '\u{20d0}'..='\u{20ff}'
+            | '\u{fe20}'..='\u{fe2f}'
+            // Kana voiced and semi-voiced sound marks, combining and
+            // half-width.
+            | '\u{3099}'..='\u{309a}'
+            | '\u{ff9e}'..='\u{ff9f}'
+            // Variation selectors, including the ideographic ones.
+            | '\u{fe00}'..='\u{fe0f}'
+            | '\u{e0100}'..='\u{e01ef}'
+            // Zero width non-joiner and joiner.
+            | '\u{200c}'..='\u{200d}'
+            // Emoji skin tone modifiers and tags.
+            | '\u{1f3fb}'..='\u{1f3ff}'
+            | '\u{e0020}'..='\u{e007f}'
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_and_chars() {
+        assert_eq!(truncate_display("", 0, Units::Bytes), "");
+        assert_eq!(truncate_display("Habu", 10, Units::Bytes), "Habu");
+        assert_eq!(truncate_display("Habu", 4, Units::Chars), "Habu");
+        assert_eq!(truncate_display("Habu", 2, Units::Chars), "Ha");
+        assert_eq!(truncate_display("Habu", 0, Units::Chars), "");
+        // Never half a code point: 羽 is 3 bytes.
+        assert_eq!(truncate_display("羽生", 5, Units::Bytes), "羽");
+        assert_eq!(truncate_display("羽生", 2, Units::Bytes), "");
+        // Full-width punctuation counts as one character of three bytes.
+        let event = "名人戦（第一局）";
+        assert_eq!(truncate_display(event, 4, Units::Chars), "名人戦（");
+        assert_eq!(truncate_display(event, 13, Units::Bytes), "名人戦（");
+        assert_eq!(truncate_display(event, 24, Units::Bytes), event);
+    }
+
+    #[test]
+    fn clusters() {
+        // e and a combining acute accent.
+        let name = "Jose\u{301} Rau\u{301}l";
+        assert_eq!(truncate_display(name, 4, Units::Chars), "Jos");
+        assert_eq!(truncate_display(name, 5, Units::Chars), "Jose\u{301}");
+        assert_eq!(truncate_display(name, 5, Units::Bytes), "Jos");
+        // An ideographic variation selector stays with its kanji.
+        let name = "渡邉\u{e0100}";
+        assert_eq!(truncate_display(name, 2, Units::Chars), "渡");
+        assert_eq!(truncate_display(name, 3, Units::Chars), name);
+        // A family emoji is one cluster of joined characters.
+        let family = "a\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}b";
+        assert_eq!(truncate_display(family, 5, Units::Chars), "a");
+        assert_eq!(
+            truncate_display(family, 6, Units::Chars),
+            "a\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}"
+        );
+        // Two marks on one letter.
+        assert_eq!(truncate_display("a\u{301}\u{302}b", 2, Units::Chars), "");
+    }
+}
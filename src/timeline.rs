@@ -0,0 +1,326 @@
+//! The clocks of a game against the wall clock, for broadcasting overlays.
+
+use std::time::Duration;
+
+use time::PrimitiveDateTime;
+
+use crate::board::flip;
+use crate::clock::charged;
+use crate::value::{Action, Color, GameRecord, Time};
+
+/// What a player's clock shows, see [`TimelinePoint`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Remaining {
+    /// Main time left.
+    Main(Duration),
+    /// The main time is used up and the byoyomi counts down from this.
+    Byoyomi(Duration),
+}
+
+impl Remaining {
+    /// The time shown, whichever clock it is on.
+    pub fn duration(self) -> Duration {
+        match self {
+            Remaining::Main(time) | Remaining::Byoyomi(time) => time,
+        }
+    }
+}
+
+/// The state of the game at one moment, see [`GameRecord::clock_timeline`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TimelinePoint {
+    /// The number of plies played.
+    pub ply: usize,
+    /// Playing time since the start of the game, the times of the moves
+    /// added up. Time spent adjourned is not counted.
+    pub elapsed: Duration,
+    /// The time of day, written as in CSA, e.g. `2024/01/06 10:00:40`, when
+    /// the record says when play started or resumed.
+    pub wall_time: Option<String>,
+    /// The player thinking from this point on, `None` once the game has ended
+    /// or been adjourned.
+    pub side_thinking: Option<Color>,
+    /// What Black's clock shows, `None` without a time limit.
+    pub black_remaining: Option<Remaining>,
+    /// What White's clock shows, `None` without a time limit.
+    pub white_remaining: Option<Remaining>,
+    /// The move reaching this point has no recorded time and is counted as
+    /// taking the per-move minimum, so `elapsed` and `wall_time` from here on
+    /// are only lower bounds.
+    pub gap: bool,
+    /// The stretch of continuous play the point belongs to, counting from 0
+    /// and raised when play resumes after an adjournment.
+    pub segment: usize,
+}
+
+impl GameRecord {
+    /// Returns the clocks of both players at every move boundary: a point for
+    /// the start, one after each move, one when the game ends, and after an
+    /// adjournment with a known [`crate::Adjournment::resume_time`], one
+    /// starting the next segment with the player who sealed a move thinking.
+    ///
+    /// Moves are charged as by [`GameRecord::clocks`]. A clock in byoyomi
+    /// shows the full byoyomi at each move boundary, as it starts again for
+    /// every move; only the point ending the game can show it part used.
+    ///
+    /// Wall times are counted from `$START_TIME` when it has a time of day,
+    /// and from the resume time after an adjournment; without them the
+    /// timeline is relative, in `elapsed` only.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use csa::{parse_csa, Color, Remaining};
+    ///
+    /// let game = parse_csa(
+    ///     "V2.2\n$START_TIME:2024/01/06 10:00\n$TIME_LIMIT:00:01+30\nPI\n+\n+7776FU\nT70\n",
+    /// )
+    /// .unwrap();
+    /// let timeline = game.clock_timeline();
+    /// assert_eq!(timeline[1].wall_time.as_deref(), Some("2024/01/06 10:01:10"));
+    /// assert_eq!(timeline[1].side_thinking, Some(Color::White));
+    /// assert_eq!(
+    ///     timeline[1].black_remaining,
+    ///     Some(Remaining::Byoyomi(Duration::from_secs(30)))
+    /// );
+    /// ```
+    pub fn clock_timeline(&self) -> Vec<TimelinePoint> {
+        let mut clocks = Clocks {
+            record: self,
+            main_left: [Color::Black, Color::White]
+                .map(|color| self.time_limit_of(color).map(|limit| limit.main_time)),
+            byoyomi_used: [Duration::ZERO; 2],
+            anchor: wall_clock(self.start_time.as_ref()).map(|start| (start, Duration::ZERO)),
+        };
+        let mut point = TimelinePoint {
+            ply: 0,
+            elapsed: Duration::ZERO,
+            wall_time: None,
+            side_thinking: Some(self.start_pos.side_to_move),
+            black_remaining: None,
+            white_remaining: None,
+            gap: false,
+            segment: 0,
+        };
+        let mut timeline = vec![clocks.read(&mut point)];
+
+        for record in &self.moves {
+            match record.action {
+                Action::Move(color, ..) => {
+                    let spent = match self.time_limit_of(color) {
+                        Some(limit) => {
+                            let spent = charged(limit, record.time);
+                            if let Some(left) = clocks.main_left[color as usize].as_mut() {
+                                *left = left.saturating_sub(spent) + limit.increment;
+                            }
+                            spent
+                        }
+                        None => record.time.unwrap_or_default(),
+                    };
+                    point.ply += 1;
+                    point.elapsed += spent;
+                    point.side_thinking = Some(flip(color));
+                    point.gap = record.time.is_none();
+                    timeline.push(clocks.read(&mut point));
+                }
+                Action::Matta | Action::Custom { .. } => {}
+                action => {
+                    // The special move ends the think of the player to move,
+                    // which may have gone into the byoyomi.
+                    let thinking = point.side_thinking.unwrap_or(self.start_pos.side_to_move);
+                    let spent = record.time.unwrap_or_default();
+                    if let Some(left) = clocks.main_left[thinking as usize].as_mut() {
+                        clocks.byoyomi_used[thinking as usize] = spent.saturating_sub(*left);
+                        *left = left.saturating_sub(spent);
+                    }
+                    point.elapsed += spent;
+                    point.gap = false;
+                    point.side_thinking = None;
+                    timeline.push(clocks.read(&mut point));
+
+                    let resume_time = self
+                        .adjournment
+                        .as_ref()
+                        .and_then(|a| a.resume_time.as_ref());
+                    if let (Action::Chudan, Some(resume)) = (action, resume_time) {
+                        clocks.byoyomi_used = [Duration::ZERO; 2];
+                        clocks.anchor = wall_clock(Some(resume)).map(|time| (time, point.elapsed));
+                        point.segment += 1;
+                        point.side_thinking = Some(thinking);
+                        timeline.push(clocks.read(&mut point));
+                    }
+                    break;
+                }
+            }
+        }
+        timeline
+    }
+}
+
+/// The state of both clocks while replaying [`GameRecord::clock_timeline`].
+struct Clocks<'a> {
+    record: &'a GameRecord,
+    /// Main time left, indexed by `Color as usize`.
+    main_left: [Option<Duration>; 2],
+    /// Byoyomi used by a think that ended the game.
+    byoyomi_used: [Duration; 2],
+    /// A moment of play and the elapsed time at it, to count wall times from.
+    anchor: Option<(PrimitiveDateTime, Duration)>,
+}
+
+impl Clocks<'_> {
+    /// Fills in the clocks and the wall time of `point` and returns it.
+    fn read(&self, point: &mut TimelinePoint) -> TimelinePoint {
+        point.wall_time = self.anchor.map(|(time, since)| {
+            let now = time + (point.elapsed - since);
+            Time {
+                date: now.date(),
+                time: Some(now.time()),
+            }
+            .to_string()
+        });
+        point.black_remaining = self.remaining(Color::Black);
+        point.white_remaining = self.remaining(Color::White);
+        point.clone()
+    }
+
+    fn remaining(&self, color: Color) -> Option<Remaining> {
+        let byoyomi = self.record.time_limit_of(color)?.byoyomi;
+        let left = self.main_left[color as usize]?;
+        Some(if left.is_zero() && !byoyomi.is_zero() {
+            Remaining::Byoyomi(byoyomi.saturating_sub(self.byoyomi_used[color as usize]))
+        } else {
+            Remaining::Main(left)
+        })
+    }
+}
+
+/// Returns `time` as a moment, when it has a time of day.
+fn wall_clock(time: Option<&Time>) -> Option<PrimitiveDateTime> {
+    let time = time?;
+    Some(PrimitiveDateTime::new(time.date, time.time?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_csa;
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    fn hand_computed_timeline() {
+        let game = parse_csa(
+            "V2.2\n$START_TIME:2024/01/06 10:00\n$TIME_LIMIT:00:01+30\nPI\n+\n\
+             +7776FU\nT40\n-3334FU\nT70\n+2726FU\n-8384FU\nT25\n%CHUDAN\nT25\n\
+             'CHUDAN:RESUME_TIME:2024/01/07 09:00\n",
+        )
+        .unwrap();
+        let summary: Vec<_> = game
+            .clock_timeline()
+            .into_iter()
+            .map(|p| {
+                (
+                    p.ply,
+                    p.elapsed.as_secs(),
+                    p.wall_time.unwrap(),
+                    p.side_thinking,
+                    p.black_remaining.unwrap(),
+                    p.white_remaining.unwrap(),
+                    p.gap,
+                    p.segment,
+                )
+            })
+            .collect();
+        let (b, w) = (Some(Color::Black), Some(Color::White));
+        let (main, byoyomi) = (
+            |s| Remaining::Main(secs(s)),
+            |s| Remaining::Byoyomi(secs(s)),
+        );
+        let at = |time: &str| format!("2024/01/0{time}");
+        assert_eq!(
+            summary,
+            [
+                (0, 0, at("6 10:00:00"), b, main(60), main(60), false, 0),
+                (1, 40, at("6 10:00:40"), w, main(20), main(60), false, 0),
+                // White's main time runs out; the byoyomi starts over.
+                (2, 110, at("6 10:01:50"), b, main(20), byoyomi(30), false, 0),
+                // No recorded time.
+                (3, 110, at("6 10:01:50"), w, main(20), byoyomi(30), true, 0),
+                (4, 135, at("6 10:02:15"), b, main(20), byoyomi(30), false, 0),
+                // Black thinks into the byoyomi before the adjournment.
+                (
+                    4,
+                    160,
+                    at("6 10:02:40"),
+                    None,
+                    byoyomi(25),
+                    byoyomi(30),
+                    false,
+                    0
+                ),
+                (
+                    4,
+                    160,
+                    at("7 9:00:00"),
+                    b,
+                    byoyomi(30),
+                    byoyomi(30),
+                    false,
+                    1
+                ),
+            ]
+        );
+
+        let game = parse_csa(
+            "V2.2\n$TIME_LIMIT:00:00+10\nPI\n+\n+7776FU\nT3\n-3334FU\nT1\n%TIME_UP\nT12\n",
+        )
+        .unwrap();
+        let timeline = game.clock_timeline();
+        assert!(timeline.iter().all(|p| p.wall_time.is_none()));
+        let last = timeline.last().unwrap();
+        assert_eq!((last.elapsed, last.side_thinking), (secs(16), None));
+        assert_eq!(last.black_remaining, Some(Remaining::Byoyomi(secs(0))));
+        assert_eq!(
+            timeline[1].black_remaining,
+            Some(Remaining::Byoyomi(secs(10)))
+        );
+    }
+
+    #[test]
+    fn without_time_limit() {
+        let game = parse_csa("V2.2\nPI\n-\n-3334FU\nT7\n%MATTA\n-3334FU\nT2\n%TORYO\n").unwrap();
+        let timeline = game.clock_timeline();
+        let plies: Vec<_> = timeline
+            .iter()
+            .map(|p| (p.ply, p.elapsed.as_secs(), p.side_thinking))
+            .collect();
+        assert_eq!(
+            plies,
+            [
+                (0, 0, Some(Color::White)),
+                (1, 7, Some(Color::Black)),
+                (2, 9, Some(Color::Black)),
+                (2, 9, None),
+            ]
+        );
+        assert!(timeline.iter().all(|p| p.black_remaining.is_none()));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let game = parse_csa("V2.2\n$TIME_LIMIT:00:01+00\nPI\n+\n+7776FU\nT5\n").unwrap();
+        let timeline = game.clock_timeline();
+        let json = serde_json::to_string(&timeline).unwrap();
+        assert!(
+            json.contains(r#""black_remaining":{"Main":{"secs":55,"nanos":0}}"#),
+            "{json}"
+        );
+        let back: Vec<TimelinePoint> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, timeline);
+    }
+}
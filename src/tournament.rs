@@ -0,0 +1,350 @@
+//! Round, board and game numbers of tournament games.
+//!
+//! CSA has no statement for these, so they are kept in attributes that any
+//! CSA reader passes through:
+//!
+//! ```text
+//! $ROUND:3
+//! $BOARD:12
+//! $GAME:2
+//! $GAME_ID:wdoor+floodgate-300-10F+gikou+apery+20240106120000
+//! ```
+
+use crate::names::{DefaultNormalizer, NameNormalizer};
+use crate::value::{AttrValue, GameRecord};
+
+const ROUND: &str = "ROUND";
+const BOARD: &str = "BOARD";
+const GAME: &str = "GAME";
+const GAME_ID: &str = "GAME_ID";
+
+/// Where a game stands in a tournament or a match, see
+/// [`GameRecord::tournament_info`].
+#[derive(Debug, Default, PartialEq, Eq, Clone, Hash)]
+pub struct TournamentInfo {
+    /// The round of a tournament, such as the 3 of `3回戦`.
+    pub round: Option<u32>,
+    /// The board the game was played on within its round.
+    pub board: Option<u32>,
+    /// The number of the game within a match or a round, such as the 4 of a
+    /// title match's `第4局`.
+    pub game: Option<u32>,
+    /// An identifier of the game, such as a shogi-server game name.
+    pub game_id: Option<String>,
+}
+
+impl TournamentInfo {
+    /// Fills the fields `self` lacks from `other`.
+    fn or(self, other: TournamentInfo) -> TournamentInfo {
+        TournamentInfo {
+            round: self.round.or(other.round),
+            board: self.board.or(other.board),
+            game: self.game.or(other.game),
+            game_id: self.game_id.or(other.game_id),
+        }
+    }
+}
+
+/// Reads a [`TournamentInfo`] out of free text such as `$EVENT` or a file
+/// name, see [`GameRecord::extract_tournament_info`].
+///
+/// Implement it to recognize an archive's own naming, e.g. with a set of
+/// regular expressions; [`DefaultEventParser`] knows common forms only.
+pub trait EventParser {
+    fn parse_event(&self, text: &str) -> TournamentInfo;
+}
+
+/// Recognizes:
+///
+/// - shogi-server game names such as
+///   `wdoor+floodgate-300-10F+gikou+apery+20240106120000`, which Floodgate
+///   writes in `$EVENT` and names its files after, as the game id,
+/// - `Round 3`, `Rd. 3`, `R3`, `Board 12`, `Bd 12` and `Game 2`, in any
+///   case and separated by any punctuation, as in `club-championship-round-3`,
+/// - `3回戦` and `第3回戦` as the round, and `第4局` as the game, with
+///   full-width digits or kanji numerals as well.
+///
+/// Editions and terms, such as the 81 of `第81期名人戦` or the 13 of `13th
+/// World Computer Shogi Championship`, are not read as a round.
+///
+/// ```
+/// use csa::{DefaultEventParser, EventParser};
+///
+/// let info = DefaultEventParser.parse_event("第81期名人戦七番勝負第三局");
+/// assert_eq!(info.game, Some(3));
+/// assert_eq!(info.round, None);
+/// ```
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefaultEventParser;
+
+impl EventParser for DefaultEventParser {
+    fn parse_event(&self, text: &str) -> TournamentInfo {
+        let text = text.trim();
+        if is_game_name(text) {
+            return TournamentInfo {
+                game_id: Some(text.to_string()),
+                ..TournamentInfo::default()
+            };
+        }
+
+        let normalized = DefaultNormalizer.normalize(text);
+        let mut info = TournamentInfo::default();
+        let tokens: Vec<&str> = normalized
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|token| !token.is_empty())
+            .collect();
+        for (i, token) in tokens.iter().enumerate() {
+            let next = tokens.get(i + 1).and_then(|next| next.parse().ok());
+            let (field, number) = match *token {
+                "round" | "rd" => (&mut info.round, next),
+                "board" | "bd" => (&mut info.board, next),
+                "game" => (&mut info.game, next),
+                _ => match token.strip_prefix('r') {
+                    Some(digits) => (&mut info.round, digits.parse().ok()),
+                    None => continue,
+                },
+            };
+            if field.is_none() {
+                *field = number;
+            }
+        }
+
+        let chars: Vec<char> = normalized.chars().collect();
+        for (end, _) in chars.iter().enumerate().filter(|&(_, &c)| c == '局') {
+            if let Some((number, start)) = number_before(&chars, end) {
+                if start > 0 && chars[start - 1] == '第' && info.game.is_none() {
+                    info.game = Some(number);
+                }
+            }
+        }
+        for (end, _) in chars
+            .windows(2)
+            .enumerate()
+            .filter(|(_, w)| w == &['回', '戦'])
+        {
+            if let Some((number, _)) = number_before(&chars, end) {
+                info.round.get_or_insert(number);
+            }
+        }
+        info
+    }
+}
+
+/// Whether `text` is a shogi-server game name: at least four fields
+/// separated by `+`, the last being the start time in 14 digits.
+pub(crate) fn is_game_name(text: &str) -> bool {
+    let fields: Vec<&str> = text.split('+').collect();
+    let Some(time) = fields.last() else {
+        return false;
+    };
+    fields.len() >= 4
+        && fields[1..].iter().all(|field| !field.is_empty())
+        && !text.contains(char::is_whitespace)
+        && time.len() == 14
+        && time.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Reads the number written in ASCII digits or kanji numerals right before
+/// `chars[end]`, returning it with the index it starts at.
+fn number_before(chars: &[char], end: usize) -> Option<(u32, usize)> {
+    const KANJI: [char; 10] = ['〇', '一', '二', '三', '四', '五', '六', '七', '八', '九'];
+    let digits = chars[..end]
+        .iter()
+        .rev()
+        .take_while(|c| c.is_ascii_digit())
+        .count();
+    if digits > 0 {
+        let start = end - digits;
+        let number: String = chars[start..end].iter().collect();
+        return Some((number.parse().ok()?, start));
+    }
+
+    let kanji = chars[..end]
+        .iter()
+        .rev()
+        .take_while(|&&c| c == '十' || KANJI.contains(&c))
+        .count();
+    let start = end - kanji;
+    let value = |c: char| KANJI.iter().position(|&k| k == c).map(|v| v as u32);
+    let number = match chars[start..end] {
+        [] => return None,
+        ['十'] => 10,
+        ['十', ones] => 10 + value(ones)?,
+        [tens, '十'] => 10 * value(tens)?,
+        [tens, '十', ones] => 10 * value(tens)? + value(ones)?,
+        [ones] => value(ones)?,
+        _ => return None,
+    };
+    Some((number, start))
+}
+
+impl GameRecord {
+    /// Returns the round, board, game and game id recorded in the `$ROUND`,
+    /// `$BOARD`, `$GAME` and `$GAME_ID` attributes.
+    pub fn tournament_info(&self) -> TournamentInfo {
+        let number = |key| {
+            self.attr_int(key)
+                .and_then(|number| u32::try_from(number).ok())
+        };
+        TournamentInfo {
+            round: number(ROUND),
+            board: number(BOARD),
+            game: number(GAME),
+            game_id: self.attr_str(GAME_ID).map(str::to_string),
+        }
+    }
+
+    /// Records `info` in the `$ROUND`, `$BOARD`, `$GAME` and `$GAME_ID`
+    /// attributes, so that it is written with the record and read back by
+    /// [`GameRecord::tournament_info`]. A field that is `None` removes its
+    /// attribute.
+    ///
+    /// ```
+    /// use csa::{parse_csa, TournamentInfo};
+    ///
+    /// let mut game = parse_csa("V2.2\nPI\n+\n").unwrap();
+    /// game.set_tournament_info(&TournamentInfo {
+    ///     round: Some(3),
+    ///     board: Some(12),
+    ///     ..TournamentInfo::default()
+    /// });
+    /// assert!(game.to_string().contains("$ROUND:3\n$BOARD:12\n"));
+    /// assert_eq!(parse_csa(&game.to_string()).unwrap().tournament_info().board, Some(12));
+    /// ```
+    pub fn set_tournament_info(&mut self, info: &TournamentInfo) {
+        let values = [
+            (ROUND, info.round.map(|n| n.to_string())),
+            (BOARD, info.board.map(|n| n.to_string())),
+            (GAME, info.game.map(|n| n.to_string())),
+            (GAME_ID, info.game_id.clone()),
+        ];
+        for (key, value) in values {
+            self.extra_attrs.retain(|(k, _)| k != key);
+            if let Some(value) = value {
+                let value = value.replace(['\r', '\n'], " ");
+                self.extra_attrs
+                    .push((key.to_string(), AttrValue::parse(&value)));
+            }
+        }
+    }
+
+    /// Returns [`GameRecord::tournament_info`], with the fields the record
+    /// lacks read out of `$EVENT` by `parser`. Store the outcome with
+    /// [`GameRecord::set_tournament_info`] to keep it.
+    ///
+    /// Floodgate also names its files after the game; to read a file name,
+    /// call [`EventParser::parse_event`] on it directly.
+    ///
+    /// ```
+    /// use csa::{parse_csa, DefaultEventParser};
+    ///
+    /// let game = parse_csa("V2.2\n$EVENT:Meijin-sen Round 3 Game 2\nPI\n+\n").unwrap();
+    /// let info = game.extract_tournament_info(&DefaultEventParser);
+    /// assert_eq!((info.round, info.game), (Some(3), Some(2)));
+    /// ```
+    pub fn extract_tournament_info(&self, parser: &dyn EventParser) -> TournamentInfo {
+        let recorded = self.tournament_info();
+        match self.event {
+            Some(ref event) => recorded.or(parser.parse_event(event)),
+            None => recorded,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_csa;
+
+    #[test]
+    fn default_event_parser() {
+        let parse = |text: &str| {
+            let info = DefaultEventParser.parse_event(text);
+            (info.round, info.board, info.game)
+        };
+        // Title matches
+        assert_eq!(parse("第81期名人戦七番勝負第3局"), (None, None, Some(3)));
+        assert_eq!(
+            parse("第72期ALSOK杯王将戦七番勝負第４局"),
+            (None, None, Some(4))
+        );
+        assert_eq!(parse("第35期竜王戦七番勝負 第二局"), (None, None, Some(2)));
+        assert_eq!(
+            parse("第94期ヒューリック杯棋聖戦五番勝負第十局"),
+            (None, None, Some(10))
+        );
+        assert_eq!(parse("Meijin-sen Round 3 Game 2"), (Some(3), None, Some(2)));
+        // Tournaments
+        assert_eq!(
+            parse("第33回世界コンピュータ将棋選手権 二次予選 3回戦"),
+            (Some(3), None, None)
+        );
+        assert_eq!(
+            parse("第5回将棋電王トーナメント 第二十一回戦"),
+            (Some(21), None, None)
+        );
+        assert_eq!(parse("club-championship-round-3"), (Some(3), None, None));
+        assert_eq!(parse("Open 2024, Rd. 5, Bd 12"), (Some(5), Some(12), None));
+        assert_eq!(parse("WCSC R7 board 4"), (Some(7), Some(4), None));
+        // Editions are not rounds.
+        assert_eq!(
+            parse("13th World Computer Shogi Championship"),
+            (None, None, None)
+        );
+        assert_eq!(parse("第5回電王戦"), (None, None, None));
+        assert_eq!(parse("Round"), (None, None, None));
+        assert_eq!(parse("rating"), (None, None, None));
+    }
+
+    #[test]
+    fn game_names() {
+        for name in [
+            "wdoor+floodgate-300-10F+player1+player2+20130715200000",
+            "floodgate-600-0F5+sente_engine+gote_engine+20261015120000",
+        ] {
+            let info = DefaultEventParser.parse_event(name);
+            assert_eq!(info.game_id.as_deref(), Some(name));
+        }
+        for text in [
+            "wdoor+floodgate-300-10F+player1+player2",
+            "a+b+c+2013071520000",
+            "a b+c+d+20130715200000",
+            "a++c+20130715200000",
+        ] {
+            assert_eq!(DefaultEventParser.parse_event(text).game_id, None, "{text}");
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut game = parse_csa(include_str!("../fixtures/v2_2.csa")).unwrap();
+        assert_eq!(game.tournament_info(), TournamentInfo::default());
+
+        let info = TournamentInfo {
+            round: Some(3),
+            board: None,
+            game: Some(2),
+            game_id: Some("meijin-2024-2".to_string()),
+        };
+        game.set_tournament_info(&info);
+        let reparsed = parse_csa(&game.to_string()).unwrap();
+        assert_eq!(reparsed.tournament_info(), info);
+
+        game.set_tournament_info(&TournamentInfo::default());
+        assert!(!game.to_string().contains("$ROUND"));
+
+        // Recorded values come before those read from `$EVENT`.
+        let mut game = parse_csa(
+            "V2.2\n$EVENT:wdoor+floodgate-300-10F+player1+player2+20240106100000\n$ROUND:2\nPI\n+\n",
+        )
+        .unwrap();
+        let info = game.extract_tournament_info(&DefaultEventParser);
+        assert_eq!(info.round, Some(2));
+        assert_eq!(
+            info.game_id.as_deref(),
+            Some("wdoor+floodgate-300-10F+player1+player2+20240106100000")
+        );
+        game.set_tournament_info(&info);
+        assert_eq!(game.tournament_info(), info);
+    }
+}
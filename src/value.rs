@@ -1,64 +1,694 @@
 use std::fmt;
+#[cfg(feature = "intern")]
+use std::sync::Arc;
 use std::time::Duration;
 use time::{Date as NativeDate, Time as NativeTime};
 
-#[derive(Default, Debug, PartialEq, Eq)]
+use crate::parser::csa::{statement_requirement, EventConvention, Version};
+use crate::text::{truncate_display, Units};
+
+#[derive(Default, Debug, PartialEq, Eq, Clone)]
 pub struct GameRecord {
-    pub black_player: Option<String>,
-    pub white_player: Option<String>,
-    pub event: Option<String>,
-    pub site: Option<String>,
+    pub black_player: Option<RecordText>,
+    pub white_player: Option<RecordText>,
+    pub event: Option<RecordText>,
+    pub site: Option<RecordText>,
     pub start_time: Option<Time>,
     pub end_time: Option<Time>,
-    pub time_limit: Option<TimeLimit>,
-    pub opening: Option<String>,
+    /// Black's time limit. Use [`GameRecord::set_time_limit`] when both
+    /// players have the same clock.
+    pub time_limit_black: Option<TimeLimit>,
+    /// White's time limit.
+    pub time_limit_white: Option<TimeLimit>,
+    /// The unit the `T` lines count in, from a `'Time_Unit:1min` comment as
+    /// servers write the `Time_Unit` of the game summary. The times of
+    /// `moves` are real times whatever the unit; it is kept to write them
+    /// back as they were recorded.
+    pub time_unit: Option<TimeUnit>,
+    pub opening: Option<RecordText>,
+    /// Whether the game counted for ratings, as `$RATED` says. The attribute
+    /// itself stays in `extra_attrs` as written.
+    pub rated: Option<bool>,
+    /// Black's rating, from a Floodgate `'black_rate:name:1234.5` or
+    /// `'rating:name:1234` comment, see [`Layout::rating_comments`].
+    pub black_rating: Option<u32>,
+    /// White's rating, from a `'white_rate:` or `'rating:` comment.
+    pub white_rating: Option<u32>,
+    /// Attributes without a dedicated field, in the order they appeared.
+    pub extra_attrs: Vec<(String, AttrValue)>,
+    /// The comments before the version line, such as a licence or the URL
+    /// the record came from, with their leading `'`. The V3.0 writer puts
+    /// them back after the encoding line; older versions leave them out. A
+    /// V3.0 encoding line is not one of them, see [`Layout::encoding_line`].
+    pub header_comments: Vec<String>,
     pub start_pos: Position,
     pub moves: Vec<MoveRecord>,
+    /// Resumption details when the game ends with `%CHUDAN`.
+    pub adjournment: Option<Adjournment>,
+    /// Positions marked for study, in the order they were read or added.
+    pub bookmarks: Vec<Bookmark>,
+    /// How the record was laid out, where that differs from the writer's default.
+    pub layout: Layout,
 }
 
+/// Writes the record as CSA with [`WriteOptions::default`], the same as
+/// [`GameRecord::to_csa`] with default options: version 2.2, the starting
+/// position in the form it was read in, and the attributes that came after
+/// the moves after them again.
+///
+/// The output for a given record stays the same across releases, as pinned
+/// by the files in `fixtures/display`. Other output comes from new
+/// [`WriteOptions`] fields, never from a change to the default.
 impl fmt::Display for GameRecord {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "V2.2")?;
+        self.write_csa(f, &WriteOptions::default())
+    }
+}
+
+impl GameRecord {
+    /// Writes the record as CSA, as `to_string` does but with the given
+    /// options.
+    ///
+    /// ```
+    /// use csa::{parse_csa, PositionStyle, WriteOptions};
+    ///
+    /// let game = parse_csa("V2.2\nPI\n+\n").unwrap();
+    /// let options = WriteOptions {
+    ///     position_style: Some(PositionStyle::Grid),
+    ///     ..WriteOptions::default()
+    /// };
+    /// assert!(game.to_csa(&options).contains("\nP1-KY-KE-GI-KI-OU-KI-GI-KE-KY\n"));
+    /// ```
+    pub fn to_csa(&self, options: &WriteOptions) -> String {
+        let mut s = String::new();
+        self.write_csa(&mut s, options).unwrap();
+        s
+    }
+
+    /// Writes the record as CSA as [`GameRecord::to_csa`] does, but fails
+    /// when the record cannot be written the way `options` asks instead of
+    /// leaving out what does not fit.
+    ///
+    /// ```
+    /// use csa::{parse_csa, WriteError, WriteOptions, WritePreset};
+    ///
+    /// let options = WriteOptions::preset(WritePreset::ShogiServerCompatible);
+    /// let game = parse_csa("V2.2\nPI\n+\n+7776FU\n").unwrap();
+    /// assert!(game.try_to_csa(&options).unwrap().starts_with("V2.2\n"));
+    ///
+    /// let game = parse_csa("'Floodgate record\nV2.2\nPI\n+\n+7776FU\n").unwrap();
+    /// assert_eq!(
+    ///     game.try_to_csa(&options),
+    ///     Err(WriteError::BeforeVersion("'Floodgate record".to_string()))
+    /// );
+    /// ```
+    pub fn try_to_csa(&self, options: &WriteOptions) -> Result<String, WriteError> {
+        if options.version_first {
+            let version = self.write_version(options);
+            let encoding = (version == Version::V3
+                && (self.layout.encoding_line || options.encoding_line))
+                .then_some("'CSA encoding=UTF-8");
+            let first = encoding.or(self.header_comments.first().map(String::as_str));
+            if let Some(line) = first {
+                return Err(WriteError::BeforeVersion(line.to_string()));
+            }
+        }
+        Ok(self.to_csa(options))
+    }
+
+    /// Writes the record as CSA to `f`, as [`GameRecord::to_csa`] returns it.
+    pub fn write_csa(&self, f: &mut dyn fmt::Write, options: &WriteOptions) -> fmt::Result {
+        let version = self.write_version(options);
+        if version == Version::V3 && !options.version_first {
+            if self.layout.encoding_line || options.encoding_line {
+                writeln!(f, "'CSA encoding=UTF-8")?;
+            }
+            for comment in &self.header_comments {
+                writeln!(f, "{comment}")?;
+            }
+        }
+        writeln!(f, "{version}")?;
 
         // Metadata
-        let metadata = [
-            ("N+", self.black_player.as_ref().map(|x| x.to_string())),
-            ("N-", self.white_player.as_ref().map(|x| x.to_string())),
-            ("$EVENT:", self.event.as_ref().map(|x| x.to_string())),
-            ("$SITE:", self.site.as_ref().map(|x| x.to_string())),
+        let cut = |text: &str| match options.max_text_len {
+            Some((max, units)) => truncate_display(text, max, units).to_string(),
+            None => text.to_string(),
+        };
+        let player = |name: &Option<RecordText>, bare: bool| match name {
+            Some(name) => Some(cut(name)),
+            None if bare => Some(String::new()),
+            None => None,
+        };
+        let players = [
             (
-                "$START_TIME:",
-                self.start_time.as_ref().map(|x| x.to_string()),
+                "N+",
+                player(&self.black_player, self.layout.bare_black_player),
             ),
-            ("$END_TIME:", self.end_time.as_ref().map(|x| x.to_string())),
             (
-                "$TIME_LIMIT:",
-                self.time_limit.as_ref().map(|x| x.to_string()),
+                "N-",
+                player(&self.white_player, self.layout.bare_white_player),
             ),
-            ("$OPENING:", self.opening.as_ref().map(|x| x.to_string())),
         ];
-        for (key, value) in &metadata {
-            if let Some(ref value) = *value {
-                writeln!(f, "{key}{value}")?;
+        for (prefix, name) in &players {
+            if let Some(ref name) = *name {
+                writeln!(f, "{prefix}{name}")?;
             }
         }
+        let attrs: [(&str, Option<String>); 6] = [
+            ("EVENT", self.event.as_deref().map(cut)),
+            ("SITE", self.site.as_deref().map(cut)),
+            (
+                "START_TIME",
+                self.start_time.as_ref().map(|x| x.to_string()),
+            ),
+            ("END_TIME", self.end_time.as_ref().map(|x| x.to_string())),
+            ("TIME_LIMIT", self.time_limit().map(|x| x.to_string())),
+            ("OPENING", self.opening.as_deref().map(cut)),
+        ];
+        let extra_attrs = self.extra_attrs.iter().map(|(key, value)| match value {
+            AttrValue::Text(text) => (key.as_str(), Some(cut(text))),
+            value => (key.as_str(), Some(value.to_string())),
+        });
+        let rated = match (self.rated, self.attr("RATED")) {
+            (Some(rated), None) => {
+                Some(("RATED", Some(if rated { "YES" } else { "NO" }.to_string())))
+            }
+            _ => None,
+        };
+        // Attributes that came after the moves go there again.
+        let mut trailing = Vec::new();
+        for (key, value) in attrs.into_iter().chain(extra_attrs).chain(rated) {
+            let Some(value) = value else {
+                continue;
+            };
+            match self.layout.trailing_attrs.iter().position(|k| k == key) {
+                Some(order) if !options.attrs_first => {
+                    trailing.push((order, format!("${key}:{value}")))
+                }
+                _ => writeln!(f, "${key}:{value}")?,
+            }
+        }
+        trailing.sort_by_key(|&(order, _)| order);
+        self.write_ratings(f)?;
+        if let Some(unit) = self.time_unit {
+            writeln!(f, "'Time_Unit:{unit}")?;
+        }
 
         // Position
-        write!(f, "{}", self.start_pos)?;
+        self.start_pos.write_csa(f, options)?;
+        let mut plies = 0;
+        self.write_bookmarks(f, |ply| ply == plies)?;
 
         // Move records
         for record in &self.moves {
-            write!(f, "{record}")?;
+            if statement_requirement(version, &record.action.to_string()).is_some() {
+                continue;
+            }
+            writeln!(f, "{}", record.action)?;
+            let time = record.time.map(|time| match self.time_unit {
+                Some(unit) => unit.recorded(time),
+                None => time,
+            });
+            match time {
+                Some(time) if version == Version::V3 && time.subsec_millis() > 0 => {
+                    writeln!(f, "T{}.{:03}", time.as_secs(), time.subsec_millis())?
+                }
+                Some(time) => writeln!(f, "T{}", time.as_secs())?,
+                None => {}
+            }
+            for event in &record.events {
+                self.write_event(f, event)?;
+            }
+            if let Action::Move(..) = record.action {
+                plies += 1;
+                self.write_bookmarks(f, |ply| ply == plies)?;
+            }
+        }
+        self.write_bookmarks(f, |ply| ply > plies)?;
+        if let Some(ref adjournment) = self.adjournment {
+            write!(f, "{adjournment}")?;
+        }
+        for (_, line) in &trailing {
+            writeln!(f, "{line}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl GameRecord {
+    /// Writes `event` as the convention the record was read with has it,
+    /// falling back to its `Display` form when the convention has no line
+    /// for it.
+    fn write_event(&self, f: &mut dyn fmt::Write, event: &InterMoveEvent) -> fmt::Result {
+        let line = self
+            .layout
+            .event_convention
+            .as_ref()
+            .and_then(|convention| convention.line(event));
+        match line {
+            Some(line) => writeln!(f, "{line}"),
+            None => writeln!(f, "{event}"),
+        }
+    }
+
+    fn write_bookmarks(&self, f: &mut dyn fmt::Write, at: impl Fn(usize) -> bool) -> fmt::Result {
+        for bookmark in self.bookmarks.iter().filter(|b| at(b.ply)) {
+            writeln!(f, "{bookmark}")?;
+        }
+        Ok(())
+    }
+
+    /// Bookmarks the position reached so far, after the last move of the
+    /// record, as when building a record move by move.
+    ///
+    /// ```
+    /// use csa::{parse_csa, Bookmark};
+    ///
+    /// let mut game = parse_csa("V2.2\nPI\n+\n+7776FU\n").unwrap();
+    /// game.add_bookmark("opening", None);
+    /// assert_eq!(game.bookmarks[0].ply, 1);
+    /// assert!(game.to_string().ends_with("+7776FU\n'&opening\n"));
+    /// ```
+    pub fn add_bookmark(&mut self, label: &str, comment: Option<&str>) {
+        self.bookmarks.push(Bookmark {
+            ply: self.ply_count(),
+            label: label.to_string(),
+            comment: comment.map(str::to_string),
+        });
+    }
+
+    /// Returns whether the record has an `N+` (black) or `N-` (white) line,
+    /// either with a name or bare for an anonymous player.
+    pub fn has_player_line(&self, color: Color) -> bool {
+        match color {
+            Color::Black => self.black_player.is_some() || self.layout.bare_black_player,
+            Color::White => self.white_player.is_some() || self.layout.bare_white_player,
+        }
+    }
+
+    /// Returns the number of moves actually played, not counting special
+    /// moves such as `%TORYO`.
+    pub fn ply_count(&self) -> usize {
+        self.moves
+            .iter()
+            .filter(|m| matches!(m.action, Action::Move(..)))
+            .count()
+    }
+
+    /// Returns the ply number of `moves[index]`.
+    ///
+    /// Plies count the moves played, starting at 1 for the first one whoever
+    /// moves first, so a handicap game where White starts also begins at ply 1.
+    /// Special moves such as `%TORYO` have no ply number (`None`); they apply to
+    /// the position after the last ply before them.
+    ///
+    /// ```
+    /// use csa::parse_csa;
+    ///
+    /// let game = parse_csa("V2.2\nPI\n+\n+7776FU\n-3334FU\n%TORYO\n").unwrap();
+    /// assert_eq!(game.ply_of(0), Some(1));
+    /// assert_eq!(game.ply_of(1), Some(2));
+    /// // Resigning is not a ply; it happens in the position after ply 2.
+    /// assert_eq!(game.ply_of(2), None);
+    /// assert_eq!(game.ply_count(), 2);
+    /// assert_eq!(game.index_of_ply(2), Some(1));
+    /// assert_eq!(game.index_of_ply(3), None);
+    /// ```
+    pub fn ply_of(&self, index: usize) -> Option<usize> {
+        match self.moves.get(index)?.action {
+            Action::Move(..) => Some(
+                self.moves[..index]
+                    .iter()
+                    .filter(|m| matches!(m.action, Action::Move(..)))
+                    .count()
+                    + 1,
+            ),
+            _ => None,
+        }
+    }
+
+    /// Returns the index in `moves` of ply `ply`, the inverse of
+    /// [`GameRecord::ply_of`]. Ply 0, the starting position, has no move.
+    pub fn index_of_ply(&self, ply: usize) -> Option<usize> {
+        self.moves
+            .iter()
+            .enumerate()
+            .filter(|(_, m)| matches!(m.action, Action::Move(..)))
+            .nth(ply.checked_sub(1)?)
+            .map(|(i, _)| i)
+    }
+
+    /// Returns the record cut after ply `ply`, keeping the metadata and the
+    /// starting position.
+    ///
+    /// The moves after that ply are dropped along with the result: a special
+    /// move ending the game and any adjournment details. A game of no more than
+    /// `ply` plies is returned whole, result included.
+    pub fn truncated(&self, ply: usize) -> GameRecord {
+        let mut record = self.clone();
+        if ply >= self.ply_count() {
+            return record;
+        }
+        let end = self.index_of_ply(ply).map_or(0, |i| i + 1);
+        record.moves.truncate(end);
+        record.adjournment = None;
+        record
+    }
+
+    /// Returns the time limit both players share, or `None` when there is
+    /// none or the two sides differ.
+    pub fn time_limit(&self) -> Option<&TimeLimit> {
+        match (&self.time_limit_black, &self.time_limit_white) {
+            (Some(black), Some(white)) if black == white => Some(black),
+            _ => None,
+        }
+    }
+
+    /// Gives both players the same time limit.
+    pub fn set_time_limit(&mut self, time_limit: Option<TimeLimit>) {
+        self.time_limit_white = time_limit.clone();
+        self.time_limit_black = time_limit;
+    }
+
+    /// Returns the time limit of one side.
+    pub fn time_limit_of(&self, color: Color) -> Option<&TimeLimit> {
+        match color {
+            Color::Black => self.time_limit_black.as_ref(),
+            Color::White => self.time_limit_white.as_ref(),
+        }
+    }
+
+    /// Writes the ratings as the comments they were read from, or, when the
+    /// ratings were changed since, as `'black_rate:`/`'white_rate:` comments.
+    fn write_ratings(&self, f: &mut dyn fmt::Write) -> fmt::Result {
+        let mut read = [None, None];
+        for comment in &self.layout.rating_comments {
+            if let Some((color, rating)) = self.rating_comment(comment) {
+                read[color as usize] = Some(rating);
+            }
+        }
+        if read == [self.black_rating, self.white_rating] {
+            for comment in &self.layout.rating_comments {
+                writeln!(f, "{comment}")?;
+            }
+            return Ok(());
         }
 
+        let ratings = [
+            ("black", &self.black_player, self.black_rating),
+            ("white", &self.white_player, self.white_rating),
+        ];
+        for (side, name, rating) in ratings {
+            if let Some(rating) = rating {
+                writeln!(f, "'{side}_rate:{}:{rating}", name.as_deref().unwrap_or(""))?;
+            }
+        }
         Ok(())
     }
+
+    /// Reads a rating comment: `'black_rate:name:1234.5` or
+    /// `'white_rate:name:1234.5` as Floodgate writes them, or
+    /// `'rating:name:1234` for the player of that name. A Floodgate name may
+    /// carry a `+` and a hash after the player name. Fractional ratings are
+    /// rounded.
+    pub(crate) fn rating_comment(&self, comment: &str) -> Option<(Color, u32)> {
+        let (kind, rest) = comment.strip_prefix('\'')?.split_once(':')?;
+        let (name, rating) = rest.rsplit_once(':')?;
+        let rating: f64 = rating.trim().parse().ok()?;
+        if !(0.0..=u32::MAX as f64).contains(&rating) {
+            return None;
+        }
+        let color = match kind {
+            "black_rate" => Color::Black,
+            "white_rate" => Color::White,
+            "rating" => {
+                let is = |player: &Option<RecordText>| {
+                    player.as_deref().is_some_and(|player| {
+                        name == player
+                            || name
+                                .strip_prefix(player)
+                                .is_some_and(|hash| hash.starts_with('+'))
+                    })
+                };
+                if is(&self.black_player) {
+                    Color::Black
+                } else if is(&self.white_player) {
+                    Color::White
+                } else {
+                    return None;
+                }
+            }
+            _ => return None,
+        };
+        Some((color, rating.round() as u32))
+    }
+
+    /// Describes the information `Display` cannot express in CSA and leaves
+    /// out of the written record. Empty when the output is lossless.
+    pub fn write_losses(&self) -> Vec<String> {
+        self.write_losses_with(&WriteOptions::default())
+    }
+
+    /// Describes the information [`GameRecord::to_csa`] leaves out of the
+    /// record it writes with `options`, such as the special moves an older
+    /// version does not have.
+    ///
+    /// ```
+    /// use csa::{parse_csa, Version, WriteOptions};
+    ///
+    /// let game = parse_csa("V2.2\nPI\n+\n+7776FU\n%KACHI\n").unwrap();
+    /// let options = WriteOptions {
+    ///     version: Some(Version::V2),
+    ///     ..WriteOptions::default()
+    /// };
+    /// assert_eq!(game.to_csa(&options), "V2\nPI\n+\n+7776FU\n");
+    /// assert_eq!(
+    ///     game.write_losses_with(&options),
+    ///     ["%KACHI omitted: %KACHI moves require V2.1 or later"]
+    /// );
+    /// ```
+    pub fn write_losses_with(&self, options: &WriteOptions) -> Vec<String> {
+        let version = self.write_version(options);
+        let mut losses = Vec::new();
+        if self.time_limit().is_none()
+            && (self.time_limit_black.is_some() || self.time_limit_white.is_some())
+        {
+            let describe = |limit: &Option<TimeLimit>| match limit {
+                Some(limit) => limit.to_string(),
+                None => "none".to_string(),
+            };
+            losses.push(format!(
+                "$TIME_LIMIT omitted: the players have different time limits (black {}, white {})",
+                describe(&self.time_limit_black),
+                describe(&self.time_limit_white)
+            ));
+        }
+        for record in &self.moves {
+            let action = record.action.to_string();
+            if let Some(requirement) = statement_requirement(version, &action) {
+                losses.push(format!("{action} omitted: {requirement}"));
+            }
+        }
+        let millis = statement_requirement(version, "T0.001");
+        let unit = if millis.is_some() {
+            1_000_000_000
+        } else {
+            1_000_000
+        };
+        let mut times = self.moves.iter().filter_map(|m| m.time);
+        if times.any(|time| time.subsec_nanos() % unit > 0) {
+            losses.push(match millis {
+                Some(requirement) => format!("times rounded down to the second: {requirement}"),
+                None => "times rounded down to the millisecond".to_string(),
+            });
+        }
+        for bookmark in self.bookmarks.iter().filter(|b| !b.round_trips()) {
+            losses.push(format!(
+                "bookmark {:?} at ply {} changed: it is written as `{bookmark}`",
+                bookmark.label, bookmark.ply
+            ));
+        }
+        if let Some((max, units)) = options.max_text_len {
+            for (label, text) in self.texts() {
+                if truncate_display(text, max, units).len() < text.len() {
+                    losses.push(format!("{label} cut to {max} {units}: {text}"));
+                }
+            }
+        }
+        if !self.header_comments.is_empty() && version != Version::V3 {
+            losses.push(format!(
+                "header comments omitted: only V3.0 writes them, but the record is written as {version}"
+            ));
+        }
+        if version == Version::V3 && options.version_first {
+            if !self.header_comments.is_empty() {
+                losses.push(
+                    "header comments omitted: nothing may come before the version line".to_string(),
+                );
+            }
+            if self.layout.encoding_line || options.encoding_line {
+                losses.push(
+                    "encoding line omitted: nothing may come before the version line".to_string(),
+                );
+            }
+        }
+        let (files, ranks) = self.start_pos.dimensions();
+        if (files, ranks) != (9, 9) && version != Version::V2_2 {
+            losses.push(format!(
+                "the {files}x{ranks} board requires V2.2, but the record is written as {version}"
+            ));
+        }
+        losses
+    }
+
+    /// Returns the version [`GameRecord::to_csa`] writes with `options`.
+    fn write_version(&self, options: &WriteOptions) -> Version {
+        let source = match self.layout.source_format {
+            Some(SourceFormat::Csa(version)) if options.same_version => Some(version),
+            _ => None,
+        };
+        options.version.or(source).unwrap_or(Version::V2_2)
+    }
+
+    /// Returns the free text of the record, that [`WriteOptions::max_text_len`]
+    /// applies to, labelled the way it is written: `N+`, `$EVENT` and so on.
+    fn texts(&self) -> Vec<(String, &str)> {
+        let fields = [
+            ("N+", &self.black_player),
+            ("N-", &self.white_player),
+            ("$EVENT", &self.event),
+            ("$SITE", &self.site),
+            ("$OPENING", &self.opening),
+        ];
+        let mut texts: Vec<(String, &str)> = fields
+            .into_iter()
+            .filter_map(|(label, text)| Some((label.to_string(), text.as_deref()?)))
+            .collect();
+        for (key, value) in &self.extra_attrs {
+            if let AttrValue::Text(text) = value {
+                texts.push((format!("${key}"), text));
+            }
+        }
+        texts
+    }
+
+    /// Returns the value of an attribute kept in `extra_attrs`. If the key
+    /// appears more than once, the last value is returned.
+    pub fn attr(&self, key: &str) -> Option<&AttrValue> {
+        self.extra_attrs
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v)
+    }
+
+    /// Returns the raw string of an extra attribute.
+    pub fn attr_str(&self, key: &str) -> Option<&str> {
+        self.attr(key).map(AttrValue::raw)
+    }
+
+    /// Returns an extra attribute as an integer, e.g. `$MAX_MOVES:256`.
+    pub fn attr_int(&self, key: &str) -> Option<i64> {
+        self.attr(key).and_then(AttrValue::as_int)
+    }
+
+    /// Returns an extra attribute as a boolean, e.g. `$RATED:YES`.
+    pub fn attr_bool(&self, key: &str) -> Option<bool> {
+        self.attr(key).and_then(AttrValue::as_bool)
+    }
+
+    /// Returns an extra attribute as a URL, e.g. `$EVENT_URL:https://...`.
+    pub fn attr_url(&self, key: &str) -> Option<&str> {
+        self.attr(key).and_then(AttrValue::as_url)
+    }
+
+    /// Returns an extra attribute as a date and time.
+    pub fn attr_time(&self, key: &str) -> Option<&Time> {
+        self.attr(key).and_then(AttrValue::as_time)
+    }
+
+    /// Returns a copy of the record with its starting position in canonical
+    /// form. See [`Position::normalize`].
+    pub fn normalized(&self) -> GameRecord {
+        let mut record = self.clone();
+        record.start_pos.normalize();
+        record
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+/// Presentation details of a parsed record that carry no game information,
+/// kept so that writing the record back reproduces the original lines.
+///
+/// Equality leaves out [`Layout::source_format`], [`Layout::encoding_line`]
+/// and [`Layout::event_convention`], so the same game read from two CSA
+/// versions compares equal.
+#[derive(Debug, Default, Clone)]
+pub struct Layout {
+    /// A bare `N+` line without a name was present, as shogi-server writes for
+    /// anonymous players. Ignored when `black_player` is set.
+    pub bare_black_player: bool,
+    /// A bare `N-` line without a name was present. Ignored when
+    /// `white_player` is set.
+    pub bare_white_player: bool,
+    /// The comments `black_rating` and `white_rating` were read from, written
+    /// back as they were while the ratings are unchanged.
+    pub rating_comments: Vec<String>,
+    /// The keys of the attributes that came after the last move, as in a
+    /// `$END_TIME` line after `%TORYO`, written back there in this order.
+    pub trailing_attrs: Vec<String>,
+    /// The format the record was read from, `None` for a record built in
+    /// code. [`WriteOptions::same_version`] writes the record back in it.
+    pub source_format: Option<SourceFormat>,
+    /// The record started with a V3.0 `'CSA encoding=` line. The V3.0 writer
+    /// writes one back, naming `UTF-8` as that is what it writes.
+    pub encoding_line: bool,
+    /// The convention the events of [`MoveRecord::events`] were read with,
+    /// written back with it. `None` writes them in their `Display` form.
+    pub event_convention: Option<EventConvention>,
+}
 
+impl PartialEq for Layout {
+    fn eq(&self, other: &Layout) -> bool {
+        let Layout {
+            bare_black_player,
+            bare_white_player,
+            rating_comments,
+            trailing_attrs,
+            source_format: _,
+            encoding_line: _,
+            event_convention: _,
+        } = self;
+        *bare_black_player == other.bare_black_player
+            && *bare_white_player == other.bare_white_player
+            && *rating_comments == other.rating_comments
+            && *trailing_attrs == other.trailing_attrs
+    }
+}
+
+impl Eq for Layout {}
+
+/// The format a record was read from, see [`Layout::source_format`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[non_exhaustive]
+pub enum SourceFormat {
+    /// CSA, in the version of its version line.
+    Csa(Version),
+}
+
+impl fmt::Display for SourceFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SourceFormat::Csa(version) => write!(f, "CSA {version}"),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(PartialEq, Eq, Clone)]
 pub struct Time {
     pub date: NativeDate,
     pub time: Option<NativeTime>,
@@ -73,6 +703,123 @@ impl Time {
             time: Some(now.time()),
         }
     }
+
+    /// Parses a date, optionally followed by a time of day.
+    ///
+    /// The date may be written `YYYY/MM/DD`, `YYYY-MM-DD` or `YYYY年MM月DD日`,
+    /// with or without zero padding. Two-digit years are resolved against
+    /// [`TWO_DIGIT_YEAR_PIVOT`]. The time of day is separated by whitespace and
+    /// written `HH:MM[:SS]` or `HH時MM分[SS秒]`; missing seconds read as zero.
+    pub fn parse(s: &str) -> Option<Time> {
+        let s = s.trim();
+        let (date_str, time_str) = match s.find('日') {
+            Some(i) => s.split_at(i + '日'.len_utf8()),
+            None => s.split_once(char::is_whitespace).unwrap_or((s, "")),
+        };
+
+        let date = parse_date(date_str)?;
+        let time_str = time_str.trim_start();
+        let time = if time_str.is_empty() {
+            None
+        } else {
+            Some(parse_time_of_day(time_str)?)
+        };
+
+        Some(Time { date, time })
+    }
+}
+
+/// Whether `raw` has the shape of `YYYY/MM/DD[ HH:MM:SS]`, leaving the digits
+/// to [`Time::parse`].
+fn is_csa_datetime(raw: &str) -> bool {
+    let (date, time) = match raw.split_once(' ') {
+        Some((date, time)) => (date, Some(time)),
+        None => (raw, None),
+    };
+    let fields: Vec<&str> = date.split('/').collect();
+    fields.len() == 3
+        && fields[0].len() == 4
+        && time.is_none_or(|time| time.split(':').count() == 3)
+}
+
+/// Two-digit years below the pivot are read as 20YY, the others as 19YY.
+pub const TWO_DIGIT_YEAR_PIVOT: u16 = 70;
+
+fn parse_date(s: &str) -> Option<NativeDate> {
+    let (year, month, day) = if let Some(rest) = s.strip_suffix('日') {
+        let (year, rest) = rest.split_once('年')?;
+        let (month, day) = rest.split_once('月')?;
+        (year, month, day)
+    } else {
+        let sep = if s.contains('/') { '/' } else { '-' };
+        let mut parts = s.split(sep);
+        let fields = (parts.next()?, parts.next()?, parts.next()?);
+        if parts.next().is_some() {
+            return None;
+        }
+        fields
+    };
+
+    let year = match year.len() {
+        2 => {
+            let yy = parse_number(year, 2)?;
+            if yy < TWO_DIGIT_YEAR_PIVOT {
+                2000 + yy
+            } else {
+                1900 + yy
+            }
+        }
+        4 => parse_number(year, 4)?,
+        _ => return None,
+    };
+    let month = time::Month::try_from(parse_number(month, 2)? as u8).ok()?;
+    let day = parse_number(day, 2)? as u8;
+    NativeDate::from_calendar_date(year.into(), month, day).ok()
+}
+
+fn parse_time_of_day(s: &str) -> Option<NativeTime> {
+    let (hour, minute, second) = if let Some((hour, rest)) = s.split_once('時') {
+        let (minute, rest) = rest.split_once('分')?;
+        let second = match rest {
+            "" => None,
+            _ => Some(rest.strip_suffix('秒')?),
+        };
+        (hour, minute, second)
+    } else {
+        let mut parts = s.split(':');
+        let fields = (parts.next()?, parts.next()?, parts.next());
+        if parts.next().is_some() {
+            return None;
+        }
+        fields
+    };
+
+    let hour = parse_number(hour, 2)? as u8;
+    let minute = parse_number(minute, 2)? as u8;
+    let second = match second {
+        Some(second) => parse_number(second, 2)? as u8,
+        None => 0,
+    };
+    NativeTime::from_hms(hour, minute, second).ok()
+}
+
+/// Parses a run of one to `max_digits` ASCII digits.
+fn parse_number(s: &str, max_digits: usize) -> Option<u16> {
+    if s.is_empty() || s.len() > max_digits || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+/// Shows the time as `Display` writes it, `2024/01/06 10:30:00`.
+impl fmt::Debug for Time {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)?;
+        match self.time {
+            Some(time) if time.nanosecond() > 0 => write!(f, ".{:09}", time.nanosecond()),
+            _ => Ok(()),
+        }
+    }
 }
 
 impl fmt::Display for Time {
@@ -100,10 +847,193 @@ impl fmt::Display for Time {
 
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+/// The longest time the parsers accept in a time limit or a `T` line: ten
+/// years. Larger values are rejected rather than wrapped or clamped.
+pub const MAX_TIME: Duration = Duration::from_secs(10 * 365 * 24 * 60 * 60);
+
+/// A time control.
+///
+/// `$TIME_LIMIT` only carries the main time and byoyomi; the increment and the
+/// per-move minimum come from V3 `$TIME` or the server protocol and are not
+/// written back by `Display`.
+#[derive(Default, Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TimeLimit {
     pub main_time: Duration,
     pub byoyomi: Duration,
+    /// Time added after every move (Fischer increment).
+    pub increment: Duration,
+    /// Time charged for a move even when it was played faster.
+    pub least_time_per_move: Duration,
+}
+
+impl TimeLimit {
+    /// Parses a `$TIME_LIMIT` value, `HH:MM+SS`.
+    ///
+    /// Padding is optional and either part may be missing: `0:25` has no
+    /// byoyomi and `+30` is byoyomi only. Times over [`MAX_TIME`] are
+    /// rejected.
+    pub fn parse(s: &str) -> Option<TimeLimit> {
+        let s = s.trim();
+        let (main, byoyomi) = match s.split_once('+') {
+            Some((main, byoyomi)) => (main, Some(byoyomi)),
+            None => (s, None),
+        };
+
+        let main_time = if main.is_empty() && byoyomi.is_some() {
+            0
+        } else {
+            let (hours, minutes) = main.split_once(':')?;
+            let hours = parse_seconds_field(hours)?;
+            let minutes = match minutes.len() {
+                1 | 2 => parse_seconds_field(minutes)?,
+                _ => return None,
+            };
+            hours.checked_mul(3600)?.checked_add(minutes * 60)?
+        };
+        let byoyomi = match byoyomi {
+            Some(byoyomi) => parse_seconds_field(byoyomi)?,
+            None => 0,
+        };
+
+        let (main_time, byoyomi) = (Duration::from_secs(main_time), Duration::from_secs(byoyomi));
+        if main_time > MAX_TIME || byoyomi > MAX_TIME {
+            return None;
+        }
+        Some(TimeLimit {
+            main_time,
+            byoyomi,
+            ..TimeLimit::default()
+        })
+    }
+
+    /// Parses a V3 `$TIME` value, `main+byoyomi+increment`, in seconds with up
+    /// to millisecond precision. Times over [`MAX_TIME`] are rejected.
+    pub fn parse_time_control(s: &str) -> Option<TimeLimit> {
+        let mut parts = s.trim().split('+');
+        let main_time = parse_duration(parts.next()?)?;
+        let byoyomi = parse_duration(parts.next()?)?;
+        let increment = parse_duration(parts.next()?)?;
+        if parts.next().is_some() {
+            return None;
+        }
+
+        Some(TimeLimit {
+            main_time,
+            byoyomi,
+            increment,
+            ..TimeLimit::default()
+        })
+    }
+
+    /// Whether there is no main time, only byoyomi.
+    pub fn is_byoyomi_only(&self) -> bool {
+        self.main_time.is_zero() && !self.byoyomi.is_zero()
+    }
+
+    /// Whether the clock never runs out: no main time, byoyomi or increment.
+    pub fn is_unlimited(&self) -> bool {
+        self.main_time.is_zero() && self.byoyomi.is_zero() && self.increment.is_zero()
+    }
+}
+
+/// The unit of the recorded times of a game, the `Time_Unit` of the server
+/// protocol: a count of milliseconds, seconds or minutes, such as `1sec`,
+/// `1min` or `200msec`. See [`GameRecord::time_unit`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TimeUnit {
+    pub count: u32,
+    pub scale: TimeScale,
+}
+
+/// What a [`TimeUnit`] counts.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TimeScale {
+    Millisecond,
+    Second,
+    Minute,
+}
+
+impl TimeUnit {
+    /// One second, the unit CSA times count in when nothing says otherwise.
+    pub const SECOND: TimeUnit = TimeUnit {
+        count: 1,
+        scale: TimeScale::Second,
+    };
+
+    /// Parses a `Time_Unit` value such as `1min`. The count must be positive.
+    pub fn parse(s: &str) -> Option<TimeUnit> {
+        let s = s.trim();
+        let digits = s.bytes().take_while(u8::is_ascii_digit).count();
+        let (count, scale) = s.split_at(digits);
+        let scale = match scale {
+            "msec" => TimeScale::Millisecond,
+            "sec" => TimeScale::Second,
+            "min" => TimeScale::Minute,
+            _ => return None,
+        };
+        let count = count.parse().ok().filter(|&count| count > 0)?;
+        Some(TimeUnit { count, scale })
+    }
+
+    /// The length of the unit.
+    pub fn duration(self) -> Duration {
+        let millis = match self.scale {
+            TimeScale::Millisecond => 1,
+            TimeScale::Second => 1000,
+            TimeScale::Minute => 60_000,
+        };
+        Duration::from_millis(millis * u64::from(self.count))
+    }
+
+    /// Turns a `T` value read as seconds into the time it stands for, or
+    /// `None` past [`MAX_TIME`].
+    pub(crate) fn real(self, recorded: Duration) -> Option<Duration> {
+        let millis = recorded.as_millis() * self.duration().as_millis() / 1000;
+        let time = Duration::from_millis(u64::try_from(millis).ok()?);
+        (time <= MAX_TIME).then_some(time)
+    }
+
+    /// Turns a time back into the `T` value recording it, as seconds to
+    /// millisecond precision.
+    pub(crate) fn recorded(self, time: Duration) -> Duration {
+        let millis = time.as_millis() * 1000 / self.duration().as_millis();
+        Duration::from_millis(millis as u64)
+    }
+}
+
+impl fmt::Display for TimeUnit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let scale = match self.scale {
+            TimeScale::Millisecond => "msec",
+            TimeScale::Second => "sec",
+            TimeScale::Minute => "min",
+        };
+        write!(f, "{}{scale}", self.count)
+    }
+}
+
+fn parse_seconds_field(s: &str) -> Option<u64> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+/// Parses `SS[.fff]` seconds, up to [`MAX_TIME`].
+pub(crate) fn parse_duration(s: &str) -> Option<Duration> {
+    let (secs, millis) = match s.split_once('.') {
+        Some((secs, frac)) if (1..=3).contains(&frac.len()) => {
+            let millis = parse_seconds_field(frac)? * 10u64.pow(3 - frac.len() as u32);
+            (secs, millis)
+        }
+        Some(_) => return None,
+        None => (s, 0),
+    };
+    let duration = Duration::from_secs(parse_seconds_field(secs)?) + Duration::from_millis(millis);
+    (duration <= MAX_TIME).then_some(duration)
 }
 
 impl fmt::Display for TimeLimit {
@@ -143,45 +1073,193 @@ impl fmt::Display for GameAttribute {
 
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
-pub enum Color {
-    #[default]
-    Black,
-    White,
+/// The value of an attribute that has no dedicated field in [`GameRecord`].
+///
+/// The type is detected when the attribute is parsed, but the raw string is
+/// always kept and is what gets written back, so nothing is lost when the
+/// detection guesses wrong.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum AttrValue {
+    Text(String),
+    Integer(i64, String),
+    Boolean(bool, String),
+    Url(String),
+    DateTime(Time, String),
 }
 
-impl fmt::Display for Color {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            Color::Black => write!(f, "+"),
-            Color::White => write!(f, "-"),
+impl AttrValue {
+    /// Detects the type of a raw attribute value.
+    ///
+    /// Integers are an optional sign followed by digits; booleans are `YES`,
+    /// `NO`, `TRUE` or `FALSE` in any case; URLs start with `http://` or
+    /// `https://`; date times are the `YYYY/MM/DD[ HH:MM:SS]` form used by
+    /// `$START_TIME`. Anything else, including partial dates and the other
+    /// forms [`Time::parse`] reads for `$START_TIME` and `$END_TIME`, such as
+    /// `12-03-04`, is text: a free-form value is only taken for a date when
+    /// it is written the CSA way.
+    pub fn parse(raw: &str) -> AttrValue {
+        let s = raw.to_string();
+        let digits = raw.strip_prefix(['+', '-']).unwrap_or(raw);
+
+        if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+            if let Ok(n) = raw.parse() {
+                return AttrValue::Integer(n, s);
+            }
         }
-    }
-}
 
-////////////////////////////////////////////////////////////////////////////////
+        match raw.to_ascii_uppercase().as_str() {
+            "YES" | "TRUE" => return AttrValue::Boolean(true, s),
+            "NO" | "FALSE" => return AttrValue::Boolean(false, s),
+            _ => {}
+        }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
-pub struct Square {
-    pub file: u8,
-    pub rank: u8,
-}
+        let lower = raw.to_ascii_lowercase();
+        if (lower.starts_with("http://") || lower.starts_with("https://"))
+            && !raw.contains(char::is_whitespace)
+        {
+            return AttrValue::Url(s);
+        }
 
-impl Square {
-    pub fn new(file: u8, rank: u8) -> Square {
-        Square { file, rank }
+        if let Some(time) = Time::parse(raw).filter(|_| is_csa_datetime(raw)) {
+            return AttrValue::DateTime(time, s);
+        }
+
+        AttrValue::Text(s)
     }
-}
 
-impl fmt::Display for Square {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}{}", self.file, self.rank)
+    /// Returns the string as it appeared in the file.
+    pub fn raw(&self) -> &str {
+        match *self {
+            AttrValue::Text(ref s)
+            | AttrValue::Integer(_, ref s)
+            | AttrValue::Boolean(_, ref s)
+            | AttrValue::Url(ref s)
+            | AttrValue::DateTime(_, ref s) => s,
+        }
     }
-}
 
-////////////////////////////////////////////////////////////////////////////////
+    pub fn as_int(&self) -> Option<i64> {
+        match *self {
+            AttrValue::Integer(n, _) => Some(n),
+            _ => None,
+        }
+    }
 
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            AttrValue::Boolean(b, _) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn as_url(&self) -> Option<&str> {
+        match *self {
+            AttrValue::Url(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_time(&self) -> Option<&Time> {
+        match *self {
+            AttrValue::DateTime(ref t, _) => Some(t),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for AttrValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.raw())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Color {
+    #[default]
+    Black,
+    White,
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Color::Black => write!(f, "+"),
+            Color::White => write!(f, "-"),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A square of the board, its file and rank each from 1 to 9, or
+/// [`Square::HAND`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Square {
+    pub file: u8,
+    pub rank: u8,
+}
+
+impl Square {
+    /// The `00` of CSA, not a square of the board: the square a dropped piece
+    /// moves from and the square of a piece placed in hand.
+    pub const HAND: Square = Square { file: 0, rank: 0 };
+
+    /// Returns the square without checking it, for files and ranks known to
+    /// be from 1 to 9. Anything else makes a square no board has; use
+    /// [`Square::try_new`] for files and ranks from input.
+    pub fn new(file: u8, rank: u8) -> Square {
+        Square { file, rank }
+    }
+
+    /// Returns the square, or an error unless `file` and `rank` are both from
+    /// 1 to 9. [`Square::HAND`] is not made by this.
+    pub fn try_new(file: u8, rank: u8) -> Result<Square, SquareError> {
+        if (1..=9).contains(&file) && (1..=9).contains(&rank) {
+            Ok(Square { file, rank })
+        } else {
+            Err(SquareError { file, rank })
+        }
+    }
+
+    /// Whether the square is [`Square::HAND`].
+    pub fn is_hand(self) -> bool {
+        self == Square::HAND
+    }
+}
+
+/// A file and rank that make no square, see [`Square::try_new`].
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct SquareError {
+    pub file: u8,
+    pub rank: u8,
+}
+
+impl fmt::Display for SquareError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "square {}{} is off the board, files and ranks run from 1 to 9",
+            self.file, self.rank
+        )
+    }
+}
+
+impl std::error::Error for SquareError {}
+
+impl fmt::Display for Square {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.file, self.rank)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PieceType {
     Pawn,
     Lance,
@@ -200,6 +1278,136 @@ pub enum PieceType {
     All,
 }
 
+impl PieceType {
+    /// Parses a two-letter CSA piece code such as `FU` or `RY`.
+    pub fn parse(s: &str) -> Option<PieceType> {
+        let pt = match s {
+            "FU" => PieceType::Pawn,
+            "KY" => PieceType::Lance,
+            "KE" => PieceType::Knight,
+            "GI" => PieceType::Silver,
+            "KI" => PieceType::Gold,
+            "KA" => PieceType::Bishop,
+            "HI" => PieceType::Rook,
+            "OU" => PieceType::King,
+            "TO" => PieceType::ProPawn,
+            "NY" => PieceType::ProLance,
+            "NK" => PieceType::ProKnight,
+            "NG" => PieceType::ProSilver,
+            "UM" => PieceType::Horse,
+            "RY" => PieceType::Dragon,
+            "AL" => PieceType::All,
+            _ => return None,
+        };
+        Some(pt)
+    }
+
+    /// Returns the promoted form of the piece, or `None` if it cannot promote.
+    pub fn promote(self) -> Option<PieceType> {
+        match self {
+            PieceType::Pawn => Some(PieceType::ProPawn),
+            PieceType::Lance => Some(PieceType::ProLance),
+            PieceType::Knight => Some(PieceType::ProKnight),
+            PieceType::Silver => Some(PieceType::ProSilver),
+            PieceType::Bishop => Some(PieceType::Horse),
+            PieceType::Rook => Some(PieceType::Dragon),
+            _ => None,
+        }
+    }
+
+    /// Returns the unpromoted form of the piece, which is the piece itself
+    /// unless it is promoted.
+    pub fn unpromote(self) -> PieceType {
+        match self {
+            PieceType::ProPawn => PieceType::Pawn,
+            PieceType::ProLance => PieceType::Lance,
+            PieceType::ProKnight => PieceType::Knight,
+            PieceType::ProSilver => PieceType::Silver,
+            PieceType::Horse => PieceType::Bishop,
+            PieceType::Dragon => PieceType::Rook,
+            pt => pt,
+        }
+    }
+
+    /// Returns whether the piece is a promoted one.
+    pub fn is_promoted(self) -> bool {
+        self.unpromote() != self
+    }
+}
+
+/// Whether a move promotes the moving piece, see [`infer_promotion`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum PromotionStatus {
+    /// The piece is the same after the move.
+    Unchanged,
+    /// The piece promotes with the move.
+    Promoted,
+}
+
+/// An error raised when a move's pieces before and after do not fit a
+/// promotion, see [`infer_promotion`] and [`apply_promotion`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PromotionError {
+    /// The piece after the move is neither the piece before it nor its
+    /// promoted form, e.g. a Silver that becomes `UM`.
+    Inconsistent(PieceType, PieceType),
+    /// The piece cannot promote: a Gold, a King or a piece already promoted.
+    CannotPromote(PieceType),
+}
+
+impl fmt::Display for PromotionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PromotionError::Inconsistent(pre, post) => write!(f, "{pre} cannot become {post}"),
+            PromotionError::CannotPromote(pt) => write!(f, "{pt} cannot promote"),
+        }
+    }
+}
+
+impl std::error::Error for PromotionError {}
+
+/// Works out whether a move promotes from the piece before and after it.
+///
+/// CSA writes the piece after the move, so `+8822UM` of a Bishop promotes,
+/// while formats such as USI and KIF write a promotion flag instead. This and
+/// [`apply_promotion`] convert between the two.
+///
+/// ```
+/// use csa::{infer_promotion, PieceType, PromotionError, PromotionStatus};
+///
+/// assert_eq!(
+///     infer_promotion(PieceType::Bishop, PieceType::Horse),
+///     Ok(PromotionStatus::Promoted)
+/// );
+/// assert_eq!(
+///     infer_promotion(PieceType::Horse, PieceType::Horse),
+///     Ok(PromotionStatus::Unchanged)
+/// );
+/// assert_eq!(
+///     infer_promotion(PieceType::Silver, PieceType::Horse),
+///     Err(PromotionError::Inconsistent(PieceType::Silver, PieceType::Horse))
+/// );
+/// ```
+pub fn infer_promotion(pre: PieceType, post: PieceType) -> Result<PromotionStatus, PromotionError> {
+    if pre == post && pre != PieceType::All {
+        Ok(PromotionStatus::Unchanged)
+    } else if pre.promote() == Some(post) {
+        Ok(PromotionStatus::Promoted)
+    } else {
+        Err(PromotionError::Inconsistent(pre, post))
+    }
+}
+
+/// Returns the piece after a move of `pre` that promotes or not, the inverse
+/// of [`infer_promotion`].
+pub fn apply_promotion(pre: PieceType, promote: bool) -> Result<PieceType, PromotionError> {
+    match (promote, pre.promote()) {
+        (false, _) if pre != PieceType::All => Ok(pre),
+        (true, Some(promoted)) => Ok(promoted),
+        _ => Err(PromotionError::CannotPromote(pre)),
+    }
+}
+
 impl fmt::Display for PieceType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let pt = match *self {
@@ -225,90 +1433,703 @@ impl fmt::Display for PieceType {
 
 ////////////////////////////////////////////////////////////////////////////////
 
-/// Standard 9x9 shogi board
-pub type Board = [[Option<(Color, PieceType)>; 9]; 9];
+/// Standard 9x9 shogi board grid, as written by `P1`..`P9`
+///
+/// The array is indexed as `[row][column]`, in the order the `P1`..`P9` lines
+/// are written: `row = rank - 1` and `column = 9 - file`, so `[0][0]` is square 91
+/// and `[8][8]` is square 19. Prefer [`Position::piece_at`] and
+/// [`Position::set_piece`], which take a [`Square`] and do this translation.
+pub type Grid = [[Option<(Color, PieceType)>; 9]; 9];
 
-/// Minishogi 5x5 board
-pub type MinishogiBoard = [[Option<(Color, PieceType)>; 5]; 5];
+/// Minishogi 5x5 board grid
+///
+/// Indexed as `[rank - 1][5 - file]`, so `[0][0]` is square 51.
+pub type MinishogiGrid = [[Option<(Color, PieceType)>; 5]; 5];
+
+/// Wild Cat Shogi 3x5 board grid (3 files, 5 ranks)
+///
+/// Indexed as `[rank - 1][3 - file]`, so `[0][0]` is square 31.
+pub type WildcatGrid = [[Option<(Color, PieceType)>; 3]; 5];
+
+/// List of pieces used for `PI` removals and `P+`/`P-` placements.
+///
+/// A `Vec` by default; with the `smallvec` feature the first few entries are
+/// stored inline, since most positions list only a handful of pieces.
+#[cfg(not(feature = "smallvec"))]
+pub type PieceList<T> = Vec<T>;
+
+/// List of pieces used for `PI` removals and `P+`/`P-` placements.
+///
+/// A `Vec` by default; with the `smallvec` feature the first few entries are
+/// stored inline, since most positions list only a handful of pieces.
+#[cfg(feature = "smallvec")]
+pub type PieceList<T> = smallvec::SmallVec<[T; 4]>;
+
+/// Player names and the `$EVENT`, `$SITE` and `$OPENING` values.
+///
+/// A `String` by default; with the `intern` feature a shared `Arc<str>`, so
+/// records parsed with one `Interner` keep a single copy
+/// of each value.
+#[cfg(not(feature = "intern"))]
+pub type RecordText = String;
+
+/// Player names and the `$EVENT`, `$SITE` and `$OPENING` values.
+///
+/// A `String` by default; with the `intern` feature a shared `Arc<str>`, so
+/// records parsed with one `Interner` keep a single copy
+/// of each value.
+#[cfg(feature = "intern")]
+pub type RecordText = Arc<str>;
+
+/// The standard starting position (hirate), as described by `PI`.
+const HIRATE: Grid = {
+    use Color::{Black as B, White as W};
+    use PieceType::*;
+
+    const fn back_rank(c: Color) -> [Option<(Color, PieceType)>; 9] {
+        [
+            Some((c, Lance)),
+            Some((c, Knight)),
+            Some((c, Silver)),
+            Some((c, Gold)),
+            Some((c, King)),
+            Some((c, Gold)),
+            Some((c, Silver)),
+            Some((c, Knight)),
+            Some((c, Lance)),
+        ]
+    }
 
-/// Wild Cat Shogi 3x5 board (3 files, 5 ranks)
-pub type WildcatBoard = [[Option<(Color, PieceType)>; 3]; 5];
+    let mut grid: Grid = [[None; 9]; 9];
+    grid[0] = back_rank(W);
+    grid[1][1] = Some((W, Rook));
+    grid[1][7] = Some((W, Bishop));
+    grid[2] = [Some((W, Pawn)); 9];
+    grid[6] = [Some((B, Pawn)); 9];
+    grid[7][1] = Some((B, Bishop));
+    grid[7][7] = Some((B, Rook));
+    grid[8] = back_rank(B);
+    grid
+};
 
-#[derive(Debug, Default, PartialEq, Eq)]
+/// A starting position as written in the position section of a CSA file.
+///
+/// The board can be described in one of several ways: `PI` with optional
+/// removals (`drop_pieces`), a full grid (`bulk`, `minishogi_bulk` or
+/// `wildcat_bulk`), and/or individual `P+`/`P-` placements (`add_pieces`).
+/// The fields mirror that structure; to read or change what sits on a given
+/// square regardless of representation, use [`Position::piece_at`] and
+/// [`Position::set_piece`] rather than indexing the grids directly.
+///
+/// `==` compares the representation, so that a position is written back as it
+/// was read. To tell whether two positions set up the same board, use
+/// [`Position::semantically_equals`].
+///
+/// `Position::default()` is the standard starting position with Black to
+/// move, written `PI` with no removals. [`Position::empty`] is the board
+/// without any piece, to build a position on piece by piece.
+///
+/// ```
+/// use csa::{GameRecord, Position};
+///
+/// assert_eq!(Position::default().to_string(), "PI\n+\n");
+/// assert_eq!(GameRecord::default().to_string(), "V2.2\nPI\n+\n");
+/// ```
+#[derive(Default, PartialEq, Eq, Clone)]
 pub struct Position {
-    pub drop_pieces: Vec<(Square, PieceType)>,
-    pub bulk: Option<Board>,
-    pub minishogi_bulk: Option<MinishogiBoard>,
-    pub wildcat_bulk: Option<WildcatBoard>,
-    pub add_pieces: Vec<(Color, Square, PieceType)>,
+    /// Pieces removed from the standard starting position by `PI`.
+    pub drop_pieces: PieceList<(Square, PieceType)>,
+    /// 9x9 grid from `P1`..`P9`. See [`Grid`] for the index layout.
+    pub bulk: Option<Box<Grid>>,
+    /// 5x5 grid from `P1`..`P5`. See [`MinishogiGrid`] for the index layout.
+    pub minishogi_bulk: Option<Box<MinishogiGrid>>,
+    /// 3x5 grid from `P1`..`P5`. See [`WildcatGrid`] for the index layout.
+    pub wildcat_bulk: Option<Box<WildcatGrid>>,
+    /// Pieces placed by `P+`/`P-` lines, applied after the grid or `PI`.
+    /// Square `00` denotes a piece in hand.
+    pub add_pieces: PieceList<(Color, Square, PieceType)>,
+    /// The position was written as `P+`/`P-` lines alone, which place pieces
+    /// on an empty board. `bulk` then holds that empty board. See
+    /// [`Position::source_repr`].
+    pub placements_only: bool,
     pub side_to_move: Color,
 }
 
+/// How a starting position is written in CSA, see [`Position::source_repr`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum PositionRepr {
+    /// `PI`, with the given number of pieces removed.
+    Pi { removals: usize },
+    /// A 9x9 grid, `P1`..`P9`.
+    Grid,
+    /// A 5x5 grid, `P1`..`P5`.
+    MinishogiGrid,
+    /// A 3x5 grid, `P1`..`P5`.
+    WildcatGrid,
+    /// `P+`/`P-` lines alone, on an empty board.
+    Placements,
+    /// `PI` or a grid, followed by `P+`/`P-` lines.
+    Mixed,
+}
+
+impl Position {
+    /// Returns an empty 9x9 board with Black to move, written as `P+`/`P-`
+    /// lines alone. See [`Position::place`] and [`Position::add_to_hand`] to
+    /// fill it.
+    pub fn empty() -> Position {
+        Position {
+            bulk: Some(Box::default()),
+            placements_only: true,
+            ..Position::default()
+        }
+    }
+
+    /// Returns the board size as `(files, ranks)`.
+    pub fn dimensions(&self) -> (u8, u8) {
+        if self.minishogi_bulk.is_some() {
+            (5, 5)
+        } else if self.wildcat_bulk.is_some() {
+            (3, 5)
+        } else {
+            (9, 9)
+        }
+    }
+
+    /// Returns the 9x9 grid, if the position was given as one.
+    pub fn grid(&self) -> Option<&Grid> {
+        self.bulk.as_deref()
+    }
+
+    /// Returns the 5x5 grid, if the position was given as one.
+    pub fn minishogi_grid(&self) -> Option<&MinishogiGrid> {
+        self.minishogi_bulk.as_deref()
+    }
+
+    /// Returns the 3x5 grid, if the position was given as one.
+    pub fn wildcat_grid(&self) -> Option<&WildcatGrid> {
+        self.wildcat_bulk.as_deref()
+    }
+
+    /// Returns how the position is written: for a parsed position, as it was
+    /// in the file. The CSA writer keeps to it unless
+    /// [`WriteOptions::position_style`] says otherwise.
+    ///
+    /// ```
+    /// use csa::{parse_csa, PositionRepr};
+    ///
+    /// let game = parse_csa("V2.2\nPI82HI22KA\n-\n").unwrap();
+    /// assert_eq!(game.start_pos.source_repr(), PositionRepr::Pi { removals: 2 });
+    /// let game = parse_csa("V2.2\nP-51OU\nP+00HI\n+\n").unwrap();
+    /// assert_eq!(game.start_pos.source_repr(), PositionRepr::Placements);
+    /// ```
+    pub fn source_repr(&self) -> PositionRepr {
+        if self.placements_only {
+            PositionRepr::Placements
+        } else if !self.add_pieces.is_empty() {
+            PositionRepr::Mixed
+        } else if self.minishogi_bulk.is_some() {
+            PositionRepr::MinishogiGrid
+        } else if self.wildcat_bulk.is_some() {
+            PositionRepr::WildcatGrid
+        } else if self.bulk.is_some() {
+            PositionRepr::Grid
+        } else {
+            PositionRepr::Pi {
+                removals: self.drop_pieces.len(),
+            }
+        }
+    }
+
+    fn contains(&self, sq: Square) -> bool {
+        let (files, ranks) = self.dimensions();
+        (1..=files).contains(&sq.file) && (1..=ranks).contains(&sq.rank)
+    }
+
+    /// Returns the piece on the given square, or `None` if it is empty or lies
+    /// outside the board.
+    ///
+    /// The grid (or the `PI` layout) is consulted first, then any `P+`/`P-`
+    /// placements on that square, the last one winning.
+    pub fn piece_at(&self, sq: Square) -> Option<(Color, PieceType)> {
+        if !self.contains(sq) {
+            return None;
+        }
+
+        let placed = self
+            .add_pieces
+            .iter()
+            .rev()
+            .find(|(_, s, pt)| *s == sq && *pt != PieceType::All);
+        if let Some((color, _, pt)) = placed {
+            return Some((*color, *pt));
+        }
+
+        self.base_piece_at(sq)
+    }
+
+    /// Puts `piece` on the given square, or clears it with `None`.
+    ///
+    /// The existing representation is kept: grids are updated in place, `PI`
+    /// positions record the change as a removal and/or a placement, and
+    /// positions written as placements alone as a placement.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the square lies outside the board.
+    pub fn set_piece(&mut self, sq: Square, piece: Option<(Color, PieceType)>) {
+        assert!(self.contains(sq), "square {sq} is outside the board");
+
+        self.add_pieces.retain(|(_, s, _)| *s != sq);
+
+        let row = (sq.rank - 1) as usize;
+        if self.placements_only {
+            // The grid stays empty, every piece is a placement.
+            if let Some((color, pt)) = piece {
+                self.add_pieces.push((color, sq, pt));
+            }
+        } else if let Some(ref mut grid) = self.bulk {
+            grid[row][(9 - sq.file) as usize] = piece;
+        } else if let Some(ref mut grid) = self.minishogi_bulk {
+            grid[row][(5 - sq.file) as usize] = piece;
+        } else if let Some(ref mut grid) = self.wildcat_bulk {
+            grid[row][(3 - sq.file) as usize] = piece;
+        } else {
+            let hirate = HIRATE[row][(9 - sq.file) as usize];
+            let removed = self.drop_pieces.iter().position(|(s, _)| *s == sq);
+
+            match (hirate, removed) {
+                (Some((_, pt)), None) if piece != hirate => {
+                    self.drop_pieces.push((sq, pt));
+                }
+                (Some(_), Some(i)) if piece == hirate => {
+                    self.drop_pieces.remove(i);
+                    return;
+                }
+                _ => {}
+            }
+
+            if piece == hirate {
+                return;
+            }
+            if let Some((color, pt)) = piece {
+                self.add_pieces.push((color, sq, pt));
+            }
+        }
+    }
+
+    /// Puts the `PI` removals and the `P+`/`P-` placements into canonical order,
+    /// so that positions describing the same setup compare and serialize equally.
+    ///
+    /// Removals are sorted by square. Placements are grouped by color, black
+    /// first; within a color, pieces on the board come first in ascending square
+    /// order (11, 12, .., 19, 21, ..), followed by pieces in hand (`00`) ordered
+    /// by [`PieceType`] declaration order. `AL` entries come after the
+    /// placements of both colors, in the order they were given, as each hands
+    /// out the pieces left when it is read. When a square is placed more than
+    /// once only the last placement, the one that takes effect, is kept.
+    ///
+    /// The CSA writer always emits this order, packing the pieces of one color
+    /// into as few lines as [`MAX_PLACEMENTS_PER_LINE`] allows.
+    pub fn normalize(&mut self) {
+        self.drop_pieces = normalized_removals(&self.drop_pieces);
+        self.add_pieces = normalized_placements(&self.add_pieces);
+    }
+
+    fn base_piece_at(&self, sq: Square) -> Option<(Color, PieceType)> {
+        let row = (sq.rank - 1) as usize;
+        if let Some(ref grid) = self.bulk {
+            grid[row][(9 - sq.file) as usize]
+        } else if let Some(ref grid) = self.minishogi_bulk {
+            grid[row][(5 - sq.file) as usize]
+        } else if let Some(ref grid) = self.wildcat_bulk {
+            grid[row][(3 - sq.file) as usize]
+        } else if self.drop_pieces.iter().any(|(s, _)| *s == sq) {
+            None
+        } else {
+            HIRATE[row][(9 - sq.file) as usize]
+        }
+    }
+}
+
+/// The maximum number of pieces written on a single `P+`/`P-` line, which keeps
+/// each line within 80 characters.
+pub const MAX_PLACEMENTS_PER_LINE: usize = 19;
+
+fn square_order(sq: &Square) -> (u8, u8) {
+    // Pieces in hand sort after every square on the board.
+    if sq.is_hand() {
+        (u8::MAX, u8::MAX)
+    } else {
+        (sq.file, sq.rank)
+    }
+}
+
+/// Returns why the `PI` removal at `index` is not allowed: its square holds a
+/// different piece in the standard position, or no piece at all, or an
+/// earlier removal already names it.
+pub(crate) fn removal_problem(removals: &[(Square, PieceType)], index: usize) -> Option<String> {
+    let (sq, pt) = removals[index];
+    let on_board = (1..=9).contains(&sq.file) && (1..=9).contains(&sq.rank);
+    let standard = if on_board {
+        HIRATE[(sq.rank - 1) as usize][(9 - sq.file) as usize]
+    } else {
+        None
+    };
+    match standard {
+        _ if removals[..index].iter().any(|(s, _)| *s == sq) => {
+            Some(format!("PI removes {sq} twice"))
+        }
+        Some((_, expected)) if expected == pt => None,
+        Some((_, expected)) => Some(format!(
+            "PI removes {sq}{pt}, but the standard position has {expected} on {sq}"
+        )),
+        None => Some(format!(
+            "PI removes {sq}{pt}, but the standard position has no piece on {sq}"
+        )),
+    }
+}
+
+fn normalized_removals(removals: &[(Square, PieceType)]) -> PieceList<(Square, PieceType)> {
+    let mut removals = PieceList::from(removals);
+    removals.sort_by_key(|(sq, pt)| (square_order(sq), *pt as u8));
+    removals
+}
+
+fn normalized_placements(
+    placements: &[(Color, Square, PieceType)],
+) -> PieceList<(Color, Square, PieceType)> {
+    let mut seen = Vec::new();
+    let mut placements: PieceList<_> = placements
+        .iter()
+        .rev()
+        .filter(|(_, sq, _)| {
+            if sq.is_hand() {
+                return true;
+            }
+            if seen.contains(sq) {
+                return false;
+            }
+            seen.push(*sq);
+            true
+        })
+        .copied()
+        .collect();
+    placements.reverse();
+    // `AL` hands out the pieces left when it is read, so moving it across
+    // another placement changes who gets what. Only the explicit placements
+    // are sorted, and every `AL` follows them in its original order.
+    let (mut explicit, all): (PieceList<_>, PieceList<_>) = placements
+        .into_iter()
+        .partition(|(_, _, pt)| *pt != PieceType::All);
+    explicit.sort_by_key(|(color, sq, pt)| (*color as u8, square_order(sq), *pt as u8));
+    explicit.extend(all);
+    explicit
+}
+
 impl fmt::Display for Position {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        if let Some(ref bulk) = self.bulk {
-            // Standard 9x9 shogi
-            for (i, row) in bulk.iter().enumerate() {
-                write!(f, "P{}", i + 1)?;
-
-                for pc in row.iter() {
-                    match *pc {
-                        Some((ref color, ref pt)) => write!(f, "{color}{pt}")?,
-                        None => write!(f, " * ")?,
-                    }
-                }
+        self.write_csa(f, &WriteOptions::default())
+    }
+}
 
-                writeln!(f)?;
+/// Lists the position as CSA lines, one per entry with `{:#?}`: each grid
+/// that is set, the `PI` removals when there are any or no grid, the
+/// placements and the side to move. Unlike `Display`, nothing is put in
+/// canonical order, so positions that differ show differently.
+///
+/// ```
+/// use csa::parse_csa;
+///
+/// let game = parse_csa("V2.2\nPI82HI22KA\nP-00FU\n-\n").unwrap();
+/// assert_eq!(
+///     format!("{:?}", game.start_pos),
+///     "[PI82HI22KA, P-00FU, -]",
+/// );
+/// ```
+impl fmt::Debug for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use fmt::Write;
+
+        let mut csa = String::new();
+        if self.placements_only {
+            csa.push_str("'placements only\n");
+        }
+        if let Some(ref bulk) = self.bulk {
+            write_rows(&mut csa, bulk.iter().map(|row| &row[..]))?;
+        }
+        if let Some(ref bulk) = self.minishogi_bulk {
+            write_rows(&mut csa, bulk.iter().map(|row| &row[..]))?;
+        }
+        if let Some(ref bulk) = self.wildcat_bulk {
+            write_rows(&mut csa, bulk.iter().map(|row| &row[..]))?;
+        }
+        let no_grid =
+            self.bulk.is_none() && self.minishogi_bulk.is_none() && self.wildcat_bulk.is_none();
+        if !self.drop_pieces.is_empty() || no_grid {
+            csa.push_str("PI");
+            for (sq, pt) in &self.drop_pieces {
+                write!(csa, "{sq}{pt}")?;
             }
-        } else if let Some(ref bulk) = self.minishogi_bulk {
-            // Minishogi 5x5
-            for (i, row) in bulk.iter().enumerate() {
-                write!(f, "P{}", i + 1)?;
+            csa.push('\n');
+        }
+        for (color, sq, pt) in &self.add_pieces {
+            writeln!(csa, "P{color}{sq}{pt}")?;
+        }
+        writeln!(csa, "{}", self.side_to_move)?;
+        f.debug_list().entries(csa.lines().map(Verbatim)).finish()
+    }
+}
+
+/// Shows a string as it is, without the quotes and escapes of `str`'s
+/// `Debug`.
+struct Verbatim<'a>(&'a str);
+
+impl fmt::Debug for Verbatim<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl Position {
+    fn write_csa(&self, f: &mut dyn fmt::Write, options: &WriteOptions) -> fmt::Result {
+        let on_9x9 = self.dimensions() == (9, 9);
+        let style = match options.position_style {
+            Some(PositionStyle::Placements) if !on_9x9 => Some(PositionStyle::Grid),
+            None if self.placements_only && on_9x9 => Some(PositionStyle::Placements),
+            style => style,
+        };
+        let hands = self
+            .add_pieces
+            .iter()
+            .filter(|(_, sq, _)| sq.is_hand())
+            .copied();
 
-                for pc in row.iter() {
-                    match *pc {
-                        Some((ref color, ref pt)) => write!(f, "{color}{pt}")?,
-                        None => write!(f, " * ")?,
+        match style {
+            Some(PositionStyle::Placements) => {
+                let mut pieces: PieceList<_> = hands.collect();
+                for file in 1..=9 {
+                    for rank in 1..=9 {
+                        let sq = Square::new(file, rank);
+                        if let Some((color, pt)) = self.piece_at(sq) {
+                            pieces.push((color, sq, pt));
+                        }
                     }
                 }
-
-                writeln!(f)?;
+                if pieces.is_empty() {
+                    // Without any line the position would be missing, so an
+                    // empty board is written as a grid.
+                    self.write_grid(f)?;
+                }
+                write_placements(f, &pieces)?;
             }
-        } else if let Some(ref bulk) = self.wildcat_bulk {
-            // Wild Cat Shogi 3x5
-            for (i, row) in bulk.iter().enumerate() {
-                write!(f, "P{}", i + 1)?;
-
-                for pc in row.iter() {
-                    match *pc {
-                        Some((ref color, ref pt)) => write!(f, "{color}{pt}")?,
-                        None => write!(f, " * ")?,
+            Some(PositionStyle::Grid) => {
+                self.write_grid(f)?;
+                write_placements(f, &hands.collect::<PieceList<_>>())?;
+            }
+            None => {
+                if let Some(ref bulk) = self.bulk {
+                    // Standard 9x9 shogi
+                    write_rows(f, bulk.iter().map(|row| &row[..]))?;
+                } else if let Some(ref bulk) = self.minishogi_bulk {
+                    // Minishogi 5x5
+                    write_rows(f, bulk.iter().map(|row| &row[..]))?;
+                } else if let Some(ref bulk) = self.wildcat_bulk {
+                    // Wild Cat Shogi 3x5
+                    write_rows(f, bulk.iter().map(|row| &row[..]))?;
+                } else {
+                    write!(f, "PI")?;
+                    for (sq, pt) in &normalized_removals(&self.drop_pieces) {
+                        write!(f, "{sq}{pt}")?;
                     }
+                    writeln!(f)?;
                 }
+                write_placements(f, &self.add_pieces)?;
+            }
+        }
+
+        writeln!(f, "{}", self.side_to_move)?;
 
-                writeln!(f)?;
+        Ok(())
+    }
+
+    /// Writes the board, placements included, as a grid of its size.
+    fn write_grid(&self, f: &mut dyn fmt::Write) -> fmt::Result {
+        let (files, ranks) = self.dimensions();
+        let rows: Vec<Vec<_>> = (1..=ranks)
+            .map(|rank| {
+                (1..=files)
+                    .rev()
+                    .map(|file| self.piece_at(Square::new(file, rank)))
+                    .collect()
+            })
+            .collect();
+        write_rows(f, rows.iter().map(Vec::as_slice))
+    }
+}
+
+fn write_rows<'a>(
+    f: &mut dyn fmt::Write,
+    rows: impl Iterator<Item = &'a [Option<(Color, PieceType)>]>,
+) -> fmt::Result {
+    for (i, row) in rows.enumerate() {
+        write!(f, "P{}", i + 1)?;
+
+        for pc in row.iter() {
+            match *pc {
+                Some((ref color, ref pt)) => write!(f, "{color}{pt}")?,
+                None => write!(f, " * ")?,
             }
-        } else {
-            write!(f, "PI")?;
-            for (sq, pt) in &self.drop_pieces {
+        }
+
+        writeln!(f)?;
+    }
+    Ok(())
+}
+
+fn write_placements(
+    f: &mut dyn fmt::Write,
+    placements: &[(Color, Square, PieceType)],
+) -> fmt::Result {
+    let placements = normalized_placements(placements);
+    // Each run of one color shares lines; the `AL` entries after the
+    // explicit placements may start new runs.
+    for run in placements.chunk_by(|(a, _, _), (b, _, _)| a == b) {
+        let color = run[0].0;
+        for line in run.chunks(MAX_PLACEMENTS_PER_LINE) {
+            write!(f, "P{color}")?;
+            for (_, sq, pt) in line {
                 write!(f, "{sq}{pt}")?;
             }
             writeln!(f)?;
         }
+    }
+    Ok(())
+}
 
-        for (color, sq, pt) in &self.add_pieces {
-            writeln!(f, "P{color}{sq}{pt}")?;
+/// How [`GameRecord::to_csa`] writes a starting position in place of the way
+/// it was read.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum PositionStyle {
+    /// A grid of the board's size, with the pieces in hand on `P+00`/`P-00`
+    /// lines. Every cell takes three characters, an empty square being
+    /// written ` * `, and rows have no trailing whitespace beyond that. The
+    /// parsers also read ` *` and `*` for an empty square and ignore spaces
+    /// after a row.
+    Grid,
+    /// `P+`/`P-` lines alone. Only a 9x9 board can be written this way, so
+    /// other boards are written as a grid.
+    Placements,
+}
+
+/// Options for [`GameRecord::to_csa`]. The default writes a record the way
+/// its `Display` implementation does, and will keep doing so: a new way of
+/// writing records comes as a new field, off by default.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct WriteOptions {
+    /// Writes the starting position in this style instead of keeping to
+    /// [`Position::source_repr`].
+    pub position_style: Option<PositionStyle>,
+    /// Writes this CSA version instead of V2.2. Moves and times the version
+    /// cannot express are left out or rounded, see
+    /// [`GameRecord::write_losses_with`].
+    pub version: Option<Version>,
+    /// Writes every attribute before the starting position, including those
+    /// [`Layout::trailing_attrs`] keeps after the moves, for readers that
+    /// stop looking for attributes at the position. The version line always
+    /// comes first, after the encoding line and header comments of a V3.0
+    /// record, and the `N+`/`N-` lines before the attributes.
+    pub attrs_first: bool,
+    /// Cuts the player names and the text attributes, `$EVENT`, `$SITE`,
+    /// `$OPENING` and the extra attributes holding [`AttrValue::Text`], to
+    /// this many units with [`truncate_display`], for readers with a length
+    /// limit. Times, numbers and the other typed values are never cut.
+    pub max_text_len: Option<(usize, Units)>,
+    /// Writes the CSA version the record was read as, see
+    /// [`Layout::source_format`], when `version` is not set. Records built
+    /// in code are still written as V2.2.
+    pub same_version: bool,
+    /// Starts a V3.0 record with `'CSA encoding=UTF-8` even when
+    /// [`Layout::encoding_line`] is not set. Other versions never get one.
+    pub encoding_line: bool,
+    /// Makes the version line the very first line: the encoding line and the
+    /// header comments of a V3.0 record are left out.
+    /// [`GameRecord::try_to_csa`] fails instead for a record that has them.
+    pub version_first: bool,
+}
+
+/// Ready-made [`WriteOptions`] for the readers records are commonly written
+/// for, see [`WriteOptions::preset`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum WritePreset {
+    /// The layout the CSA standard describes, in the version the record was
+    /// read as: a V3.0 record keeps its encoding line and header comments
+    /// before the version line, and attributes read after the moves are
+    /// written there again.
+    Spec,
+    /// The layout shogi-server and the tools built around it expect: V2.2,
+    /// the version line first with nothing before it, `N+`/`N-` before the
+    /// attributes and every attribute before the starting position.
+    ShogiServerCompatible,
+}
+
+impl WriteOptions {
+    /// Returns the options of `preset`. Fields can be changed on the result
+    /// like on any other options.
+    ///
+    /// ```
+    /// use csa::{parse_csa, WriteOptions, WritePreset};
+    ///
+    /// let game = parse_csa(
+    ///     "V2.2\nN+sente\nN-gote\nPI\n+\n+7776FU\n%TORYO\n$END_TIME:2024/01/06 10:01:12\n",
+    /// )
+    /// .unwrap();
+    /// let options = WriteOptions::preset(WritePreset::ShogiServerCompatible);
+    /// assert!(game
+    ///     .to_csa(&options)
+    ///     .starts_with("V2.2\nN+sente\nN-gote\n$END_TIME:2024/01/06 10:01:12\nPI\n"));
+    /// ```
+    pub fn preset(preset: WritePreset) -> WriteOptions {
+        match preset {
+            WritePreset::Spec => WriteOptions {
+                same_version: true,
+                ..WriteOptions::default()
+            },
+            WritePreset::ShogiServerCompatible => WriteOptions {
+                version: Some(Version::V2_2),
+                attrs_first: true,
+                version_first: true,
+                ..WriteOptions::default()
+            },
         }
+    }
+}
 
-        writeln!(f, "{}", self.side_to_move)?;
+/// Why [`GameRecord::try_to_csa`] could not write a record.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum WriteError {
+    /// [`WriteOptions::version_first`] is set, but the record has this line
+    /// to write before the version line: its encoding line or a header
+    /// comment.
+    BeforeVersion(String),
+}
 
-        Ok(())
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WriteError::BeforeVersion(line) => write!(
+                f,
+                "`{line}` would have to be written before the version line"
+            ),
+        }
     }
 }
 
+impl std::error::Error for WriteError {}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Action {
     Move(Color, Square, Square, PieceType),
     Toryo,
@@ -324,37 +2145,393 @@ pub enum Action {
     Tsumi,
     Fuzumi,
     Error,
+    /// The game reached the most moves it may have, see
+    /// [`GameRecord::max_moves`].
+    MaxMoves,
+    /// A special move CSA does not define, such as `%DRAW_OFFER`, read as
+    /// registered in [`CustomSpecials`](crate::CustomSpecials) with the
+    /// caller's `tag`. It does not end the game.
+    Custom {
+        token: SpecialToken,
+        tag: u16,
+    },
 }
 
-impl fmt::Display for Action {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            Action::Move(ref color, ref from, ref to, ref pt) => {
-                write!(f, "{color}{from}{to}{pt}")
-            }
-            Action::Toryo => write!(f, "%TORYO"),
-            Action::Chudan => write!(f, "%CHUDAN"),
-            Action::Sennichite => write!(f, "%SENNICHITE"),
-            Action::TimeUp => write!(f, "%TIME_UP"),
-            Action::IllegalMove => write!(f, "%ILLEGAL_MOVE"),
-            Action::IllegalAction(ref color) => write!(f, "%{color}ILLEGAL_ACTION"),
-            Action::Jishogi => write!(f, "%JISHOGI"),
-            Action::Kachi => write!(f, "%KACHI"),
-            Action::Hikiwake => write!(f, "%HIKIWAKE"),
-            Action::Matta => write!(f, "%MATTA"),
-            Action::Tsumi => write!(f, "%TSUMI"),
-            Action::Fuzumi => write!(f, "%FUZUMI"),
-            Action::Error => write!(f, "%ERROR"),
+impl Action {
+    /// Parses a move such as `+7776FU` or a special move such as `%TORYO`,
+    /// the inverse of `Display`.
+    pub fn parse(s: &str) -> Option<Action> {
+        if let Some(special) = s.strip_prefix('%') {
+            let action = match special {
+                "TORYO" => Action::Toryo,
+                "CHUDAN" => Action::Chudan,
+                "SENNICHITE" => Action::Sennichite,
+                "TIME_UP" => Action::TimeUp,
+                "ILLEGAL_MOVE" => Action::IllegalMove,
+                "+ILLEGAL_ACTION" => Action::IllegalAction(Color::Black),
+                "-ILLEGAL_ACTION" => Action::IllegalAction(Color::White),
+                "JISHOGI" => Action::Jishogi,
+                "KACHI" => Action::Kachi,
+                "HIKIWAKE" => Action::Hikiwake,
+                "MATTA" => Action::Matta,
+                "TSUMI" => Action::Tsumi,
+                "FUZUMI" => Action::Fuzumi,
+                "ERROR" => Action::Error,
+                "MAX_MOVES" => Action::MaxMoves,
+                _ => return None,
+            };
+            return Some(action);
+        }
+
+        let b = s.as_bytes();
+        if b.len() != 7 || !b[1..5].iter().all(u8::is_ascii_digit) {
+            return None;
         }
+        let color = match b[0] {
+            b'+' => Color::Black,
+            b'-' => Color::White,
+            _ => return None,
+        };
+        let square = |i: usize| Square::new(b[i] - b'0', b[i + 1] - b'0');
+        Some(Action::Move(
+            color,
+            square(1),
+            square(3),
+            PieceType::parse(&s[5..])?,
+        ))
     }
-}
 
-////////////////////////////////////////////////////////////////////////////////
+    /// The CSA token of a special move without its leading `%`, such as
+    /// `TORYO` or `+ILLEGAL_ACTION`. `None` for a move.
+    pub fn code(&self) -> Option<&str> {
+        let code = match *self {
+            Action::Move(..) => return None,
+            Action::Toryo => "TORYO",
+            Action::Chudan => "CHUDAN",
+            Action::Sennichite => "SENNICHITE",
+            Action::TimeUp => "TIME_UP",
+            Action::IllegalMove => "ILLEGAL_MOVE",
+            Action::IllegalAction(Color::Black) => "+ILLEGAL_ACTION",
+            Action::IllegalAction(Color::White) => "-ILLEGAL_ACTION",
+            Action::Jishogi => "JISHOGI",
+            Action::Kachi => "KACHI",
+            Action::Hikiwake => "HIKIWAKE",
+            Action::Matta => "MATTA",
+            Action::Tsumi => "TSUMI",
+            Action::Fuzumi => "FUZUMI",
+            Action::Error => "ERROR",
+            Action::MaxMoves => "MAX_MOVES",
+            Action::Custom { ref token, .. } => token.as_str(),
+        };
+        Some(code)
+    }
 
-#[derive(Debug, PartialEq, Eq)]
+    /// A name for the kind of action to show to English readers.
+    pub fn english_name(&self) -> &'static str {
+        match *self {
+            Action::Move(..) => "Move",
+            Action::Toryo => "Resignation",
+            Action::Chudan => "Adjournment",
+            Action::Sennichite => "Repetition",
+            Action::TimeUp => "Time up",
+            Action::IllegalMove => "Illegal move",
+            Action::IllegalAction(_) => "Illegal action",
+            Action::Jishogi => "Impasse",
+            Action::Kachi => "Entering king win",
+            Action::Hikiwake => "Draw",
+            Action::Matta => "Takeback",
+            Action::Tsumi => "Checkmate",
+            Action::Fuzumi => "No checkmate",
+            Action::Error => "Error",
+            Action::MaxMoves => "Move limit",
+            Action::Custom { .. } => "Custom",
+        }
+    }
+
+    /// The name the kind of action goes by in Japanese.
+    pub fn japanese_name(&self) -> &'static str {
+        match *self {
+            Action::Move(..) => "指し手",
+            Action::Toryo => "投了",
+            Action::Chudan => "中断",
+            Action::Sennichite => "千日手",
+            Action::TimeUp => "時間切れ",
+            Action::IllegalMove => "反則手",
+            Action::IllegalAction(_) => "反則行為",
+            Action::Jishogi => "持将棋",
+            Action::Kachi => "入玉宣言勝ち",
+            Action::Hikiwake => "引き分け",
+            Action::Matta => "待った",
+            Action::Tsumi => "詰み",
+            Action::Fuzumi => "不詰",
+            Action::Error => "エラー",
+            Action::MaxMoves => "最大手数",
+            Action::Custom { .. } => "独自",
+        }
+    }
+
+    /// Whether the action ends the game. Every special move does except
+    /// `%MATTA`, which takes a move back, and custom ones.
+    pub fn is_terminal(&self) -> bool {
+        match *self {
+            Action::Move(..) | Action::Matta | Action::Custom { .. } => false,
+            Action::Toryo
+            | Action::Chudan
+            | Action::Sennichite
+            | Action::TimeUp
+            | Action::IllegalMove
+            | Action::IllegalAction(_)
+            | Action::Jishogi
+            | Action::Kachi
+            | Action::Hikiwake
+            | Action::Tsumi
+            | Action::Fuzumi
+            | Action::Error
+            | Action::MaxMoves => true,
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Action::Move(ref color, ref from, ref to, ref pt) => {
+                write!(f, "{color}{from}{to}{pt}")
+            }
+            _ => write!(f, "%{}", self.code().unwrap_or_default()),
+        }
+    }
+}
+
+/// The token of a custom special move, without its leading `%`: one to
+/// [`SpecialToken::CAPACITY`] ASCII capital letters, digits, `_`, `+` or `-`.
+/// It is kept inline so that [`Action`] stays `Copy`.
+#[derive(PartialEq, Eq, Clone, Copy, Hash)]
+pub struct SpecialToken {
+    bytes: [u8; SpecialToken::CAPACITY],
+    len: u8,
+}
+
+impl SpecialToken {
+    pub const CAPACITY: usize = 15;
+
+    /// Returns the token, or `None` if it is empty, too long or has other
+    /// characters.
+    pub fn new(token: &str) -> Option<SpecialToken> {
+        let valid = |b: &u8| b.is_ascii_uppercase() || b.is_ascii_digit() || b"_+-".contains(b);
+        if token.is_empty() || token.len() > Self::CAPACITY || !token.bytes().all(|b| valid(&b)) {
+            return None;
+        }
+        let mut bytes = [0; Self::CAPACITY];
+        bytes[..token.len()].copy_from_slice(token.as_bytes());
+        Some(SpecialToken {
+            bytes,
+            len: token.len() as u8,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only ASCII is ever stored.
+        std::str::from_utf8(&self.bytes[..self.len as usize]).unwrap_or_default()
+    }
+}
+
+impl fmt::Debug for SpecialToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for SpecialToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for SpecialToken {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SpecialToken {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let token = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        SpecialToken::new(&token).ok_or_else(|| {
+            serde::de::Error::custom(format!("invalid special move token {token:?}"))
+        })
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Details of an adjourned game, recorded with `%CHUDAN` as its last move.
+///
+/// CSA has no statement for these, so they are written as comments right after
+/// `%CHUDAN`, one per line, and read back from the same form:
+///
+/// ```text
+/// %CHUDAN
+/// 'CHUDAN:SEALED_MOVE:+7776FU
+/// 'CHUDAN:RESUME_TIME:2024/01/06 10:00:00
+/// 'CHUDAN:CONTINUATION:wdoor+floodgate-300-10F+a+b+20240106100000
+/// ```
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Adjournment {
+    /// The move sealed by the player to move, played when the game resumes.
+    pub sealed_move: Option<Action>,
+    /// When play is scheduled to resume.
+    pub resume_time: Option<Time>,
+    /// An identifier or URL of the record continuing the game.
+    pub continuation: Option<String>,
+}
+
+impl Adjournment {
+    const COMMENT_PREFIX: &'static str = "'CHUDAN:";
+
+    /// Applies one `'CHUDAN:KEY:value` comment. Returns `false` when the comment
+    /// is not in that form or the value is not understood.
+    pub fn read_comment(&mut self, comment: &str) -> bool {
+        let Some((key, value)) = comment
+            .strip_prefix(Self::COMMENT_PREFIX)
+            .and_then(|rest| rest.split_once(':'))
+        else {
+            return false;
+        };
+        match key {
+            "SEALED_MOVE" => match Action::parse(value) {
+                Some(action @ Action::Move(..)) => self.sealed_move = Some(action),
+                _ => return false,
+            },
+            "RESUME_TIME" => match Time::parse(value) {
+                Some(time) => self.resume_time = Some(time),
+                None => return false,
+            },
+            "CONTINUATION" if !value.is_empty() => self.continuation = Some(value.to_string()),
+            _ => return false,
+        }
+        true
+    }
+}
+
+impl fmt::Display for Adjournment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let prefix = Self::COMMENT_PREFIX;
+        if let Some(ref action) = self.sealed_move {
+            writeln!(f, "{prefix}SEALED_MOVE:{action}")?;
+        }
+        if let Some(ref time) = self.resume_time {
+            writeln!(f, "{prefix}RESUME_TIME:{time}")?;
+        }
+        if let Some(ref continuation) = self.continuation {
+            writeln!(f, "{prefix}CONTINUATION:{continuation}")?;
+        }
+        Ok(())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A position marked for study, such as the losing move or the start of the
+/// endgame.
+///
+/// CSA has no statement for these, so they are written as comments after the
+/// move leading to the position, `'&label` or `'&label: comment`, and read
+/// back from the same form; `&` marks bookmarks in KIF files too. A label
+/// holding `: ` reads back with the rest as the comment, and line breaks are
+/// written as spaces.
+///
+/// ```text
+/// +2726FU
+/// T12
+/// '&endgame: the attack starts here
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Bookmark {
+    /// The number of plies played to reach the position, 0 for the starting
+    /// position.
+    pub ply: usize,
+    pub label: String,
+    pub comment: Option<String>,
+}
+
+impl Bookmark {
+    const COMMENT_PREFIX: &'static str = "'&";
+
+    /// Reads a `'&label` or `'&label: comment` comment as a bookmark of the
+    /// position after `ply` plies. Returns `None` when the comment is not in
+    /// that form.
+    pub fn from_comment(comment: &str, ply: usize) -> Option<Bookmark> {
+        let text = comment.strip_prefix(Self::COMMENT_PREFIX)?;
+        let (label, comment) = match text.split_once(": ") {
+            Some((label, comment)) => (label, Some(comment.to_string())),
+            None => (text, None),
+        };
+        (!label.is_empty()).then(|| Bookmark {
+            ply,
+            label: label.to_string(),
+            comment,
+        })
+    }
+
+    /// Returns whether the bookmark reads back from its comment the same.
+    pub(crate) fn round_trips(&self) -> bool {
+        Bookmark::from_comment(&self.to_string(), self.ply).as_ref() == Some(self)
+    }
+}
+
+impl fmt::Display for Bookmark {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let line = |text: &str| text.replace(['\r', '\n'], " ");
+        write!(f, "{}{}", Self::COMMENT_PREFIX, line(&self.label))?;
+        if let Some(ref comment) = self.comment {
+            write!(f, ": {}", line(comment))?;
+        }
+        Ok(())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(PartialEq, Eq, Clone)]
 pub struct MoveRecord {
     pub action: Action,
     pub time: Option<Duration>,
+    /// What was noted after the move and before the next one, in order, see
+    /// [`EventConvention`].
+    pub events: Vec<InterMoveEvent>,
+}
+
+impl MoveRecord {
+    /// A record of `action` without a time or events.
+    pub fn new(action: Action) -> MoveRecord {
+        MoveRecord {
+            action,
+            time: None,
+            events: Vec::new(),
+        }
+    }
+}
+
+/// Shows the move and its time as one CSA line, `+7776FU,T5`, with the
+/// time to the nanosecond, and the events after it, `+7776FU,'-DRAW_OFFER`.
+impl fmt::Debug for MoveRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.action)?;
+        if let Some(time) = self.time {
+            write!(f, ",T{}", time.as_secs())?;
+            if time.subsec_nanos() > 0 {
+                let nanos = format!("{:09}", time.subsec_nanos());
+                write!(f, ".{}", nanos.trim_end_matches('0'))?;
+            }
+        }
+        for event in &self.events {
+            write!(f, ",{event}")?;
+        }
+        Ok(())
+    }
 }
 
 impl fmt::Display for MoveRecord {
@@ -364,17 +2541,78 @@ impl fmt::Display for MoveRecord {
         if let Some(ref time) = self.time {
             writeln!(f, "T{}", time.as_secs())?;
         }
+        for event in &self.events {
+            writeln!(f, "{event}")?;
+        }
 
         Ok(())
     }
 }
 
+/// Something noted between two moves that is not a move, such as a draw
+/// offer, see [`MoveRecord::events`].
+///
+/// CSA has no statement for these. Servers and people note them as comments
+/// or special moves of their own, which an [`EventConvention`] reads. The
+/// `Display` form is the comment written when the record keeps no
+/// convention, see [`EventConvention::standard`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum InterMoveEvent {
+    /// The player offered a draw, written `'+DRAW_OFFER` or `'-DRAW_OFFER`.
+    DrawOffered(Color),
+    /// The player declined a draw offer, written `'+DRAW_DECLINE` or
+    /// `'-DRAW_DECLINE`.
+    DrawDeclined(Color),
+    /// Play stopped with the clocks, written `'PAUSE`.
+    Pause,
+    /// Play went on after a pause, written `'RESUME`.
+    Resume,
+}
+
+impl InterMoveEvent {
+    pub(crate) const ALL: [InterMoveEvent; 6] = [
+        InterMoveEvent::DrawOffered(Color::Black),
+        InterMoveEvent::DrawOffered(Color::White),
+        InterMoveEvent::DrawDeclined(Color::Black),
+        InterMoveEvent::DrawDeclined(Color::White),
+        InterMoveEvent::Pause,
+        InterMoveEvent::Resume,
+    ];
+}
+
+impl fmt::Display for InterMoveEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InterMoveEvent::DrawOffered(color) => write!(f, "'{color}DRAW_OFFER"),
+            InterMoveEvent::DrawDeclined(color) => write!(f, "'{color}DRAW_DECLINE"),
+            InterMoveEvent::Pause => write!(f, "'PAUSE"),
+            InterMoveEvent::Resume => write!(f, "'RESUME"),
+        }
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn square_try_new() {
+        assert_eq!(Square::try_new(7, 6), Ok(Square::new(7, 6)));
+        assert_eq!(Square::try_new(9, 9), Ok(Square::new(9, 9)));
+        for (file, rank) in [(0, 0), (0, 5), (10, 3), (5, 10)] {
+            assert_eq!(Square::try_new(file, rank), Err(SquareError { file, rank }));
+        }
+        assert!(Square::HAND.is_hand());
+        assert!(!Square::new(1, 1).is_hand());
+        assert_eq!(
+            SquareError { file: 10, rank: 3 }.to_string(),
+            "square 103 is off the board, files and ranks run from 1 to 9"
+        );
+    }
+
     #[test]
     fn piece_type() {
         assert_eq!(&PieceType::Pawn.to_string(), "FU");
@@ -427,6 +2665,795 @@ mod tests {
         assert_eq!(&Action::Tsumi.to_string(), "%TSUMI");
         assert_eq!(&Action::Fuzumi.to_string(), "%FUZUMI");
         assert_eq!(&Action::Error.to_string(), "%ERROR");
+        assert_eq!(&Action::MaxMoves.to_string(), "%MAX_MOVES");
+    }
+
+    #[test]
+    fn action_names() {
+        let specials = [
+            Action::Toryo,
+            Action::Chudan,
+            Action::Sennichite,
+            Action::TimeUp,
+            Action::IllegalMove,
+            Action::IllegalAction(Color::Black),
+            Action::IllegalAction(Color::White),
+            Action::Jishogi,
+            Action::Kachi,
+            Action::Hikiwake,
+            Action::Matta,
+            Action::Tsumi,
+            Action::Fuzumi,
+            Action::Error,
+            Action::MaxMoves,
+        ];
+        for action in specials {
+            let code = action.code().unwrap();
+            assert_eq!(Action::parse(&format!("%{code}")), Some(action));
+            assert!(!action.english_name().is_empty());
+            assert!(!action.japanese_name().is_empty());
+            assert_eq!(action.is_terminal(), action != Action::Matta);
+        }
+
+        let m = Action::Move(
+            Color::Black,
+            Square::new(7, 7),
+            Square::new(7, 6),
+            PieceType::Pawn,
+        );
+        assert_eq!(m.code(), None);
+        assert!(!m.is_terminal());
+        assert_eq!(Action::Toryo.english_name(), "Resignation");
+        assert_eq!(Action::Toryo.japanese_name(), "投了");
+        assert_eq!(
+            Action::IllegalAction(Color::White).code(),
+            Some("-ILLEGAL_ACTION")
+        );
+    }
+
+    #[test]
+    fn action_parse() {
+        let actions = [
+            Action::Move(
+                Color::White,
+                Square::new(0, 0),
+                Square::new(5, 5),
+                PieceType::Knight,
+            ),
+            Action::Toryo,
+            Action::Chudan,
+            Action::TimeUp,
+            Action::IllegalAction(Color::Black),
+            Action::IllegalAction(Color::White),
+            Action::Error,
+        ];
+        for action in actions {
+            assert_eq!(Action::parse(&action.to_string()), Some(action));
+        }
+        for pt in [
+            "FU", "KY", "KE", "GI", "KI", "KA", "HI", "OU", "TO", "NY", "NK", "NG", "UM", "RY",
+            "AL",
+        ] {
+            assert_eq!(PieceType::parse(pt).unwrap().to_string(), pt);
+        }
+
+        for raw in [
+            "", "+7776", "7776FU", "+7776XX", "*7776FU", "+77a6FU", "%RESIGN", "TORYO",
+        ] {
+            assert_eq!(Action::parse(raw), None, "{raw:?}");
+        }
+    }
+
+    #[test]
+    fn adjournment_comments() {
+        let mut adjournment = Adjournment::default();
+        assert!(adjournment.read_comment("'CHUDAN:SEALED_MOVE:-3334FU"));
+        assert!(adjournment.read_comment("'CHUDAN:RESUME_TIME:2024/01/06 10:00"));
+        assert!(adjournment.read_comment("'CHUDAN:CONTINUATION:game-2"));
+        assert!(!adjournment.read_comment("'CHUDAN:SEALED_MOVE:%TORYO"));
+        assert!(!adjournment.read_comment("'CHUDAN:RESUME_TIME:tomorrow"));
+        assert!(!adjournment.read_comment("'CHUDAN:WEATHER:rain"));
+        assert!(!adjournment.read_comment("'sealed -3334FU"));
+
+        assert_eq!(
+            adjournment.sealed_move,
+            Some(Action::Move(
+                Color::White,
+                Square::new(3, 3),
+                Square::new(3, 4),
+                PieceType::Pawn
+            ))
+        );
+        assert_eq!(
+            adjournment.to_string(),
+            "'CHUDAN:SEALED_MOVE:-3334FU\n\
+             'CHUDAN:RESUME_TIME:2024/01/06 10:00:00\n\
+             'CHUDAN:CONTINUATION:game-2\n"
+        );
+    }
+
+    #[test]
+    fn position_piece_at_hirate() {
+        let mut pos = Position::default();
+        assert_eq!(
+            pos.piece_at(Square::new(8, 2)),
+            Some((Color::White, PieceType::Rook))
+        );
+        assert_eq!(
+            pos.piece_at(Square::new(2, 8)),
+            Some((Color::Black, PieceType::Rook))
+        );
+        assert_eq!(
+            pos.piece_at(Square::new(5, 9)),
+            Some((Color::Black, PieceType::King))
+        );
+        assert_eq!(pos.piece_at(Square::new(5, 5)), None);
+        assert_eq!(pos.piece_at(Square::new(0, 0)), None);
+        assert_eq!(pos.piece_at(Square::new(10, 1)), None);
+
+        pos.drop_pieces.push((Square::new(2, 2), PieceType::Bishop));
+        assert_eq!(pos.piece_at(Square::new(2, 2)), None);
+
+        pos.add_pieces
+            .push((Color::Black, Square::new(5, 5), PieceType::Gold));
+        pos.add_pieces
+            .push((Color::Black, Square::new(0, 0), PieceType::Pawn));
+        assert_eq!(
+            pos.piece_at(Square::new(5, 5)),
+            Some((Color::Black, PieceType::Gold))
+        );
+    }
+
+    #[test]
+    fn position_set_piece_pi() {
+        let mut pos = Position::default();
+
+        pos.set_piece(Square::new(8, 2), None);
+        assert_eq!(pos.piece_at(Square::new(8, 2)), None);
+        assert_eq!(pos.drop_pieces[..], [(Square::new(8, 2), PieceType::Rook)]);
+
+        pos.set_piece(Square::new(5, 5), Some((Color::White, PieceType::Rook)));
+        assert_eq!(
+            pos.piece_at(Square::new(5, 5)),
+            Some((Color::White, PieceType::Rook))
+        );
+
+        pos.set_piece(Square::new(8, 2), Some((Color::White, PieceType::Rook)));
+        assert!(pos.drop_pieces.is_empty());
+        pos.set_piece(Square::new(5, 5), None);
+        assert!(pos.add_pieces.is_empty());
+        assert_eq!(pos, Position::default());
+    }
+
+    #[test]
+    fn position_set_piece_grids() {
+        let mut pos = Position {
+            bulk: Some(Box::new([[None; 9]; 9])),
+            ..Position::default()
+        };
+        pos.set_piece(Square::new(9, 1), Some((Color::White, PieceType::Lance)));
+        pos.set_piece(Square::new(1, 9), Some((Color::Black, PieceType::Lance)));
+        let grid = pos.bulk.unwrap();
+        assert_eq!(grid[0][0], Some((Color::White, PieceType::Lance)));
+        assert_eq!(grid[8][8], Some((Color::Black, PieceType::Lance)));
+
+        let mut mini = Position {
+            minishogi_bulk: Some(Box::new([[None; 5]; 5])),
+            ..Position::default()
+        };
+        assert_eq!(mini.dimensions(), (5, 5));
+        mini.set_piece(Square::new(5, 1), Some((Color::White, PieceType::Rook)));
+        mini.set_piece(Square::new(1, 2), Some((Color::White, PieceType::Pawn)));
+        assert_eq!(
+            mini.minishogi_grid().unwrap()[0][0],
+            Some((Color::White, PieceType::Rook))
+        );
+        assert_eq!(
+            mini.piece_at(Square::new(1, 2)),
+            Some((Color::White, PieceType::Pawn))
+        );
+        assert_eq!(mini.piece_at(Square::new(6, 1)), None);
+
+        let mut wildcat = Position {
+            wildcat_bulk: Some(Box::new([[None; 3]; 5])),
+            ..Position::default()
+        };
+        assert_eq!(wildcat.dimensions(), (3, 5));
+        wildcat.set_piece(Square::new(1, 5), Some((Color::Black, PieceType::Bishop)));
+        assert_eq!(
+            wildcat.wildcat_bulk.unwrap()[4][2],
+            Some((Color::Black, PieceType::Bishop))
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn position_set_piece_outside_board() {
+        let mut pos = Position {
+            minishogi_bulk: Some(Box::new([[None; 5]; 5])),
+            ..Position::default()
+        };
+        pos.set_piece(Square::new(9, 9), None);
+    }
+
+    #[test]
+    fn position_size() {
+        use std::mem::size_of;
+
+        // Grids are boxed, so a position costs a few pointers whichever
+        // representation it uses. Guard against one slipping back inline.
+        assert!(size_of::<Position>() <= 96, "{}", size_of::<Position>());
+        assert!(
+            size_of::<GameRecord>() <= 632,
+            "{}",
+            size_of::<GameRecord>()
+        );
+    }
+
+    #[test]
+    fn position_normalize() {
+        let mut pos = Position {
+            drop_pieces: [
+                (Square::new(8, 2), PieceType::Rook),
+                (Square::new(2, 2), PieceType::Bishop),
+            ]
+            .into_iter()
+            .collect(),
+            add_pieces: [
+                (Color::White, Square::new(0, 0), PieceType::Pawn),
+                (Color::White, Square::new(5, 5), PieceType::Silver),
+                (Color::Black, Square::new(0, 0), PieceType::Rook),
+                (Color::Black, Square::new(5, 5), PieceType::Gold),
+                (Color::Black, Square::new(1, 3), PieceType::Pawn),
+                (Color::White, Square::new(0, 0), PieceType::Pawn),
+            ]
+            .into_iter()
+            .collect(),
+            ..Position::default()
+        };
+        let before = pos.piece_at(Square::new(5, 5));
+        pos.normalize();
+
+        assert_eq!(
+            pos.drop_pieces[..],
+            [
+                (Square::new(2, 2), PieceType::Bishop),
+                (Square::new(8, 2), PieceType::Rook),
+            ]
+        );
+        assert_eq!(
+            pos.add_pieces[..],
+            [
+                (Color::Black, Square::new(1, 3), PieceType::Pawn),
+                (Color::Black, Square::new(5, 5), PieceType::Gold),
+                (Color::Black, Square::new(0, 0), PieceType::Rook),
+                (Color::White, Square::new(0, 0), PieceType::Pawn),
+                (Color::White, Square::new(0, 0), PieceType::Pawn),
+            ]
+        );
+        assert_eq!(pos.piece_at(Square::new(5, 5)), before);
+        assert_eq!(
+            pos.to_string(),
+            "PI22KA82HI\nP+13FU55KI00HI\nP-00FU00FU\n+\n"
+        );
+    }
+
+    #[test]
+    fn position_all_placements_keep_their_order() {
+        use crate::board::Board;
+
+        for (csa, written) in [
+            ("V2.2\nPI82HI22KA\nP-00AL\nP+00AL\n+\n", "P-00AL\nP+00AL\n"),
+            ("V2.2\nPI82HI22KA\nP-00KA\nP+00AL\n+\n", "P-00KA\nP+00AL\n"),
+            (
+                "V2.2\nPI82HI22KA\nP+00AL\nP-00HI\nP-00AL\n+\n",
+                "P-00HI\nP+00AL\nP-00AL\n",
+            ),
+        ] {
+            let game = crate::parse_csa(csa).unwrap();
+            let text = game.to_string();
+            assert!(text.contains(written), "{text}");
+            let reparsed = crate::parse_csa(&text).unwrap();
+            assert_eq!(
+                Board::from_position(&reparsed.start_pos).unwrap(),
+                Board::from_position(&game.start_pos).unwrap(),
+                "{csa}"
+            );
+        }
+    }
+
+    #[test]
+    fn position_placement_line_length() {
+        let pos = Position {
+            add_pieces: std::iter::repeat_n((Color::Black, Square::new(0, 0), PieceType::Pawn), 20)
+                .collect(),
+            ..Position::default()
+        };
+        let csa = pos.to_string();
+        let lines: Vec<&str> = csa.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[1].len(), 2 + 4 * MAX_PLACEMENTS_PER_LINE);
+        assert!(lines[1].len() <= 80);
+        assert_eq!(lines[2], "P+00FU");
+    }
+
+    #[test]
+    fn position_source_repr() {
+        let parse = |csa: &str| crate::parse_csa(csa).unwrap().start_pos;
+
+        assert_eq!(
+            parse("V2.2\nPI\n+\n").source_repr(),
+            PositionRepr::Pi { removals: 0 }
+        );
+        assert_eq!(
+            parse("V2.2\nPI\nP+00FU\n+\n").source_repr(),
+            PositionRepr::Mixed
+        );
+        let grid = crate::Board::from_position(&Position::default())
+            .unwrap()
+            .to_position();
+        assert_eq!(grid.source_repr(), PositionRepr::Grid);
+        assert_eq!(
+            parse(&format!("V2.2\n{grid}")).source_repr(),
+            PositionRepr::Grid
+        );
+
+        let placed = parse(include_str!("../fixtures/placements_only.csa"));
+        assert_eq!(placed.source_repr(), PositionRepr::Placements);
+        assert_eq!(placed.piece_at(Square::new(5, 9)), None);
+        assert_eq!(
+            placed.piece_at(Square::new(1, 1)),
+            Some((Color::White, PieceType::King))
+        );
+        // Unlike the default position, an empty board is told apart.
+        assert!(!placed.semantically_equals(&Position::default()).unwrap());
+    }
+
+    #[test]
+    fn position_write_styles() {
+        let mut game = crate::parse_csa(include_str!("../fixtures/placements_only.csa")).unwrap();
+        // V2.2 does not write the header comments back.
+        game.header_comments.clear();
+        let written = game.to_string();
+        assert!(
+            written.contains("\nP+23KI00KI\nP-11OU21KE00AL\n+\n"),
+            "{written}"
+        );
+        assert_eq!(crate::parse_csa(&written).unwrap(), game);
+
+        let grid = WriteOptions {
+            position_style: Some(PositionStyle::Grid),
+            ..WriteOptions::default()
+        };
+        let written = game.to_csa(&grid);
+        assert!(
+            written.contains("\nP1 *  *  *  *  *  *  * -KE-OU\n"),
+            "{written}"
+        );
+        assert!(written.contains("\nP+00KI\nP-00AL\n+\n"), "{written}");
+        let reparsed = crate::parse_csa(&written).unwrap();
+        assert_eq!(reparsed.start_pos.source_repr(), PositionRepr::Mixed);
+        assert!(reparsed
+            .start_pos
+            .semantically_equals(&game.start_pos)
+            .unwrap());
+
+        let placements = WriteOptions {
+            position_style: Some(PositionStyle::Placements),
+            ..WriteOptions::default()
+        };
+        let hirate = crate::parse_csa("V2.2\nPI82HI\n-\n").unwrap();
+        let written = hirate.to_csa(&placements);
+        assert!(!written.contains("PI"), "{written}");
+        let reparsed = crate::parse_csa(&written).unwrap();
+        assert!(reparsed
+            .start_pos
+            .semantically_equals(&hirate.start_pos)
+            .unwrap());
+        assert_eq!(hirate.to_csa(&WriteOptions::default()), hirate.to_string());
+
+        // Boards other than 9x9 can only be written as grids.
+        let mini = Position {
+            minishogi_bulk: Some(Box::default()),
+            placements_only: true,
+            ..Position::default()
+        };
+        let record = GameRecord {
+            start_pos: mini,
+            ..GameRecord::default()
+        };
+        assert!(record.to_csa(&placements).contains("\nP5 *  *  *  *  * \n"));
+    }
+
+    #[test]
+    fn game_record_normalized() {
+        let mut a = GameRecord::default();
+        a.start_pos.add_pieces = [
+            (Color::White, Square::new(5, 1), PieceType::King),
+            (Color::Black, Square::new(5, 9), PieceType::King),
+        ]
+        .into_iter()
+        .collect();
+        let mut b = GameRecord::default();
+        b.start_pos.add_pieces = [
+            (Color::Black, Square::new(5, 9), PieceType::King),
+            (Color::White, Square::new(5, 1), PieceType::King),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_ne!(a, b);
+        assert_eq!(a.normalized(), b.normalized());
+        assert_eq!(a.to_string(), b.to_string());
+    }
+
+    #[test]
+    fn time_parse_forms() {
+        let at = |y, m, d, hms: Option<(u8, u8, u8)>| Time {
+            date: time::Date::from_calendar_date(y, time::Month::try_from(m).unwrap(), d).unwrap(),
+            time: hms.map(|(h, mi, s)| time::Time::from_hms(h, mi, s).unwrap()),
+        };
+        let accepted = [
+            ("2024/01/05", at(2024, 1, 5, None)),
+            ("2024/1/5", at(2024, 1, 5, None)),
+            ("2024-01-05", at(2024, 1, 5, None)),
+            ("24/01/05", at(2024, 1, 5, None)),
+            ("69/12/31", at(2069, 12, 31, None)),
+            ("70/01/01", at(1970, 1, 1, None)),
+            ("2024/01/05 19:00:30", at(2024, 1, 5, Some((19, 0, 30)))),
+            ("2024/01/05 19:00", at(2024, 1, 5, Some((19, 0, 0)))),
+            ("2024/1/5 9:05", at(2024, 1, 5, Some((9, 5, 0)))),
+            ("2024-01-05  19:00:00", at(2024, 1, 5, Some((19, 0, 0)))),
+            (" 2024/01/05 ", at(2024, 1, 5, None)),
+            ("2024年1月5日", at(2024, 1, 5, None)),
+            ("2024年1月5日 19時00分", at(2024, 1, 5, Some((19, 0, 0)))),
+            (
+                "2024年01月05日 19時00分12秒",
+                at(2024, 1, 5, Some((19, 0, 12))),
+            ),
+            ("2024年1月5日19時00分", at(2024, 1, 5, Some((19, 0, 0)))),
+        ];
+        for (raw, expected) in accepted {
+            assert_eq!(Time::parse(raw), Some(expected), "{raw:?}");
+        }
+
+        let rejected = [
+            "",
+            "2024",
+            "2024/01",
+            "2024/01/05/06",
+            "2024/01-05",
+            "024/01/05",
+            "2/01/05",
+            "2024/001/05",
+            "2024/13/01",
+            "2023/02/29",
+            "2024/+1/05",
+            "2024/01/05 19",
+            "2024/01/05 19:00:00:00",
+            "2024/01/05 24:00",
+            "2024/01/05 19:60",
+            "2024/01/05 evening",
+            "2024年1月",
+            "2024年1月5日 19時",
+            "2024年1月5日 19時00分12",
+            "Jan 5 2024",
+        ];
+        for raw in rejected {
+            assert_eq!(Time::parse(raw), None, "{raw:?}");
+        }
+    }
+
+    #[test]
+    fn time_limit_parse_forms() {
+        let limit = |main, byoyomi| TimeLimit {
+            main_time: Duration::from_secs(main),
+            byoyomi: Duration::from_secs(byoyomi),
+            ..TimeLimit::default()
+        };
+        let accepted = [
+            ("00:25+00", limit(1500, 0), "00:25+00"),
+            ("0:25+00", limit(1500, 0), "00:25+00"),
+            ("1:5+10", limit(3900, 10), "01:05+10"),
+            ("00:00+30", limit(0, 30), "00:00+30"),
+            ("+30", limit(0, 30), "00:00+30"),
+            ("0:25", limit(1500, 0), "00:25+00"),
+            ("100:00+60", limit(360_000, 60), "100:00+60"),
+            ("00:00+00", limit(0, 0), "00:00+00"),
+        ];
+        for (raw, expected, written) in accepted {
+            let parsed = TimeLimit::parse(raw);
+            assert_eq!(parsed.as_ref(), Some(&expected), "{raw:?}");
+            assert_eq!(expected.to_string(), written, "{raw:?}");
+        }
+
+        for raw in [
+            "", "+", "25", "10+30", "0:25+", ":25+0", "0:123+0", "0:25+1.5", "a:b+c",
+        ] {
+            assert_eq!(TimeLimit::parse(raw), None, "{raw:?}");
+        }
+
+        // Huge values are rejected, whether or not they fit in a u64.
+        let ten_years = MAX_TIME.as_secs();
+        for raw in [
+            "99999999999999999999:00+00".to_string(),
+            "5124095576030431:00+00".to_string(),
+            format!("{}:00+00", ten_years / 3600 + 1),
+            format!("0:00+{}", ten_years + 1),
+        ] {
+            assert_eq!(TimeLimit::parse(&raw), None, "{raw:?}");
+        }
+        let most = format!("{}:00+{ten_years}", ten_years / 3600);
+        assert_eq!(
+            TimeLimit::parse(&most),
+            Some(limit(ten_years, ten_years)),
+            "{most}"
+        );
+
+        assert!(limit(0, 30).is_byoyomi_only());
+        assert!(!limit(60, 30).is_byoyomi_only());
+        assert!(!limit(0, 0).is_byoyomi_only());
+        assert!(limit(0, 0).is_unlimited());
+        assert!(!limit(0, 30).is_unlimited());
+    }
+
+    #[test]
+    fn time_limit_parse_time_control() {
+        let fischer = TimeLimit::parse_time_control("600+0+10.5").unwrap();
+        assert_eq!(fischer.main_time, Duration::from_secs(600));
+        assert_eq!(fischer.byoyomi, Duration::ZERO);
+        assert_eq!(fischer.increment, Duration::from_millis(10_500));
+        assert!(!fischer.is_byoyomi_only());
+        assert!(!fischer.is_unlimited());
+        assert_eq!(fischer.to_string(), "00:10+00");
+
+        let increment_only = TimeLimit::parse_time_control("0+0+5").unwrap();
+        assert!(!increment_only.is_unlimited());
+
+        for raw in [
+            "600+10",
+            "600+10+0+0",
+            "600+10+0.1234",
+            "600+x+0",
+            "600.+0+0",
+            "315360001+0+0",
+            "0+0+99999999999999999999.999",
+        ] {
+            assert_eq!(TimeLimit::parse_time_control(raw), None, "{raw:?}");
+        }
+    }
+
+    #[test]
+    fn attr_value_detection() {
+        let date = time::Date::from_calendar_date(2023, time::Month::April, 1).unwrap();
+        let cases = [
+            ("256", AttrValue::Integer(256, "256".to_string())),
+            ("-15", AttrValue::Integer(-15, "-15".to_string())),
+            ("007", AttrValue::Integer(7, "007".to_string())),
+            ("YES", AttrValue::Boolean(true, "YES".to_string())),
+            ("false", AttrValue::Boolean(false, "false".to_string())),
+            (
+                "https://example.com/a?b=1",
+                AttrValue::Url("https://example.com/a?b=1".to_string()),
+            ),
+            (
+                "2023/04/01",
+                AttrValue::DateTime(Time { date, time: None }, "2023/04/01".to_string()),
+            ),
+            (
+                "2023/04/01 10:00:00",
+                AttrValue::DateTime(
+                    Time {
+                        date,
+                        time: Some(time::Time::from_hms(10, 0, 0).unwrap()),
+                    },
+                    "2023/04/01 10:00:00".to_string(),
+                ),
+            ),
+            (
+                "2023/04/01 Meijin",
+                AttrValue::Text("2023/04/01 Meijin".to_string()),
+            ),
+            ("2023/02/30", AttrValue::Text("2023/02/30".to_string())),
+            // Dates `$START_TIME` accepts, but too ambiguous in free text.
+            ("12-03-04", AttrValue::Text("12-03-04".to_string())),
+            ("2023-04-01", AttrValue::Text("2023-04-01".to_string())),
+            ("23/04/01", AttrValue::Text("23/04/01".to_string())),
+            ("2023年4月1日", AttrValue::Text("2023年4月1日".to_string())),
+            (
+                "2023/04/01 10:00",
+                AttrValue::Text("2023/04/01 10:00".to_string()),
+            ),
+            ("04/01", AttrValue::Text("04/01".to_string())),
+            ("http://a b", AttrValue::Text("http://a b".to_string())),
+            (
+                "99999999999999999999",
+                AttrValue::Text("99999999999999999999".to_string()),
+            ),
+            ("+", AttrValue::Text("+".to_string())),
+            ("", AttrValue::Text("".to_string())),
+        ];
+
+        for (raw, expected) in cases {
+            let value = AttrValue::parse(raw);
+            assert_eq!(value, expected, "{raw:?}");
+            assert_eq!(value.raw(), raw);
+            assert_eq!(value.to_string(), raw);
+        }
+    }
+
+    #[test]
+    fn game_record_attr_getters() {
+        let g = GameRecord {
+            extra_attrs: vec![
+                ("MAX_MOVES".to_string(), AttrValue::parse("256")),
+                ("RATED".to_string(), AttrValue::parse("YES")),
+                ("EVENT_URL".to_string(), AttrValue::parse("https://x.jp/1")),
+                ("NOTE".to_string(), AttrValue::parse("first")),
+                ("NOTE".to_string(), AttrValue::parse("second")),
+            ],
+            ..GameRecord::default()
+        };
+
+        assert_eq!(g.attr_int("MAX_MOVES"), Some(256));
+        assert_eq!(g.attr_bool("RATED"), Some(true));
+        assert_eq!(g.attr_int("RATED"), None);
+        assert_eq!(g.attr_url("EVENT_URL"), Some("https://x.jp/1"));
+        assert_eq!(g.attr_str("NOTE"), Some("second"));
+        assert_eq!(g.attr_time("NOTE"), None);
+        assert_eq!(g.attr("MISSING"), None);
+        assert!(g.to_string().contains(
+            "$MAX_MOVES:256\n$RATED:YES\n$EVENT_URL:https://x.jp/1\n$NOTE:first\n$NOTE:second\n"
+        ));
+    }
+
+    #[test]
+    fn game_record_ply_numbering() {
+        let mv = |color, from: (u8, u8), to: (u8, u8), pt| MoveRecord {
+            action: Action::Move(
+                color,
+                Square::new(from.0, from.1),
+                Square::new(to.0, to.1),
+                pt,
+            ),
+            time: None,
+            events: Vec::new(),
+        };
+        let special = |action| MoveRecord::new(action);
+
+        // A handicap game: White moves first and still plays ply 1.
+        let mut g = GameRecord::default();
+        g.start_pos.side_to_move = Color::White;
+        g.moves = vec![
+            mv(Color::White, (5, 1), (4, 2), PieceType::King),
+            special(Action::Matta),
+            mv(Color::Black, (7, 7), (7, 6), PieceType::Pawn),
+            special(Action::Chudan),
+        ];
+
+        assert_eq!(g.ply_count(), 2);
+        let plies: Vec<_> = (0..5).map(|i| g.ply_of(i)).collect();
+        assert_eq!(plies, vec![Some(1), None, Some(2), None, None]);
+        assert_eq!(g.index_of_ply(0), None);
+        assert_eq!(g.index_of_ply(1), Some(0));
+        assert_eq!(g.index_of_ply(2), Some(2));
+        assert_eq!(g.index_of_ply(3), None);
+        for ply in 1..=g.ply_count() {
+            assert_eq!(g.ply_of(g.index_of_ply(ply).unwrap()), Some(ply));
+        }
+
+        assert_eq!(GameRecord::default().ply_of(0), None);
+        assert_eq!(GameRecord::default().index_of_ply(1), None);
+    }
+
+    #[test]
+    fn promotion_inference() {
+        use PieceType::*;
+
+        for (pre, post) in [
+            (Pawn, ProPawn),
+            (Lance, ProLance),
+            (Knight, ProKnight),
+            (Silver, ProSilver),
+            (Bishop, Horse),
+            (Rook, Dragon),
+        ] {
+            assert_eq!(infer_promotion(pre, post), Ok(PromotionStatus::Promoted));
+            assert_eq!(infer_promotion(pre, pre), Ok(PromotionStatus::Unchanged));
+            assert_eq!(infer_promotion(post, post), Ok(PromotionStatus::Unchanged));
+            assert_eq!(apply_promotion(pre, true), Ok(post));
+            assert_eq!(apply_promotion(pre, false), Ok(pre));
+            assert_eq!(
+                apply_promotion(post, true),
+                Err(PromotionError::CannotPromote(post))
+            );
+            assert_eq!(
+                infer_promotion(post, pre),
+                Err(PromotionError::Inconsistent(post, pre))
+            );
+        }
+        for pt in [Gold, King] {
+            assert_eq!(infer_promotion(pt, pt), Ok(PromotionStatus::Unchanged));
+            assert_eq!(apply_promotion(pt, false), Ok(pt));
+            assert_eq!(
+                apply_promotion(pt, true),
+                Err(PromotionError::CannotPromote(pt))
+            );
+        }
+
+        assert_eq!(
+            infer_promotion(Silver, Horse),
+            Err(PromotionError::Inconsistent(Silver, Horse))
+        );
+        assert_eq!(
+            infer_promotion(All, All),
+            Err(PromotionError::Inconsistent(All, All))
+        );
+        assert_eq!(
+            apply_promotion(All, false),
+            Err(PromotionError::CannotPromote(All))
+        );
+        assert_eq!(
+            PromotionError::Inconsistent(Silver, Horse).to_string(),
+            "GI cannot become UM"
+        );
+    }
+
+    #[test]
+    fn game_record_truncated() {
+        let mut g =
+            crate::parse_csa("V2.2\nPI\n+\n+7776FU\n-3334FU\n%MATTA\n+2726FU\n%CHUDAN\n").unwrap();
+        g.adjournment = Some(Adjournment {
+            continuation: Some("next".to_string()),
+            ..Adjournment::default()
+        });
+
+        let first = g.truncated(1);
+        assert_eq!(first.moves, g.moves[..1]);
+        assert_eq!(first.adjournment, None);
+        assert_eq!(first.start_pos, g.start_pos);
+
+        // The %MATTA after ply 2 goes with the later plies.
+        assert_eq!(g.truncated(2).moves, g.moves[..2]);
+        assert!(g.truncated(0).moves.is_empty());
+
+        // Nothing to cut: the result is kept.
+        assert_eq!(g.truncated(3), g);
+        assert_eq!(g.truncated(100), g);
+    }
+
+    #[test]
+    fn game_record_asymmetric_time_limits() {
+        let minutes = |m: u64| TimeLimit {
+            main_time: Duration::from_secs(m * 60),
+            ..TimeLimit::default()
+        };
+        let mut g = GameRecord::default();
+        g.set_time_limit(Some(minutes(10)));
+        assert_eq!(g.time_limit(), Some(&minutes(10)));
+        assert_eq!(g.time_limit_of(Color::White), Some(&minutes(10)));
+        assert!(g.to_string().contains("$TIME_LIMIT:00:10+00\n"));
+        assert!(g.write_losses().is_empty());
+
+        g.time_limit_white = Some(minutes(5));
+        assert_eq!(g.time_limit(), None);
+        assert_eq!(g.time_limit_of(Color::Black), Some(&minutes(10)));
+        assert_eq!(g.time_limit_of(Color::White), Some(&minutes(5)));
+        assert!(!g.to_string().contains("$TIME_LIMIT"));
+        assert_eq!(
+            g.write_losses(),
+            vec![
+                "$TIME_LIMIT omitted: the players have different time limits \
+                 (black 00:10+00, white 00:05+00)"
+                    .to_string()
+            ]
+        );
+
+        g.time_limit_black = None;
+        assert_eq!(g.write_losses().len(), 1);
+
+        g.set_time_limit(None);
+        assert!(g.write_losses().is_empty());
     }
 
     #[test]
@@ -444,13 +3471,14 @@ mod tests {
                 date: time::Date::from_calendar_date(2003, time::Month::May, 3).unwrap(),
                 time: Some(time::Time::from_hms(11, 11, 5).unwrap()),
             }),
-            time_limit: Some(TimeLimit {
-                main_time: Duration::from_secs(1500),
-                byoyomi: Duration::from_secs(0),
-            }),
             opening: Some("YAGURA".to_string()),
             ..GameRecord::default()
         };
+        g.set_time_limit(Some(TimeLimit {
+            main_time: Duration::from_secs(1500),
+            byoyomi: Duration::from_secs(0),
+            ..TimeLimit::default()
+        }));
         g.moves.push(MoveRecord {
             action: Action::Move(
                 Color::Black,
@@ -459,11 +3487,9 @@ mod tests {
                 PieceType::Pawn,
             ),
             time: Some(Duration::from_secs(5)),
+            events: Vec::new(),
         });
-        g.moves.push(MoveRecord {
-            action: Action::Toryo,
-            time: None,
-        });
+        g.moves.push(MoveRecord::new(Action::Toryo));
 
         let csa = "\
 V2.2
@@ -484,4 +3510,253 @@ T5
 
         assert_eq!(csa, g.to_string());
     }
+
+    /// Pins what `Display` writes for each fixture to the file of the same
+    /// name in `fixtures/display`.
+    #[test]
+    fn display_golden() {
+        let mut checked = 0;
+        for entry in std::fs::read_dir("fixtures/").unwrap() {
+            let path = entry.unwrap().path();
+            if !path.is_file() {
+                continue;
+            }
+            let Ok(record) = crate::parse_csa(&std::fs::read_to_string(&path).unwrap()) else {
+                continue;
+            };
+            let name = path.file_name().unwrap().to_str().unwrap();
+            let golden = std::fs::read_to_string(format!("fixtures/display/{name}")).unwrap();
+            assert_eq!(record.to_string(), golden, "{name}");
+            assert_eq!(record.to_csa(&WriteOptions::default()), golden, "{name}");
+            checked += 1;
+        }
+        assert_eq!(
+            std::fs::read_dir("fixtures/display").unwrap().count(),
+            checked
+        );
+    }
+
+    /// Pins what each [`WritePreset`] writes for each fixture to the file of
+    /// the same name in `fixtures/presets/spec` and
+    /// `fixtures/presets/shogi_server`.
+    #[test]
+    fn write_preset_golden() {
+        let presets = [
+            ("spec", WritePreset::Spec),
+            ("shogi_server", WritePreset::ShogiServerCompatible),
+        ];
+        let mut checked = 0;
+        for entry in std::fs::read_dir("fixtures/").unwrap() {
+            let path = entry.unwrap().path();
+            if !path.is_file() {
+                continue;
+            }
+            let Ok(record) = crate::parse_csa(&std::fs::read_to_string(&path).unwrap()) else {
+                continue;
+            };
+            let name = path.file_name().unwrap().to_str().unwrap();
+            for (dir, preset) in presets {
+                let options = WriteOptions::preset(preset);
+                let golden =
+                    std::fs::read_to_string(format!("fixtures/presets/{dir}/{name}")).unwrap();
+                let written = record.to_csa(&options);
+                assert_eq!(written, golden, "{dir}/{name}");
+                match record.header_comments.first() {
+                    Some(comment) if options.version_first => assert_eq!(
+                        record.try_to_csa(&options),
+                        Err(WriteError::BeforeVersion(comment.clone())),
+                        "{dir}/{name}"
+                    ),
+                    _ => assert_eq!(record.try_to_csa(&options), Ok(written), "{dir}/{name}"),
+                }
+            }
+            let server = std::fs::read_to_string(format!("fixtures/presets/shogi_server/{name}"));
+            let server = server.unwrap();
+            assert!(server.starts_with("V2.2\n"), "{name}");
+            if let Some(position) = server.find("\nP") {
+                assert!(!server[position..].contains("\n$"), "{name}");
+            }
+            checked += 1;
+        }
+        for (dir, _) in presets {
+            assert_eq!(
+                std::fs::read_dir(format!("fixtures/presets/{dir}"))
+                    .unwrap()
+                    .count(),
+                checked
+            );
+        }
+    }
+
+    #[test]
+    fn write_preset_version_first() {
+        let csa = "'CSA encoding=UTF-8\n'source\nV3.0\nN+sente\nN-gote\nPI\n+\n+7776FU\n";
+        let game = crate::parse_csa(csa).unwrap();
+        let spec = WriteOptions::preset(WritePreset::Spec);
+        assert_eq!(game.try_to_csa(&spec).unwrap(), csa);
+
+        let version_first = WriteOptions {
+            version_first: true,
+            ..spec
+        };
+        assert_eq!(
+            game.try_to_csa(&version_first),
+            Err(WriteError::BeforeVersion("'CSA encoding=UTF-8".to_string()))
+        );
+        assert_eq!(
+            game.to_csa(&version_first),
+            "V3.0\nN+sente\nN-gote\nPI\n+\n+7776FU\n"
+        );
+        assert_eq!(
+            game.write_losses_with(&version_first),
+            [
+                "header comments omitted: nothing may come before the version line",
+                "encoding line omitted: nothing may come before the version line",
+            ]
+        );
+        assert_eq!(
+            WriteError::BeforeVersion("'source".to_string()).to_string(),
+            "`'source` would have to be written before the version line"
+        );
+
+        let mut game = game;
+        game.header_comments.clear();
+        game.layout.encoding_line = false;
+        assert!(game.try_to_csa(&version_first).is_ok());
+        let encoding_line = WriteOptions {
+            encoding_line: true,
+            ..version_first
+        };
+        assert!(game.try_to_csa(&encoding_line).is_err());
+    }
+
+    #[test]
+    fn bookmarks_round_trip() {
+        let csa = "V2.2\nPI\n+\n'&start\n+7776FU\nT3\n'&losing move: too slow\n%TORYO\n";
+        let mut game = crate::parse_csa(csa).unwrap();
+        assert_eq!(
+            game.bookmarks,
+            [
+                Bookmark {
+                    ply: 0,
+                    label: "start".to_string(),
+                    comment: None,
+                },
+                Bookmark {
+                    ply: 1,
+                    label: "losing move".to_string(),
+                    comment: Some("too slow".to_string()),
+                },
+            ]
+        );
+        assert_eq!(game.to_string(), csa);
+        assert!(game.write_losses().is_empty());
+
+        game.add_bookmark("two\nlines", None);
+        assert_eq!(
+            game.write_losses(),
+            ["bookmark \"two\\nlines\" at ply 1 changed: it is written as `'&two lines`"]
+        );
+    }
+
+    #[test]
+    fn debug_lists_csa_lines() {
+        let record = crate::parse_csa(
+            "V2.2\n$START_TIME:2024/01/06 10:00:00\nP1 *  *  * \nP2 * -OU * \nP3 *  *  * \nP4 *  *  * \nP5 * +OU * \nP+00KI\n+\n+0023KI\nT15\n%TSUMI\n",
+        )
+        .unwrap();
+        assert_eq!(
+            format!("{record:#?}").split("start_pos: ").nth(1).unwrap(),
+            "[
+        P1 *  *  * ,
+        P2 * -OU * ,
+        P3 *  *  * ,
+        P4 *  *  * ,
+        P5 * +OU * ,
+        P+00KI,
+        +,
+    ],
+    moves: [
+        +0023KI,T15,
+        %TSUMI,
+    ],
+    adjournment: None,
+    bookmarks: [],
+    layout: Layout {
+        bare_black_player: false,
+        bare_white_player: false,
+        rating_comments: [],
+        trailing_attrs: [],
+        source_format: Some(
+            Csa(
+                V2_2,
+            ),
+        ),
+        encoding_line: false,
+        event_convention: None,
+    },
+}"
+        );
+        assert_eq!(
+            format!("{:?}", record.start_time),
+            "Some(2024/01/06 10:00:00)"
+        );
+        let timed = MoveRecord {
+            action: Action::Toryo,
+            time: Some(Duration::from_millis(1500)),
+            events: Vec::new(),
+        };
+        assert_eq!(format!("{timed:?}"), "%TORYO,T1.5");
+
+        let mut position = Position::default();
+        position
+            .drop_pieces
+            .push((Square::new(8, 2), PieceType::Rook));
+        position
+            .add_pieces
+            .push((Color::White, Square::new(0, 0), PieceType::Pawn));
+        position
+            .add_pieces
+            .push((Color::Black, Square::new(5, 5), PieceType::Rook));
+        position.placements_only = true;
+        assert_eq!(
+            format!("{position:?}"),
+            "['placements only, PI82HI, P-00FU, P+55HI, +]"
+        );
+    }
+
+    #[test]
+    fn max_text_len() {
+        let record = crate::parse_csa(
+            "V2.2\nN+Habu Yoshiharu\nN-Sato\n$EVENT:名人戦（第一局）\n$START_TIME:2024/01/06 10:00:00\n$NOTE:ｶﾞ\u{309a}ja\n$MAX_MOVES:256\nPI\n+\n",
+        )
+        .unwrap();
+        let options = WriteOptions {
+            max_text_len: Some((4, Units::Chars)),
+            ..WriteOptions::default()
+        };
+        assert_eq!(
+            record.to_csa(&options),
+            "V2.2\nN+Habu\nN-Sato\n$EVENT:名人戦（\n$START_TIME:2024/01/06 10:00:00\n$NOTE:ｶﾞ\u{309a}j\n$MAX_MOVES:256\nPI\n+\n"
+        );
+        assert_eq!(
+            record.write_losses_with(&options),
+            [
+                "N+ cut to 4 chars: Habu Yoshiharu",
+                "$EVENT cut to 4 chars: 名人戦（第一局）",
+                "$NOTE cut to 4 chars: ｶﾞ\u{309a}ja",
+            ]
+        );
+
+        let options = WriteOptions {
+            max_text_len: Some((10, Units::Bytes)),
+            ..WriteOptions::default()
+        };
+        let written = record.to_csa(&options);
+        assert!(written.contains("\nN+Habu Yoshi\n"));
+        assert!(written.contains("\n$EVENT:名人戦\n"));
+        assert!(written.contains("\n$NOTE:ｶﾞ\u{309a}j\n"));
+        assert_eq!(record.write_losses_with(&options).len(), 3);
+        assert!(record.write_losses().is_empty());
+    }
 }
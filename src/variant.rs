@@ -0,0 +1,198 @@
+//! The rules a record is played under.
+
+use std::fmt;
+
+use crate::board::{Board, BoardError};
+use crate::value::{GameRecord, PieceType};
+
+/// A shogi variant, see [`GameRecord::variant`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[non_exhaustive]
+pub enum Variant {
+    /// Shogi on a 9x9 board.
+    Standard,
+    /// Minishogi on a 5x5 board.
+    Minishogi,
+    /// Wild cat shogi on a 3x5 board.
+    Wildcat,
+    /// Kyoto shogi on a 5x5 board, where every piece but the king turns into
+    /// its other face each time it moves, see [`Variant::kyoto_flip`].
+    Kyoto,
+}
+
+impl Variant {
+    /// Returns the variant played on a board of the given size by default,
+    /// [`Variant::Standard`] for any size but 5x5 and 3x5.
+    pub fn of_dimensions(dimensions: (u8, u8)) -> Variant {
+        match dimensions {
+            (5, 5) => Variant::Minishogi,
+            (3, 5) => Variant::Wildcat,
+            _ => Variant::Standard,
+        }
+    }
+
+    /// Returns the board size of the variant as `(files, ranks)`.
+    pub fn dimensions(self) -> (u8, u8) {
+        match self {
+            Variant::Standard => (9, 9),
+            Variant::Minishogi | Variant::Kyoto => (5, 5),
+            Variant::Wildcat => (3, 5),
+        }
+    }
+
+    /// Returns the name of the variant: `shogi`, `minishogi`, `wildcat` or
+    /// `kyoto`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Variant::Standard => "shogi",
+            Variant::Minishogi => "minishogi",
+            Variant::Wildcat => "wildcat",
+            Variant::Kyoto => "kyoto",
+        }
+    }
+
+    /// Returns the other face of a Kyoto shogi piece: 香 and と, 銀 and 角,
+    /// 金 and 桂, 飛 and 歩 are the two faces of one piece, and the king has
+    /// one face. Returns `None` for the promoted pieces Kyoto shogi does not
+    /// have.
+    ///
+    /// ```
+    /// use csa::{PieceType, Variant};
+    ///
+    /// assert_eq!(Variant::kyoto_flip(PieceType::Lance), Some(PieceType::ProPawn));
+    /// assert_eq!(Variant::kyoto_flip(PieceType::Rook), Some(PieceType::Pawn));
+    /// assert_eq!(Variant::kyoto_flip(PieceType::Dragon), None);
+    /// ```
+    pub fn kyoto_flip(pt: PieceType) -> Option<PieceType> {
+        match pt {
+            PieceType::Lance => Some(PieceType::ProPawn),
+            PieceType::ProPawn => Some(PieceType::Lance),
+            PieceType::Silver => Some(PieceType::Bishop),
+            PieceType::Bishop => Some(PieceType::Silver),
+            PieceType::Gold => Some(PieceType::Knight),
+            PieceType::Knight => Some(PieceType::Gold),
+            PieceType::Rook => Some(PieceType::Pawn),
+            PieceType::Pawn => Some(PieceType::Rook),
+            PieceType::King => Some(PieceType::King),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl GameRecord {
+    /// Returns the variant the game is played under.
+    ///
+    /// Kyoto shogi is played on a minishogi grid, so records say so with the
+    /// attribute `$VARIANT:KYOTO`. Without it, the variant follows from the
+    /// size of the starting position, see [`Variant::of_dimensions`].
+    ///
+    /// ```
+    /// use csa::{parse_csa, Variant};
+    ///
+    /// let game = parse_csa("V2.2\n$VARIANT:KYOTO\nPI\n+\n").unwrap();
+    /// assert_eq!(game.variant(), Variant::Kyoto);
+    /// ```
+    pub fn variant(&self) -> Variant {
+        match self.attr_str("VARIANT") {
+            Some("KYOTO") => Variant::Kyoto,
+            _ => Variant::of_dimensions(self.start_pos.dimensions()),
+        }
+    }
+
+    /// Sets up the starting position on a [`Board`] playing by the rules of
+    /// [`GameRecord::variant`], to replay the moves on.
+    pub fn start_board(&self) -> Result<Board, BoardError> {
+        Board::with_variant(&self.start_pos, self.variant())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_csa;
+    use crate::value::{Action, Color, Square};
+
+    const KYOTO: &str = "V2.2\n$VARIANT:KYOTO\n\
+        P1-FU-KI-OU-GI-KY\nP2 *  *  *  *  * \nP3 *  *  *  *  * \nP4 *  *  *  *  * \nP5+KY+GI+OU+KI+FU\n+\n";
+
+    #[test]
+    fn kyoto_replay() {
+        let game = parse_csa(&format!(
+            "{KYOTO}+5554TO\n-5152HI\n+5453KY\n-5253FU\n+1514HI\n-0044TO\n"
+        ))
+        .unwrap();
+        assert_eq!(game.variant(), Variant::Kyoto);
+        assert_eq!(game.summary(false).variant, "kyoto");
+        let mut board = game.start_board().unwrap();
+        for m in &game.moves {
+            assert!(board.legal_moves().contains(&m.action), "{}", m.action);
+            board.apply(&m.action).unwrap();
+        }
+        assert_eq!(
+            board.piece_at(Square::new(4, 4)),
+            Some((Color::White, PieceType::ProPawn))
+        );
+        // The lance taken as a lance was dropped as a tokin.
+        assert_eq!(board.hand(Color::White, PieceType::Lance), 0);
+        let findings = crate::lint_record(&game, &Default::default());
+        assert!(findings.iter().all(|f| !f.rule.starts_with("KIFU-MOVE")));
+
+        // A moving piece must turn over, into its own other face.
+        let mut board = game.start_board().unwrap();
+        let unturned = Action::Move(
+            Color::Black,
+            Square::new(5, 5),
+            Square::new(5, 4),
+            PieceType::Lance,
+        );
+        assert!(board.apply(&unturned).is_err());
+        let promoted = Action::Move(
+            Color::Black,
+            Square::new(5, 5),
+            Square::new(5, 4),
+            PieceType::ProLance,
+        );
+        assert!(board.apply(&promoted).is_err());
+    }
+
+    #[test]
+    fn kyoto_drops_either_face() {
+        let game = parse_csa(&format!(
+            "{KYOTO}+5554TO\n-5152HI\n+5453KY\n-5253FU\n+1514HI\n"
+        ))
+        .unwrap();
+        let mut board = game.start_board().unwrap();
+        for m in &game.moves {
+            board.apply(&m.action).unwrap();
+        }
+        let drops: Vec<PieceType> = board
+            .legal_moves()
+            .into_iter()
+            .filter_map(|action| match action {
+                Action::Move(_, from, to, pt)
+                    if from == Square::new(0, 0) && to == Square::new(3, 3) =>
+                {
+                    Some(pt)
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(drops, [PieceType::Lance, PieceType::ProPawn]);
+        assert_eq!(board.to_position().add_pieces.len(), 1);
+    }
+
+    #[test]
+    fn variants_by_size() {
+        let game = parse_csa(&KYOTO.replace("$VARIANT:KYOTO\n", "")).unwrap();
+        assert_eq!(game.variant(), Variant::Minishogi);
+        assert_eq!(game.start_board().unwrap().variant(), Variant::Minishogi);
+        let game = parse_csa("V2.2\n$VARIANT:KYOTO\nPI\n+\n").unwrap();
+        assert!(game.start_board().is_err());
+    }
+}
@@ -0,0 +1,38 @@
+//! Interned record strings, which change the type of the metadata fields and
+//! so are tested apart from the unit tests, written against `String`s.
+
+#![cfg(feature = "intern")]
+
+use std::sync::Arc;
+
+use csa::{parse_csa, parse_csa_with_options, Interner, ParseOptions};
+
+#[test]
+fn interned_metadata() {
+    let interner = Interner::new();
+    let options = ParseOptions {
+        intern: Some(&interner),
+        ..ParseOptions::default()
+    };
+    let first = "V2.2\nN+alice\nN-bob\n$EVENT:Floodgate-300-10F\nPI\n+\n";
+    let second = "V3.0\nN+bob\nN-alice\n$EVENT:Floodgate-300-10F\nPI\n+\n";
+    let first = parse_csa_with_options(first, &options).unwrap().record;
+    let second = parse_csa_with_options(second, &options).unwrap().record;
+
+    assert_eq!(interner.len(), 3);
+    assert_eq!(first.black_player.as_deref(), Some("alice"));
+    assert!(Arc::ptr_eq(
+        first.black_player.as_ref().unwrap(),
+        second.white_player.as_ref().unwrap()
+    ));
+    assert!(Arc::ptr_eq(
+        first.event.as_ref().unwrap(),
+        second.event.as_ref().unwrap()
+    ));
+
+    let third = parse_csa("V2.2\nN+alice\nPI\n+\n").unwrap();
+    assert!(!Arc::ptr_eq(
+        first.black_player.as_ref().unwrap(),
+        third.black_player.as_ref().unwrap()
+    ));
+}